@@ -1,15 +1,23 @@
 use std::{
     fmt::{self, Debug},
+    path::PathBuf,
     sync::Arc,
 };
+use uuid::Uuid;
 use webrtc::data_channel::RTCDataChannel;
 
 use crate::{
     app::{
         event::BasicEvent,
-        file_manager::{FileProgressReport, InputFile, SpeedReport},
+        file_manager::{FileId, FileProgressReport, InputFile, SpeedReport},
+        session::SessionId,
+    },
+    client::{
+        message::{Message, TransferOffer},
+        receipt::Verdict,
+        rtc_base::WebConnection,
+        signaling::negotiator::HandshakeState,
     },
-    client::{message::Message, rtc_base::WebConnection, signaling::negotiator::HandshakeState},
     server::types::{RoomId, RoomUser, UserMessage},
 };
 
@@ -22,6 +30,7 @@ pub enum AppEvent {
     FocusNext,
     FocusPrev,
     Client(AppEventClient),
+    Session(SessionId, AppEventClient), // Came from a session's background task
     Server(AppEventServer),
 }
 impl From<AppEvent> for BasicEvent {
@@ -39,10 +48,22 @@ pub enum AppEventClient {
     InitConnection(WebConnection),
     /// A WebRTC channel was open
     ChannelOpened(DebugDataChannel),
+    /// User confirmed the pending transfer
+    ConfirmTransfer,
+    /// The peer wants to send files and awaits a decision
+    IncomingTransferOffer(TransferOffer),
+    /// User answered yes or no to whatever is being asked
+    Decision(bool),
+    /// Terminal pasted paths, most likely dropped files
+    PastedPaths(Vec<PathBuf>),
+    /// We declined the incoming transfer, time to hang up
+    TransferDeclined,
     /// Connection was established
     Connected,
-    /// Connection has broken event
-    Disconnected,
+    /// Connection has ended, one way or another
+    Disconnected(DisconnectKind),
+    /// No file data flowed for too long
+    IdleTimeout,
     /// Updates server signaling UI
     UpdateHandshakeState(HandshakeState),
     /// Manual signaling initialization event
@@ -51,6 +72,8 @@ pub enum AppEventClient {
     ManualSignalingInput(String),
     /// Manual signaling output event
     ManualSignalingOutput(String),
+    /// No offer came for a while, the peers might have the same politeness
+    HandshakeStalled,
     /// A message got sent back
     MessageReceived(Message),
     /// Output file progress update
@@ -62,9 +85,33 @@ pub enum AppEventClient {
     /// Output file was successfully sent
     OutputFileFinished(DebugDataChannel),
     /// New incoming file was added
-    InputFileNew(InputFile),
+    InputFileNew(Box<InputFile>), // Boxed, metadata is big
     /// Metadata was successfully sent
     MetaSent(DebugDataChannel),
+    /// Files settled down in the watched directory
+    WatchedFiles(Vec<PathBuf>),
+    /// Ask for what another tab talks to and sends
+    NewSession,
+    /// Open another transfer in a new tab, with its own room or remote and files
+    OpenSession(Option<String>, Vec<PathBuf>),
+    /// Switch to the next or the previous tab
+    SwitchSession(bool),
+    /// Close the current tab
+    CloseSession,
+    /// A secondary session ran into an error
+    SessionFailed(String),
+    /// Show the output directory in the system file manager
+    OpenOutputDir,
+    /// The peer's UUID came in during the handshake
+    PeerIdentified(Uuid),
+    /// Show or hide the peers panel
+    TogglePeers,
+    /// Start renaming the selected outgoing file
+    StartRename,
+    /// The user typed a new name for an outgoing file
+    RenameFile(FileId, String),
+    /// The sent files were compared against the receiver's receipt
+    ReceiptChecked(Verdict),
 }
 impl From<AppEventClient> for AppEvent {
     fn from(ev: AppEventClient) -> Self {
@@ -77,12 +124,21 @@ impl From<AppEventClient> for BasicEvent {
     }
 }
 
+/// How the peer went away
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DisconnectKind {
+    /// The peer hung up on purpose
+    Closed,
+    /// The peer stopped answering
+    Lost,
+}
+
 /// Holds possible server app events
 #[derive(Clone, Debug)]
 pub enum AppEventServer {
     /// Quit the application.
     Quit,
-    AddRoom(RoomId),
+    AddRoom(RoomId, usize), // Room and its capacity
     RemoveRoom(RoomId),
     AddRoomUser(RoomUser),
     RemoveRoomUser(RoomUser),
@@ -1,6 +1,7 @@
 use crossterm::event::{KeyCode, KeyEvent};
 use rat_focus::Focus;
 use ratatui::DefaultTerminal;
+use std::path::PathBuf;
 use tokio::sync::mpsc::UnboundedReceiver;
 use tokio_util::sync::CancellationToken;
 
@@ -12,21 +13,25 @@ use crate::{
         handlers::{
             app_handler::AppHandler, client_handler::ClientHandler, server_handler::ServerHandler,
         },
-        models::{ClientState, ErrorTX, Maid},
+        models::{ErrorTX, Maid, Toast},
+        session::{ClientSession, SessionId},
     },
-    cli::{Cli, Commands, SignalingSolutions},
+    cli::{Cli, ClientArgs, Commands, SignalingSolutions},
     client::{
-        client_init::init,
-        signaling::{negotiator::HandshakeState, signaling_manual::SignalingManual},
+        client_init::init, rtc_base::HANG_UP_TIMEOUT, signaling::signaling_manual::SignalingManual,
+        watch::DirWatch,
     },
+    logger::LogBuffer,
     server,
     ui::{
         theme::Theme,
-        utils::{CombinedWidgetState, Shortcut},
+        utils::{Ansi, CombinedWidgetState, Shortcut, SystemClipboard},
+        views::accessible_view::Transcript,
         widgets::{
-            files_widget::FileListWidgetState, history_widget::HistoryWidgetState,
-            manual_handshake_widget::ManualHandshakeWidgetState, rooms_widget::RoomListWidgetState,
-            throbber::ThrobberStateCounter, users_widget::UserListWidgetState,
+            history_widget::HistoryWidgetState, log_widget::LogPanelState,
+            new_tab_widget::NewTabState, room_detail_widget::RoomDetailWidgetState,
+            rooms_widget::RoomListWidgetState, throbber::ThrobberStateCounter,
+            users_widget::UserListWidgetState,
         },
     },
 };
@@ -36,9 +41,15 @@ pub struct App {
     // Base app stuff
     /// Should the application exit?
     pub exit: bool,
+    /// Ticks left to press `q` again and quit in the middle of a transfer
+    pub quit_armed: u16,
     /// Should the application redraw?
     /// Tied directly to the tick event
     pub redraw: bool,
+    /// Something changed since the last tick
+    pub dirty: bool,
+    /// The last frame showed something that moves on its own, like a throbber or a clock
+    pub animating: bool,
     /// User-provided arguments
     pub args: Cli,
     /// General event handler
@@ -53,14 +64,18 @@ pub struct App {
     pub cancellation_token: CancellationToken,
     /// Theme colors
     pub theme: Theme,
-
-    // App handlers and app states
-    /// File handler, allows to operate on files with ease
-    pub file_manager: FileManager,
-    /// File-sharing client state
-    pub client_state: ClientState,
-    /// Signaling handshake state
-    pub handshake_state: HandshakeState,
+    /// Brief notification shown in the main frame
+    pub toast: Option<Toast>,
+    /// Recent log lines for the log panel
+    pub log_buffer: LogBuffer,
+
+    // Client sessions
+    /// Session shown on screen, events of the others get routed through it
+    pub session: ClientSession,
+    /// Sessions in the other tabs
+    pub sessions: Vec<ClientSession>,
+    /// Id for the next tab
+    pub next_session_id: SessionId,
 
     // Base widget stuff
     /// Focus handler, simplifies focus management (updates after each re-render)
@@ -69,48 +84,69 @@ pub struct App {
     pub throbber_sc: ThrobberStateCounter,
     /// Shortcuts of a focused widget
     pub widget_shortcuts: Vec<Shortcut>,
-
-    // Client widget states
-    pub handshake_widget_state: ManualHandshakeWidgetState,
-    pub input_list_widget_state: FileListWidgetState,
-    pub output_list_widget_state: FileListWidgetState,
+    /// Log overlay, takes the keys while open
+    pub log_panel_state: LogPanelState,
+    /// What `--accessible` shows instead of the widgets
+    pub transcript: Transcript,
+    /// Every tab's peer at a glance, toggled with `p`
+    pub peers_panel: bool,
+    /// Room or remote and files of the next tab, takes the keys while open
+    pub new_tab: NewTabState,
 
     // Server widget states
     pub room_list_widget_state: RoomListWidgetState,
+    pub room_detail_widget_state: RoomDetailWidgetState,
     pub user_list_widget_state: UserListWidgetState,
     pub history_widget_state: HistoryWidgetState,
 }
 impl App {
-    pub fn new(args: Cli) -> color_eyre::Result<Self> {
+    pub fn new(args: Cli, log_buffer: LogBuffer) -> color_eyre::Result<Self> {
         let (error_tx, error_rx) = tokio::sync::mpsc::unbounded_channel::<color_eyre::Report>();
-        let ignore_empty: bool = if let Commands::Client(client_args) = &args.app_mode {
-            client_args.ignore_empty
-        } else {
-            false
+        let error_tx = ErrorTX(error_tx);
+        let events = EventHandler::new();
+        let cancellation_token = CancellationToken::new();
+        let client_args = match &args.app_mode {
+            Commands::Client(client_args) => Some(client_args),
+            _ => None,
         };
+        let session = ClientSession::new(
+            ClientSession::PRIMARY,
+            client_args,
+            error_tx.clone(),
+            events.sender(),
+            cancellation_token.child_token(),
+        );
+        let theme = Theme::load(args.colors_off())?;
 
         Ok(Self {
             // App
             exit: false,
+            quit_armed: 0,
             redraw: true,
+            dirty: false,
+            animating: false,
             args,
-            events: EventHandler::new(),
+            events,
             error: None,
-            error_tx: ErrorTX(error_tx),
+            error_tx,
             error_rx,
-            theme: Theme::load_default()?,
-            file_manager: FileManager::new(ignore_empty),
-            client_state: ClientState::default(),
-            handshake_state: HandshakeState::default(),
-            cancellation_token: CancellationToken::new(),
+            theme,
+            toast: None,
+            log_buffer,
+            session,
+            sessions: vec![],
+            next_session_id: ClientSession::PRIMARY + 1,
+            cancellation_token,
             // UI
             focus: Focus::default(),
             throbber_sc: ThrobberStateCounter::new(3),
             widget_shortcuts: vec![],
-            handshake_widget_state: ManualHandshakeWidgetState::default(),
-            input_list_widget_state: FileListWidgetState::default(),
-            output_list_widget_state: FileListWidgetState::default(),
+            log_panel_state: LogPanelState::default(),
+            transcript: Transcript::default(),
+            peers_panel: false,
+            new_tab: NewTabState::default(),
             room_list_widget_state: RoomListWidgetState::default(),
+            room_detail_widget_state: RoomDetailWidgetState::default(),
             user_list_widget_state: UserListWidgetState::default(),
             history_widget_state: HistoryWidgetState::default(),
         })
@@ -133,7 +169,21 @@ impl App {
         startup(&mut self, args)?; // Start up the side process
 
         self.main_loop(terminal).await?; // Run the main loop
+        for session in std::iter::once(&self.session).chain(&self.sessions) {
+            // The peers hear it from us instead of waiting for a timeout
+            tokio::time::timeout(HANG_UP_TIMEOUT * 2, session.hang_up())
+                .await
+                .ok();
+        }
         self.cancellation_token.cancel(); // Cancel all tasks
+        if let Commands::Client(client_args) = &self.args.app_mode {
+            for session in std::iter::once(&self.session).chain(&self.sessions) {
+                // A chunk stuck on the way leaves its file be rather than have it written after deleting
+                tokio::time::timeout(HANG_UP_TIMEOUT, session.discard_partial_files(client_args))
+                    .await
+                    .ok();
+            }
+        }
         self.error_loop(terminal).await?; // Show an error screen if something went wrong
 
         if let Some(error) = self.error {
@@ -174,6 +224,7 @@ impl App {
     async fn error_loop(&mut self, terminal: &mut DefaultTerminal) -> color_eyre::Result<()> {
         // Simple error loop
         if self.error.is_some() {
+            self.redraw = true;
             loop {
                 // Redraw
                 if self.redraw {
@@ -186,14 +237,17 @@ impl App {
                     BasicEvent::Tick => {
                         self.on_tick();
                     }
-                    BasicEvent::Crossterm(crossterm::event::Event::Key(key_event)) => {
-                        if key_event.is_release()
-                            && let KeyCode::Char('q') = key_event.code
-                        {
-                            break;
+                    BasicEvent::Crossterm(crossterm::event::Event::Key(key_event))
+                        if key_event.is_release() =>
+                    {
+                        match key_event.code {
+                            KeyCode::Char('q') => break,
+                            KeyCode::Char('c') => self.copy_error_report(),
+                            _ => {}
                         }
+                        self.dirty = true;
                     }
-                    _ => {}
+                    _ => self.dirty = true,
                 }
             }
         }
@@ -201,8 +255,27 @@ impl App {
         Ok(())
     }
 
+    /// Copies the full error report without colors
+    fn copy_error_report(&mut self) {
+        if let Some(error) = &self.error {
+            let report = Ansi::strip_colors(&format!("Error: {:?}", error));
+            self.toast = Some(match SystemClipboard::copy(&report) {
+                Ok(_) => Toast::new("Copied"),
+                Err(err) => {
+                    log::warn!("Failed to copy the error report: {}", err);
+                    Toast::error("Failed to copy")
+                }
+            });
+        }
+    }
+
     /// Cool and sexy event processor!
     async fn process_event(&mut self, event: BasicEvent) -> color_eyre::Result<()> {
+        // Anything but a tick might change what's on screen, the next tick draws it
+        if !matches!(event, BasicEvent::Tick) {
+            self.dirty = true;
+        }
+
         // Handle key and tick events
         self.handle_tick_and_crossterm(&event)?;
 
@@ -211,6 +284,7 @@ impl App {
             match self.args.app_mode {
                 Commands::Client(_) => ClientHandler::handle_app_events(self, app_event)?,
                 Commands::Server(_) => ServerHandler::handle_app_events(self, app_event)?,
+                Commands::Loopback(_) | Commands::Diagnose(_) | Commands::GenSecret => {} // Runs headless
             }
         }
 
@@ -231,6 +305,27 @@ impl App {
         match event {
             BasicEvent::Tick => self.on_tick(),
             BasicEvent::Crossterm(crossterm::event::Event::Key(key_event)) => {
+                if let Some(event) = self.new_tab.handle_typing(key_event) {
+                    self.events.send_app_event(event);
+                    return Ok(());
+                }
+                // The handshake can contain any key, shortcuts included
+                if let Some(event) = self.session.handshake_widget_state.handle_typing(key_event) {
+                    self.events.send_app_event(event);
+                    return Ok(());
+                }
+                if let Some(event) = self
+                    .session
+                    .output_list_widget_state
+                    .handle_typing(key_event)
+                {
+                    self.events.send_app_event(event);
+                    return Ok(());
+                }
+                if self.log_panel_state.handle_key_events(key_event) {
+                    return Ok(()); // The panel covers everything else
+                }
+
                 let mut app_events: Vec<AppEvent> = vec![];
 
                 // Handle focus key events
@@ -240,6 +335,9 @@ impl App {
                 let handler_event = match self.args.app_mode {
                     Commands::Client(_) => ClientHandler::handle_key_events(key_event)?,
                     Commands::Server(_) => ServerHandler::handle_key_events(key_event)?,
+                    Commands::Loopback(_) | Commands::Diagnose(_) | Commands::GenSecret => {
+                        AppEvent::None
+                    }
                 };
                 app_events.push(handler_event);
 
@@ -256,6 +354,22 @@ impl App {
                     self.events.send_app_event(ev);
                 }
             }
+            BasicEvent::Crossterm(crossterm::event::Event::Paste(text)) => {
+                if self.new_tab.handle_paste(text)
+                    || self.session.handshake_widget_state.handle_paste(text)
+                    || self.session.output_list_widget_state.handle_paste(text)
+                {
+                    return Ok(());
+                }
+                let handler_event = match self.args.app_mode {
+                    Commands::Client(_) => ClientHandler::handle_paste_events(text)?,
+                    Commands::Server(_) => ServerHandler::handle_paste_events(text)?,
+                    Commands::Loopback(_) | Commands::Diagnose(_) | Commands::GenSecret => {
+                        AppEvent::None
+                    }
+                };
+                self.events.send_app_event(handler_event);
+            }
             _ => {}
         }
 
@@ -275,8 +389,13 @@ impl App {
                 KeyCode::BackTab => {
                     self.focus.prev();
                 }
-                _ => {}
+                _ => return,
             };
+
+            // Counts don't carry over to another widget
+            for cws in self.get_focusable_widgets() {
+                cws.reset_pending();
+            }
         }
     }
 
@@ -290,33 +409,186 @@ impl App {
     ///
     /// The tick event is where you can update the state of your application with any logic that
     /// needs to be updated at a fixed frame rate. E.g. polling a server, updating an animation.
+    /// Redraws only if something changed, an idle screen stays as it is
     pub fn on_tick(&mut self) {
-        self.throbber_sc.update();
-        self.redraw = true;
+        let spun = self.throbber_sc.update();
+        self.quit_armed = self.quit_armed.saturating_sub(1);
+        if let Some(toast) = &mut self.toast
+            && !toast.update()
+        {
+            self.toast = None;
+            self.dirty = true;
+        }
+        if self.args.accessible && self.transcript.update(&self.session, self.toast.as_ref()) {
+            self.dirty = true;
+        }
+        // Throbbers and clocks move at the throbber's pace
+        self.redraw |= self.dirty || (spun && self.animating);
+        self.dirty = false;
     }
 
     pub fn focusable_widgets_client(&mut self) -> Vec<Box<&mut dyn CombinedWidgetState>> {
         vec![
-            Box::new(&mut self.handshake_widget_state),
-            Box::new(&mut self.input_list_widget_state),
-            Box::new(&mut self.output_list_widget_state),
+            Box::new(&mut self.session.handshake_widget_state),
+            Box::new(&mut self.session.input_list_widget_state),
+            Box::new(&mut self.session.output_list_widget_state),
         ]
     }
     pub fn focusable_widgets_server(&mut self) -> Vec<Box<&mut dyn CombinedWidgetState>> {
         vec![
             Box::new(&mut self.room_list_widget_state),
+            Box::new(&mut self.room_detail_widget_state),
             Box::new(&mut self.user_list_widget_state),
             Box::new(&mut self.history_widget_state),
         ]
     }
+    /// Opens a new tab with the same signaling settings, but its own room or remote and files
+    pub fn open_session(&mut self, target: Option<String>, files: Vec<PathBuf>) {
+        let Commands::Client(args) = &self.args.app_mode else {
+            return;
+        };
+        let mut args = args.clone();
+        if let Some(target) = &target {
+            // Two tabs in the same room would find each other
+            let taken = (std::iter::once(&self.session).chain(&self.sessions))
+                .filter_map(|session| session.signaling.as_ref()?.peer_target())
+                .any(|taken| &taken == target);
+            if taken {
+                self.toast = Some(Toast::error(format!("Another tab already uses {}", target)));
+                return;
+            }
+            args.signaling_mode = args.signaling_mode.with_peer_target(target);
+        }
+
+        let mut session = ClientSession::new(
+            self.next_session_id,
+            Some(&args),
+            self.error_tx.clone(),
+            self.events.sender(),
+            self.cancellation_token.child_token(),
+        );
+        if !files.is_empty() {
+            match session.file_manager.add_output_files(&files) {
+                Ok(skipped) => self.toast = Toast::skipped(skipped.len()),
+                Err(err) => {
+                    log::warn!("Couldn't add the files of the new tab: {}", err);
+                    self.toast = Some(Toast::error("Couldn't add the files, see the log"));
+                    return;
+                }
+            }
+        }
+        self.next_session_id += 1;
+        start_session(&mut session, &args);
+        log::info!("Opened session {}", session.id);
+
+        let previous = std::mem::replace(&mut self.session, session);
+        self.sessions.push(previous);
+    }
+    /// Tabs are ordered by id, switching wraps around
+    pub fn switch_session(&mut self, forward: bool) {
+        let current = self.session.id;
+        let mut ids: Vec<SessionId> = self.sessions.iter().map(|s| s.id).collect();
+        ids.sort_unstable();
+        let target = if forward {
+            ids.iter().find(|&&id| id > current).or(ids.first())
+        } else {
+            ids.iter().rev().find(|&&id| id < current).or(ids.last())
+        };
+
+        if let Some(&target) = target
+            && let Some(i) = self.sessions.iter().position(|s| s.id == target)
+        {
+            std::mem::swap(&mut self.session, &mut self.sessions[i]);
+        }
+    }
+    /// Any tab still has a file on its way
+    pub fn is_transferring(&self) -> bool {
+        (std::iter::once(&self.session).chain(&self.sessions))
+            .any(|session| session.file_manager.is_transferring())
+    }
+    /// The last tab can't be closed, quitting does that
+    pub fn close_session(&mut self, id: SessionId) {
+        if self.sessions.is_empty() {
+            return;
+        }
+
+        if self.session.id == id {
+            self.switch_session(true);
+        }
+        if let Some(i) = self.sessions.iter().position(|s| s.id == id) {
+            let session = self.sessions.remove(i);
+            session.close();
+            if let Commands::Client(client_args) = &self.args.app_mode {
+                tokio::spawn(session.discard_partial_files(client_args));
+            }
+            log::info!("Closed session {}", id);
+        }
+    }
+    /// Runs `f` with the given session swapped in as the current one
+    ///
+    /// Events of closed sessions are dropped
+    pub fn with_session<F>(&mut self, id: SessionId, f: F) -> color_eyre::Result<()>
+    where
+        F: FnOnce(&mut App) -> color_eyre::Result<()>,
+    {
+        if self.session.id == id {
+            return f(self);
+        }
+        let Some(i) = self.sessions.iter().position(|s| s.id == id) else {
+            return Ok(());
+        };
+
+        let active = self.session.id;
+        std::mem::swap(&mut self.session, &mut self.sessions[i]);
+        let result = f(self);
+        if let Some(i) = self.sessions.iter().position(|s| s.id == active) {
+            std::mem::swap(&mut self.session, &mut self.sessions[i]);
+        }
+        result
+    }
+    /// Ids of all sessions in tab order
+    pub fn session_ids(&self) -> Vec<SessionId> {
+        let mut ids: Vec<SessionId> = self.sessions.iter().map(|s| s.id).collect();
+        ids.push(self.session.id);
+        ids.sort_unstable();
+        ids
+    }
+
     pub fn get_focusable_widgets(&mut self) -> Vec<Box<&mut dyn CombinedWidgetState>> {
         match self.args.app_mode {
             Commands::Client(_) => self.focusable_widgets_client(),
             Commands::Server(_) => self.focusable_widgets_server(),
+            Commands::Loopback(_) | Commands::Diagnose(_) | Commands::GenSecret => vec![],
         }
     }
 }
 
+/// Kicks off the signaling and the connection of a session
+fn start_session(session: &mut ClientSession, args: &ClientArgs) {
+    let maid = session.get_maid();
+    let args = args.clone();
+
+    // Prepare manual signaling
+    let mut signaling_manual: Option<SignalingManual> = None;
+    if let SignalingSolutions::Manual(args) = &args.signaling_mode {
+        let sm = SignalingManual::new(session.sender(), args.clone());
+        session.client_state.handshake_tx = Some(sm.sender());
+        signaling_manual = Some(sm);
+    }
+
+    // Run main task
+    tokio::spawn(async move {
+        let token: CancellationToken = maid.token.child_token();
+        let error_tx = maid.error_tx.clone();
+        tokio::select! {
+            _ = token.cancelled() => {},
+            result = init(maid, signaling_manual, args) => {
+                if let Err(err) = result { error_tx.send_error(err); }
+            },
+        }
+    });
+}
+
 /// Startup process
 fn startup(app: &mut App, args: &Cli) -> color_eyre::Result<()> {
     if let Commands::Client(client_args) = &app.args.app_mode {
@@ -325,34 +597,23 @@ fn startup(app: &mut App, args: &Cli) -> color_eyre::Result<()> {
 
     match &args.app_mode {
         Commands::Client(args) => {
-            // Clone stuff
-            let maid = app.get_maid();
-            let args_client = args.clone();
-
             // Add files to the file handler
-            if let Some(files) = args.files.clone() {
-                app.file_manager.add_output_files(&files)?;
+            let mut files = args.files.clone().unwrap_or_default();
+            if let Some(list) = &args.files_from {
+                files.extend(FileManager::read_file_list(list)?);
             }
-
-            // Prepare manual signaling
-            let mut signaling_manual: Option<SignalingManual> = None;
-            if let SignalingSolutions::Manual(args) = &args.signaling_mode {
-                let sm = SignalingManual::new(app.events.sender(), args.clone());
-                app.client_state.handshake_tx = Some(sm.sender());
-                signaling_manual = Some(sm);
+            if !files.is_empty() {
+                let skipped = app.session.file_manager.add_output_files(&files)?;
+                app.toast = Toast::skipped(skipped.len());
+            }
+            if let Some(archive) = &args.from_archive {
+                app.session.file_manager.add_archive_files(archive)?;
+            }
+            if let Some(dir) = &args.watch {
+                app.session.client_state.watch = Some(DirWatch::new(app.session.get_maid(), dir)?);
             }
 
-            // Run main task
-            tokio::spawn(async move {
-                let token: CancellationToken = maid.token.child_token();
-                let error_tx = maid.error_tx.clone();
-                tokio::select! {
-                    _ = token.cancelled() => {},
-                    result = init(maid, signaling_manual, args_client) => {
-                        if let Err(err) = result { error_tx.send_error(err); }
-                    },
-                }
-            });
+            start_session(&mut app.session, args);
         }
         Commands::Server(args) => {
             // Clone stuff
@@ -371,6 +632,7 @@ fn startup(app: &mut App, args: &Cli) -> color_eyre::Result<()> {
                 }
             });
         }
+        Commands::Loopback(_) | Commands::Diagnose(_) | Commands::GenSecret => {} // Runs headless, see main.rs
     }
 
     Ok(())
@@ -0,0 +1,177 @@
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Component, Path, PathBuf},
+};
+use zip::{CompressionMethod, ZipArchive};
+
+/// A local file header, or the end of the central directory in an empty archive
+const ZIP_MAGIC: [&[u8; 4]; 2] = [b"PK\x03\x04", b"PK\x05\x06"];
+
+/// Points at the byte range of an entry stored inside an archive
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ArchiveSource {
+    pub archive: PathBuf,
+    pub offset: u64,
+    /// Index of a deflated zip entry, there's no reading those by offset
+    #[serde(default)]
+    pub deflated: Option<usize>,
+}
+impl ArchiveSource {
+    /// Hands the `size` bytes of data from `skip` on to `read`, deflated entries get inflated on the way
+    ///
+    /// A zip entry borrows its archive, so it can't be returned instead
+    pub fn read<T>(
+        &self,
+        size: usize,
+        skip: usize,
+        read: impl FnOnce(&mut dyn Read) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut file = File::open(&self.archive)?;
+        let Some(index) = self.deflated else {
+            file.seek(SeekFrom::Start(self.offset + skip as u64))?;
+            return read(&mut file.take(size.saturating_sub(skip) as u64));
+        };
+
+        let mut zip = ZipArchive::new(file)?;
+        let mut entry = zip.by_index(index)?;
+        io::copy(&mut (&mut entry).take(skip as u64), &mut io::sink())?;
+        read(&mut entry)
+    }
+}
+
+/// An archive entry that can be sent without extraction
+#[derive(Clone, Debug)]
+pub struct ArchiveEntry {
+    pub path: PathBuf,
+    pub size: usize,
+    pub is_dir: bool,
+    pub source: ArchiveSource,
+}
+
+/// Lists the entries of a tar or zip archive, whatever the extension says
+///
+/// Stored entries are read directly by offset, deflated zip entries get inflated while they're sent
+pub fn read_entries(archive: &Path) -> color_eyre::Result<Vec<ArchiveEntry>> {
+    if is_zip(archive)? {
+        read_zip_entries(archive)
+    } else {
+        read_tar_entries(archive)
+    }
+}
+
+fn is_zip(archive: &Path) -> io::Result<bool> {
+    let mut magic = [0u8; 4];
+    let read = File::open(archive)?.read_exact(&mut magic);
+    Ok(read.is_ok() && ZIP_MAGIC.contains(&&magic))
+}
+
+fn read_tar_entries(archive: &Path) -> color_eyre::Result<Vec<ArchiveEntry>> {
+    let mut result: Vec<ArchiveEntry> = vec![];
+    let mut tar = tar::Archive::new(File::open(archive)?);
+
+    for entry in tar.entries()? {
+        let entry = entry?;
+        let entry_type = entry.header().entry_type();
+        if !entry_type.is_file() && !entry_type.is_dir() {
+            continue; // Links and special files are skipped
+        }
+
+        let path = entry.path()?.into_owned();
+        if !is_safe_path(&path) {
+            log::warn!("Skipped unsafe archive entry {:?}", path);
+            continue;
+        }
+
+        result.push(ArchiveEntry {
+            path,
+            size: entry.size() as usize,
+            is_dir: entry_type.is_dir(),
+            source: ArchiveSource {
+                archive: archive.to_path_buf(),
+                offset: entry.raw_file_position(),
+                deflated: None,
+            },
+        });
+    }
+
+    Ok(result)
+}
+
+fn read_zip_entries(archive: &Path) -> color_eyre::Result<Vec<ArchiveEntry>> {
+    let mut result: Vec<ArchiveEntry> = vec![];
+    let mut zip = ZipArchive::new(File::open(archive)?)?;
+
+    for i in 0..zip.len() {
+        let entry = zip.by_index_raw(i)?;
+        let Some(path) = entry.enclosed_name() else {
+            log::warn!("Skipped unsafe archive entry {:?}", entry.name());
+            continue;
+        };
+
+        let deflated = match entry.compression() {
+            CompressionMethod::Stored => None,
+            CompressionMethod::Deflated => Some(i),
+            method => {
+                return Err(eyre!(
+                    "Only stored and deflated zip entries can be sent, {:?} is {:?}",
+                    entry.name(),
+                    method
+                ));
+            }
+        };
+
+        result.push(ArchiveEntry {
+            path,
+            size: entry.size() as usize,
+            is_dir: entry.is_dir(),
+            source: ArchiveSource {
+                archive: archive.to_path_buf(),
+                offset: entry.data_start(),
+                deflated,
+            },
+        });
+    }
+
+    Ok(result)
+}
+
+/// Makes sure the path can't escape the receiving directory
+pub fn is_safe_path(path: &Path) -> bool {
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::io::Write;
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    #[test]
+    fn deflated_zip_without_the_extension() {
+        let data = b"tappi ".repeat(1000);
+        let dir = TempDir::new("archive");
+        let path = dir.join("bundle.bin");
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        zip.start_file("notes.txt", options).unwrap();
+        zip.write_all(&data).unwrap();
+        zip.finish().unwrap();
+
+        let entries = read_entries(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].size, data.len());
+        assert_eq!(entries[0].source.deflated, Some(0));
+
+        let mut rest = vec![];
+        let source = &entries[0].source;
+        source
+            .read(data.len(), 6, |entry| entry.read_to_end(&mut rest))
+            .unwrap();
+        assert_eq!(rest, data[6..]);
+    }
+}
@@ -1,25 +1,66 @@
 use pasetors::{
     Local,
     claims::{Claims, ClaimsValidationRules},
-    keys::SymmetricKey,
+    keys::{Generate, SymmetricKey},
     local,
     token::{TrustedToken, UntrustedToken},
     version4::V4,
 };
 use serde::Deserialize;
-use std::str::FromStr;
+use std::{fs, path::Path, str::FromStr};
+
+use crate::client::error::TappiError;
 
 #[derive(Deserialize)]
 pub struct MessageClaim {
     msg: String,
 }
 
+/// Environment variable the secret is taken from when no flag gives one
+pub const SECRET_ENV: &str = "TAPPI_SECRET";
+
+/// Characters a generated secret is made of, 64 of them so every byte maps evenly
+const SECRET_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
 #[derive(Clone, Debug)]
 pub struct Secret(String);
 impl Secret {
-    pub fn get_key(&self) -> color_eyre::Result<SymmetricKey<V4>> {
+    pub fn get_key(&self) -> Result<SymmetricKey<V4>, TappiError> {
         Ok(SymmetricKey::<V4>::from(self.0.as_bytes())?)
     }
+
+    /// The flags win over the environment, a file's trailing newlines don't count
+    pub fn resolve(secret: &mut Option<Secret>, file: Option<&Path>) -> color_eyre::Result<()> {
+        if secret.is_some() {
+            return Ok(());
+        }
+        if let Some(path) = file {
+            let text = fs::read_to_string(path).map_err(TappiError::file_io(path))?;
+            *secret = Some(Secret(text.trim_end_matches(['\n', '\r']).to_string()));
+        } else if let Ok(text) = std::env::var(SECRET_ENV)
+            && !text.is_empty()
+        {
+            *secret = Some(Secret(text));
+        }
+        Ok(())
+    }
+
+    /// A random secret that's easy to paste, the bytes come from a freshly generated key
+    pub fn generate() -> Result<Self, TappiError> {
+        let key = SymmetricKey::<V4>::generate()?;
+        let secret = key
+            .as_bytes()
+            .iter()
+            .map(|byte| SECRET_ALPHABET[(byte & 63) as usize] as char)
+            .collect();
+        Ok(Self(secret))
+    }
+}
+impl std::fmt::Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
 }
 impl FromStr for Secret {
     type Err = String;
@@ -29,7 +70,7 @@ impl FromStr for Secret {
 }
 
 /// Encrypt if there's a key provided
-pub fn try_encrypt_claims(text: String, secret: &Option<Secret>) -> color_eyre::Result<String> {
+pub fn try_encrypt_claims(text: String, secret: &Option<Secret>) -> Result<String, TappiError> {
     let result = if let Some(secret) = secret {
         let mut claims = Claims::new()?;
         claims.add_additional("msg", text)?;
@@ -41,11 +82,12 @@ pub fn try_encrypt_claims(text: String, secret: &Option<Secret>) -> color_eyre::
     Ok(result)
 }
 
-pub fn try_decrypt_claims(text: &str, secret: &Option<Secret>) -> color_eyre::Result<String> {
+pub fn try_decrypt_claims(text: &str, secret: &Option<Secret>) -> Result<String, TappiError> {
     let result = if let Some(secret) = secret {
         let trusted = decrypt(&secret.get_key()?, text)?;
         let payload_json = trusted.payload();
-        let parsed: MessageClaim = serde_json::from_str(payload_json)?;
+        let parsed: MessageClaim =
+            serde_json::from_str(payload_json).map_err(|err| TappiError::Crypto(err.into()))?;
         parsed.msg
     } else {
         text.to_owned()
@@ -54,9 +96,42 @@ pub fn try_decrypt_claims(text: &str, secret: &Option<Secret>) -> color_eyre::Re
     Ok(result)
 }
 
-fn decrypt(key: &SymmetricKey<V4>, token: &str) -> color_eyre::Result<TrustedToken> {
+fn decrypt(key: &SymmetricKey<V4>, token: &str) -> Result<TrustedToken, TappiError> {
     let untrusted = UntrustedToken::<Local, V4>::try_from(token)?;
     let rules = ClaimsValidationRules::default();
     let trusted = local::decrypt(key, &untrusted, &rules, None, None)?;
     Ok(trusted)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn generated_secret_works() {
+        let secret = Secret::generate().unwrap();
+        assert_eq!(secret.to_string().len(), 32);
+        assert_ne!(secret.to_string(), Secret::generate().unwrap().to_string());
+
+        let secret = Some(secret);
+        let token = try_encrypt_claims("hi".to_owned(), &secret).unwrap();
+        assert_eq!(try_decrypt_claims(&token, &secret).unwrap(), "hi");
+    }
+
+    #[test]
+    fn secret_from_file() {
+        let dir = TempDir::new("secret");
+        let path = dir.join("secret");
+        fs::write(&path, "0123456789abcdef0123456789abcdef\r\n").unwrap();
+
+        let mut secret = None;
+        Secret::resolve(&mut secret, Some(&path)).unwrap();
+        assert_eq!(secret.unwrap().to_string().len(), 32);
+
+        // A secret that was passed directly stays
+        let mut secret = Some(Secret("given".to_string()));
+        Secret::resolve(&mut secret, Some(&path)).unwrap();
+        assert_eq!(secret.unwrap().to_string(), "given");
+    }
+}
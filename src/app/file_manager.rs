@@ -1,97 +1,355 @@
-use indexmap::IndexMap;
-use serde::{Deserialize, Serialize};
+use color_eyre::eyre::{Context, eyre};
+use indexmap::{IndexMap, IndexSet};
+use serde::{Deserialize, Serialize, Serializer};
 use std::{
-    collections::VecDeque,
+    collections::{HashSet, VecDeque},
     fs,
-    path::{Path, PathBuf},
-    sync::atomic,
-    time::SystemTime,
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+    sync::{OnceLock, atomic},
+    time::{Duration, Instant, SystemTime},
 };
 use walkdir::WalkDir;
 
+use crate::app::archive::{self, ArchiveSource};
+use crate::cli::SendOrder;
+use crate::client::error::TappiError;
+use crate::client::message::{append_ext, append_part_ext};
+
 pub type FileId = usize;
+/// Fewer files than this aren't worth the threads
+const PARALLEL_STAT_MIN: usize = 256;
+/// Disks don't get any faster past this many at once
+const MAX_STAT_THREADS: usize = 16;
 static NEXT_OUTPUT_FILEID: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
 fn get_new_output_file_id() -> usize {
     NEXT_OUTPUT_FILEID.fetch_add(1, atomic::Ordering::Relaxed) // Get and increment
 }
 
+/// Absolute with the `..` resolved, `std::path::absolute` leaves them in on Unix
+fn normalized(path: &Path) -> PathBuf {
+    let abs = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut result = PathBuf::new();
+    for component in abs.components() {
+        match component {
+            Component::ParentDir => {
+                result.pop();
+            }
+            Component::CurDir => {}
+            component => result.push(component),
+        }
+    }
+    result
+}
+
+/// Looks up a lot of files at once, the results come back in the order of the paths
+fn stat_parallel(paths: &[PathBuf]) -> Vec<io::Result<fs::Metadata>> {
+    let threads = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(MAX_STAT_THREADS);
+    if paths.len() < PARALLEL_STAT_MIN || threads < 2 {
+        return paths.iter().map(fs::metadata).collect();
+    }
+
+    let chunk = paths.len().div_ceil(threads);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = paths
+            .chunks(chunk)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(fs::metadata).collect::<Vec<_>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect()
+    })
+}
+
 pub struct FileManager {
     pub ignore_empty: bool,                 // Should it ignore empty directories
+    pub speed_window: usize,                // Speed samples kept per file
+    pub order: SendOrder,                   // How each batch gets sorted
     pub output_queue: VecDeque<OutputFile>, // Regulates the queue
     pub input_map: IndexMap<FileId, InputFile>, // Input file list
     pub output_map: IndexMap<FileId, OutputFile>, // Output file list
+    pub input_started: Option<Instant>,     // First incoming chunk
+    pub output_started: Option<Instant>,    // First outgoing chunk
 }
 impl FileManager {
-    pub fn new(ignore_empty: bool) -> Self {
+    pub fn new(ignore_empty: bool, speed_window: usize, order: SendOrder) -> Self {
         Self {
             ignore_empty,
+            speed_window,
+            order,
             output_queue: VecDeque::default(),
             input_map: IndexMap::default(),
             output_map: IndexMap::default(),
+            input_started: None,
+            output_started: None,
         }
     }
 }
 impl FileManager {
-    pub fn add_output_files(&mut self, files: &Vec<PathBuf>) -> color_eyre::Result<()> {
+    /// Returns the files that were skipped, either vanished between the directory walk and
+    /// reading their metadata or not regular files
+    pub fn add_output_files(&mut self, files: &[PathBuf]) -> color_eyre::Result<Vec<PathBuf>> {
         let mut output_files: Vec<OutputFile> = vec![];
+        let mut skipped: Vec<PathBuf> = vec![];
+        let files = Self::expand_globs(files)?;
 
         // Walk directory recursively if path is a directory
-        for path in files {
+        for (path, shared_base) in Self::shared_roots(files) {
+            let path = &path;
             if path.is_dir() {
-                // Contains empty directories to preserve the structure
-                let mut empty_directories: Vec<PathBuf> = vec![];
-                if !self.ignore_empty {
-                    for entry in WalkDir::new(path)
-                        .into_iter()
-                        .filter_map(Result::ok)
-                        .filter(|e| e.file_type().is_dir())
+                let base = shared_base.unwrap_or_else(|| path.clone());
+                // One walk for everything, special files get skipped right away
+                let mut directories: Vec<PathBuf> = vec![];
+                let mut directory_files: Vec<PathBuf> = vec![];
+                let mut parents: HashSet<PathBuf> = HashSet::new();
+                for entry in WalkDir::new(path).into_iter().filter_map(Result::ok) {
+                    if entry.depth() > 0
+                        && let Some(parent) = entry.path().parent()
                     {
-                        // Check if directory is empty
-                        let path = entry.path();
-                        if fs::read_dir(path)
+                        parents.insert(parent.to_path_buf());
+                    }
+                    let file_type = entry.file_type();
+                    if file_type.is_dir() {
+                        directories.push(entry.into_path());
+                    } else if file_type.is_file() {
+                        directory_files.push(entry.into_path());
+                    } else if !file_type.is_symlink() {
+                        log::warn!("Skipping {:?}, it isn't a regular file", entry.path());
+                        skipped.push(entry.into_path());
+                    }
+                }
+
+                // Empty directories preserve the structure, the walk only hints at which are
+                if !self.ignore_empty {
+                    for p in directories.into_iter().filter(|p| !parents.contains(p)) {
+                        let empty = fs::read_dir(&p)
                             .map(|mut i| i.next().is_none())
-                            .unwrap_or(false)
-                        {
-                            empty_directories.push(path.into());
+                            .unwrap_or(false);
+                        if empty {
+                            output_files.push(OutputFile::new(p, Some(base.clone()), true)?);
                         }
                     }
                 }
 
-                // Contains all files
-                let directory_files: Vec<PathBuf> = WalkDir::new(path)
-                    .into_iter()
-                    .filter_map(Result::ok)
-                    .filter(|entry| entry.file_type().is_file())
-                    .map(|entry| entry.path().to_path_buf())
-                    .collect();
-
-                // Add output files to the list
-                for p in empty_directories {
-                    let of = OutputFile::new(p, Some(path.clone()), true)?;
-                    output_files.push(of);
-                }
-                for p in directory_files {
-                    let of = OutputFile::new(p, Some(path.clone()), false)?;
-                    output_files.push(of);
+                // Add output files to the list, one gone missing shouldn't take the rest with it
+                let stats = stat_parallel(&directory_files);
+                for (p, stat) in directory_files.into_iter().zip(stats) {
+                    match OutputFile::from_stat(p.clone(), Some(base.clone()), stat) {
+                        Ok(of) => output_files.push(of),
+                        Err(err) => {
+                            log::warn!("Skipping {:?}: {}", p, err);
+                            skipped.push(p);
+                        }
+                    }
                 }
             } else {
-                let of = OutputFile::new(path.clone(), None, false)?;
-                output_files.push(of);
+                match OutputFile::new(path.clone(), shared_base, false) {
+                    Ok(of) => output_files.push(of),
+                    Err(err)
+                        if matches!(
+                            TappiError::find(&err),
+                            Some(TappiError::NotRegularFile(_))
+                        ) =>
+                    {
+                        log::warn!("Skipping {:?}: {}", path, err);
+                        skipped.push(path.clone());
+                    }
+                    Err(err) => return Err(err),
+                }
             }
         }
 
-        self.output_queue.extend(output_files.iter().cloned());
+        self.queue_output_files(output_files);
 
-        for file in output_files {
-            self.output_map.insert(file.id, file.clone());
+        Ok(skipped)
+    }
+
+    /// Pairs every root with the base its received path starts from, roots inside another
+    /// added folder are dropped since the walk picks them up anyway
+    ///
+    /// Roots right under the folder they all share keep the usual layout, deeper ones bring the
+    /// folders in between along, so `a/x/f.txt a/y/f.txt` arrive as `x/f.txt y/f.txt`.
+    /// Nothing is shared above the filesystem root, disjoint roots are left alone.
+    /// The roots come back absolute, a relative one like `../y` has no folder name to keep
+    fn shared_roots(files: Vec<PathBuf>) -> Vec<(PathBuf, Option<PathBuf>)> {
+        let mut roots: Vec<(PathBuf, PathBuf)> = vec![];
+        for path in files {
+            let abs = normalized(&path);
+            let covered = roots.iter().any(|(root, root_abs)| {
+                abs == *root_abs || (abs.starts_with(root_abs) && root.is_dir())
+            });
+            if covered {
+                log::warn!("{:?} was already added", path);
+                continue;
+            }
+            // A folder added after its own contents takes them over
+            if path.is_dir() {
+                roots.retain(|(_, root_abs)| !root_abs.starts_with(&abs));
+            }
+            roots.push((path, abs));
+        }
+
+        let mut shared: Option<PathBuf> = None;
+        for (_, abs) in &roots {
+            let parent = abs.parent().unwrap_or(abs);
+            let shared = shared.get_or_insert_with(|| parent.to_path_buf());
+            while !parent.starts_with(&*shared) && shared.pop() {}
         }
+        let shared = shared.filter(|s| s.file_name().is_some());
+
+        roots
+            .into_iter()
+            .map(|(_, abs)| {
+                let depth = (shared.as_ref())
+                    .and_then(|s| abs.strip_prefix(s).ok())
+                    .map_or(0, |rest| rest.components().count());
+                let base = (depth > 1)
+                    .then(|| abs.ancestors().nth(depth - 1))
+                    .flatten()
+                    .filter(|base| base.file_name().is_some())
+                    .map(Path::to_path_buf);
+                (abs, base)
+            })
+            .collect()
+    }
+
+    /// Expands wildcard patterns, existing paths are always taken literally
+    pub fn expand_globs(files: &[PathBuf]) -> color_eyre::Result<Vec<PathBuf>> {
+        let mut expanded: Vec<PathBuf> = vec![];
+
+        for path in files {
+            let pattern = path.to_string_lossy();
+            if path.exists() || !pattern.contains(['*', '?', '[']) {
+                expanded.push(path.clone());
+                continue;
+            }
+
+            let matches: Vec<PathBuf> = glob::glob(&pattern)
+                .wrap_err_with(|| format!("Invalid pattern {:?}", pattern))?
+                .filter_map(Result::ok)
+                .collect();
+            if matches.is_empty() {
+                return Err(eyre!("Pattern {:?} didn't match anything", pattern));
+            }
+            expanded.extend(matches);
+        }
+
+        Ok(expanded)
+    }
+
+    /// Reads a file list, `-` stands for stdin
+    pub fn read_file_list(path: &Path) -> color_eyre::Result<Vec<PathBuf>> {
+        let text = if path == Path::new("-") {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            text
+        } else {
+            fs::read_to_string(path).wrap_err_with(|| format!("Couldn't read {:?}", path))?
+        };
+        Ok(Self::parse_file_list(&text))
+    }
+    /// Null separated lists are taken as is, newline separated ones may have `#` comments
+    pub fn parse_file_list(text: &str) -> Vec<PathBuf> {
+        if text.contains('\0') {
+            text.split('\0')
+                .filter(|p| !p.is_empty())
+                .map(PathBuf::from)
+                .collect()
+        } else {
+            text.lines()
+                .map(|l| l.trim_end_matches('\r'))
+                .filter(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+                .map(PathBuf::from)
+                .collect()
+        }
+    }
+
+    /// Splits pasted text into paths, respecting quotes and escaped whitespace
+    pub fn parse_pasted_paths(text: &str) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = vec![];
+        let mut current = String::new();
+        let mut quote: Option<char> = None;
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), c) => current.push(c),
+                (None, '\\')
+                    if chars
+                        .peek()
+                        .is_some_and(|n| n.is_whitespace() || "'\"".contains(*n)) =>
+                {
+                    current.extend(chars.next()); // Other backslashes are kept for Windows paths
+                }
+                (None, '\'' | '"') => quote = Some(c),
+                (None, c) if c.is_whitespace() => {
+                    if !current.is_empty() {
+                        paths.push(std::mem::take(&mut current).into());
+                    }
+                }
+                (None, c) => current.push(c),
+            }
+        }
+        if !current.is_empty() {
+            paths.push(current.into());
+        }
+
+        paths
+    }
+
+    /// Adds archive entries as if they were files inside a directory named after the archive
+    pub fn add_archive_files(&mut self, archive: &Path) -> color_eyre::Result<()> {
+        let base_path: PathBuf = archive.file_stem().unwrap_or_default().into();
+        let mut output_files: Vec<OutputFile> = vec![];
+
+        for entry in archive::read_entries(archive)? {
+            if entry.is_dir && self.ignore_empty {
+                continue;
+            }
+
+            let path = base_path.join(&entry.path);
+            let meta = MetaData::new(&path, entry.size, Some(base_path.clone()), entry.is_dir);
+            output_files.push(OutputFile::from_meta(meta, Some(entry.source)));
+        }
+
+        self.queue_output_files(output_files);
 
         Ok(())
     }
 
-    // fn add_input_files(&mut self, files: Vec<InputFile>) {
-    //     self.input_files.push(file);
-    // }
+    fn queue_output_files(&mut self, mut output_files: Vec<OutputFile>) {
+        for file in output_files.iter_mut() {
+            file.speed_counter = SpeedCounter::new(self.speed_window);
+        }
+        Self::sort_output_files(&mut output_files, self.order);
+
+        self.output_queue.extend(output_files.iter().cloned());
+
+        for file in output_files {
+            self.output_map.insert(file.id, file.clone());
+        }
+    }
+    /// Stable, so equal files keep the order they were given in
+    fn sort_output_files(files: &mut [OutputFile], order: SendOrder) {
+        match order {
+            SendOrder::AsGiven => {}
+            SendOrder::Alphabetical => files.sort_by_cached_key(|f| f.meta.get_path()),
+            SendOrder::SmallestFirst => files.sort_by_key(|f| f.meta.size),
+            SendOrder::LargestFirst => files.sort_by_key(|f| std::cmp::Reverse(f.meta.size)),
+        }
+    }
+    pub fn add_input_file(&mut self, mut input_file: InputFile) {
+        input_file.speed_counter = SpeedCounter::new(self.speed_window);
+        self.input_map.insert(input_file.id, input_file);
+    }
 
     pub fn get_next_output_file(&mut self) -> Option<OutputFile> {
         self.output_queue.pop_front()
@@ -99,33 +357,124 @@ impl FileManager {
     pub fn get_input_map(&self) -> IndexMap<&FileId, &InputFile> {
         self.input_map.iter().collect()
     }
-    pub fn get_output_map_no_dir(&self) -> IndexMap<&FileId, &OutputFile> {
-        self.output_map
-            .iter()
-            .filter(|v| !v.1.meta.is_dir)
+    /// A row for every folder that was added, showing the share of its files that are done
+    pub fn get_output_dirs(&self) -> Vec<DirRow> {
+        let mut dirs: IndexMap<&PathBuf, (usize, usize)> = IndexMap::new();
+        for f in self.output_map.values() {
+            if let Some(base_path) = &f.meta.base_path {
+                let (completed, total) = dirs.entry(base_path).or_default();
+                if !f.meta.is_dir {
+                    *completed += f.get_finished() as usize;
+                    *total += 1;
+                }
+            }
+        }
+
+        dirs.into_iter()
+            .map(|(base_path, (completed, total))| {
+                let progress = if total > 0 {
+                    completed as f64 / total as f64
+                } else {
+                    1.0 // Nothing but empty folders
+                };
+                DirRow {
+                    meta: MetaData::new(base_path, 0, None, true),
+                    progress,
+                    speed_counter: SpeedCounter::default(),
+                }
+            })
+            .collect()
+    }
+    /// Everything that's sent with the rows from `get_output_dirs` right before their files
+    pub fn get_output_tree<'a>(
+        &'a self,
+        dirs: &'a [DirRow],
+    ) -> IndexMap<OutputRow<'a>, &'a dyn ProgressFile> {
+        let mut seen: IndexSet<&PathBuf> = IndexSet::new();
+        let mut tree: IndexMap<OutputRow, &dyn ProgressFile> = IndexMap::new();
+        for f in self.output_map.values() {
+            // Folders come in the same order they got their rows in
+            if let Some(base_path) = &f.meta.base_path
+                && let Some(dir) = dirs.get(seen.insert_full(base_path).0)
+            {
+                tree.entry(OutputRow::Dir(&dir.meta.path)).or_insert(dir);
+            }
+            // The folder's own entry is shown by its row already
+            if f.meta.is_dir && dirs.iter().any(|dir| dir.meta.path == f.meta.path) {
+                continue;
+            }
+            tree.insert(OutputRow::File(&f.id), f);
+        }
+        tree
+    }
+    /// Keyed the same as `get_output_tree`
+    pub fn get_output_map_no_dir(&self) -> IndexMap<OutputRow<'_>, &dyn ProgressFile> {
+        (self.output_map.iter())
+            .filter(|(_, f)| !f.meta.is_dir)
+            .map(|(id, f)| (OutputRow::File(id), f as &dyn ProgressFile))
             .collect()
     }
 
+    /// Queued files skip what the receiver already has
+    pub fn set_resume_offsets(&mut self, offsets: &[(FileId, usize)]) {
+        for &(id, offset) in offsets {
+            if let Some(queued) = self.output_queue.iter_mut().find(|f| f.id == id) {
+                queued.offset = offset.min(queued.meta.size);
+            }
+            if let Some(output_file) = self.output_map.get_mut(&id)
+                && output_file.meta.size > 0
+            {
+                output_file.progress = offset as f64 / output_file.meta.size as f64;
+            }
+        }
+    }
     pub fn set_output_finished(&mut self, id: FileId) {
+        self.set_output_status(id, FileStatus::Done);
+    }
+    pub fn set_output_status(&mut self, id: FileId, status: FileStatus) {
         if let Some(output_file) = self.output_map.get_mut(&id) {
-            output_file.finished = true;
+            output_file.status = status;
+        }
+    }
+    /// The queued files won't go out anymore
+    pub fn skip_queued(&mut self) {
+        for queued in self.output_queue.drain(..) {
+            if let Some(output_file) = self.output_map.get_mut(&queued.id) {
+                output_file.status = FileStatus::Skipped;
+            }
+        }
+    }
+    /// Whatever was on its way won't get there, the pending files might still make it later
+    pub fn fail_active(&mut self) {
+        let output = self.output_map.values_mut().map(|f| &mut f.status);
+        let input = self.input_map.values_mut().map(|f| &mut f.status);
+        for status in output.chain(input) {
+            if matches!(status, FileStatus::Active | FileStatus::Corrupted) {
+                *status = FileStatus::Failed;
+            }
         }
     }
     pub fn add_input_report(&mut self, report: SpeedReport) {
+        self.input_started.get_or_insert_with(Instant::now);
         if let Some(output_file) = self.input_map.get_mut(&report.file_id) {
             output_file.speed_counter.add_report(report);
         }
     }
     pub fn add_output_report(&mut self, report: SpeedReport) {
+        self.output_started.get_or_insert_with(Instant::now);
         if let Some(output_file) = self.output_map.get_mut(&report.file_id) {
             output_file.speed_counter.add_report(report);
         }
     }
+    /// Time since the first chunk went either way
+    pub fn get_elapsed(started: Option<Instant>) -> Option<Duration> {
+        started.map(|started| started.elapsed())
+    }
     // in seconds
     pub fn get_estimate<P: ProgressFile>(files: &IndexMap<FileId, P>) -> f64 {
         let mut total_size: f64 = 0.0;
         for (_i, f) in files {
-            if !f.get_meta().is_dir && !f.get_finished() {
+            if !f.get_meta().is_dir && f.get_status().is_open() {
                 total_size += (f.get_meta().size as f64) * (1.0 - f.get_progress());
             }
         }
@@ -155,6 +504,57 @@ impl FileManager {
             0.0
         }
     }
+    /// Part files of the incoming files that didn't finish
+    pub fn partial_files(&self, receive_dir: &Path) -> Vec<PathBuf> {
+        (self.input_map.values())
+            .filter(|file| !file.meta.is_dir && !file.get_finished())
+            .map(|file| append_part_ext(receive_dir.join(file.meta.get_path())))
+            .collect()
+    }
+    /// True while any started file hasn't finished yet, in either direction
+    pub fn is_transferring(&self) -> bool {
+        let sending = self
+            .output_map
+            .values()
+            .any(|f| !f.meta.is_dir && f.status.is_open() && f.progress > 0.0);
+        let receiving = self.input_map.values().any(|f| f.status.is_open());
+        sending || receiving
+    }
+    /// Bytes sent and received this session
+    pub fn total_transferred(&self) -> (usize, usize) {
+        (
+            Self::get_transferred(&self.output_map),
+            Self::get_transferred(&self.input_map),
+        )
+    }
+    pub fn get_transferred<P: ProgressFile>(files: &IndexMap<FileId, P>) -> usize {
+        let mut total: usize = 0;
+        for (_i, f) in files {
+            let size = f.get_meta().size;
+            if f.get_finished() {
+                total += size;
+            } else {
+                total += ((size as f64) * f.get_progress().clamp(0.0, 1.0)) as usize;
+            }
+        }
+        total
+    }
+    /// Finished and total files, folders don't count
+    pub fn get_counts<P: ProgressFile>(files: &IndexMap<FileId, P>) -> (usize, usize) {
+        files
+            .values()
+            .filter(|f| !f.get_meta().is_dir)
+            .fold((0, 0), |(completed, total), f| {
+                (completed + f.get_finished() as usize, total + 1)
+            })
+    }
+    /// Same as `get_completion`, but the files that couldn't be written here don't hold it up
+    pub fn get_received<P: ProgressFile>(files: &IndexMap<FileId, P>) -> bool {
+        !files.is_empty()
+            && files
+                .values()
+                .all(|f| f.get_finished() || f.get_status() == FileStatus::Skipped)
+    }
     pub fn get_completion<P: ProgressFile>(files: &IndexMap<FileId, P>) -> bool {
         if !files.is_empty() {
             let mut result = true;
@@ -168,14 +568,88 @@ impl FileManager {
             false
         }
     }
+
+    /// Writes the received files as a JSON array, through a temporary file so it's never partial
+    pub fn write_received_manifest(
+        &self,
+        path: &Path,
+        output_dir: &Path,
+    ) -> color_eyre::Result<()> {
+        let entries: Vec<ManifestEntry> = self
+            .input_map
+            .values()
+            .filter(|f| f.status != FileStatus::Skipped) // Never written
+            .map(|f| ManifestEntry {
+                path: output_dir.join(f.meta.get_path()),
+                size: f.meta.size,
+                meta: &f.meta,
+            })
+            .collect();
+        let json = serde_json::to_string_pretty(&entries)?;
+
+        let temporary = append_ext("tmp", path.to_path_buf());
+        fs::write(&temporary, json)
+            .wrap_err_with(|| format!("Couldn't write the manifest to {:?}", temporary))?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+}
+
+/// Where a received file landed
+#[derive(Serialize)]
+struct ManifestEntry<'a> {
+    path: PathBuf,
+    size: usize,
+    meta: &'a MetaData,
+}
+
+/// Where a file is at, only `Done` counts as finished
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FileStatus {
+    #[default]
+    Pending,
+    Active,
+    Done,
+    /// The connection went away halfway through
+    Failed,
+    /// It was never going to be sent, e.g. the transfer got declined
+    Skipped,
+    /// A chunk arrived corrupt and is being sent again
+    Corrupted,
+}
+impl FileStatus {
+    pub fn from_progress(progress: f64) -> Self {
+        if progress >= 1.0 {
+            Self::Done
+        } else if progress > 0.0 {
+            Self::Active
+        } else {
+            Self::Pending
+        }
+    }
+    /// Still has data to come
+    pub fn is_open(self) -> bool {
+        matches!(self, Self::Pending | Self::Active | Self::Corrupted)
+    }
 }
 
 pub trait ProgressFile {
     fn get_name(&self) -> Option<&str>;
     fn get_progress(&self) -> f64;
-    fn get_finished(&self) -> bool;
+    fn get_status(&self) -> FileStatus;
+    fn get_finished(&self) -> bool {
+        self.get_status() == FileStatus::Done
+    }
     fn get_speed(&self) -> f64;
+    fn get_speed_counter(&self) -> &SpeedCounter;
     fn get_meta(&self) -> &MetaData;
+    /// Where the file is on this side
+    fn get_location(&self) -> PathBuf;
+    /// Derived from the ratio since the sender never counts the bytes itself
+    fn get_progress_bytes(&self) -> usize {
+        let size = self.get_meta().size;
+        ((self.get_progress() * size as f64).round() as usize).min(size)
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -183,48 +657,112 @@ pub struct OutputFile {
     pub id: FileId,
     pub meta: MetaData,
     pub progress: f64,
-    pub finished: bool,
+    pub status: FileStatus,
     pub speed_counter: SpeedCounter,
+    pub source: Option<ArchiveSource>, // Set if the data lives inside an archive
+    /// Where the data starts, the receiver already has everything before it
+    #[serde(skip)]
+    pub offset: usize,
 }
 impl OutputFile {
     fn new(path: PathBuf, base_path: Option<PathBuf>, is_dir: bool) -> color_eyre::Result<Self> {
-        let meta: MetaData = if is_dir {
-            MetaData::new(&path, 0, base_path.clone(), true)
-        } else {
-            let metadata = fs::metadata(path.clone())?;
-            MetaData::new(&path, metadata.len() as usize, base_path.clone(), false)
-        };
-
-        Ok(Self {
+        if is_dir {
+            let meta = MetaData::new(&path, 0, base_path, true);
+            return Ok(Self::from_meta(meta, None));
+        }
+        let stat = fs::metadata(&path);
+        Self::from_stat(path, base_path, stat)
+    }
+    /// A file that was already looked up, possibly on another thread
+    fn from_stat(
+        path: PathBuf,
+        base_path: Option<PathBuf>,
+        stat: io::Result<fs::Metadata>,
+    ) -> color_eyre::Result<Self> {
+        let stat = stat?;
+        // Opening a FIFO blocks until someone writes to it, its length means nothing anyway
+        if !stat.is_file() {
+            return Err(TappiError::NotRegularFile(path).into());
+        }
+        let meta = MetaData::new(&path, stat.len() as usize, base_path, false);
+        Ok(Self::from_meta(meta, None))
+    }
+    fn from_meta(meta: MetaData, source: Option<ArchiveSource>) -> Self {
+        Self {
             id: get_new_output_file_id(),
             meta,
             progress: 0.0,
-            finished: false,
+            status: FileStatus::Pending,
             speed_counter: SpeedCounter::default(),
-        })
+            source,
+            offset: 0,
+        }
     }
 }
 impl ProgressFile for OutputFile {
     fn get_name(&self) -> Option<&str> {
-        let name = self.meta.path.file_name();
-        if let Some(name) = name {
-            name.to_str()
-        } else {
-            None
-        }
+        Some(&self.meta.name)
     }
     fn get_progress(&self) -> f64 {
         self.progress
     }
-    fn get_finished(&self) -> bool {
-        self.finished
+    fn get_status(&self) -> FileStatus {
+        self.status
     }
     fn get_speed(&self) -> f64 {
         self.speed_counter.get_speed().unwrap_or(0.0)
     }
+    fn get_speed_counter(&self) -> &SpeedCounter {
+        &self.speed_counter
+    }
     fn get_meta(&self) -> &MetaData {
         &self.meta
     }
+    fn get_location(&self) -> PathBuf {
+        match &self.source {
+            Some(source) => source.archive.join(self.meta.get_path()),
+            None => self.meta.path.clone(),
+        }
+    }
+}
+
+/// Key of a row in the outgoing list, the folder rows aren't files and have no `FileId`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OutputRow<'a> {
+    File(&'a FileId),
+    /// Same as the `base_path` of the files in it
+    Dir(&'a PathBuf),
+}
+
+/// Row of `--show-dirs`, only ever shown
+#[derive(Clone, Debug)]
+pub struct DirRow {
+    pub meta: MetaData,
+    pub progress: f64,
+    speed_counter: SpeedCounter,
+}
+impl ProgressFile for DirRow {
+    fn get_name(&self) -> Option<&str> {
+        Some(&self.meta.name)
+    }
+    fn get_progress(&self) -> f64 {
+        self.progress
+    }
+    fn get_status(&self) -> FileStatus {
+        FileStatus::from_progress(self.progress)
+    }
+    fn get_speed(&self) -> f64 {
+        0.0
+    }
+    fn get_speed_counter(&self) -> &SpeedCounter {
+        &self.speed_counter
+    }
+    fn get_meta(&self) -> &MetaData {
+        &self.meta
+    }
+    fn get_location(&self) -> PathBuf {
+        self.meta.path.clone()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -232,17 +770,30 @@ pub struct InputFile {
     pub id: FileId,
     pub meta: MetaData,
     pub progress: f64,
+    pub status: FileStatus,
     pub speed_counter: SpeedCounter,
 }
 impl InputFile {
     pub fn new(id: usize, meta: MetaData) -> Self {
+        // Resumed files start with what they already have
+        let progress = match meta.size {
+            0 => 0.0,
+            size => meta.progress_bytes as f64 / size as f64,
+        };
         Self {
             id,
             meta,
-            progress: 0.0,
+            progress,
+            status: FileStatus::Pending,
             speed_counter: SpeedCounter::default(),
         }
     }
+    pub fn set_progress(&mut self, progress: f64) {
+        self.progress = progress;
+        if self.status.is_open() {
+            self.status = FileStatus::from_progress(progress);
+        }
+    }
 }
 impl ProgressFile for InputFile {
     fn get_name(&self) -> Option<&str> {
@@ -251,26 +802,59 @@ impl ProgressFile for InputFile {
     fn get_progress(&self) -> f64 {
         self.progress
     }
-    fn get_finished(&self) -> bool {
-        self.progress >= 1.0
+    fn get_status(&self) -> FileStatus {
+        self.status
     }
     fn get_speed(&self) -> f64 {
         self.speed_counter.get_speed().unwrap_or(0.0)
     }
+    fn get_speed_counter(&self) -> &SpeedCounter {
+        &self.speed_counter
+    }
     fn get_meta(&self) -> &MetaData {
         &self.meta
     }
+    /// Relative to the output directory, unless it's an absolute destination
+    fn get_location(&self) -> PathBuf {
+        self.meta.get_path()
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct MetaData {
     pub is_dir: bool,
+    #[serde(serialize_with = "serialize_lossy")]
     pub path: PathBuf,
+    #[serde(serialize_with = "serialize_lossy_opt")]
     pub base_path: Option<PathBuf>,
+    /// Display name, non-UTF8 bytes show up as �
     pub name: String,
     pub extension: String,
     pub size: usize,
     pub progress_bytes: usize,
+    /// Relative path bytes if they aren't valid UTF-8, lets the receiver recreate the exact name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_path: Option<Vec<u8>>,
+    /// File name the sender picked instead of the one on disk, the folders above it stay
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub renamed: Option<String>,
+    /// Where `--output` puts the file on the receiving side, never sent
+    #[serde(skip)]
+    pub destination: Option<PathBuf>,
+    /// CRC32 of the data received so far, only kept for files received from the very start
+    #[serde(skip)]
+    pub crc: Option<u32>,
+    /// `get_path` runs for every chunk, the path doesn't change once it's known
+    #[serde(skip)]
+    path_cache: PathCache,
+}
+/// Memoized path, it never makes two metadata different
+#[derive(Clone, Debug, Default)]
+struct PathCache(OnceLock<PathBuf>);
+impl PartialEq for PathCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
 }
 impl MetaData {
     pub fn new(path: &Path, size: usize, base_path: Option<PathBuf>, is_dir: bool) -> Self {
@@ -278,55 +862,121 @@ impl MetaData {
         let mut name: String = "".to_string();
         let mut extension: String = "".to_string();
 
-        if let Some(n) = p.file_name()
-            && let Some(s) = n.to_str()
-        {
-            name = s.into();
-            extension = s.into();
+        if let Some(n) = p.file_name() {
+            name = n.to_string_lossy().into();
+        };
+        if let Some(e) = p.extension() {
+            extension = e.to_string_lossy().into();
         };
 
-        Self {
+        let mut meta = Self {
             is_dir,
             base_path,
             name,
             extension,
             size,
             progress_bytes: 0,
+            raw_path: None,
+            renamed: None,
+            destination: None,
+            crc: None,
             path: p,
+            path_cache: PathCache::default(),
+        };
+
+        // The lossy name is only for show, the bytes go along with it
+        let relative = meta
+            .local_path()
+            .or_else(|| meta.path.file_name().map(PathBuf::from));
+        if let Some(relative) = relative
+            && relative.to_str().is_none()
+        {
+            log::warn!("{:?} isn't valid UTF-8, shown as {:?}", relative, meta.name);
+            meta.raw_path = Some(relative.into_os_string().into_encoded_bytes());
         }
+        meta
     }
     fn normalize_path(path: &Path) -> PathBuf {
-        path.to_string_lossy().replace('\\', "/").into()
+        match path.to_str() {
+            Some(s) => s.replace('\\', "/").into(),
+            None => path.to_path_buf(), // Lossy would point to a file that doesn't exist
+        }
     }
-    // TODO: find a less stupid way to do this, please?
+    /// The path under the added folder, starting with the folder's own name
     pub fn local_path(&self) -> Option<PathBuf> {
-        let mut result: Option<PathBuf> = None;
-
-        if let Some(path) = self.base_path.clone() {
-            let cloned_path = path.clone();
-            let parent = cloned_path.file_name();
-            if let Some(parent) = parent {
-                let stripped_path = self.path.strip_prefix(path);
-                if let Ok(stripped_path) = stripped_path {
-                    let mut os_string = parent.to_os_string();
-                    os_string.push("/");
-                    os_string.push(stripped_path);
-                    result = Some(os_string.into());
-                }
+        let base_path = self.base_path.as_ref()?;
+        let parent = base_path.file_name()?;
+        let stripped_path = self.path.strip_prefix(base_path).ok()?;
+        Some(Path::new(parent).join(stripped_path))
+    }
+    /// Relative to the output directory, unless it's an absolute destination
+    pub fn get_path(&self) -> PathBuf {
+        let path = self.path_cache.0.get_or_init(|| {
+            if let Some(destination) = &self.destination {
+                return destination.clone();
             }
-        }
+            let path: PathBuf = if let Some(raw_path) = &self.raw_path {
+                path_from_bytes(raw_path)
+            } else if let Some(local_path) = self.local_path() {
+                local_path
+            } else {
+                self.name.clone().into()
+            };
+            // Only the last component, a name can't move the file somewhere else
+            match self
+                .renamed
+                .as_deref()
+                .and_then(|r| Path::new(r).file_name())
+            {
+                Some(renamed) => path.with_file_name(renamed),
+                None => path,
+            }
+        });
+        path.clone()
+    }
+}
 
-        result
+impl MetaData {
+    /// Sent under `name` instead, the file on disk stays as it is
+    pub fn rename(&mut self, name: &str) {
+        self.path_cache = PathCache::default();
+        self.renamed = Some(name.to_string());
+        self.name = name.to_string();
+        self.extension = (Path::new(name).extension())
+            .map(|e| e.to_string_lossy().into())
+            .unwrap_or_default();
     }
-    pub fn get_path(&self) -> PathBuf {
-        if let Some(local_path) = self.local_path() {
-            local_path
-        } else {
-            self.name.clone().into()
+
+    /// Sends the file to `path` instead, it should be absolute to get out of the output directory
+    pub fn redirect(&mut self, path: &Path) {
+        self.path_cache = PathCache::default();
+        self.destination = Some(path.to_path_buf());
+        if let Some(name) = path.file_name() {
+            self.name = name.to_string_lossy().into();
         }
     }
 }
 
+fn serialize_lossy<P: AsRef<Path>, S: Serializer>(path: &P, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&path.as_ref().to_string_lossy())
+}
+fn serialize_lossy_opt<S: Serializer>(path: &Option<PathBuf>, s: S) -> Result<S::Ok, S::Error> {
+    match path {
+        Some(path) => s.serialize_some(&path.to_string_lossy()),
+        None => s.serialize_none(),
+    }
+}
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+    OsStr::from_bytes(bytes).into()
+}
+// Other systems only get what they can take, the rest gets replaced
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    String::from_utf8_lossy(bytes).into_owned().into()
+}
+
 #[derive(Clone, Debug)]
 pub struct FileProgressReport {
     pub file_id: FileId,
@@ -338,6 +988,49 @@ impl FileProgressReport {
     }
 }
 
+/// How often progress gets reported by default, in milliseconds
+pub const DEFAULT_PROGRESS_INTERVAL: u64 = 100;
+
+/// Holds back progress reports so small chunks don't flood the event loop
+///
+/// A report goes through once enough time passed or enough bytes piled up,
+/// the final 100 is sent on its own and never held back
+#[derive(Clone, Copy, Debug)]
+pub struct ProgressThrottle {
+    interval: Duration,
+    /// Zero only goes by the time
+    bytes: usize,
+    last: Option<Instant>,
+    pending: usize,
+}
+impl Default for ProgressThrottle {
+    fn default() -> Self {
+        Self::new(DEFAULT_PROGRESS_INTERVAL, None)
+    }
+}
+impl ProgressThrottle {
+    pub fn new(interval_ms: u64, bytes: Option<usize>) -> Self {
+        Self {
+            interval: Duration::from_millis(interval_ms),
+            bytes: bytes.unwrap_or(0),
+            last: None,
+            pending: 0,
+        }
+    }
+
+    /// Counts `n` more bytes, true if it's time to report
+    pub fn ready(&mut self, n: usize) -> bool {
+        self.pending += n;
+        let due = self.last.is_none_or(|last| last.elapsed() >= self.interval)
+            || (self.bytes > 0 && self.pending >= self.bytes);
+        if due {
+            self.last = Some(Instant::now());
+            self.pending = 0;
+        }
+        due
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SpeedReport {
     file_id: FileId,
@@ -356,26 +1049,45 @@ impl SpeedReport {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SpeedCounter {
+    capacity: usize,
     report_buffer: VecDeque<SpeedReport>,
+    /// The first report is dropped, the time before it is spent setting things up
+    #[serde(default)]
+    started: bool,
 }
 impl Default for SpeedCounter {
     fn default() -> Self {
-        Self {
-            report_buffer: VecDeque::with_capacity(SpeedCounter::CAPACITY),
-        }
+        Self::new(SpeedCounter::DEFAULT_CAPACITY)
     }
 }
 impl SpeedCounter {
-    const CAPACITY: usize = 10;
+    pub const DEFAULT_CAPACITY: usize = 10;
+    /// Samples needed before there's a speed to show, fewer are mostly noise
+    pub const WARMUP: usize = 4;
+
+    /// Capacity below 2 can't measure anything so it's bumped up
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(2);
+        Self {
+            capacity,
+            report_buffer: VecDeque::with_capacity(capacity),
+            started: false,
+        }
+    }
 
     fn add_report(&mut self, report: SpeedReport) {
-        if self.report_buffer.len() == SpeedCounter::CAPACITY {
+        if !self.started {
+            self.started = true;
+            return;
+        }
+        while self.report_buffer.len() >= self.capacity {
             self.report_buffer.pop_front();
         }
         self.report_buffer.push_back(report);
     }
+    /// None until the warm-up is over, a small window warms up once it's full
     fn get_speed(&self) -> Option<f64> {
-        if self.report_buffer.len() > 1 {
+        if self.report_buffer.len() >= Self::WARMUP.min(self.capacity) {
             let beginning = self.report_buffer[0].timestamp;
             let end = self.report_buffer[self.report_buffer.len() - 1].timestamp;
             let duration = end.duration_since(beginning).unwrap(); // Should be fine since the messages are ordered
@@ -392,4 +1104,446 @@ impl SpeedCounter {
             None
         }
     }
+    /// Speed between every two samples in Mbps, oldest first
+    pub fn history(&self) -> Vec<f64> {
+        let reports = self.report_buffer.iter();
+        reports
+            .clone()
+            .zip(reports.skip(1))
+            .filter_map(|(previous, report)| {
+                let duration = report.timestamp.duration_since(previous.timestamp).ok()?;
+                let seconds = duration.as_secs_f64();
+                (seconds > 0.0).then(|| (report.bytes as f64) * 8.0 / 1_000_000.0 / seconds)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn extension_of(path: &str) -> String {
+        MetaData::new(Path::new(path), 0, None, false).extension
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_survive() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let path = Path::new("dir").join(OsStr::from_bytes(b"caf\xe9.txt"));
+        let meta = MetaData::new(&path, 1, None, false);
+        assert_eq!(meta.name, "caf\u{FFFD}.txt");
+
+        let json = serde_json::to_string(&meta).unwrap();
+        let received: MetaData = serde_json::from_str(&json).unwrap();
+        assert_eq!(received.get_path(), path.file_name().unwrap());
+    }
+
+    #[test]
+    fn redirected_output() {
+        let json =
+            serde_json::to_string(&MetaData::new(Path::new("a.txt"), 1, None, false)).unwrap();
+        let mut received: MetaData = serde_json::from_str(&json).unwrap();
+        received.redirect(Path::new("/tmp/out/b.bin"));
+        assert_eq!(received.name, "b.bin");
+        assert_eq!(
+            Path::new("downloads").join(received.get_path()),
+            Path::new("/tmp/out/b.bin")
+        );
+    }
+
+    #[test]
+    fn renamed_before_sending() {
+        let mut meta = MetaData::new(
+            Path::new("/tmp/photos/2024/a.jpg"),
+            1,
+            Some(PathBuf::from("/tmp/photos")),
+            false,
+        );
+        meta.get_path(); // Cached already
+        meta.rename("beach.png");
+        assert_eq!(meta.get_path(), Path::new("photos/2024/beach.png"));
+        assert_eq!(meta.extension, "png");
+
+        let json = serde_json::to_string(&meta).unwrap();
+        let received: MetaData = serde_json::from_str(&json).unwrap();
+        assert_eq!(received.get_path(), Path::new("photos/2024/beach.png"));
+        assert_eq!(meta.path, Path::new("/tmp/photos/2024/a.jpg")); // Still read from here
+
+        // A name that smuggles folders in only keeps its last part
+        let mut sneaky: MetaData = serde_json::from_str(&json).unwrap();
+        sneaky.renamed = Some("../../evil.sh".to_string());
+        assert_eq!(sneaky.get_path(), Path::new("photos/2024/evil.sh"));
+    }
+
+    #[test]
+    fn throttled_progress() {
+        // The first report always goes through
+        let mut throttle = ProgressThrottle::new(60_000, Some(100));
+        assert!(throttle.ready(10));
+        assert!(!throttle.ready(50));
+        assert!(throttle.ready(50));
+        assert!(!throttle.ready(99));
+
+        // No interval lets everything through
+        let mut throttle = ProgressThrottle::new(0, None);
+        assert!((0..5).all(|_| throttle.ready(1)));
+    }
+
+    #[test]
+    fn cached_path_round_trip() {
+        let base_path = PathBuf::from("/tmp/photos");
+        let meta = MetaData::new(
+            Path::new("/tmp/photos/2024/a.jpg"),
+            1,
+            Some(base_path),
+            false,
+        );
+        assert_eq!(meta.get_path(), Path::new("photos/2024/a.jpg"));
+
+        // The cache stays home, the receiver works the path out again
+        let json = serde_json::to_string(&meta).unwrap();
+        assert!(!json.contains("cache"));
+        let received: MetaData = serde_json::from_str(&json).unwrap();
+        assert_eq!(received, meta);
+        assert_eq!(received.get_path(), meta.get_path());
+    }
+
+    #[test]
+    fn metadata_extension() {
+        assert_eq!(extension_of("dir/file.txt"), "txt");
+        assert_eq!(extension_of("archive.tar.gz"), "gz");
+        assert_eq!(extension_of("Makefile"), "");
+        assert_eq!(extension_of(".bashrc"), "");
+        assert_eq!(extension_of(".config.toml"), "toml");
+    }
+
+    #[test]
+    fn pasted_path_parsing() {
+        let pasted = "'/tmp/my file.txt' \"/tmp/b.txt\"\n/tmp/c\\ d.txt C:\\dir\\e.txt";
+        assert_eq!(
+            FileManager::parse_pasted_paths(pasted),
+            vec![
+                PathBuf::from("/tmp/my file.txt"),
+                PathBuf::from("/tmp/b.txt"),
+                PathBuf::from("/tmp/c d.txt"),
+                PathBuf::from("C:\\dir\\e.txt"),
+            ]
+        );
+        assert!(FileManager::parse_pasted_paths("  \n ").is_empty());
+    }
+
+    #[test]
+    fn file_list_parsing() {
+        let list = "a.txt\n\n# comment\n  # indented comment\ndir/b c.bin\r\n";
+        assert_eq!(
+            FileManager::parse_file_list(list),
+            vec![PathBuf::from("a.txt"), PathBuf::from("dir/b c.bin")]
+        );
+
+        let list = "#not a comment\0with\nnewline\0";
+        assert_eq!(
+            FileManager::parse_file_list(list),
+            vec![
+                PathBuf::from("#not a comment"),
+                PathBuf::from("with\nnewline")
+            ]
+        );
+    }
+
+    #[test]
+    fn glob_expansion() {
+        let dir = TempDir::new("glob");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        for name in ["a.mp4", "b.mp4", "c.txt", "sub/d.mp4", "[x].txt"] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        let expand = |pattern: &str| {
+            let mut files = FileManager::expand_globs(&[dir.join(pattern)]).unwrap();
+            files.sort();
+            files
+        };
+
+        assert_eq!(expand("*.mp4"), vec![dir.join("a.mp4"), dir.join("b.mp4")]);
+        assert_eq!(
+            expand("**/*.mp4"),
+            vec![dir.join("a.mp4"), dir.join("b.mp4"), dir.join("sub/d.mp4")]
+        );
+        assert_eq!(expand("[x].txt"), vec![dir.join("[x].txt")]); // Literal, not a class
+        assert_eq!(expand("missing.txt"), vec![dir.join("missing.txt")]); // Left for later errors
+        assert!(FileManager::expand_globs(&[dir.join("*.mkv")]).is_err());
+    }
+
+    #[test]
+    fn large_tree_keeps_walk_order() {
+        let dir = TempDir::new("large");
+        fs::create_dir_all(dir.join("empty")).unwrap();
+        let count = PARALLEL_STAT_MIN * 2;
+        for i in 0..count {
+            fs::write(dir.join(format!("{:04}", i)), vec![0u8; i % 7]).unwrap();
+        }
+
+        let mut file_manager =
+            FileManager::new(false, SpeedCounter::DEFAULT_CAPACITY, SendOrder::AsGiven);
+        file_manager.add_output_files(&[dir.to_path_buf()]).unwrap();
+        let walked: Vec<PathBuf> = WalkDir::new(&dir)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .collect();
+
+        let files: Vec<&OutputFile> = file_manager.output_map.values().collect();
+        assert!(files[0].meta.is_dir); // The empty one goes first
+        assert_eq!(files.len(), count + 1);
+        for (file, path) in files[1..].iter().zip(&walked) {
+            assert_eq!(&file.meta.path, path);
+            assert_eq!(file.meta.size, fs::metadata(path).unwrap().len() as usize);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn special_files_are_skipped() {
+        let dir = TempDir::new("special");
+        fs::write(dir.join("a.txt"), b"a").unwrap();
+        let socket = dir.join("b.sock");
+        let _listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+
+        let mut file_manager =
+            FileManager::new(false, SpeedCounter::DEFAULT_CAPACITY, SendOrder::AsGiven);
+        let skipped = file_manager.add_output_files(&[dir.to_path_buf()]).unwrap();
+        assert_eq!(skipped, vec![socket.clone()]);
+        assert_eq!(file_manager.output_map.len(), 1);
+
+        // Named directly it's skipped too, not sent as an empty file
+        let skipped = file_manager
+            .add_output_files(std::slice::from_ref(&socket))
+            .unwrap();
+        assert_eq!(skipped, vec![socket]);
+        assert_eq!(file_manager.output_map.len(), 1);
+    }
+
+    #[test]
+    fn roots_under_a_shared_folder() {
+        let dir = TempDir::new("shared");
+        for name in [
+            "x/f.txt",
+            "y/f.txt",
+            "y/sub/g.txt",
+            "docs/a.txt",
+            "notes.txt",
+        ] {
+            fs::create_dir_all(dir.join(name).parent().unwrap()).unwrap();
+            fs::write(dir.join(name), b"a").unwrap();
+        }
+
+        let received = |roots: &[PathBuf]| {
+            let mut file_manager =
+                FileManager::new(false, SpeedCounter::DEFAULT_CAPACITY, SendOrder::AsGiven);
+            file_manager.add_output_files(roots).unwrap();
+            let mut paths: Vec<PathBuf> = (file_manager.output_map.values())
+                .map(|f| f.meta.get_path())
+                .collect();
+            paths.sort();
+            paths
+        };
+        let paths = |names: &[&str]| names.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+        // Several paths under one parent, the folders in between come along
+        assert_eq!(
+            received(&[dir.join("x/f.txt"), dir.join("y/f.txt"), dir.join("y/sub")]),
+            paths(&["x/f.txt", "y/f.txt", "y/sub/g.txt"])
+        );
+        // Straight under it they arrive the same as always
+        assert_eq!(
+            received(&[dir.join("docs"), dir.join("notes.txt")]),
+            paths(&["docs/a.txt", "notes.txt"])
+        );
+        // Disjoint ones only share the filesystem root
+        let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+        assert_eq!(
+            received(&[dir.join("docs"), manifest]),
+            paths(&["Cargo.toml", "docs/a.txt"])
+        );
+        // A mix, what's inside an added folder isn't sent twice
+        assert_eq!(
+            received(&[dir.join("y/sub/g.txt"), dir.join("y"), dir.join("x/f.txt")]),
+            paths(&["x/f.txt", "y/f.txt", "y/sub/g.txt"])
+        );
+        // Relative ones the same, `..` is no folder name
+        let cwd = std::env::current_dir().unwrap();
+        let up: PathBuf = (cwd.components())
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .map(|_| "..")
+            .collect();
+        let root = cwd.ancestors().last().unwrap();
+        let relative = up.join(dir.strip_prefix(root).unwrap());
+        assert_eq!(
+            received(&[relative.join("x/f.txt"), relative.join("x/../y/sub/g.txt")]),
+            paths(&["x/f.txt", "y/sub/g.txt"])
+        );
+    }
+
+    #[test]
+    fn received_manifest() {
+        let dir = TempDir::new("manifest");
+        let mut file_manager =
+            FileManager::new(false, SpeedCounter::DEFAULT_CAPACITY, SendOrder::AsGiven);
+        let meta = MetaData::new(Path::new("a.txt"), 3, None, false);
+        file_manager.add_input_file(InputFile::new(0, meta));
+
+        let manifest = dir.join("manifest.json");
+        file_manager
+            .write_received_manifest(&manifest, Path::new("out"))
+            .unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&manifest).unwrap()).unwrap();
+        assert_eq!(json[0]["path"], "out/a.txt");
+        assert_eq!(json[0]["size"], 3);
+        assert_eq!(json[0]["meta"]["name"], "a.txt");
+        assert!(!dir.join("manifest.json.tmp").exists());
+    }
+
+    #[test]
+    fn send_order() {
+        let files = || {
+            [("b", 1), ("c", 3), ("a", 2)].map(|(name, size)| {
+                OutputFile::from_meta(MetaData::new(Path::new(name), size, None, false), None)
+            })
+        };
+        let sorted = |order| {
+            let mut files = files();
+            FileManager::sort_output_files(&mut files, order);
+            files.map(|f| f.meta.name).join("")
+        };
+
+        assert_eq!(sorted(SendOrder::AsGiven), "bca");
+        assert_eq!(sorted(SendOrder::Alphabetical), "abc");
+        assert_eq!(sorted(SendOrder::SmallestFirst), "bac");
+        assert_eq!(sorted(SendOrder::LargestFirst), "cab");
+    }
+
+    #[test]
+    fn file_counts() {
+        let mut files = IndexMap::new();
+        for (id, (name, is_dir)) in [("a", false), ("dir", true), ("b", false)]
+            .iter()
+            .enumerate()
+        {
+            let meta = MetaData::new(Path::new(name), 1, None, *is_dir);
+            files.insert(id, OutputFile::from_meta(meta, None));
+        }
+        files[0].status = FileStatus::Done;
+        assert_eq!(FileManager::get_counts(&files), (1, 2));
+    }
+
+    #[test]
+    fn status_transitions() {
+        let mut file_manager =
+            FileManager::new(false, SpeedCounter::DEFAULT_CAPACITY, SendOrder::AsGiven);
+        let meta = MetaData::new(Path::new("a"), 10, None, false);
+        let mut input = InputFile::new(0, meta.clone());
+        input.set_progress(0.5);
+        assert_eq!(input.status, FileStatus::Active);
+        file_manager.input_map.insert(0, input);
+
+        let queued = OutputFile::from_meta(meta, None);
+        file_manager.output_map.insert(queued.id, queued.clone());
+        file_manager.output_queue.push_back(queued.clone());
+
+        file_manager.fail_active();
+        file_manager.skip_queued();
+        assert_eq!(file_manager.input_map[0].status, FileStatus::Failed);
+        assert_eq!(file_manager.output_map[0].status, FileStatus::Skipped);
+
+        // A stray report doesn't bring a failed file back
+        file_manager.input_map[0].set_progress(1.0);
+        assert!(!file_manager.input_map[0].get_finished());
+    }
+
+    #[test]
+    fn output_tree() {
+        let mut file_manager =
+            FileManager::new(false, SpeedCounter::DEFAULT_CAPACITY, SendOrder::AsGiven);
+        let dir = PathBuf::from("/tmp/dir");
+        let files = [
+            ("/tmp/loose.txt", None),
+            ("/tmp/dir/a.txt", Some(dir.clone())),
+            ("/tmp/dir/b.txt", Some(dir.clone())),
+        ];
+        for (path, base_path) in files {
+            let meta = MetaData::new(Path::new(path), 1, base_path, false);
+            let file = OutputFile::from_meta(meta, None);
+            file_manager.output_map.insert(file.id, file);
+        }
+        file_manager.output_map[1].status = FileStatus::Done;
+        // The folder's own entry doesn't get a second row
+        let meta = MetaData::new(&dir, 0, Some(dir.clone()), true);
+        let file = OutputFile::from_meta(meta, None);
+        file_manager.output_map.insert(file.id, file);
+
+        let dirs = file_manager.get_output_dirs();
+        assert_eq!(dirs.len(), 1);
+        assert_eq!(dirs[0].meta.name, "dir");
+        assert_eq!(dirs[0].progress, 0.5);
+
+        let tree = file_manager.get_output_tree(&dirs);
+        let names: Vec<&str> = tree.values().filter_map(|f| f.get_name()).collect();
+        assert_eq!(names, ["loose.txt", "dir", "a.txt", "b.txt"]);
+        assert_eq!(tree.get_index(1).unwrap().0, &OutputRow::Dir(&dir));
+    }
+
+    #[test]
+    fn speed_counter_capacity() {
+        for capacity in [2, 3, 10] {
+            let mut counter = SpeedCounter::new(capacity);
+            for _ in 0..capacity * 3 {
+                counter.add_report(SpeedReport::new(0, 1024));
+                assert!(counter.report_buffer.len() <= capacity);
+            }
+            assert_eq!(counter.report_buffer.len(), capacity);
+        }
+        assert_eq!(SpeedCounter::new(0).capacity, 2);
+    }
+
+    #[test]
+    fn speed_history() {
+        let mut counter = SpeedCounter::new(4);
+        let start = SystemTime::now();
+        // The first one is dropped, it only says when the setup was done
+        for (i, bytes) in [0, 0, 125_000, 250_000, 125_000].into_iter().enumerate() {
+            let mut report = SpeedReport::new(0, bytes);
+            report.timestamp = start + Duration::from_secs(i as u64);
+            counter.add_report(report);
+        }
+        assert_eq!(counter.history(), [1.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn speed_warmup() {
+        let mut counter = SpeedCounter::new(10);
+        let start = SystemTime::now();
+        // A slow setup followed by a steady 1 Mbps
+        let times = [0, 5000, 5100, 5200, 5300];
+        for (i, millis) in times.into_iter().enumerate() {
+            let mut report = SpeedReport::new(0, 12_500);
+            report.timestamp = start + Duration::from_millis(millis);
+            counter.add_report(report);
+            assert_eq!(counter.get_speed().is_some(), i == times.len() - 1);
+        }
+        let speed = counter.get_speed().unwrap();
+        assert!((speed - 1.0).abs() < 1e-9);
+
+        // Two samples are all a small window ever has
+        let mut counter = SpeedCounter::new(2);
+        for _ in 0..3 {
+            counter.add_report(SpeedReport::new(0, 1));
+        }
+        assert!(counter.get_speed().is_some());
+    }
 }
@@ -8,6 +8,10 @@ use crate::app::{app_event::AppEvent, app_main::App};
 pub trait AppHandler {
     /// Handle key events here
     fn handle_key_events(key_event: &KeyEvent) -> color_eyre::Result<AppEvent>;
+    /// Handle bracketed paste events here
+    fn handle_paste_events(_text: &str) -> color_eyre::Result<AppEvent> {
+        Ok(AppEvent::None)
+    }
     /// Handle app events here
     fn handle_app_events(app: &mut App, event: AppEvent) -> color_eyre::Result<()>;
 }
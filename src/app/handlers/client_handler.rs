@@ -1,23 +1,39 @@
-use color_eyre::eyre::Context;
+use color_eyre::eyre::eyre;
 use crossterm::event::{KeyCode, KeyEvent};
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
 
 use crate::{
     app::{
-        app_event::{AppEvent, AppEventClient, DebugDataChannel},
+        app_event::{AppEvent, AppEventClient, DebugDataChannel, DisconnectKind},
         app_main::App,
         encrypt::try_decrypt_claims,
-        file_manager::{FileProgressReport, InputFile, OutputFile, SpeedReport},
+        file_manager::{
+            FileId, FileManager, FileProgressReport, FileStatus, InputFile, OutputRow,
+            ProgressFile, SpeedReport,
+        },
         handlers::app_handler::AppHandler,
+        models::{CompletionSummary, Toast},
+        session::SessionId,
     },
-    cli::{Commands, SignalingSolutions},
+    cli::{Commands, MIN_CHUNK_SIZE, SignalingSolutions},
     client::{
-        message::Message,
-        payload,
+        data_cap::CapState,
+        error::TappiError,
+        message::{self, Message, TransferOffer},
+        receipt::{self, ReceiptEntry, ReceiptState, Verdict},
         rtc_base::WebConnection,
         signaling::{negotiator::HandshakeState, signaling_solution::SignalingMessage},
+        transfer::Transfer,
     },
 };
 
+/// Both manual peers made an offer or both wait for one
+const SAME_POLITENESS: &str = "Both peers appear to be the same politeness; one must use --polite";
+
 /// Struct for handling client app events
 pub struct ClientHandler;
 impl AppHandler for ClientHandler {
@@ -27,6 +43,15 @@ impl AppHandler for ClientHandler {
         if key_event.is_release() {
             result = match key_event.code {
                 KeyCode::Char('q') => AppEventClient::Quit.into(),
+                KeyCode::Enter => AppEventClient::ConfirmTransfer.into(),
+                KeyCode::Char('y') => AppEventClient::Decision(true).into(),
+                KeyCode::Char('n') => AppEventClient::Decision(false).into(),
+                KeyCode::Char('t') => AppEventClient::NewSession.into(),
+                KeyCode::Char(']') => AppEventClient::SwitchSession(true).into(),
+                KeyCode::Char('[') => AppEventClient::SwitchSession(false).into(),
+                KeyCode::Char('x') => AppEventClient::CloseSession.into(),
+                KeyCode::Char('o') => AppEventClient::OpenOutputDir.into(),
+                KeyCode::Char('p') => AppEventClient::TogglePeers.into(),
                 _ => AppEvent::None,
             }
         }
@@ -34,64 +59,216 @@ impl AppHandler for ClientHandler {
         Ok(result)
     }
 
+    fn handle_paste_events(text: &str) -> color_eyre::Result<AppEvent> {
+        // Anything that isn't made of existing paths is not a drop
+        let paths = FileManager::parse_pasted_paths(text);
+        if paths.is_empty() || !paths.iter().all(|p| p.exists()) {
+            return Ok(AppEvent::None);
+        }
+        Ok(AppEventClient::PastedPaths(paths).into())
+    }
+
     fn handle_app_events(app: &mut App, event: AppEvent) -> color_eyre::Result<()> {
-        if let AppEvent::Client(app_event) = event {
-            match app_event {
-                AppEventClient::Quit => on_quit(app),
-                AppEventClient::UpdateHandshakeState(state) => {
-                    on_update_handshake_state(app, state)
-                }
-                AppEventClient::ManualSignalingInit(polite) => {
-                    on_manual_signaling_init(app, polite)
-                }
-                AppEventClient::ManualSignalingInput(text) => on_manual_signaling_input(app, text)?,
-                AppEventClient::ManualSignalingOutput(msg) => on_manual_signaling_output(app, msg),
-                AppEventClient::InitConnection(wc) => on_init_connection(app, wc),
-                AppEventClient::ChannelOpened(ddc) => on_channel_opened(app, ddc),
-                AppEventClient::Connected => on_connected(app),
-                AppEventClient::Disconnected => on_disconnected(app),
-                AppEventClient::MessageReceived(message) => on_message_received(app, message),
-                AppEventClient::ReportFileSpeed(report) => on_report_file_speed(app, report),
-                AppEventClient::OutputFileProgress(progress) => {
-                    on_file_progress(app, progress, true)
-                }
-                AppEventClient::InputFileProgress(progress) => {
-                    on_file_progress(app, progress, false)
-                }
-                AppEventClient::OutputFileFinished(ddc) => on_file_finished(app, ddc),
-                AppEventClient::InputFileNew(input_file) => on_input_file_new(app, input_file),
-                AppEventClient::MetaSent(ddc) => on_meta_sent(app, ddc),
+        match event {
+            AppEvent::Client(app_event) => handle_client_event(app, app_event)?,
+            AppEvent::Session(id, AppEventClient::SessionFailed(msg)) => {
+                on_session_failed(app, id, msg)
             }
+            // The other tabs keep going in the background
+            AppEvent::Session(id, app_event) => {
+                app.with_session(id, |app| handle_client_event(app, app_event))?
+            }
+            _ => {}
         }
 
         Ok(())
     }
 }
 
+fn handle_client_event(app: &mut App, app_event: AppEventClient) -> color_eyre::Result<()> {
+    match app_event {
+        AppEventClient::Quit => on_quit(app),
+        AppEventClient::UpdateHandshakeState(state) => on_update_handshake_state(app, state),
+        AppEventClient::ManualSignalingInit(polite) => on_manual_signaling_init(app, polite),
+        AppEventClient::ManualSignalingInput(text) => on_manual_signaling_input(app, text)?,
+        AppEventClient::ManualSignalingOutput(msg) => on_manual_signaling_output(app, msg),
+        AppEventClient::HandshakeStalled => on_handshake_stalled(app),
+        AppEventClient::InitConnection(wc) => on_init_connection(app, wc),
+        AppEventClient::ChannelOpened(ddc) => on_channel_opened(app, ddc),
+        AppEventClient::ConfirmTransfer => on_confirm_transfer(app),
+        AppEventClient::IncomingTransferOffer(offer) => on_incoming_transfer_offer(app, offer),
+        AppEventClient::Decision(accepted) => on_decision(app, accepted),
+        AppEventClient::PastedPaths(paths) => on_pasted_paths(app, paths),
+        AppEventClient::TransferDeclined => on_transfer_declined(app),
+        AppEventClient::Connected => on_connected(app),
+        AppEventClient::Disconnected(kind) => on_disconnected(app, kind),
+        AppEventClient::IdleTimeout => on_idle_timeout(app),
+        AppEventClient::MessageReceived(message) => on_message_received(app, message),
+        AppEventClient::ReportFileSpeed(report) => on_report_file_speed(app, report),
+        AppEventClient::OutputFileProgress(progress) => on_file_progress(app, progress, true),
+        AppEventClient::InputFileProgress(progress) => on_file_progress(app, progress, false),
+        AppEventClient::OutputFileFinished(ddc) => on_file_finished(app, ddc),
+        AppEventClient::InputFileNew(input_file) => on_input_file_new(app, input_file),
+        AppEventClient::MetaSent(ddc) => on_meta_sent(app, ddc),
+        AppEventClient::WatchedFiles(paths) => on_watched_files(app, paths),
+        AppEventClient::NewSession => on_new_session(app),
+        AppEventClient::OpenSession(target, files) => app.open_session(target, files),
+        AppEventClient::SwitchSession(forward) => app.switch_session(forward),
+        AppEventClient::CloseSession => app.close_session(app.session.id),
+        AppEventClient::SessionFailed(msg) => on_session_failed(app, app.session.id, msg),
+        AppEventClient::OpenOutputDir => on_open_output_dir(app),
+        AppEventClient::PeerIdentified(uuid) => on_peer_identified(app, uuid),
+        AppEventClient::TogglePeers => app.peers_panel = !app.peers_panel,
+        AppEventClient::StartRename => on_start_rename(app),
+        AppEventClient::RenameFile(id, name) => on_rename_file(app, id, name),
+        AppEventClient::ReceiptChecked(verdict) => on_receipt_checked(app, verdict),
+    }
+
+    Ok(())
+}
+
+fn on_new_session(app: &mut App) {
+    let needs_target = (app.session.signaling.as_ref()).is_some_and(|s| s.peer_target().is_some());
+    app.new_tab.start(needs_target);
+}
 fn on_quit(app: &mut App) {
-    app.exit = true;
+    // Two seconds to press it again, as long as the toast stays up
+    const QUIT_WINDOW: u16 = 60;
+
+    if app.quit_armed > 0 || !app.is_transferring() {
+        app.exit = true;
+        return;
+    }
+    app.quit_armed = QUIT_WINDOW;
+    app.toast = Some(Toast::error(
+        "Transfer in progress, really quit? Press q again",
+    ));
+}
+fn on_open_output_dir(app: &mut App) {
+    let Commands::Client(client_args) = &app.args.app_mode else {
+        return;
+    };
+
+    // Resolving it also tells whether it exists
+    match client_args.output_dir.canonicalize() {
+        Ok(dir) => {
+            if let Err(err) = opener::open(&dir) {
+                log::warn!("Couldn't open {:?}: {}", dir, err);
+                app.toast = Some(Toast::error("Couldn't open the output directory"));
+            }
+        }
+        Err(_) => app.toast = Some(Toast::error("The output directory doesn't exist yet")),
+    }
+}
+fn on_start_rename(app: &mut App) {
+    let show_dirs = matches!(&app.args.app_mode, Commands::Client(args) if args.show_dirs);
+    let file_manager = &app.session.file_manager;
+    // Same rows the list was drawn with
+    let dirs = if show_dirs {
+        file_manager.get_output_dirs()
+    } else {
+        vec![]
+    };
+    let files = if show_dirs {
+        file_manager.get_output_tree(&dirs)
+    } else {
+        file_manager.get_output_map_no_dir()
+    };
+    let state = &app.session.output_list_widget_state;
+    let Some((row, file)) = (state.get_selected_index()).and_then(|i| files.get_index(i)) else {
+        return;
+    };
+
+    let (id, name) = match row {
+        OutputRow::File(id) if !file.get_meta().is_dir => (**id, file.get_meta().name.clone()),
+        _ => {
+            app.toast = Some(Toast::error("Only files can be renamed"));
+            return;
+        }
+    };
+    if !can_rename(app, id) {
+        app.toast = Some(Toast::error("It was announced to the peer already"));
+    } else {
+        app.session.output_list_widget_state.renaming = Some((id, name));
+    }
+}
+fn on_rename_file(app: &mut App, id: FileId, name: String) {
+    let name = name.trim();
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        app.toast = Some(Toast::error("That isn't a valid file name"));
+        return;
+    }
+    // The metadata might've gone out while typing
+    if !can_rename(app, id) {
+        app.toast = Some(Toast::error("It was announced to the peer already"));
+        return;
+    }
+
+    let file_manager = &mut app.session.file_manager;
+    let queued = file_manager.output_queue.iter_mut().filter(|f| f.id == id);
+    for file in queued.chain(file_manager.output_map.get_mut(&id)) {
+        file.meta.rename(name);
+    }
+    log::info!("File {} will be sent as {:?}", id, name);
+    app.toast = Some(Toast::new(format!("Will be sent as {}", name)));
+}
+/// Only until the metadata goes out, the peer knows the file by its name after that
+fn can_rename(app: &App, id: FileId) -> bool {
+    let file_manager = &app.session.file_manager;
+    !app.session.client_state.announced.contains(&id)
+        && file_manager.output_queue.iter().any(|f| f.id == id)
+}
+fn on_session_failed(app: &mut App, id: SessionId, msg: String) {
+    // Nothing left to fall back on
+    if app.sessions.is_empty() {
+        app.error = Some(eyre!(msg));
+        app.exit = true;
+        return;
+    }
+
+    app.toast = Some(Toast::error(format!("Tab {} failed: {}", id + 1, msg)));
+    app.close_session(id);
 }
 fn on_update_handshake_state(app: &mut App, state: HandshakeState) {
-    app.handshake_state = state;
+    app.session.handshake_state = state;
 }
 fn on_manual_signaling_init(app: &mut App, polite: bool) {
-    app.handshake_widget_state.polite = Some(polite);
+    app.session.handshake_widget_state.polite = Some(polite);
 }
 // Manual signaling part, should be pretty foolproof
 fn on_manual_signaling_input(app: &mut App, text: String) -> color_eyre::Result<()> {
     // If signaling is manual and the handshake wasn't provided yet
     if let Commands::Client(client_args) = &app.args.app_mode
         && let SignalingSolutions::Manual(args) = &client_args.signaling_mode
-        && app.handshake_widget_state.input_text.is_empty()
-        && let Some(tx) = &mut app.client_state.handshake_tx
+        && app.session.handshake_widget_state.input_text.is_empty()
+        && let Some(tx) = &mut app.session.client_state.handshake_tx
     {
-        // Validate it and send it to the signaling side
-        let text =
-            try_decrypt_claims(&text, &args.secret).wrap_err("Incorrect manual handshake")?;
-        let result: Result<SignalingMessage, serde_json::Error> = serde_json::from_str(&text);
+        // Pasting back our own output would lead nowhere
+        if !text.is_empty() && text.trim() == app.session.handshake_widget_state.output_text.trim()
+        {
+            app.toast = Some(Toast::error("That's your own handshake"));
+            return Ok(());
+        }
 
-        // We can ignore errors here methinks, but maybe a toast notification would be nice
-        if let Ok(msg) = result {
+        // Validate it and send it to the signaling side
+        let text = match try_decrypt_claims(&text, &args.secret) {
+            Ok(text) => text,
+            Err(err) => {
+                // Might just be cut short, it's left in the input to add the rest to
+                log::warn!("Incorrect manual handshake: {:?}", err);
+                app.toast = Some(Toast::error("Incorrect handshake, it may be incomplete"));
+                app.session.handshake_widget_state.pending = text;
+                return Ok(());
+            }
+        };
+        let msg = match SignalingMessage::from_json(&text) {
+            Ok(msg) => msg,
+            Err(err) => {
+                app.toast = Some(Toast::error(err.to_string()));
+                return Ok(());
+            }
+        };
+        if let Some(msg) = msg {
             let mut send_flag = false;
             if args.polite {
                 if let SignalingMessage::Offer(_) = msg {
@@ -104,8 +281,12 @@ fn on_manual_signaling_input(app: &mut App, text: String) -> color_eyre::Result<
             } // If impolite it should be an answer
 
             if send_flag {
-                app.handshake_widget_state.input_text = text;
+                app.session.handshake_widget_state.input_text = text;
+                app.session.handshake_widget_state.pending.clear();
                 tx.send(msg).ok();
+            } else {
+                // Two offers or two answers, both sides picked the same role
+                app.toast = Some(Toast::error(SAME_POLITENESS));
             }
         }
     }
@@ -113,120 +294,618 @@ fn on_manual_signaling_input(app: &mut App, text: String) -> color_eyre::Result<
     Ok(())
 }
 fn on_manual_signaling_output(app: &mut App, msg: String) {
-    app.handshake_widget_state.output_text = msg;
+    app.session.handshake_widget_state.output_text = msg;
+}
+fn on_handshake_stalled(app: &mut App) {
+    app.toast = Some(Toast::error(SAME_POLITENESS));
 }
 fn on_init_connection(app: &mut App, wc: WebConnection) {
-    app.client_state.wc = Some(wc);
+    app.session.client_state.wc = Some(wc);
 }
 fn on_channel_opened(app: &mut App, ddc: DebugDataChannel) {
-    send_all_meta(app, ddc);
+    // Messages go through it even if we never send a file
+    app.session.client_state.channel = Some(ddc.clone());
+    if let Commands::Client(client_args) = &app.args.app_mode
+        && client_args.heartbeat > 0
+        && let Some(wc) = &app.session.client_state.wc
+    {
+        let interval = Duration::from_secs(client_args.heartbeat);
+        wc.heartbeat
+            .spawn(app.session.get_maid(), ddc.dc.clone(), interval);
+    }
+
+    // Nothing to confirm if there's nothing to send
+    if let Commands::Client(client_args) = &app.args.app_mode
+        && client_args.confirm
+        && !app.session.file_manager.output_queue.is_empty()
+    {
+        app.session.client_state.pending_confirm = Some(ddc);
+    } else {
+        send_all_meta(app, ddc);
+    }
+}
+// Enter acknowledges whatever waits for the user
+fn on_confirm_transfer(app: &mut App) {
+    if app.session.completion_summary.take().is_some() {
+        return;
+    }
+    if let Some(ddc) = app.session.client_state.pending_confirm.take() {
+        send_all_meta(app, ddc);
+    }
+}
+fn on_peer_identified(app: &mut App, uuid: Uuid) {
+    log::info!("Peer identified as {}", uuid);
+    app.session.client_state.peer_uuid = Some(uuid);
+}
+fn on_incoming_transfer_offer(app: &mut App, offer: TransferOffer) {
+    if let Some(sender) = &offer.summary.sender {
+        app.session.client_state.peer_name = Some(sender.clone());
+    }
+    app.session.client_state.incoming_offer = Some(offer);
+}
+fn on_decision(app: &mut App, accepted: bool) {
+    if let Some(offer) = app.session.client_state.incoming_offer.take() {
+        offer.reply_tx.send(accepted).ok();
+    } else if let Some(paths) = app.session.client_state.pasted_paths.take()
+        && accepted
+    {
+        app.session.client_state.dropped_paths.extend(paths);
+        if app.session.client_state.sending {
+            app.toast = Some(Toast::new("Added once the current files are sent"));
+        }
+        send_dropped_files(app);
+    } else if app.session.client_state.data_cap.state == CapState::Reached {
+        if accepted {
+            let (sent, received) = app.session.file_manager.total_transferred();
+            app.session.client_state.data_cap.extend(sent + received);
+            set_paused(app, false);
+        } else {
+            log::info!("Stopped at the data cap");
+            app.session.client_state.data_cap.stop();
+            close_connection(app);
+        }
+    }
+}
+fn on_pasted_paths(app: &mut App, paths: Vec<PathBuf>) {
+    app.session.client_state.pasted_paths = Some(paths);
+}
+fn on_transfer_declined(app: &mut App) {
+    log::info!("Incoming transfer declined");
+    app.toast = Some(Toast::new("Transfer declined"));
+    close_connection(app);
 }
 fn on_connected(app: &mut App) {
+    // Back after an ICE restart, everything else is still in place
+    if app.session.client_state.connected {
+        log::info!("Connection recovered");
+        app.toast = Some(Toast::new("Reconnected"));
+        return;
+    }
     log::info!("Connection established");
-    app.client_state.connected = true;
+    app.session.client_state.connected = true;
+    app.session.client_state.was_connected = true;
+
+    if let Commands::Client(client_args) = &app.args.app_mode
+        && let Some(wc) = &app.session.client_state.wc
+    {
+        let chunk_size = wc.fit_chunk(client_args.chunk_size);
+        if let Some(max) = wc.max_message()
+            && max < MIN_CHUNK_SIZE
+        {
+            log::warn!(
+                "The peer claims to take messages up to {} bytes only, sending {} byte chunks anyway",
+                max,
+                chunk_size
+            );
+        } else if chunk_size < client_args.chunk_size {
+            log::warn!(
+                "The peer takes messages up to {} bytes, sending chunks of that size instead of {}",
+                chunk_size,
+                client_args.chunk_size
+            );
+        } else {
+            log::info!("Sending chunks of {} bytes", chunk_size);
+        }
+    }
+
+    if let Commands::Client(client_args) = &app.args.app_mode
+        && let Some(seconds) = client_args.idle_timeout
+    {
+        let timeout = Duration::from_secs(seconds);
+        app.session
+            .client_state
+            .idle
+            .spawn(app.session.get_maid(), timeout);
+    }
 }
-fn on_disconnected(app: &mut App) {
-    log::info!("Disconnected");
-    app.client_state.connected = false;
+fn on_disconnected(app: &mut App, kind: DisconnectKind) {
+    log::info!("Disconnected: {:?}", kind);
+    // Hanging up ourselves needs no explaining
+    if !app.session.client_state.connected {
+        return;
+    }
+    // The files carry on once ICE found another way, failing for good is an error anyway
+    if kind == DisconnectKind::Lost
+        && let Commands::Client(client_args) = &app.args.app_mode
+        && client_args.ice_restart
+        && !matches!(client_args.signaling_mode, SignalingSolutions::Manual(_))
+    {
+        app.toast = Some(Toast::error("Connection lost, trying to reconnect"));
+        return;
+    }
+    app.session.client_state.connected = false;
+    app.session.file_manager.fail_active();
+    if let Commands::Client(client_args) = &app.args.app_mode {
+        tokio::spawn(app.session.discard_partial_files(client_args));
+    }
+
+    let file_manager = &app.session.file_manager;
+    let complete = FileManager::get_completion(&file_manager.output_map)
+        || FileManager::get_received(&file_manager.input_map);
+    // The reason the peer gave, like declining, says more than the hang up
+    if kind == DisconnectKind::Closed && app.toast.as_ref().is_some_and(|toast| toast.is_error) {
+        return;
+    }
+    app.toast = Some(match kind {
+        DisconnectKind::Closed if complete => Toast::new("Transfer complete, peer disconnected"),
+        DisconnectKind::Closed => Toast::new("Peer closed the connection"),
+        DisconnectKind::Lost => Toast::error("Connection lost"),
+    });
+}
+/// The peer hung up on purpose, there's nothing to reconnect to
+fn on_peer_left(app: &mut App) {
+    log::info!("The peer said goodbye");
+    on_disconnected(app, DisconnectKind::Closed);
+    // Closing it ourselves beats waiting for ICE to give up on it
+    if let Some(wc) = app.session.client_state.wc.clone() {
+        tokio::spawn(async move { wc.hang_up(None).await });
+    }
+}
+fn on_idle_timeout(app: &mut App) {
+    log::info!("Closing the idle connection");
+    app.toast = Some(Toast::new("Connection closed after being idle"));
+    close_connection(app);
 }
 fn on_message_received(app: &mut App, message: Message) {
     match message {
-        Message::TextMessage(_) => {} // TODO: implement
+        Message::TextMessage(_) => {}       // TODO: implement
+        Message::TransferOffer(_) => {}     // Handled by the receiving side
+        Message::Hello(_) => {}             // Checked by the channel handler
+        Message::Ping | Message::Pong => {} // Answered by the channel handler
+        Message::TransferDeclined => {
+            let err = TappiError::PeerRejected;
+            log::info!("{}", err);
+            app.toast = Some(Toast::error(err.to_string()));
+            app.session.file_manager.skip_queued();
+        }
         Message::FilePacketReceived(report) => {
-            app.file_manager.add_output_report(report);
+            app.session.file_manager.add_output_report(report);
+            note_activity(app);
         }
         Message::FileReceived(id) => {
-            app.file_manager.set_output_finished(id);
+            app.session.file_manager.set_output_finished(id);
+            note_activity(app);
+            check_completion(app);
+            request_receipt(app);
+        }
+        Message::ResendChunk(id, offset) => {
+            let file_manager = &mut app.session.file_manager;
+            file_manager.set_output_status(id, FileStatus::Corrupted);
+            resend_file_data(app, id, offset);
         }
+        Message::ResumeQuery => {} // Answered by the channel handler
+        Message::ResumeOffsets(offsets) => {
+            app.session.file_manager.set_resume_offsets(&offsets);
+            if let Some(ddc) = app.session.client_state.channel.clone() {
+                send_next_file(app, ddc);
+            }
+        }
+        Message::ReceiptQuery => {} // Answered by the channel handler
+        Message::Receipt(receipt) => on_receipt(app, receipt),
+        Message::Goodbye => on_peer_left(app),
+        Message::Pause(paused) => {
+            app.session.client_state.peer_paused = paused;
+            if paused {
+                app.toast = Some(Toast::new("The peer reached its data cap, waiting for it"));
+            }
+            update_paused(app);
+        }
+    }
+}
+/// Asks once everything got delivered, only with `--receipt`
+fn request_receipt(app: &mut App) {
+    if let Commands::Client(client_args) = &app.args.app_mode
+        && client_args.receipt
+        && matches!(app.session.client_state.receipt, ReceiptState::Idle)
+        && FileManager::get_completion(&app.session.file_manager.output_map)
+        && let Some(wc) = &app.session.client_state.wc
+        && let Some(ddc) = app.session.client_state.channel.clone()
+    {
+        app.session.client_state.receipt = ReceiptState::Pending;
+        Transfer::send_message(app.session.get_maid(), wc, ddc, Message::ReceiptQuery);
+    }
+}
+fn on_receipt(app: &mut App, receipt: Vec<ReceiptEntry>) {
+    // Only the receipt we asked for
+    if !matches!(app.session.client_state.receipt, ReceiptState::Pending) {
+        return;
+    }
+    let files = app.session.file_manager.output_map.values().cloned();
+    receipt::check_receipt(app.session.get_maid(), files.collect(), receipt);
+}
+fn on_receipt_checked(app: &mut App, verdict: Verdict) {
+    for mismatch in &verdict.mismatches {
+        log::warn!("Receipt mismatch, {}", mismatch);
     }
+    log::info!("Receipt {}", verdict.text());
+    app.toast = Some(if verdict.is_ok() {
+        Toast::new(verdict.text())
+    } else {
+        Toast::error(verdict.text())
+    });
+    app.session.client_state.receipt = ReceiptState::Checked(verdict);
 }
 fn on_report_file_speed(app: &mut App, report: SpeedReport) {
-    app.file_manager.add_input_report(report);
+    app.session.file_manager.add_input_report(report);
+    note_activity(app);
 }
 fn on_file_progress(app: &mut App, progress_report: FileProgressReport, output: bool) {
     if output {
         let output_file = app
+            .session
             .file_manager
             .output_map
             .get_mut(&progress_report.file_id);
         if let Some(output_file) = output_file {
             output_file.progress = progress_report.progress;
+            // Done only once the receiver says so
+            if output_file.status == FileStatus::Pending {
+                output_file.status = FileStatus::Active;
+            }
         }
     } else {
-        let input_file = app.file_manager.input_map.get_mut(&progress_report.file_id);
+        let input_file = app
+            .session
+            .file_manager
+            .input_map
+            .get_mut(&progress_report.file_id);
         if let Some(input_file) = input_file {
-            input_file.progress = progress_report.progress;
+            let was_finished = input_file.get_finished();
+            input_file.set_progress(progress_report.progress);
+            let finished = !was_finished && input_file.get_finished();
+            if finished {
+                commit_staging(app);
+                write_received_manifest(app);
+            }
+            save_resume_state(app, finished);
         }
     }
+    check_data_cap(app);
 }
 fn on_file_finished(app: &mut App, ddc: DebugDataChannel) {
     send_next_file(app, ddc);
 }
-fn on_input_file_new(app: &mut App, input_file: InputFile) {
-    app.file_manager.input_map.insert(input_file.id, input_file);
+fn on_input_file_new(app: &mut App, input_file: Box<InputFile>) {
+    let skipped = input_file.status == FileStatus::Skipped;
+    if skipped {
+        let name = input_file.get_name().unwrap_or_default();
+        app.toast = Some(Toast::error(format!("Skipped {}, see the log", name)));
+    }
+    app.session.file_manager.add_input_file(*input_file);
+    save_resume_state(app, true);
+    // It might've been the last one the batch was waiting for
+    if skipped {
+        commit_staging(app);
+        write_received_manifest(app);
+    }
 }
 fn on_meta_sent(app: &mut App, ddc: DebugDataChannel) {
+    // The files go out once the receiver said what it already has
+    if let Commands::Client(client_args) = &app.args.app_mode
+        && client_args.resume
+        && let Some(wc) = &app.session.client_state.wc
+    {
+        Transfer::send_message(app.session.get_maid(), wc, ddc, Message::ResumeQuery);
+        return;
+    }
     send_next_file(app, ddc);
 }
+fn on_watched_files(app: &mut App, paths: Vec<PathBuf>) {
+    if let Some(watch) = &mut app.session.client_state.watch {
+        watch.accept(paths);
+    }
+    send_watched_files(app);
+}
 
-fn send_next_file(app: &mut App, ddc: DebugDataChannel) {
-    let mut exit: bool = false;
-    while !exit {
-        if let Some(of) = app.file_manager.get_next_output_file() {
-            if !of.meta.is_dir && of.meta.size > 0 {
-                send_file_data(app, &ddc, &of);
-                exit = true;
+fn check_completion(app: &mut App) {
+    // A watched directory is never really done
+    let enabled = matches!(&app.args.app_mode, Commands::Client(args) if !args.no_summary)
+        && app.session.client_state.watch.is_none();
+    if !enabled
+        || app.session.summary_shown
+        || !FileManager::get_completion(&app.session.file_manager.output_map)
+    {
+        return;
+    }
+
+    let output_map = &app.session.file_manager.output_map;
+    app.session.summary_shown = true;
+    app.session.completion_summary = Some(CompletionSummary {
+        files: output_map.values().filter(|f| !f.meta.is_dir).count(),
+        bytes: FileManager::get_transferred(output_map),
+        elapsed: (app.session.client_state.transfer_started)
+            .map(|started| started.elapsed())
+            .unwrap_or_default(),
+    });
+}
+/// Moves the `--staging` files over once everything that was announced has arrived
+fn commit_staging(app: &mut App) {
+    if let Commands::Client(client_args) = &app.args.app_mode
+        && let Some(wc) = &app.session.client_state.wc
+        && let Some(staging_dir) = &wc.staging_dir
+        && FileManager::get_received(&app.session.file_manager.input_map)
+    {
+        match message::commit_staging(staging_dir, &client_args.output_dir) {
+            Ok(()) => log::info!("Moved the staged files to {:?}", client_args.output_dir),
+            Err(err) => {
+                log::warn!("{:?}, the files are left in {:?}", err, staging_dir);
+                app.toast = Some(Toast::error("Couldn't move the staged files"));
             }
-        } else {
-            exit = true;
         }
     }
 }
-fn send_file_data(app: &mut App, ddc: &DebugDataChannel, output_file: &OutputFile) {
+/// Keeps the `--resume` state in step, `force` is for changes that mustn't wait
+fn save_resume_state(app: &mut App, force: bool) {
+    if let Some(journal) = &mut app.session.client_state.resume
+        && let Err(err) = journal.update(&app.session.file_manager.input_map, force)
+    {
+        log::warn!("Couldn't save the session state: {:?}", err);
+    }
+}
+/// Rewritten whenever everything that was announced has arrived
+fn write_received_manifest(app: &mut App) {
     if let Commands::Client(client_args) = &app.args.app_mode
-        && let Some(wc) = &app.client_state.wc
-    {
-        let maid = app.get_maid();
-        let dc = ddc.dc.clone();
-
-        let mut buffer_watch_rx = wc.buffer_watch_tx.subscribe();
-        let output_file = output_file.clone();
-        let chunk_size = client_args.chunk_size;
-
-        tokio::spawn(async move {
-            let token = maid.token.child_token();
-            tokio::select! {
-                _ = token.cancelled() => {},
-                result = payload::send_file_data(
-                    dc, &output_file, chunk_size, &mut buffer_watch_rx, Some(&maid.event_tx)
-                ) => {
-                    if let Err(err) = result { maid.error_tx.send_error(err); }
-                }
-            }
-        });
+        && let Some(path) = &client_args.received_manifest
+        && FileManager::get_received(&app.session.file_manager.input_map)
+    {
+        let output_dir = &client_args.output_dir;
+        if let Err(err) = app
+            .session
+            .file_manager
+            .write_received_manifest(path, output_dir)
+        {
+            log::warn!("{}", err);
+            app.toast = Some(Toast::error("Couldn't write the manifest"));
+        }
+    }
+}
+fn note_activity(app: &mut App) {
+    let busy = app.session.file_manager.is_transferring();
+    app.session.client_state.idle.touch(busy);
+}
+fn check_data_cap(app: &mut App) {
+    let (sent, received) = app.session.file_manager.total_transferred();
+    if app.session.client_state.data_cap.check(sent + received) {
+        log::info!("Data cap reached at {} bytes", sent + received);
+        set_paused(app, true);
+    }
+}
+/// Our data cap holds back both sides, the peer is asked to wait along with us
+fn set_paused(app: &mut App, paused: bool) {
+    update_paused(app);
+    if let Some(wc) = &app.session.client_state.wc
+        && let Some(ddc) = app.session.client_state.channel.clone()
+    {
+        Transfer::send_message(app.session.get_maid(), wc, ddc, Message::Pause(paused));
+    }
+}
+/// Our file data waits while either side sits at its data cap
+fn update_paused(app: &mut App) {
+    let state = &app.session.client_state;
+    let paused = state.data_cap.state == CapState::Reached || state.peer_paused;
+    if let Some(wc) = &state.wc {
+        wc.pause_tx.send_replace(paused);
+    }
+}
+/// Hanging up ourselves skips `on_disconnected`, the unfinished files are dealt with here instead
+fn close_connection(app: &mut App) {
+    let dc = app.session.goodbye_channel();
+    app.session.client_state.connected = false;
+    app.session.file_manager.fail_active();
+    if let Commands::Client(client_args) = &app.args.app_mode {
+        tokio::spawn(app.session.discard_partial_files(client_args));
+    }
+    if let Some(wc) = app.session.client_state.wc.clone() {
+        tokio::spawn(async move { wc.hang_up(dc).await });
+    }
+}
+
+fn send_next_file(app: &mut App, ddc: DebugDataChannel) {
+    if let Commands::Client(client_args) = &app.args.app_mode
+        && let Some(wc) = &app.session.client_state.wc
+    {
+        let maid = app.session.get_maid();
+        let chunk_size = wc.fit_chunk(client_args.chunk_size);
+        let retries = client_args.send_retries;
+        let active = Transfer::send_next_file(
+            maid,
+            wc,
+            ddc,
+            &mut app.session.file_manager,
+            chunk_size,
+            client_args.chunk_crc,
+            client_args.compression(),
+            retries,
+        );
+        app.session.client_state.active_file = active;
+        if active.is_none() {
+            app.session.client_state.sending = false;
+            send_dropped_files(app);
+            send_watched_files(app);
+        }
+    }
+}
+fn resend_file_data(app: &mut App, id: FileId, offset: usize) {
+    if let Commands::Client(client_args) = &app.args.app_mode
+        && let Some(wc) = &app.session.client_state.wc
+        && let Some(ddc) = app.session.client_state.channel.clone()
+        && let Some(output_file) = app.session.file_manager.output_map.get(&id)
+    {
+        log::info!("Resending file {} from {}", id, offset);
+        Transfer::resend_file_data(
+            app.session.get_maid(),
+            wc,
+            ddc,
+            output_file.clone(),
+            offset,
+            wc.fit_chunk(client_args.chunk_size),
+            client_args.compression(),
+            client_args.send_retries,
+        );
     }
 }
 fn send_all_meta(app: &mut App, ddc: DebugDataChannel) {
     if let Commands::Client(client_args) = &app.args.app_mode
-        && let Some(wc) = &app.client_state.wc
-    {
-        let maid = app.get_maid();
-        let dc = ddc.dc.clone();
-
-        let mut buffer_watch_rx = wc.buffer_watch_tx.subscribe();
-        let output_files = app.file_manager.output_queue.clone();
-        let chunk_size = client_args.chunk_size;
-
-        tokio::spawn(async move {
-            let token = maid.token.child_token();
-            tokio::select! {
-                _ = token.cancelled() => {},
-                result = payload::send_all_meta(
-                    dc, &output_files, chunk_size, &mut buffer_watch_rx, Some(&maid.event_tx)
-                ) => {
-                    if let Err(err) = result { maid.error_tx.send_error(err); }
-                },
+        && let Some(wc) = &app.session.client_state.wc
+    {
+        let maid = app.session.get_maid();
+        app.session.client_state.transfer_started = Some(Instant::now());
+        app.session.client_state.channel = Some(ddc.clone());
+        app.session.client_state.sending = true;
+        let output_files = app.session.file_manager.output_queue.clone();
+        let announced = output_files.iter().map(|f| f.id);
+        app.session.client_state.announced.extend(announced);
+        let chunk_size = wc.fit_chunk(client_args.chunk_size);
+        let sender_name = client_args.signaling_mode.local_name();
+        let retries = client_args.send_retries;
+        Transfer::send_all_meta(
+            maid,
+            wc,
+            ddc,
+            output_files,
+            chunk_size,
+            sender_name,
+            retries,
+        );
+    }
+}
+/// Queues the files the watcher picked up
+fn send_watched_files(app: &mut App) {
+    // Holding them back keeps the batch in one piece
+    if app.session.client_state.sending {
+        return;
+    }
+    let Some(watch) = &mut app.session.client_state.watch else {
+        return;
+    };
+
+    for path in watch.take_pending() {
+        // It might've been removed in the meantime
+        if let Err(err) = app
+            .session
+            .file_manager
+            .add_output_files(std::slice::from_ref(&path))
+        {
+            log::warn!("Couldn't queue {:?}: {}", path, err);
+        }
+    }
+    send_queued_files(app);
+}
+/// Same as the watched files, a batch that's going doesn't take any more
+fn send_dropped_files(app: &mut App) {
+    if app.session.client_state.sending {
+        return;
+    }
+    let paths = std::mem::take(&mut app.session.client_state.dropped_paths);
+    if paths.is_empty() {
+        return;
+    }
+
+    let mut skipped = 0;
+    for path in paths {
+        match app
+            .session
+            .file_manager
+            .add_output_files(std::slice::from_ref(&path))
+        {
+            Ok(vanished) => skipped += vanished.len(),
+            Err(err) => {
+                log::warn!("Couldn't add {:?}: {}", path, err);
+                app.toast = Some(Toast::error("Couldn't add some of the files"));
+            }
+        }
+    }
+    if let Some(toast) = Toast::skipped(skipped) {
+        app.toast = Some(toast);
+    }
+    send_queued_files(app);
+}
+/// Sends the files added after the connection was up, one batch at a time so the metadata goes first
+fn send_queued_files(app: &mut App) {
+    if app.session.client_state.sending
+        || !app.session.client_state.connected
+        || app.session.client_state.pending_confirm.is_some()
+        || app.session.file_manager.output_queue.is_empty()
+    {
+        return;
+    }
+    if let Some(ddc) = app.session.client_state.channel.clone() {
+        send_all_meta(app, ddc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::file_manager::MetaData;
+    use crate::cli::Cli;
+    use crate::client::message::append_part_ext;
+    use crate::logger::LogBuffer;
+    use crate::test_util::TempDir;
+    use clap::Parser;
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn stopping_at_the_data_cap_fails_the_files() {
+        let dir = TempDir::new("data-cap");
+        let output_dir = dir.to_str().unwrap();
+        let args = [
+            "tappi-share",
+            "client",
+            "-o",
+            output_dir,
+            "--on-fail",
+            "delete",
+            "--data-cap",
+            "1",
+            "-i",
+            "mqtt",
+            "-l",
+            "me",
+            "-r",
+            "you",
+        ];
+        let mut app = App::new(Cli::try_parse_from(args).unwrap(), LogBuffer::default()).unwrap();
+        let mut input_file = InputFile::new(0, MetaData::new(Path::new("a.bin"), 10, None, false));
+        input_file.status = FileStatus::Active;
+        app.session.file_manager.input_map.insert(0, input_file);
+        let part = append_part_ext(dir.join("a.bin"));
+        std::fs::write(&part, [0u8; 4]).unwrap();
+        app.session.client_state.connected = true;
+        app.session.client_state.data_cap.state = CapState::Reached;
+
+        on_decision(&mut app, false);
+
+        assert_eq!(
+            app.session.file_manager.input_map[0].status,
+            FileStatus::Failed
+        );
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while part.exists() {
+                tokio::task::yield_now().await;
             }
-        });
+        })
+        .await
+        .unwrap();
     }
 }
@@ -30,7 +30,7 @@ impl AppHandler for ServerHandler {
         if let AppEvent::Server(app_event) = event {
             match app_event {
                 AppEventServer::Quit => on_quit(app),
-                AppEventServer::AddRoom(room_id) => on_add_room(app, room_id),
+                AppEventServer::AddRoom(room_id, capacity) => on_add_room(app, room_id, capacity),
                 AppEventServer::RemoveRoom(room_id) => on_remove_room(app, room_id),
                 AppEventServer::AddRoomUser(user) => on_add_room_user(app, user),
                 AppEventServer::RemoveRoomUser(user) => on_remove_room_user(app, user),
@@ -45,10 +45,10 @@ impl AppHandler for ServerHandler {
 fn on_quit(app: &mut App) {
     app.exit = true;
 }
-fn on_add_room(app: &mut App, room_id: String) {
+fn on_add_room(app: &mut App, room_id: String, capacity: usize) {
     app.room_list_widget_state
         .rooms
-        .insert(room_id, SyncRoom::default());
+        .insert(room_id, SyncRoom::new(capacity));
 }
 fn on_remove_room(app: &mut App, room_id: String) {
     app.room_list_widget_state.rooms.shift_remove(&room_id);
@@ -69,5 +69,6 @@ fn on_add_message(app: &mut App, user_msg: UserMessage) {
     let room = app.room_list_widget_state.rooms.get_mut(&user_msg.room_id);
     if let Some(room) = room {
         room.history.push(user_msg);
+        room.messages += 1;
     }
 }
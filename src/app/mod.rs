@@ -1,7 +1,9 @@
 pub mod app_event;
 pub mod app_main;
+pub mod archive;
 pub mod encrypt;
 pub mod event;
 pub mod file_manager;
 pub mod handlers;
 pub mod models;
+pub mod session;
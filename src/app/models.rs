@@ -1,10 +1,24 @@
 use indexmap::IndexMap;
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 use tokio::sync::mpsc::UnboundedSender;
 use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
 
+use crate::app::app_event::DebugDataChannel;
 use crate::app::event::BasicEvent;
+use crate::app::file_manager::FileId;
+use crate::client::data_cap::DataCap;
+use crate::client::idle::IdleWatch;
+use crate::client::message::TransferOffer;
+use crate::client::receipt::ReceiptState;
+use crate::client::resume::ResumeJournal;
 use crate::client::rtc_base::WebConnection;
 use crate::client::signaling::signaling_solution::SignalingMessage;
+use crate::client::watch::DirWatch;
 use crate::server::types::{RoomUser, UserId, UserMessage};
 
 /// Client-related data structure
@@ -14,7 +28,61 @@ use crate::server::types::{RoomUser, UserId, UserMessage};
 pub struct ClientState {
     pub wc: Option<WebConnection>,
     pub connected: bool,
+    /// Latches on the first connection, the connection banner doesn't come back after it
+    pub was_connected: bool,
     pub handshake_tx: Option<UnboundedSender<SignalingMessage>>,
+    /// Channel that is ready to send but waits for the user to confirm
+    pub pending_confirm: Option<DebugDataChannel>,
+    /// Incoming transfer that waits for the user to accept or decline
+    pub incoming_offer: Option<TransferOffer>,
+    /// Tracks file activity for `--idle-timeout`
+    pub idle: IdleWatch,
+    /// When we started sending the files
+    pub transfer_started: Option<Instant>,
+    /// Channel the files and messages go through, kept from the moment it opens
+    pub channel: Option<DebugDataChannel>,
+    /// A batch of files is on its way
+    pub sending: bool,
+    /// Outgoing file whose data is being sent right now
+    pub active_file: Option<FileId>,
+    /// Who's on the other end, the name comes from the signaling or the peer's transfer offer
+    pub peer_uuid: Option<Uuid>,
+    pub peer_name: Option<String>,
+    /// Outgoing files whose metadata went out already, they keep their names
+    pub announced: HashSet<FileId>,
+    /// Directory watched for `--watch`
+    pub watch: Option<DirWatch>,
+    /// Dropped paths that wait for the user to add them
+    pub pasted_paths: Option<Vec<PathBuf>>,
+    /// Dropped paths added while a batch was going, they make the next one
+    pub dropped_paths: Vec<PathBuf>,
+    /// Bytes allowed through before `--data-cap` pauses the transfer
+    pub data_cap: DataCap,
+    /// The peer sits at its data cap, our file data waits for it
+    pub peer_paused: bool,
+    /// Keeps the `--resume` state file in step with the incoming files
+    pub resume: Option<ResumeJournal>,
+    /// Asked for and checked once with `--receipt`
+    pub receipt: ReceiptState,
+}
+
+/// What got sent, shown once everything is delivered
+#[derive(Clone, Debug)]
+pub struct CompletionSummary {
+    pub files: usize,
+    pub bytes: usize,
+    pub elapsed: Duration,
+}
+impl CompletionSummary {
+    /// Average speed in Mbps
+    pub fn speed(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            (self.bytes as f64) * 8.0 / 1_000_000.0 / seconds
+        } else {
+            0.0
+        }
+    }
 }
 
 // I probably should rename it, but it's too cute and i love it
@@ -54,8 +122,67 @@ impl ErrorTX {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct SyncRoom {
     pub users: IndexMap<UserId, RoomUser>,
     pub history: Vec<UserMessage>,
+    pub capacity: usize,
+    pub created_at: Instant,
+    pub messages: usize, // Messages sent since the room was opened
+}
+impl SyncRoom {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            users: IndexMap::default(),
+            history: vec![],
+            capacity,
+            created_at: Instant::now(),
+            messages: 0,
+        }
+    }
+
+    /// Users that count towards the capacity
+    pub fn peers(&self) -> usize {
+        self.users.values().filter(|u| !u.observer).count()
+    }
+}
+
+/// Short-lived notification, counts down on tick
+#[derive(Clone, Debug)]
+pub struct Toast {
+    pub text: String,
+    pub is_error: bool,
+    ticks_left: u16,
+}
+impl Toast {
+    /// How many ticks a toast stays on screen
+    const LIFETIME: u16 = 60;
+
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            is_error: false,
+            ticks_left: Self::LIFETIME,
+        }
+    }
+    pub fn error(text: impl Into<String>) -> Self {
+        Self {
+            is_error: true,
+            ..Self::new(text)
+        }
+    }
+    /// Notes the files that disappeared during the scan or weren't regular files, if any
+    pub fn skipped(count: usize) -> Option<Self> {
+        match count {
+            0 => None,
+            1 => Some(Self::error("1 file skipped, see the log")),
+            _ => Some(Self::error(format!("{} files skipped, see the log", count))),
+        }
+    }
+
+    /// Returns false once the toast has expired
+    pub fn update(&mut self) -> bool {
+        self.ticks_left = self.ticks_left.saturating_sub(1);
+        self.ticks_left > 0
+    }
 }
@@ -0,0 +1,251 @@
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tokio_util::sync::CancellationToken;
+use webrtc::data_channel::RTCDataChannel;
+
+use crate::app::app_event::{AppEvent, AppEventClient};
+use crate::app::event::BasicEvent;
+use crate::app::file_manager::ProgressFile;
+use crate::app::file_manager::{FileManager, SpeedCounter};
+use crate::app::models::{ClientState, CompletionSummary, ErrorTX, Maid};
+use crate::cli::{ClientArgs, OnFail, SendOrder, SignalingSolutions};
+use crate::client::data_cap::DataCap;
+use crate::client::resume::{PartMeta, ResumeJournal};
+use crate::client::signaling::negotiator::HandshakeState;
+use crate::ui::widgets::files_widget::FileListWidgetState;
+use crate::ui::widgets::manual_handshake_widget::ManualHandshakeWidgetState;
+
+pub type SessionId = usize;
+
+/// One transfer with its own signaling, connection and files, shown as a tab
+pub struct ClientSession {
+    pub id: SessionId,
+    /// What this tab signals through, tabs differ in their room or remote
+    pub signaling: Option<SignalingSolutions>,
+    /// File handler, allows to operate on files with ease
+    pub file_manager: FileManager,
+    /// File-sharing client state
+    pub client_state: ClientState,
+    /// Signaling handshake state
+    pub handshake_state: HandshakeState,
+    /// Summary modal, dismissed with enter
+    pub completion_summary: Option<CompletionSummary>,
+    /// Latches once the summary was shown so it doesn't fire again
+    pub summary_shown: bool,
+    /// The connection banner counts from here
+    pub started: Instant,
+
+    // Widget states
+    pub handshake_widget_state: ManualHandshakeWidgetState,
+    pub input_list_widget_state: FileListWidgetState,
+    pub output_list_widget_state: FileListWidgetState,
+
+    // Everything spawned for the session reports through these
+    error_tx: ErrorTX,
+    event_tx: UnboundedSender<BasicEvent>,
+    token: CancellationToken,
+}
+impl ClientSession {
+    /// Started from the command line arguments
+    pub const PRIMARY: SessionId = 0;
+
+    /// The primary session fails the whole app like before, the others only close their tab
+    pub fn new(
+        id: SessionId,
+        args: Option<&ClientArgs>,
+        app_error_tx: ErrorTX,
+        app_event_tx: UnboundedSender<BasicEvent>,
+        token: CancellationToken,
+    ) -> Self {
+        let (ignore_empty, speed_window, order, data_cap) = match args {
+            Some(args) => (
+                args.ignore_empty,
+                args.speed_window,
+                args.order,
+                args.data_cap,
+            ),
+            None => (
+                false,
+                SpeedCounter::DEFAULT_CAPACITY,
+                SendOrder::default(),
+                None,
+            ),
+        };
+        let resume = args
+            .filter(|args| args.resume)
+            .map(|args| ResumeJournal::new(args.output_dir.clone()));
+
+        let (event_tx, event_rx) = unbounded_channel::<BasicEvent>();
+        tokio::spawn(forward_events(
+            id,
+            event_rx,
+            app_event_tx.clone(),
+            token.clone(),
+        ));
+        let error_tx = if id == Self::PRIMARY {
+            app_error_tx
+        } else {
+            let (error_tx, error_rx) = unbounded_channel::<color_eyre::Report>();
+            tokio::spawn(forward_errors(id, error_rx, app_event_tx, token.clone()));
+            ErrorTX(error_tx)
+        };
+
+        Self {
+            id,
+            signaling: args.map(|args| args.signaling_mode.clone()),
+            file_manager: FileManager::new(ignore_empty, speed_window, order),
+            client_state: ClientState {
+                data_cap: DataCap::new(data_cap),
+                resume,
+                peer_name: args.and_then(|args| args.signaling_mode.remote_name()),
+                ..Default::default()
+            },
+            handshake_state: HandshakeState::default(),
+            completion_summary: None,
+            summary_shown: false,
+            started: Instant::now(),
+            handshake_widget_state: ManualHandshakeWidgetState::default(),
+            input_list_widget_state: FileListWidgetState::default(),
+            output_list_widget_state: FileListWidgetState {
+                renamable: true,
+                ..Default::default()
+            },
+            error_tx,
+            event_tx,
+            token,
+        }
+    }
+
+    pub fn get_maid(&self) -> Maid {
+        Maid::new(
+            self.error_tx.clone(),
+            self.event_tx.clone(),
+            self.token.child_token(),
+        )
+    }
+    pub fn sender(&self) -> UnboundedSender<BasicEvent> {
+        self.event_tx.clone()
+    }
+
+    /// `--on-fail delete` throws away whatever the unfinished files left behind
+    ///
+    /// Waits for the chunk being written first, the chunks after it find no file to go to
+    pub fn discard_partial_files(&self, args: &ClientArgs) -> impl Future<Output = ()> + use<> {
+        let wc = self.client_state.wc.as_ref();
+        let receiving = wc.map(|wc| wc.metadata_map.clone());
+        let parts = (args.on_fail == OnFail::Delete).then(|| {
+            let receive_dir =
+                (wc.and_then(|wc| wc.staging_dir.clone())).unwrap_or(args.output_dir.clone());
+            self.file_manager.partial_files(&receive_dir)
+        });
+        async move {
+            let Some(parts) = parts else {
+                return;
+            };
+            // Held until the files are gone so nothing writes in between
+            let _receiving = match &receiving {
+                Some(receiving) => {
+                    let mut metadata_map = receiving.lock().await;
+                    metadata_map.clear();
+                    Some(metadata_map)
+                }
+                None => None,
+            };
+            for part in parts {
+                match PartMeta::discard(&part) {
+                    Ok(()) => log::info!("Deleted the partial {:?}", part),
+                    Err(err) => log::warn!("{:?}", err),
+                }
+            }
+        }
+    }
+
+    /// The peer's name if it's known, the start of its UUID otherwise
+    pub fn peer_label(&self) -> Option<String> {
+        let state = &self.client_state;
+        (state.peer_name.clone()).or_else(|| {
+            state
+                .peer_uuid
+                .map(|uuid| uuid.simple().to_string()[..8].to_string())
+        })
+    }
+
+    /// Share of all bytes going either way that made it, none before any file was announced
+    pub fn progress(&self) -> Option<f64> {
+        let file_manager = &self.file_manager;
+        let sizes = (file_manager.output_map.values().map(|f| &f.meta))
+            .chain(file_manager.input_map.values().map(|f| f.get_meta()))
+            .filter(|meta| !meta.is_dir)
+            .map(|meta| meta.size);
+        let total: usize = sizes.sum();
+        let (sent, received) = file_manager.total_transferred();
+        (total > 0).then(|| (sent + received) as f64 / total as f64)
+    }
+
+    /// Stops everything the session spawned and hangs up
+    pub fn close(&self) {
+        self.token.cancel();
+        if let Some(wc) = self.client_state.wc.clone() {
+            let dc = self.goodbye_channel();
+            tokio::spawn(async move { wc.hang_up(dc).await });
+        }
+    }
+    /// Same as `close`, but waits for the goodbye to go out
+    pub async fn hang_up(&self) {
+        if let Some(wc) = &self.client_state.wc {
+            wc.hang_up(self.goodbye_channel()).await;
+        }
+    }
+    /// Only a connected peer has anything to hear
+    pub fn goodbye_channel(&self) -> Option<Arc<RTCDataChannel>> {
+        let state = &self.client_state;
+        (state.channel.as_ref())
+            .filter(|_| state.connected)
+            .map(|ddc| ddc.dc.clone())
+    }
+}
+
+/// Tags the client events with the session they came from
+async fn forward_events(
+    id: SessionId,
+    mut event_rx: UnboundedReceiver<BasicEvent>,
+    app_event_tx: UnboundedSender<BasicEvent>,
+    token: CancellationToken,
+) {
+    loop {
+        let event = tokio::select! {
+            _ = token.cancelled() => break,
+            event = event_rx.recv() => event,
+        };
+        let Some(event) = event else {
+            break;
+        };
+
+        let event = match event {
+            BasicEvent::App(AppEvent::Client(event)) => AppEvent::Session(id, event).into(),
+            event => event,
+        };
+        if app_event_tx.send(event).is_err() {
+            break;
+        }
+    }
+}
+
+/// Turns the errors of a secondary session into an event so only its tab goes down
+async fn forward_errors(
+    id: SessionId,
+    mut error_rx: UnboundedReceiver<color_eyre::Report>,
+    app_event_tx: UnboundedSender<BasicEvent>,
+    token: CancellationToken,
+) {
+    let err = tokio::select! {
+        _ = token.cancelled() => return,
+        err = error_rx.recv() => err,
+    };
+    if let Some(err) = err {
+        log::error!("Session {} failed: {:?}", id, err);
+        let event = AppEvent::Session(id, AppEventClient::SessionFailed(err.to_string()));
+        app_event_tx.send(event.into()).ok();
+    }
+}
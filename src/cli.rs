@@ -1,11 +1,18 @@
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use log::LevelFilter;
+use serde::{Deserialize, Serialize};
 use std::{net::SocketAddr, path::PathBuf};
 
 use crate::app::encrypt::Secret;
+use crate::app::file_manager::{DEFAULT_PROGRESS_INTERVAL, ProgressThrottle};
+use crate::client::compress::CompressAlgo;
+use crate::client::heartbeat::DEFAULT_HEARTBEAT_SECS;
+use crate::client::payload::DEFAULT_SEND_RETRIES;
+use crate::client::rtc_base::{DEFAULT_CHANNEL_ID, DEFAULT_CHANNEL_LABEL};
+use crate::client::turn::DEFAULT_TURN_TTL;
 
 /// Cli parser
-#[derive(Parser, Clone, Debug)]
+#[derive(Parser, Clone, Debug, Serialize, Deserialize)]
 #[command(version, about, long_about = None, name = "tappi-share")]
 pub struct Cli {
     /// Logging level (off/error/warn/info/debug)
@@ -14,57 +21,279 @@ pub struct Cli {
     /// Log filename
     #[arg(short = 'f', long, default_value = "tappi-share.log")]
     pub log_file: String,
+    /// Add to the log file instead of starting it over, every run starts with a separator line
+    #[arg(long, default_value = "false")]
+    pub append_log: bool,
+    /// Draw without colors, a non-empty NO_COLOR does the same
+    #[arg(long, default_value = "false")]
+    pub no_color: bool,
+    /// Plain text lines instead of gauges and panels, easier on screen readers
+    #[arg(long, default_value = "false")]
+    pub accessible: bool,
+    /// Load the arguments from a saved profile, explicit ones take precedence
+    #[arg(short = 'P', long)]
+    #[serde(skip)]
+    pub profile: Option<String>,
+    /// Save the resulting arguments as a profile under the given name
+    #[arg(short = 'S', long)]
+    #[serde(skip)]
+    pub save_profile: Option<String>,
 
     /// Application mode
     #[command(subcommand)]
     pub app_mode: Commands,
 }
 
+impl Cli {
+    /// https://no-color.org
+    pub fn colors_off(&self) -> bool {
+        self.no_color || std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty())
+    }
+
+    /// Fills in the secret from `--secret-file` or TAPPI_SECRET if it wasn't passed directly
+    pub fn resolve_secret(&mut self) -> color_eyre::Result<()> {
+        let Commands::Client(args) = &mut self.app_mode else {
+            return Ok(());
+        };
+        match &mut args.signaling_mode {
+            SignalingSolutions::Manual(args) => {
+                Secret::resolve(&mut args.secret, args.secret_file.as_deref())
+            }
+            SignalingSolutions::Mqtt(args) => {
+                Secret::resolve(&mut args.secret, args.secret_file.as_deref())
+            }
+            SignalingSolutions::Socket(_) => Ok(()),
+        }
+    }
+}
+
 /// Subcommands
 #[allow(clippy::large_enum_variant)]
-#[derive(Subcommand, Clone, Debug)]
+#[derive(Subcommand, Clone, Debug, Serialize, Deserialize)]
 pub enum Commands {
     /// Start file sharing client
     Client(ClientArgs),
     /// Start signaling server
     Server(ServerArgs),
+    /// Send files to itself over a local connection and verify the result
+    #[command(hide = true)]
+    Loopback(LoopbackArgs),
+    /// Gather ICE candidates against the STUN/TURN servers to check they work
+    Diagnose(DiagnoseArgs),
+    /// Print a random secret for `--secret`, both peers need the same one
+    GenSecret,
 }
 
-#[derive(Args, Clone, Debug)]
+#[derive(Args, Clone, Debug, Serialize, Deserialize)]
 pub struct ClientArgs {
     /// Path(s) to the file(s) to upload
     #[arg(short='f', long, num_args = 1.., value_terminator(";"))]
     pub files: Option<Vec<PathBuf>>,
+    /// File with newline or null separated paths to upload, `-` reads from stdin
+    #[arg(short = 'T', long)]
+    pub files_from: Option<PathBuf>,
+    /// Path to a tar or zip archive to send the contents of without extracting it
+    #[arg(short = 'x', long)]
+    pub from_archive: Option<PathBuf>,
+    /// Directory to watch, files created in it get sent as they appear
+    #[arg(short = 'w', long)]
+    pub watch: Option<PathBuf>,
     /// Size in KiB to break the data into chunks by (valid range: 8–64)
     #[arg(short='s', long, default_value = "64", value_parser = parse_kib)]
     pub chunk_size: usize,
+    /// Directory to save the incoming files to
+    #[arg(short = 'o', long, default_value = ".")]
+    pub output_dir: PathBuf,
+    /// Save a single incoming file to exactly this path instead of the name the sender picked,
+    /// more than one file is an error
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+    /// Write a JSON list of the received files here once they're all in
+    #[arg(long)]
+    pub received_manifest: Option<PathBuf>,
+    /// Receive into a staging directory and move the files over only once all of them are in,
+    /// an aborted transfer leaves it behind for inspection
+    #[arg(long)]
+    pub staging: bool,
+    /// Pick up an interrupted transfer where it stopped, even after a restart.
+    /// The receiver keeps track of its files and the sender asks for them, so both need it
+    #[arg(long, conflicts_with = "staging")]
+    pub resume: bool,
+    /// What happens to the part files of a transfer that failed midway,
+    /// deleting them leaves nothing for `--resume`
+    #[arg(long, value_enum, default_value_t = OnFail::Keep)]
+    pub on_fail: OnFail,
+    /// Number of speed samples to average over, larger is smoother and smaller reacts faster
+    #[arg(long, default_value = "10", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(2..))]
+    pub speed_window: usize,
+    /// Milliseconds between progress updates of a file, 0 updates on every chunk
+    #[arg(long, default_value_t = DEFAULT_PROGRESS_INTERVAL)]
+    pub progress_interval: u64,
+    /// Also update the progress once this many bytes went through, even if the interval didn't pass
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub progress_bytes: Option<usize>,
     /// Ignore sending empty folders
     #[arg(short = 'i', long, default_value = "false")]
     pub ignore_empty: bool,
+    /// Order to send the files in, applies within each batch that gets added
+    #[arg(long, value_enum, default_value_t = SendOrder::AsGiven)]
+    pub order: SendOrder,
+    /// Show file type icons, requires a Nerd Font
+    #[arg(long, default_value = "false")]
+    pub icons: bool,
+    /// Tint in-progress gauges by speed relative to the average
+    #[arg(long, default_value = "false")]
+    pub speed_colors: bool,
+    /// Show each file on a single row, fits more files on small terminals
+    #[arg(long, default_value = "false")]
+    pub compact: bool,
+    /// Show the folders being sent as rows of their own, filled by the share of their files that are done
+    #[arg(long, default_value = "false")]
+    pub show_dirs: bool,
+    /// Show transferred and total bytes on the gauges, takes up room next to the name
+    #[arg(long, default_value = "false")]
+    pub byte_counts: bool,
+    /// How the file being sent right now stands out from the queued ones
+    #[arg(long, value_enum, default_value_t = ActiveHighlight::Border)]
+    pub active_highlight: ActiveHighlight,
+    /// Wait for confirmation before sending the files
+    #[arg(long, default_value = "false")]
+    pub confirm: bool,
+    /// Don't show the summary once all files are sent
+    #[arg(long, default_value = "false")]
+    pub no_summary: bool,
+    /// Treat out of order packets as errors instead of dropping them, for debugging the protocol
+    #[arg(long, default_value = "false")]
+    pub strict: bool,
+    /// Ask the receiver what it got once everything is delivered and compare it to what was sent,
+    /// reads the sent files again to hash them
+    #[arg(long, default_value = "false")]
+    pub receipt: bool,
+    /// Ask before accepting an incoming transfer
+    #[arg(long, default_value = "false")]
+    pub prompt_accept: bool,
+    /// How many times a failed packet is resent before giving up on the transfer
+    #[arg(long, default_value_t = DEFAULT_SEND_RETRIES)]
+    pub send_retries: u32,
+    /// Close the connection after this many seconds without any file data flowing
+    #[arg(long)]
+    pub idle_timeout: Option<u64>,
+    /// Seconds of silence before pinging the peer, it's dropped after a few missed pongs, 0 turns it off
+    #[arg(long, default_value_t = DEFAULT_HEARTBEAT_SECS)]
+    pub heartbeat: u64,
+    /// Add a CRC32 to every chunk so a corrupt one gets sent again, costs 14 bytes per chunk
+    #[arg(long)]
+    pub chunk_crc: bool,
+    /// Compress the chunks, pays off for text and other loose data.
+    /// Chunks that don't shrink go out as they are, every chunk is checked like with `--chunk-crc`
+    #[arg(long)]
+    pub compress: bool,
+    /// What `--compress` uses, the receiver reads it off every chunk. Browsers only undo gzip
+    #[arg(long, value_enum, default_value_t = CompressAlgo::default(), requires = "compress")]
+    pub compress_algo: CompressAlgo,
+    /// Back off when the link looks congested and ramp back up once it drains,
+    /// leaves room for everyone else on a shared link
+    #[arg(long)]
+    pub fair: bool,
+    /// Pause the transfer both ways once this many bytes went either way, then ask whether to go on.
+    /// Browsers don't send, so only our side pauses with them
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub data_cap: Option<usize>,
     /// Additional STUN/TURN server(s)
     #[arg(short='a', long, num_args = 1.., value_terminator(";"))]
     pub additional_servers: Option<Vec<String>>,
+    /// Data channel label, only matters for interop with other WebRTC tools
+    #[arg(long, hide = true, default_value = DEFAULT_CHANNEL_LABEL)]
+    pub channel_label: String,
+    /// Pre-negotiated data channel id, both peers have to use the same one
+    #[arg(long, hide = true, default_value_t = DEFAULT_CHANNEL_ID)]
+    pub channel_id: u16,
     /// Additional STUN/TURN username
     #[arg(short = 'u', long)]
     pub username: Option<String>,
     /// Additional STUN/TURN credential
     #[arg(short = 'c', long)]
     pub credential: Option<String>,
+    /// Shared secret of a TURN server with time-limited credentials (coturn's `use-auth-secret`),
+    /// fresh ones are made for every connection and `--username` becomes the user part
+    #[arg(long, conflicts_with = "credential")]
+    #[serde(skip)] // Never stored in a profile
+    pub turn_secret: Option<String>,
+    /// Seconds the credentials made from `--turn-secret` stay valid
+    #[arg(long, default_value_t = DEFAULT_TURN_TTL, value_parser = clap::value_parser!(u64).range(1..))]
+    pub turn_ttl: u64,
+    /// Restart ICE when the connection drops instead of giving up, e.g. after switching networks.
+    /// Keeps the signaling open for it, so it doesn't work with manual signaling
+    #[arg(long)]
+    pub ice_restart: bool,
+    /// Candidates ICE may use, `relay` keeps your address from the peer but needs a TURN server
+    #[arg(long, value_enum, default_value_t = IceTransportPolicy::All)]
+    pub ice_transport_policy: IceTransportPolicy,
 
     /// Signaling solution
     #[command(subcommand)]
     pub signaling_mode: SignalingSolutions,
 }
 
+#[derive(Args, Clone, Debug, Serialize, Deserialize)]
+pub struct LoopbackArgs {
+    /// Path(s) to the file(s) to send
+    #[arg(short='f', long, num_args = 1.., required = true)]
+    pub files: Vec<PathBuf>,
+    /// Size in KiB to break the data into chunks by (valid range: 8–64)
+    #[arg(short='s', long, default_value = "64", value_parser = parse_kib)]
+    pub chunk_size: usize,
+    /// Directory to receive the files to, a temporary one is used if absent
+    #[arg(short = 'o', long)]
+    pub output_dir: Option<PathBuf>,
+    /// Send checked chunks like `--chunk-crc`
+    #[arg(long)]
+    pub chunk_crc: bool,
+    /// Compress the chunks with this algorithm like `--compress` does
+    #[arg(long, value_enum)]
+    pub compress: Option<CompressAlgo>,
+}
+
+#[derive(Args, Clone, Debug, Serialize, Deserialize)]
+pub struct DiagnoseArgs {
+    /// STUN/TURN server(s) to check, only host candidates are gathered if absent
+    #[arg(short='a', long, num_args = 1.., value_terminator(";"))]
+    pub additional_servers: Option<Vec<String>>,
+    /// STUN/TURN username
+    #[arg(short = 'u', long)]
+    pub username: Option<String>,
+    /// STUN/TURN credential
+    #[arg(short = 'c', long)]
+    pub credential: Option<String>,
+    /// Seconds to wait for each server to finish gathering
+    #[arg(short = 't', long, default_value = "10", value_parser = clap::value_parser!(u64).range(1..))]
+    pub timeout: u64,
+}
+
 /// Signaling commands
-#[derive(Args, Clone, Debug)]
+#[derive(Args, Clone, Debug, Serialize, Deserialize)]
 pub struct ServerArgs {
     /// Address and port to host the server on
     #[arg(short = 'a', long, default_value = "127.0.0.1:3030")]
     pub address: SocketAddr,
+    /// Number of words in generated user names
+    #[arg(short = 'w', long, default_value = "2", value_parser = clap::value_parser!(u8).range(1..))]
+    pub petname_words: u8,
+    /// Maximum number of rooms open at once, unlimited if absent
+    #[arg(short = 'm', long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub max_rooms: Option<usize>,
+    /// Serve a browser receiver at /web?room=<room> for peers without the client
+    #[arg(long, default_value = "false")]
+    pub serve_web: bool,
+    /// List the open rooms and their user counts at GET /rooms, anyone can see the room names
+    #[arg(long, default_value = "false")]
+    pub discovery: bool,
+    /// Shut down once the first room empties out, for one-off relaying from scripts
+    #[arg(long, visible_alias = "listen-once", default_value = "false")]
+    pub once: bool,
 }
 
-#[derive(Subcommand, Clone, Debug)]
+#[derive(Subcommand, Clone, Debug, Serialize, Deserialize)]
 pub enum SignalingSolutions {
     /// Exchange the handshake manually
     Manual(SignalingSolutionManualArgs),
@@ -73,16 +302,21 @@ pub enum SignalingSolutions {
     /// Exchange the handshake using an MQTT broker
     Mqtt(SignalingSolutionMqttArgs),
 }
-#[derive(Args, Clone, Debug)]
+#[derive(Args, Clone, Debug, Serialize, Deserialize)]
 pub struct SignalingSolutionManualArgs {
     /// Force being polite during the negotiation. One of the peers has to be polite
     #[arg(short = 'p', long, default_value = "false")]
     pub polite: bool, // Polite is answering and impolite is offering
-    /// Encryption secret key, must be 32 characters long
+    /// Encryption secret key, must be 32 characters long.
+    /// Shows up in the shell history, `--secret-file` or TAPPI_SECRET don't
     #[arg(short = 's', long)]
+    #[serde(skip)] // Never stored in a profile
     pub secret: Option<Secret>,
+    /// File to read the secret from, trailing newlines are ignored
+    #[arg(long, conflicts_with = "secret")]
+    pub secret_file: Option<PathBuf>,
 }
-#[derive(Args, Clone, Debug)]
+#[derive(Args, Clone, Debug, Serialize, Deserialize)]
 pub struct SignalingSolutionSocketArgs {
     /// Address of the signaling server
     #[arg(short = 'a', long, default_value = "127.0.0.1")]
@@ -94,8 +328,14 @@ pub struct SignalingSolutionSocketArgs {
     /// Name of the room
     #[arg(short = 'r', long)]
     pub room: String,
+    /// Display name to propose to the server, a random one is given if absent or taken
+    #[arg(short = 'n', long)]
+    pub name: Option<String>,
+    /// Always take this role instead of comparing UUIDs, the peer mustn't force the same one
+    #[arg(long)]
+    pub force_role: Option<ForceRole>,
 }
-#[derive(Args, Clone, Debug)]
+#[derive(Args, Clone, Debug, Serialize, Deserialize)]
 pub struct SignalingSolutionMqttArgs {
     /// Broker address
     #[arg(short = 'b', long, default_value = "broker.emqx.io")]
@@ -113,12 +353,157 @@ pub struct SignalingSolutionMqttArgs {
     /// Remote device MQTT name
     #[arg(short = 'r', long)]
     pub remote_name: String,
-    /// Encryption secret key, must be 32 characters long
+    /// Encryption secret key, must be 32 characters long.
+    /// Shows up in the shell history, `--secret-file` or TAPPI_SECRET don't
     #[arg(short = 's', long)]
+    #[serde(skip)] // Never stored in a profile
     pub secret: Option<Secret>,
+    /// File to read the secret from, trailing newlines are ignored
+    #[arg(long, conflicts_with = "secret")]
+    pub secret_file: Option<PathBuf>,
     /// MQTT keep alive period in seconds
     #[arg(short = 'k', long, default_value = "5")]
     pub keep_alive: u16,
+    /// Maximum age in seconds of a retained message before it's considered stale
+    #[arg(short = 'm', long, default_value = "60", value_parser = clap::value_parser!(u64).range(4..))]
+    pub retained_max_age: u64,
+    /// MQTT QoS level (0: at most once, 1: at least once, 2: exactly once)
+    ///
+    /// The handshake is a retained message that gets refreshed, so a lost publish only delays it
+    /// and duplicates are filtered out. Lower it if the broker stalls on the exactly once handshake
+    #[arg(long, default_value = "2", value_parser = clap::value_parser!(u8).range(0..=2))]
+    pub mqtt_qos: u8,
+    /// Reconnect attempts in a row before a lost broker connection counts as fatal
+    #[arg(long, default_value = "5")]
+    pub reconnect_attempts: u32,
+    /// Always take this role instead of comparing UUIDs, the peer mustn't force the same one
+    #[arg(long)]
+    pub force_role: Option<ForceRole>,
+}
+/// Which candidates ICE gets to use
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IceTransportPolicy {
+    /// Direct, reflexive and relayed
+    #[default]
+    All,
+    /// Only through a TURN server
+    Relay,
+}
+/// Fate of the part files a failed transfer leaves behind
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OnFail {
+    /// Left in place along with their `.part.meta`, a later `--resume` can pick them up
+    #[default]
+    Keep,
+    /// Removed along with their `.part.meta`
+    Delete,
+}
+/// Look of the outgoing file that's being sent
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ActiveHighlight {
+    /// Thick accent colored border, a marker on compact rows
+    #[default]
+    Border,
+    /// Marker in front of the name
+    Marker,
+    /// Looks like any other file
+    Off,
+}
+/// How the output queue is sorted
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SendOrder {
+    /// Paths in the order they were given, directories in the order they're walked
+    #[default]
+    AsGiven,
+    /// By the path the receiver gets
+    Alphabetical,
+    /// Quick early wins
+    SmallestFirst,
+    /// Gets the slow part over with
+    LargestFirst,
+}
+/// Negotiation role a peer can insist on, e.g. the one behind a symmetric NAT should offer
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ForceRole {
+    Offer,
+    Answer,
+}
+impl ClientArgs {
+    /// `--compress` along with its algorithm
+    pub fn compression(&self) -> Option<CompressAlgo> {
+        self.compress.then_some(self.compress_algo)
+    }
+    /// `--progress-interval` and `--progress-bytes` together
+    pub fn progress_throttle(&self) -> ProgressThrottle {
+        ProgressThrottle::new(self.progress_interval, self.progress_bytes)
+    }
+}
+impl SignalingSolutions {
+    /// Short name of the backend
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SignalingSolutions::Manual(_) => "manual",
+            SignalingSolutions::Socket(_) => "socket",
+            SignalingSolutions::Mqtt(_) => "mqtt",
+        }
+    }
+    /// Where the handshake goes through, nothing for manual signaling
+    pub fn target(&self) -> Option<String> {
+        match self {
+            SignalingSolutions::Manual(_) => None,
+            SignalingSolutions::Socket(args) => {
+                Some(format!("{} @ {}:{}", args.room, args.address, args.port))
+            }
+            SignalingSolutions::Mqtt(args) => Some(format!(
+                "{} -> {} @ {}:{}",
+                args.local_topic(),
+                args.remote_topic(),
+                args.broker,
+                args.port
+            )),
+        }
+    }
+    /// Our own name, if the signaling solution has one
+    pub fn local_name(&self) -> Option<String> {
+        match self {
+            SignalingSolutions::Manual(_) => None,
+            SignalingSolutions::Socket(args) => args.name.clone(),
+            SignalingSolutions::Mqtt(args) => Some(args.local_name.clone()),
+        }
+    }
+    /// The peer's name, only MQTT knows it before the peer shows up
+    pub fn remote_name(&self) -> Option<String> {
+        match self {
+            SignalingSolutions::Mqtt(args) => Some(args.remote_name.clone()),
+            _ => None,
+        }
+    }
+    /// What tells one tab from another, the room or the remote name
+    pub fn peer_target(&self) -> Option<String> {
+        match self {
+            SignalingSolutions::Manual(_) => None,
+            SignalingSolutions::Socket(args) => Some(args.room.clone()),
+            SignalingSolutions::Mqtt(args) => Some(args.remote_name.clone()),
+        }
+    }
+    /// Same backend pointed at another room or remote, manual signaling has nothing to point
+    pub fn with_peer_target(&self, target: &str) -> Self {
+        let mut signaling = self.clone();
+        match &mut signaling {
+            SignalingSolutions::Manual(_) => {}
+            SignalingSolutions::Socket(args) => args.room = target.to_string(),
+            SignalingSolutions::Mqtt(args) => args.remote_name = target.to_string(),
+        }
+        signaling
+    }
+    /// Role forced on the command line, manual signaling has `--polite` instead
+    pub fn force_role(&self) -> Option<ForceRole> {
+        match self {
+            SignalingSolutions::Manual(_) => None,
+            SignalingSolutions::Socket(args) => args.force_role,
+            SignalingSolutions::Mqtt(args) => args.force_role,
+        }
+    }
 }
 impl SignalingSolutionMqttArgs {
     pub fn local_topic(&self) -> String {
@@ -129,11 +514,16 @@ impl SignalingSolutionMqttArgs {
     }
 }
 
+/// Smallest `--chunk-size`, way above what the frame headers take
+pub const MIN_CHUNK_SIZE: usize = 8 * 1024;
+/// 65535 bytes or 64KiB-1B is the max SCTP chunk size
+pub const MAX_CHUNK_SIZE: usize = 65_535;
+
 fn parse_kib(s: &str) -> Result<usize, String> {
     let kib: u64 = s
         .parse()
         .map_err(|_| "Expected an integer KiB value".to_string())?;
     let bytes = kib * 1024; // Convert kibibytes to bytes
-    let result = bytes.clamp(8_192, 65_535) as usize; // 65535 bytes or 64KiB-1B is the max SCTP chunk size
+    let result = bytes.clamp(MIN_CHUNK_SIZE as u64, MAX_CHUNK_SIZE as u64) as usize;
     Ok(result)
 }
@@ -3,7 +3,11 @@ use crate::{
     cli::ClientArgs,
     client::{
         rtc_base::WebConnection,
-        signaling::{negotiator::negotiate, signaling_manual::SignalingManual},
+        signaling::{
+            negotiator::{Restarts, negotiate},
+            signaling_manual::SignalingManual,
+        },
+        turn::TurnAuth,
     },
 };
 
@@ -15,12 +19,17 @@ pub async fn init(
     // Init WebRTC connection
     let wc = WebConnection::new(maid.clone(), &args).await?;
     let pc = wc.pc.clone();
+    let restarts = Restarts {
+        lost_rx: wc.lost_tx.subscribe(),
+        closing_rx: wc.closing_tx.subscribe(),
+        turn: TurnAuth::from_args(&args),
+    };
     maid.event_tx
         .send_event(AppEventClient::InitConnection(wc))
         .await;
 
     // Negotiate
-    negotiate(pc, args, maid, signaling_manual).await?;
+    negotiate(pc, args, maid, signaling_manual, restarts).await?;
 
     Ok(())
 }
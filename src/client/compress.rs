@@ -0,0 +1,140 @@
+use clap::ValueEnum;
+use color_eyre::eyre::eyre;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+use crate::cli::MAX_CHUNK_SIZE;
+
+/// Frame byte of a chunk that went out as is
+pub const RAW: u8 = 0;
+
+/// How `--compress` squeezes the chunks, every chunk names its own
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompressAlgo {
+    /// Slow-ish but every browser can undo it
+    #[default]
+    Gzip,
+    /// Squeezes the most, Tappi receivers only
+    Zstd,
+    /// Barely costs anything, Tappi receivers only
+    Lz4,
+}
+impl CompressAlgo {
+    /// What goes into the frame, never `RAW`
+    pub fn byte(self) -> u8 {
+        match self {
+            Self::Gzip => 1,
+            Self::Zstd => 2,
+            Self::Lz4 => 3,
+        }
+    }
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            1 => Some(Self::Gzip),
+            2 => Some(Self::Zstd),
+            3 => Some(Self::Lz4),
+            _ => None,
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> color_eyre::Result<Vec<u8>> {
+        match self {
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            Self::Zstd => Ok(zstd::encode_all(data, 1)?),
+            Self::Lz4 => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+        }
+    }
+    /// Stops at `limit` bytes, a tiny frame could unpack into gigabytes otherwise
+    pub fn decompress(self, data: &[u8], limit: usize) -> color_eyre::Result<Vec<u8>> {
+        let decoder: Box<dyn Read + '_> = match self {
+            Self::Gzip => Box::new(GzDecoder::new(data)),
+            Self::Zstd => Box::new(zstd::Decoder::new(data)?),
+            Self::Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(data)),
+        };
+        let mut result = Vec::new();
+        decoder.take(limit as u64 + 1).read_to_end(&mut result)?;
+        if result.len() > limit {
+            return Err(eyre!("Decompresses to more than {} bytes", limit));
+        }
+        Ok(result)
+    }
+}
+
+/// Compresses a chunk if it's worth it, returns the frame byte along with the data
+///
+/// Data that doesn't shrink goes out raw, so a frame never outgrows its chunk
+pub fn squeeze(algo: Option<CompressAlgo>, chunk: Vec<u8>) -> (u8, Vec<u8>) {
+    if let Some(algo) = algo
+        && let Ok(compressed) = algo.compress(&chunk)
+        && compressed.len() < chunk.len()
+    {
+        return (algo.byte(), compressed);
+    }
+    (RAW, chunk)
+}
+
+/// Undoes `squeeze`, only an algorithm we don't know is an error
+///
+/// A chunk that doesn't decompress, or unpacks past the largest chunk there is,
+/// is corrupt, it's left as is for the CRC check to catch
+pub fn unsqueeze(byte: u8, data: Vec<u8>) -> color_eyre::Result<Vec<u8>> {
+    if byte == RAW {
+        return Ok(data);
+    }
+    let algo = CompressAlgo::from_byte(byte)
+        .ok_or_else(|| eyre!("Unknown compression algorithm {}", byte))?;
+    Ok(algo.decompress(&data, MAX_CHUNK_SIZE).unwrap_or(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(algo: CompressAlgo) {
+        let chunk = b"tappi ".repeat(2000);
+        let (byte, squeezed) = squeeze(Some(algo), chunk.clone());
+        assert_eq!(byte, algo.byte());
+        assert!(squeezed.len() < chunk.len());
+        assert_eq!(unsqueeze(byte, squeezed).unwrap(), chunk);
+    }
+
+    #[test]
+    fn gzip_round_trip() {
+        round_trip(CompressAlgo::Gzip);
+    }
+
+    #[test]
+    fn zstd_round_trip() {
+        round_trip(CompressAlgo::Zstd);
+    }
+
+    #[test]
+    fn lz4_round_trip() {
+        round_trip(CompressAlgo::Lz4);
+    }
+
+    #[test]
+    fn bombs_stop_at_a_chunk() {
+        let bomb = CompressAlgo::Gzip
+            .compress(&vec![0; MAX_CHUNK_SIZE * 16])
+            .unwrap();
+        assert!(
+            CompressAlgo::Gzip
+                .decompress(&bomb, MAX_CHUNK_SIZE)
+                .is_err()
+        );
+        // Left for the CRC check
+        assert_eq!(unsqueeze(1, bomb.clone()).unwrap(), bomb);
+    }
+}
@@ -0,0 +1,73 @@
+/// Where the session stands with `--data-cap`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CapState {
+    #[default]
+    Under,
+    /// Both sides hold back their file data until the user decides
+    Reached,
+    /// The user hung up instead of going on
+    Stopped,
+}
+
+/// Counts the bytes either way since the session started or since the user last chose to go on
+#[derive(Clone, Debug, Default)]
+pub struct DataCap {
+    limit: Option<usize>,
+    base: usize,
+    pub state: CapState,
+}
+impl DataCap {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// True only for the update that crosses the limit
+    pub fn check(&mut self, total: usize) -> bool {
+        match self.limit {
+            Some(limit) if self.state == CapState::Under && total - self.base >= limit => {
+                self.state = CapState::Reached;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Allows another `limit` bytes on top of what already went through
+    pub fn extend(&mut self, total: usize) {
+        self.base = total;
+        self.state = CapState::Under;
+    }
+
+    pub fn stop(&mut self) {
+        self.state = CapState::Stopped;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cap_latches_and_extends() {
+        let mut cap = DataCap::new(Some(100));
+        assert!(!cap.check(99));
+        assert!(cap.check(100));
+        assert!(!cap.check(150)); // Already reached
+        assert_eq!(cap.state, CapState::Reached);
+
+        cap.extend(150);
+        assert!(!cap.check(200));
+        assert!(cap.check(250));
+
+        cap.stop();
+        assert!(!cap.check(1000));
+        assert!(!DataCap::new(None).check(usize::MAX));
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use webrtc::api::APIBuilder;
+use webrtc::ice_transport::ice_candidate_type::RTCIceCandidateType;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+
+use crate::cli::DiagnoseArgs;
+use crate::client::rtc_base::{WebConnection, wait_for_ice_completion};
+
+/// Candidate types gathered for a single server
+#[derive(Default, Debug)]
+pub struct Gathered {
+    pub host: usize,
+    pub srflx: usize,
+    pub relay: usize,
+    pub error: Option<String>, // Gathering error or a timeout
+}
+impl Gathered {
+    /// STUN should give a server reflexive candidate, TURN a relayed one
+    pub fn passed(&self, server: Option<&str>) -> bool {
+        if self.error.is_some() {
+            return false;
+        }
+        match server {
+            Some(url) if url.starts_with("turn") => self.relay > 0,
+            Some(_) => self.srflx > 0,
+            None => self.host > 0,
+        }
+    }
+    fn summary(&self) -> String {
+        let counts = format!(
+            "host {}, srflx {}, relay {}",
+            self.host, self.srflx, self.relay
+        );
+        match &self.error {
+            Some(err) => format!("{} ({})", err, counts),
+            None => counts,
+        }
+    }
+}
+
+/// Checks each server on its own so a failure points at the right one
+///
+/// Runs headless, returns true if every check passed
+pub async fn run(args: DiagnoseArgs) -> color_eyre::Result<bool> {
+    let timeout = Duration::from_secs(args.timeout);
+    let mut passed = true;
+
+    // Host candidates don't need any servers, if they fail nothing else will work
+    let gathered = gather(RTCConfiguration::default(), timeout).await?;
+    passed &= report("host", None, &gathered);
+
+    for server in args.additional_servers.iter().flatten() {
+        let config = WebConnection::conf(
+            args.username.clone(),
+            args.credential.clone(),
+            &mut Some(vec![server.clone()]),
+        );
+        let gathered = match gather(config, timeout).await {
+            Ok(gathered) => gathered,
+            Err(err) => Gathered {
+                error: Some(err.to_string()),
+                ..Default::default()
+            },
+        };
+        passed &= report(server, Some(server), &gathered);
+    }
+
+    Ok(passed)
+}
+
+fn report(name: &str, server: Option<&str>, gathered: &Gathered) -> bool {
+    let passed = gathered.passed(server);
+    let verdict = if passed { "pass" } else { "FAIL" };
+    println!("{}: {}, {}", name, verdict, gathered.summary());
+    log::info!("Diagnose {}: {:?}", name, gathered);
+    passed
+}
+
+/// Gathers the candidates of a throwaway peer connection
+async fn gather(config: RTCConfiguration, timeout: Duration) -> color_eyre::Result<Gathered> {
+    let api = APIBuilder::new().build();
+    let pc = Arc::new(api.new_peer_connection(config).await?);
+    let gathered = Arc::new(Mutex::new(Gathered::default()));
+
+    let g = gathered.clone();
+    pc.on_ice_candidate(Box::new(move |candidate| {
+        let g = g.clone();
+        Box::pin(async move {
+            if let Some(candidate) = candidate {
+                let mut g = g.lock().await;
+                match candidate.typ {
+                    RTCIceCandidateType::Host => g.host += 1,
+                    RTCIceCandidateType::Srflx | RTCIceCandidateType::Prflx => g.srflx += 1,
+                    RTCIceCandidateType::Relay => g.relay += 1,
+                    RTCIceCandidateType::Unspecified => {}
+                }
+            }
+        })
+    }));
+
+    // Gathering only starts once there's something to negotiate
+    pc.create_data_channel("diagnose", None).await?;
+    let offer = pc.create_offer(None).await?;
+    pc.set_local_description(offer).await?;
+
+    let completed = tokio::time::timeout(timeout, wait_for_ice_completion(pc.clone()))
+        .await
+        .is_ok();
+    pc.close().await?;
+
+    let mut gathered = std::mem::take(&mut *gathered.lock().await);
+    if !completed {
+        gathered.error = Some(format!("Timed out after {}s", timeout.as_secs()));
+    }
+    Ok(gathered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pass_criteria() {
+        let gathered = Gathered {
+            host: 2,
+            srflx: 1,
+            ..Default::default()
+        };
+        assert!(gathered.passed(None));
+        assert!(gathered.passed(Some("stun:stun.example.com:3478")));
+        assert!(!gathered.passed(Some("turn:turn.example.com:3478")));
+
+        let timed_out = Gathered {
+            error: Some("Timed out".to_string()),
+            ..gathered
+        };
+        assert!(!timed_out.passed(None));
+    }
+}
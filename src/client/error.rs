@@ -0,0 +1,88 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Client failures worth telling apart, `?` turns them into a `Report` like any other error
+#[derive(Debug, Error)]
+pub enum TappiError {
+    /// Couldn't reach the peer through the signaling channel
+    #[error("Signaling failed: {0}")]
+    SignalingFailed(String),
+    /// The signaling server turned us away, the reason comes from the server
+    #[error("The server closed the connection: {0}")]
+    ServerRejected(String),
+    /// ICE ran out of candidate pairs to try
+    #[error("ICE connection failed")]
+    IceTimeout,
+    /// The peer's frame format is different from ours
+    #[error("The peer speaks {theirs} while we speak {ours}, update the older one")]
+    ProtocolMismatch { ours: String, theirs: String },
+    /// The peer declined the transfer
+    #[error("The peer declined the transfer")]
+    PeerRejected,
+    /// `--output` names one file but the sender has more
+    #[error("--output takes a single file but the sender is sending {0}")]
+    TooManyFiles(usize),
+    /// `--strict` caught a packet that doesn't fit in, lenient mode drops those
+    #[error("Out of order packet: {0}")]
+    OutOfOrder(String),
+    /// The metadata of a file doesn't stop coming, a sane one is a path and a few numbers
+    #[error("The metadata of file {id} went over {limit} bytes")]
+    MetadataTooLarge { id: usize, limit: usize },
+    /// The sender finishes one file's metadata before the next, a pile of unfinished ones is no sender
+    #[error("Unfinished metadata of more than {0} files at once")]
+    TooMuchPendingMetadata(usize),
+    /// FIFOs, sockets and devices have no size to announce and may block on open
+    #[error("{0:?} isn't a regular file")]
+    NotRegularFile(PathBuf),
+    /// A path from the peer would write outside the output directory, the file gets skipped
+    #[error("{0:?} leads outside the output directory")]
+    UnsafePath(PathBuf),
+    /// Staged files don't replace ones the output directory already has
+    #[error("{0:?} already exists")]
+    AlreadyExists(PathBuf),
+    /// The sender's tree goes deeper than this filesystem allows, the file gets skipped
+    #[error("{path:?} is too long, this system takes up to {limit}")]
+    PathTooLong { path: PathBuf, limit: usize },
+    #[error("Couldn't access {path:?}")]
+    FileIo {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// Encrypting or decrypting a handshake went wrong, usually a wrong secret
+    #[error("Encryption failed")]
+    Crypto(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+impl TappiError {
+    pub fn file_io(path: impl Into<PathBuf>) -> impl FnOnce(std::io::Error) -> Self {
+        let path = path.into();
+        move |source| Self::FileIo { path, source }
+    }
+    /// Digs the typed error out of a report, context added on top doesn't get in the way
+    pub fn find(report: &color_eyre::Report) -> Option<&Self> {
+        report.downcast_ref::<Self>()
+    }
+}
+impl From<pasetors::errors::Error> for TappiError {
+    fn from(err: pasetors::errors::Error) -> Self {
+        Self::Crypto(Box::new(err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use color_eyre::eyre::WrapErr;
+
+    #[test]
+    fn survives_wrapping() {
+        let result: color_eyre::Result<()> = Err(TappiError::IceTimeout.into());
+        let report = result.wrap_err("Connection failed").unwrap_err();
+
+        assert!(matches!(
+            TappiError::find(&report),
+            Some(TappiError::IceTimeout)
+        ));
+        assert!(TappiError::find(&color_eyre::eyre::eyre!("Something else")).is_none());
+    }
+}
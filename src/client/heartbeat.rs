@@ -0,0 +1,139 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use webrtc::data_channel::RTCDataChannel;
+
+use crate::app::app_event::{AppEventClient, DisconnectKind};
+use crate::app::event::BasicEventSenderExt;
+use crate::app::models::Maid;
+use crate::client::message::Message;
+
+pub const DEFAULT_HEARTBEAT_SECS: u64 = 5;
+/// Pings in a row that can go unanswered before the peer counts as gone
+const MISSED_PONGS: u32 = 3;
+
+#[derive(Debug, PartialEq)]
+enum Beat {
+    Quiet,
+    Ping,
+    Dead,
+}
+
+#[derive(Debug)]
+struct HeartbeatState {
+    last_seen: Instant,
+    /// Set while a message is being handled, the ones behind it can't arrive until it's done
+    busy: bool,
+    missed: u32,
+}
+impl HeartbeatState {
+    fn new(now: Instant) -> Self {
+        Self {
+            last_seen: now,
+            busy: false,
+            missed: 0,
+        }
+    }
+
+    fn touch(&mut self, busy: bool, now: Instant) {
+        self.last_seen = now;
+        self.busy = busy;
+        self.missed = 0;
+    }
+
+    /// Pings only once the channel went quiet for a whole interval
+    fn tick(&mut self, interval: Duration, now: Instant) -> Beat {
+        if self.busy || now.duration_since(self.last_seen) < interval {
+            Beat::Quiet
+        } else if self.missed >= MISSED_PONGS {
+            Beat::Dead
+        } else {
+            self.missed += 1;
+            Beat::Ping
+        }
+    }
+}
+
+/// Application level keepalive, catches a peer that vanished without closing anything
+///
+/// Anything the peer sends counts as a pong, so a busy channel never gets pinged
+#[derive(Clone, Debug)]
+pub struct Heartbeat(Arc<Mutex<HeartbeatState>>);
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(HeartbeatState::new(Instant::now()))))
+    }
+}
+impl Heartbeat {
+    /// The peer is alive, a busy heartbeat doesn't count missed pongs at all
+    pub fn touch(&self, busy: bool) {
+        if let Ok(mut state) = self.0.lock() {
+            state.touch(busy, Instant::now());
+        }
+    }
+
+    fn tick(&self, interval: Duration) -> Beat {
+        self.0.lock().map_or(Beat::Quiet, |mut state| {
+            state.tick(interval, Instant::now())
+        })
+    }
+
+    /// Results in a `Disconnected` event once the peer stops answering
+    pub fn spawn(&self, maid: Maid, dc: Arc<RTCDataChannel>, interval: Duration) {
+        let heartbeat = self.clone();
+        heartbeat.touch(false);
+
+        tokio::spawn(async move {
+            let token = maid.token.child_token();
+            loop {
+                tokio::select! {
+                    _ = token.cancelled() => break,
+                    _ = tokio::time::sleep(interval) => match heartbeat.tick(interval) {
+                        Beat::Quiet => {}
+                        Beat::Ping => {
+                            // Straight to the channel, waiting for the buffer to drain could take forever
+                            let ping = serde_json::to_string(&Message::Ping).unwrap_or_default();
+                            if let Err(err) = dc.send_text(ping).await {
+                                log::debug!("Couldn't send a ping: {}", err);
+                            }
+                        }
+                        Beat::Dead => {
+                            log::warn!("The peer didn't answer {} pings, it's gone", MISSED_PONGS);
+                            maid.event_tx.send_event(AppEventClient::Disconnected(DisconnectKind::Lost)).await;
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pings_when_quiet_and_gives_up() {
+        let start = Instant::now();
+        let interval = Duration::from_secs(5);
+        let mut state = HeartbeatState::new(start);
+
+        assert_eq!(
+            state.tick(interval, start + Duration::from_secs(1)),
+            Beat::Quiet
+        );
+        let mut now = start;
+        for _ in 0..MISSED_PONGS {
+            now += interval;
+            assert_eq!(state.tick(interval, now), Beat::Ping);
+        }
+        now += interval;
+        assert_eq!(state.tick(interval, now), Beat::Dead);
+
+        // An answer resets the count, a busy handler holds it off
+        state.touch(true, now);
+        assert_eq!(state.tick(interval, now + interval * 10), Beat::Quiet);
+        state.touch(false, now);
+        assert_eq!(state.tick(interval, now + interval), Beat::Ping);
+    }
+}
@@ -0,0 +1,62 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::app::app_event::AppEventClient;
+use crate::app::event::BasicEventSenderExt;
+use crate::app::models::Maid;
+
+/// How often the watcher looks at the clock
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Shared "last activity" timestamp for the idle disconnect
+#[derive(Clone, Debug)]
+pub struct IdleWatch {
+    last_activity: Arc<Mutex<Instant>>,
+    busy: Arc<AtomicBool>,
+}
+impl Default for IdleWatch {
+    fn default() -> Self {
+        Self {
+            last_activity: Arc::new(Mutex::new(Instant::now())),
+            busy: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+impl IdleWatch {
+    /// Resets the timer, a busy watch doesn't count idle time at all
+    pub fn touch(&self, busy: bool) {
+        if let Ok(mut last_activity) = self.last_activity.lock() {
+            *last_activity = Instant::now();
+        }
+        self.busy.store(busy, Ordering::Relaxed);
+    }
+
+    pub fn idle_for(&self) -> Option<Duration> {
+        if self.busy.load(Ordering::Relaxed) {
+            return None;
+        }
+        self.last_activity.lock().ok().map(|l| l.elapsed())
+    }
+
+    /// Results in an `IdleTimeout` event once nothing happened for `timeout`
+    pub fn spawn(&self, maid: Maid, timeout: Duration) {
+        let watch = self.clone();
+        watch.touch(false);
+
+        tokio::spawn(async move {
+            let token = maid.token.child_token();
+            loop {
+                tokio::select! {
+                    _ = token.cancelled() => break,
+                    _ = tokio::time::sleep(CHECK_INTERVAL) => {
+                        if watch.idle_for().is_some_and(|idle| idle >= timeout) {
+                            maid.event_tx.send_event(AppEventClient::IdleTimeout).await;
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
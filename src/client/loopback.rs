@@ -0,0 +1,220 @@
+use color_eyre::eyre::eyre;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tokio::sync::mpsc::unbounded_channel;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+
+use crate::{
+    app::{
+        app_event::{AppEvent, AppEventClient, DebugDataChannel},
+        event::BasicEvent,
+        file_manager::{FileManager, SpeedCounter},
+        models::{ErrorTX, Maid},
+    },
+    cli::{LoopbackArgs, SendOrder},
+    client::{
+        message::{Message, ReceiveOptions},
+        payload,
+        rtc_base::{ChannelSettings, WebConnection},
+        signaling::{negotiator::Negotiator, signaling_loopback::SignalingLoopback},
+        transfer::Transfer,
+    },
+};
+
+/// Sends the files to itself over a local WebRTC connection and checks the received tree
+///
+/// Runs headless, returns the number of verified entries
+pub async fn run(args: LoopbackArgs) -> color_eyre::Result<usize> {
+    let temporary = args.output_dir.is_none();
+    let output_dir = args
+        .output_dir
+        .clone()
+        .unwrap_or_else(|| std::env::temp_dir().join(format!("tappi-loopback-{}", Uuid::new_v4())));
+    fs::create_dir_all(&output_dir)?;
+
+    let mut file_manager =
+        FileManager::new(false, SpeedCounter::DEFAULT_CAPACITY, SendOrder::default());
+    for path in file_manager.add_output_files(&args.files)? {
+        log::warn!("{:?} was skipped", path);
+    }
+    if file_manager.output_map.is_empty() {
+        return Err(eyre!("Nothing to send"));
+    }
+
+    // Both peers share the error channel and the cancellation token
+    let token = CancellationToken::new();
+    let (error_tx, mut error_rx) = unbounded_channel::<color_eyre::Report>();
+    let error_tx = ErrorTX(error_tx);
+    let (sender_tx, mut sender_rx) = unbounded_channel::<BasicEvent>();
+    let (receiver_tx, mut receiver_rx) = unbounded_channel::<BasicEvent>();
+    let sender_maid = Maid::new(error_tx.clone(), sender_tx, token.child_token());
+    let receiver_maid = Maid::new(error_tx, receiver_tx, token.child_token());
+
+    // Host candidates are enough to connect to itself
+    let config = RTCConfiguration::default();
+    let sender_wc = WebConnection::with_config(
+        sender_maid.clone(),
+        config.clone(),
+        ChannelSettings::default(),
+        output_dir.clone(),
+        ReceiveOptions {
+            strict: true, // Anything out of order is a bug here
+            ..Default::default()
+        },
+    )
+    .await?;
+    let receiver_wc = WebConnection::with_config(
+        receiver_maid.clone(),
+        config,
+        ChannelSettings::default(),
+        output_dir.clone(),
+        ReceiveOptions {
+            strict: true, // Anything out of order is a bug here
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    // Negotiate
+    let (sender_signaling, receiver_signaling) = SignalingLoopback::pair();
+    let mut sender_negotiator = Negotiator::new(
+        sender_maid.event_tx.clone(),
+        sender_wc.pc.clone(),
+        sender_signaling,
+        true,
+    );
+    let mut receiver_negotiator = Negotiator::new(
+        receiver_maid.event_tx.clone(),
+        receiver_wc.pc.clone(),
+        receiver_signaling,
+        true,
+    );
+    tokio::try_join!(sender_negotiator.run(), receiver_negotiator.run())?;
+
+    // Drive the transfer until every entry is acknowledged
+    // Sending before both channels are open lets the first packet claim the receiver's stream
+    let mut sender_channel: Option<DebugDataChannel> = None;
+    let mut receiver_open = false;
+    let mut resend_channel: Option<DebugDataChannel> = None;
+    let result: color_eyre::Result<()> = loop {
+        if receiver_open && let Some(ddc) = sender_channel.take() {
+            let output_files = file_manager.output_queue.clone();
+            Transfer::send_all_meta(
+                sender_maid.clone(),
+                &sender_wc,
+                ddc,
+                output_files,
+                args.chunk_size,
+                None,
+                payload::DEFAULT_SEND_RETRIES,
+            );
+        }
+
+        tokio::select! {
+            err = error_rx.recv() => {
+                break Err(err.unwrap_or_else(|| eyre!("Error channel closed")));
+            }
+            event = sender_rx.recv() => {
+                let Some(BasicEvent::App(AppEvent::Client(event))) = event else {
+                    continue;
+                };
+                match event {
+                    AppEventClient::ChannelOpened(ddc) => sender_channel = Some(ddc),
+                    AppEventClient::MetaSent(ddc) | AppEventClient::OutputFileFinished(ddc) => {
+                        resend_channel = Some(ddc.clone());
+                        Transfer::send_next_file(
+                            sender_maid.clone(), &sender_wc, ddc, &mut file_manager, args.chunk_size,
+                            args.chunk_crc, args.compress, payload::DEFAULT_SEND_RETRIES,
+                        );
+                    }
+                    AppEventClient::MessageReceived(Message::ResendChunk(id, offset)) => {
+                        if let Some(ddc) = resend_channel.clone()
+                            && let Some(output_file) = file_manager.output_map.get(&id)
+                        {
+                            Transfer::resend_file_data(
+                                sender_maid.clone(), &sender_wc, ddc, output_file.clone(), offset,
+                                args.chunk_size, args.compress, payload::DEFAULT_SEND_RETRIES,
+                            );
+                        }
+                    }
+                    AppEventClient::MessageReceived(Message::FileReceived(id)) => {
+                        file_manager.set_output_finished(id);
+                        if FileManager::get_completion(&file_manager.output_map) {
+                            break Ok(());
+                        }
+                    }
+                    AppEventClient::Disconnected(_) => {
+                        break Err(eyre!("Loopback connection was lost"));
+                    }
+                    _ => {}
+                }
+            }
+            event = receiver_rx.recv() => {
+                // Receiver progress isn't needed
+                if let Some(BasicEvent::App(AppEvent::Client(AppEventClient::ChannelOpened(_)))) = event {
+                    receiver_open = true;
+                }
+            }
+        }
+    };
+
+    token.cancel();
+    sender_wc.pc.close().await?;
+    receiver_wc.pc.close().await?;
+    result?;
+
+    let verified = verify(&file_manager, &output_dir)?;
+    if temporary {
+        fs::remove_dir_all(&output_dir)?;
+    }
+
+    Ok(verified)
+}
+
+/// Compares every sent entry against its received counterpart
+fn verify(file_manager: &FileManager, output_dir: &Path) -> color_eyre::Result<usize> {
+    for file in file_manager.output_map.values() {
+        let received: PathBuf = output_dir.join(file.meta.get_path());
+        let matches = if file.meta.is_dir {
+            received.is_dir()
+        } else {
+            received.is_file() && fs::read(&file.meta.path)? == fs::read(&received)?
+        };
+
+        if !matches {
+            return Err(eyre!("Received entry doesn't match: {:?}", received));
+        }
+    }
+
+    Ok(file_manager.output_map.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[tokio::test]
+    async fn transfer_directory() {
+        let source = TempDir::new("source");
+        fs::create_dir_all(source.join("nested/empty")).unwrap();
+        fs::write(source.join("nested/small.txt"), b"tappi").unwrap();
+        fs::write(source.join("large.bin"), vec![7u8; 200_000]).unwrap();
+        fs::write(source.join("zero"), b"").unwrap();
+
+        let args = LoopbackArgs {
+            files: vec![source.to_path_buf()],
+            chunk_size: 16_384,
+            chunk_crc: false,
+            compress: None,
+            output_dir: None,
+        };
+        let verified = run(args).await.unwrap();
+
+        assert_eq!(verified, 4);
+    }
+}
@@ -1,29 +1,181 @@
+use color_eyre::eyre::eyre;
 use rmpp::MsgPackEntry;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::{OsStr, OsString};
 use std::fs::{self, File, create_dir_all};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::mpsc::UnboundedSender;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedSender};
 use tokio::sync::{Mutex, watch};
 use webrtc::data_channel::RTCDataChannel;
 use webrtc::data_channel::data_channel_message::DataChannelMessage;
 
 use crate::app::app_event::AppEventClient;
+use crate::app::archive;
 use crate::app::event::BasicEvent;
 use crate::app::event::BasicEventSenderExt;
-use crate::app::file_manager::{FileId, SpeedReport};
-use crate::app::file_manager::{FileProgressReport, InputFile, MetaData};
+use crate::app::file_manager::{FileId, FileStatus, ProgressThrottle, SpeedReport};
+use crate::app::file_manager::{FileProgressReport, InputFile, MetaData, OutputFile};
+use crate::client::error::TappiError;
 use crate::client::packet;
 use crate::client::payload::send_message;
+use crate::client::receipt::ReceiptEntry;
+use crate::client::resume::{PartMeta, ResumeState};
+
+/// How often the sender hears from us while the user decides, its heartbeat would give up otherwise
+const DECIDING_KEEPALIVE: Duration = Duration::from_secs(1);
+/// Metadata bytes taken in for a single file, way more than any real path needs
+pub const MAX_META_LENGTH: usize = 64 * 1024;
+/// Files whose metadata is still coming in, bounds all of it to a few MiB
+const MAX_PENDING_META: usize = 64;
+/// Longest file name pretty much every filesystem takes, bytes on Unix and UTF-16 units on Windows
+const MAX_NAME_LENGTH: usize = 255;
+/// MAX_PATH without the terminating null
+#[cfg(windows)]
+const MAX_PATH_LENGTH: usize = 259;
+/// PATH_MAX without the terminating null
+#[cfg(not(windows))]
+const MAX_PATH_LENGTH: usize = 4095;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Message {
     TextMessage(String), // TODO: reserved for potential future text chat functionality
     FilePacketReceived(SpeedReport), // Speed-monitoring-related message
     FileReceived(FileId), // To make sure a file was successfully delivered
+    TransferOffer(TransferSummary), // Sent ahead of the metadata
+    TransferDeclined,    // The receiver refused the transfer
+    Hello(String),       // Data channel protocol, sent once the channel opens
+    ResendChunk(FileId, usize), // A checked chunk arrived corrupt, send the file again from that offset
+    Ping,                       // Keepalive, sent when the channel goes quiet
+    Pong,                       // Keepalive answer
+    ResumeQuery,                // Sent after the metadata, asks what the receiver already has
+    ResumeOffsets(Vec<(FileId, usize)>), // Bytes of each file the receiver kept from before
+    ReceiptQuery,               // Sent once everything is delivered, with `--receipt`
+    Receipt(Vec<ReceiptEntry>), // What the receiver ended up with
+    Goodbye,     // Sent before hanging up, so the peer doesn't take it for a lost connection
+    Pause(bool), // Holds back the peer's file data while we sit at our data cap
+}
+
+/// What the sender is about to send
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TransferSummary {
+    pub sender: Option<String>,
+    pub files: usize,
+    pub size: usize,
+}
+impl TransferSummary {
+    pub fn new<'a>(sender: Option<String>, files: impl Iterator<Item = &'a OutputFile>) -> Self {
+        let files = files.filter(|f| !f.meta.is_dir);
+        let (files, size) = files.fold((0, 0), |(c, s), f| (c + 1, s + f.meta.size));
+        Self {
+            sender,
+            files,
+            size,
+        }
+    }
+}
+
+/// An incoming transfer waiting for the user's decision
+#[derive(Clone, Debug)]
+pub struct TransferOffer {
+    pub summary: TransferSummary,
+    pub reply_tx: UnboundedSender<bool>,
+}
+
+/// Receiver side consent, only asks when `prompt` is set
+#[derive(Debug, Default)]
+pub struct AcceptGate {
+    pub prompt: bool,
+    pub accepted: Option<bool>,
+    /// Files that can't be written here, their data is dropped
+    pub skipped: HashSet<FileId>,
+}
+impl AcceptGate {
+    pub fn new(prompt: bool) -> Self {
+        Self {
+            prompt,
+            accepted: None,
+            skipped: HashSet::new(),
+        }
+    }
+
+    /// Asks the user once and remembers the answer
+    ///
+    /// The channel handler is stuck here until then, so `keepalive` answers for it in the meantime
+    async fn ask(
+        &mut self,
+        summary: TransferSummary,
+        sender: &UnboundedSender<BasicEvent>,
+        keepalive: impl Fn(),
+    ) -> bool {
+        if !self.prompt {
+            return true;
+        }
+        if let Some(accepted) = self.accepted {
+            return accepted;
+        }
+
+        let (reply_tx, mut reply_rx) = mpsc::unbounded_channel();
+        sender
+            .send_event(AppEventClient::IncomingTransferOffer(TransferOffer {
+                summary,
+                reply_tx,
+            }))
+            .await;
+        let mut ticks = tokio::time::interval(DECIDING_KEEPALIVE);
+        ticks.tick().await; // The first one is immediate
+        let accepted = loop {
+            tokio::select! {
+                reply = reply_rx.recv() => break reply.unwrap_or(false),
+                _ = ticks.tick() => keepalive(),
+            }
+        };
+        self.accepted = Some(accepted);
+        accepted
+    }
+}
+
+/// How the incoming files are taken in, set once for the whole connection
+#[derive(Debug, Default)]
+pub struct ReceiveOptions {
+    /// Where `--output` puts the single incoming file, absolute
+    pub output_file: Option<PathBuf>,
+    /// What `--resume` found from before
+    pub resume: Option<ResumeState>,
+    /// Asks the user before taking in a transfer
+    pub prompt_accept: bool,
+    pub throttle: ProgressThrottle,
+    /// Anything out of order is an error instead of a warning
+    pub strict: bool,
+    /// `--staging` moves the files at 100, so the last packet only reports it after the rename
+    pub staging: bool,
+}
+
+/// What the messages of one channel share while the files come in
+#[derive(Debug)]
+pub struct ReceiveState {
+    pub output_dir: PathBuf,
+    pub options: ReceiveOptions,
+    pub gate: Mutex<AcceptGate>,
+    pub throttle: Mutex<ProgressThrottle>,
+    /// Shared with the connection, the part files are only written while it's locked
+    pub metadata_map: Arc<Mutex<HashMap<usize, MetaData>>>,
+    pub metadata_bytes_map: Mutex<HashMap<usize, Vec<u8>>>,
+}
+impl ReceiveState {
+    pub fn new(output_dir: PathBuf, options: ReceiveOptions) -> Self {
+        Self {
+            output_dir,
+            gate: Mutex::new(AcceptGate::new(options.prompt_accept)),
+            throttle: Mutex::new(options.throttle),
+            options,
+            metadata_map: Arc::default(),
+            metadata_bytes_map: Mutex::default(),
+        }
+    }
 }
 
 // Handles files, folder structures, empty folders and empty files + file messages
@@ -32,20 +184,107 @@ pub async fn handle_message(
     channel: Arc<RTCDataChannel>,
     buffer_watch_rx: &mut watch::Receiver<bool>,
     sender: UnboundedSender<BasicEvent>,
-    metadata_map: Arc<Mutex<HashMap<usize, MetaData>>>,
-    metadata_bytes_map: Arc<Mutex<HashMap<usize, Vec<u8>>>>,
+    receiving: &ReceiveState,
 ) -> color_eyre::Result<()> {
+    let ReceiveState {
+        output_dir,
+        options,
+        gate,
+        throttle,
+        metadata_map,
+        metadata_bytes_map,
+    } = receiving;
+    let (output_file, resume) = (options.output_file.as_deref(), options.resume.as_ref());
+    let strict = options.strict;
     match msg.is_string {
         // Handle messages
         true => {
             let json = String::from_utf8(msg.data.to_vec())?;
             let message: Message = serde_json::from_str(&json)?;
+            if let Message::Hello(theirs) = &message {
+                let ours = channel.protocol();
+                if theirs != ours {
+                    return Err(TappiError::ProtocolMismatch {
+                        ours: ours.to_string(),
+                        theirs: theirs.clone(),
+                    }
+                    .into());
+                }
+                return Ok(());
+            }
+            match message {
+                Message::Ping => {
+                    return send_message(channel, buffer_watch_rx, Message::Pong).await;
+                }
+                Message::Pong => return Ok(()), // Arriving is all it has to do
+                Message::ResumeQuery => {
+                    // Only ever above zero with `--resume`
+                    let offsets = metadata_map
+                        .lock()
+                        .await
+                        .iter()
+                        .filter(|(_, m)| !m.is_dir && m.progress_bytes > 0)
+                        .filter(|(_, m)| m.progress_bytes < m.size)
+                        .map(|(id, m)| (*id, m.progress_bytes))
+                        .collect();
+                    let reply = Message::ResumeOffsets(offsets);
+                    return send_message(channel, buffer_watch_rx, reply).await;
+                }
+                Message::ReceiptQuery => {
+                    let receipt = metadata_map
+                        .lock()
+                        .await
+                        .iter()
+                        .filter(|(_, m)| !m.is_dir)
+                        .map(|(id, m)| ReceiptEntry::new(*id, m))
+                        .collect();
+                    let reply = Message::Receipt(receipt);
+                    return send_message(channel, buffer_watch_rx, reply).await;
+                }
+                _ => {}
+            }
+            if let Message::TransferOffer(summary) = &message
+                && output_file.is_some()
+                && summary.files > 1
+            {
+                send_message(channel.clone(), buffer_watch_rx, Message::TransferDeclined).await?;
+                return Err(TappiError::TooManyFiles(summary.files).into());
+            }
+            if let Message::TransferOffer(summary) = &message {
+                // Holding the handler here also holds back the packets behind it
+                let mut gate = gate.lock().await;
+                // Pings can't be answered from here until the user decides
+                let keepalive = || {
+                    let channel = channel.clone();
+                    tokio::spawn(async move {
+                        let pong = serde_json::to_string(&Message::Pong).unwrap_or_default();
+                        if let Err(err) = channel.send_text(pong).await {
+                            log::debug!("Couldn't send a keepalive: {}", err);
+                        }
+                    });
+                };
+                if !gate.ask(summary.clone(), &sender, keepalive).await {
+                    send_message(channel.clone(), buffer_watch_rx, Message::TransferDeclined)
+                        .await?;
+                    sender.send_event(AppEventClient::TransferDeclined).await;
+                }
+            }
             sender
                 .send_event(AppEventClient::MessageReceived(message))
                 .await;
         }
         // Handle file meta and data
         false => {
+            // Nothing gets written without consent
+            let consent = gate.lock().await;
+            if consent.prompt && consent.accepted != Some(true) {
+                if consent.accepted.is_none() {
+                    return Err(eyre!("Received files without a transfer offer"));
+                }
+                return Ok(());
+            }
+            drop(consent);
+
             let entry: MsgPackEntry = rmpp::unpack(&msg.data)?;
             let packet = packet::Packet::new(entry)?;
 
@@ -57,25 +296,58 @@ pub async fn handle_message(
                 // Ignore if it's already in
                 if metadata.get(&packet.id).is_none() {
                     let mut meta_bytes_map = metadata_bytes_map.lock().await; // lock mutex
-                    if let Some(bytes) = meta_bytes_map.get_mut(&packet.id) {
-                        bytes.extend(packet.binary);
-                    } else {
-                        meta_bytes_map.insert(packet.id, packet.binary);
-                    }
+                    collect_meta(&mut meta_bytes_map, packet.id, packet.binary)?;
+                } else {
+                    let what = format!("metadata of file {} again", packet.id);
+                    return out_of_order(strict, what);
                 }
             } else {
                 // File data
                 let mut metadata_map = metadata_map.lock().await;
                 if let Some(metadata) = metadata_map.get_mut(&packet.id) {
+                    // Checked chunks say where they go, the ones that don't fit are from before a resend
+                    if packet
+                        .offset
+                        .is_some_and(|offset| offset != metadata.progress_bytes)
+                    {
+                        return Ok(());
+                    }
+                    if metadata.progress_bytes >= metadata.size {
+                        let what = format!("data of file {} after its last chunk", packet.id);
+                        return out_of_order(strict, what);
+                    }
+                    if !packet.is_intact() {
+                        log::warn!(
+                            "Corrupt chunk of file {} at {}, asking for it again",
+                            packet.id,
+                            metadata.progress_bytes
+                        );
+                        let resend = Message::ResendChunk(packet.id, metadata.progress_bytes);
+                        send_message(channel.clone(), buffer_watch_rx, resend).await?;
+                        return Ok(());
+                    }
+
                     metadata.progress_bytes += packet.binary.len();
-                    append_data_to_file(metadata.get_path(), &packet.binary)?;
+                    append_data_to_file(output_dir.join(metadata.get_path()), &packet.binary)?;
+                    if let Some(crc) = metadata.crc {
+                        let mut hasher = crc32fast::Hasher::new_with_initial(crc);
+                        hasher.update(&packet.binary);
+                        metadata.crc = Some(hasher.finalize());
+                    }
 
-                    let progress = (metadata.progress_bytes as f64) / (metadata.size as f64);
-                    sender
-                        .send_event(AppEventClient::InputFileProgress(FileProgressReport::new(
-                            packet.id, progress,
-                        )))
-                        .await;
+                    if throttle.lock().await.ready(packet.binary.len()) {
+                        let mut progress =
+                            (metadata.progress_bytes as f64) / (metadata.size as f64);
+                        // Staging moves the files at 100, the last packet reports it once the file got its final name
+                        if options.staging {
+                            progress = progress.min(0.99);
+                        }
+                        sender
+                            .send_event(AppEventClient::InputFileProgress(FileProgressReport::new(
+                                packet.id, progress,
+                            )))
+                            .await;
+                    }
                     sender
                         .send_event(AppEventClient::ReportFileSpeed(SpeedReport::new(
                             packet.id,
@@ -93,33 +365,86 @@ pub async fn handle_message(
                         )),
                     )
                     .await?;
+                } else if !gate.lock().await.skipped.contains(&packet.id) {
+                    let what = format!("data of file {} before its metadata", packet.id);
+                    out_of_order(strict, what)?;
                 }
             }
 
             // Do stuff if last
             if packet.last {
                 if packet.meta {
-                    let meta_bytes_map = metadata_bytes_map.lock().await;
-                    if let Some(bytes) = meta_bytes_map.get(&packet.id) {
-                        //
-                        let meta_string = String::from_utf8_lossy(bytes);
+                    // Done with the pieces once they're put together
+                    let bytes = metadata_bytes_map.lock().await.remove(&packet.id);
+                    if let Some(bytes) = bytes {
+                        let meta_string = String::from_utf8_lossy(&bytes);
                         let mut metadata = metadata_map.lock().await;
-                        let value: MetaData = serde_json::from_str(&meta_string)?;
+                        let mut value: MetaData = serde_json::from_str(&meta_string)?;
+                        let safe = check_safe_path(&value);
+                        if safe.is_ok()
+                            && let Some(output_file) = output_file
+                            && !value.is_dir
+                        {
+                            // Later batches don't come with the count of the first one
+                            let files = metadata.values().filter(|m| !m.is_dir).count() + 1;
+                            if files > 1 {
+                                drop(metadata);
+                                let declined = Message::TransferDeclined;
+                                send_message(channel.clone(), buffer_watch_rx, declined).await?;
+                                return Err(TappiError::TooManyFiles(files).into());
+                            }
+                            value.redirect(output_file);
+                        }
+                        if safe.is_ok()
+                            && let Some(resume) = resume
+                            && !value.is_dir
+                        {
+                            resume_file(resume, &mut value, output_dir)?;
+                        }
+                        // Better to leave one file behind than to fail halfway through the rest
+                        let checked = safe.and_then(|()| check_path_length(&value, output_dir));
+                        if let Err(err) = checked {
+                            log::warn!("Skipping file {}: {}", packet.id, err);
+                            gate.lock().await.skipped.insert(packet.id);
+                            if !value.is_dir {
+                                let mut input_file = InputFile::new(packet.id, value.clone());
+                                input_file.status = FileStatus::Skipped;
+                                sender
+                                    .send_event(AppEventClient::InputFileNew(Box::new(input_file)))
+                                    .await;
+                            }
+                            // Nothing else is coming for these to answer
+                            if value.is_dir || value.size == 0 {
+                                send_message(
+                                    channel.clone(),
+                                    buffer_watch_rx,
+                                    Message::FileReceived(packet.id),
+                                )
+                                .await?;
+                            }
+                            return Ok(());
+                        }
+                        // A resumed file had its beginning hashed by somebody else
+                        value.crc = (value.progress_bytes == 0).then_some(0);
                         metadata.insert(packet.id, value.clone());
-                        create_folder_structure(&value)?;
+                        create_folder_structure(&value, output_dir)?;
+                        if !value.is_dir && value.size > 0 {
+                            let part = append_part_ext(output_dir.join(value.get_path()));
+                            PartMeta::new(&value).save(&part)?;
+                        }
 
                         if !value.is_dir {
                             if value.size > 0 {
                                 sender
-                                    .send_event(AppEventClient::InputFileNew(InputFile::new(
-                                        packet.id, value,
+                                    .send_event(AppEventClient::InputFileNew(Box::new(
+                                        InputFile::new(packet.id, value),
                                     )))
                                     .await;
                             } else {
-                                create_file(value.get_path(), false)?;
+                                create_file(output_dir.join(value.get_path()), false)?;
                                 sender
-                                    .send_event(AppEventClient::InputFileNew(InputFile::new(
-                                        packet.id, value,
+                                    .send_event(AppEventClient::InputFileNew(Box::new(
+                                        InputFile::new(packet.id, value),
                                     )))
                                     .await; // Creates the file in the UI
                                 sender
@@ -147,7 +472,14 @@ pub async fn handle_message(
                 } else {
                     let mut metadata = metadata_map.lock().await;
                     if let Some(metadata) = metadata.get_mut(&packet.id) {
-                        remove_part_ext(metadata.get_path())?;
+                        let path = output_dir.join(metadata.get_path());
+                        remove_part_ext(path.clone())?;
+                        PartMeta::discard(&append_part_ext(path))?; // Only the sidecar is left
+                        sender
+                            .send_event(AppEventClient::InputFileProgress(FileProgressReport::new(
+                                packet.id, 1.0,
+                            )))
+                            .await;
                     }
 
                     // Report to the other client
@@ -165,10 +497,107 @@ pub async fn handle_message(
     Ok(())
 }
 
-fn create_folder_structure(metadata: &MetaData) -> color_eyre::Result<()> {
+/// Out of order packets are dropped, `--strict` makes them an error instead
+fn out_of_order(strict: bool, what: String) -> color_eyre::Result<()> {
+    if strict {
+        return Err(TappiError::OutOfOrder(what).into());
+    }
+    log::debug!("Dropped {}", what);
+    Ok(())
+}
+
+/// Adds a piece of metadata, a peer that never stops sending it is an error
+fn collect_meta(
+    meta_bytes_map: &mut HashMap<usize, Vec<u8>>,
+    id: usize,
+    binary: Vec<u8>,
+) -> color_eyre::Result<()> {
+    if !meta_bytes_map.contains_key(&id) && meta_bytes_map.len() >= MAX_PENDING_META {
+        meta_bytes_map.clear();
+        return Err(TappiError::TooMuchPendingMetadata(MAX_PENDING_META).into());
+    }
+    let bytes = meta_bytes_map.entry(id).or_default();
+    if bytes.len() + binary.len() > MAX_META_LENGTH {
+        meta_bytes_map.remove(&id);
+        return Err(TappiError::MetadataTooLarge {
+            id,
+            limit: MAX_META_LENGTH,
+        }
+        .into());
+    }
+    bytes.extend(binary);
+    Ok(())
+}
+
+/// Continues the part file left from before, a stray one is in the way and gets removed
+///
+/// Only a part file its sidecar says was started for the same path and size is continued, the
+/// bytes already in it aren't checked
+fn resume_file(
+    resume: &ResumeState,
+    metadata: &mut MetaData,
+    output_dir: &Path,
+) -> color_eyre::Result<()> {
+    let part = append_part_ext(output_dir.join(metadata.get_path()));
+    let offset = resume
+        .offset_for(output_dir, metadata)
+        .filter(|_| PartMeta::matches(&part, metadata));
+    match offset {
+        Some(offset) => {
+            log::info!("Resuming {:?} from {}", metadata.get_path(), offset);
+            metadata.progress_bytes = offset;
+        }
+        None if part.exists() => PartMeta::discard(&part)?,
+        None => {}
+    }
+    Ok(())
+}
+
+/// Whether the path the peer sent stays inside the output directory, `join` would drop it otherwise
+fn check_safe_path(metadata: &MetaData) -> Result<(), TappiError> {
+    let path = metadata.get_path();
+    if path.as_os_str().is_empty() || !archive::is_safe_path(&path) {
+        return Err(TappiError::UnsafePath(path));
+    }
+    Ok(())
+}
+
+/// Whether the file, part file and sidecar included, fits the limits of this system
+fn check_path_length(metadata: &MetaData, output_dir: &Path) -> Result<(), TappiError> {
+    let mut path = output_dir.join(metadata.get_path());
+    if !metadata.is_dir {
+        path = PartMeta::path_for(&append_part_ext(path)); // The longest one of them
+    }
+    // The limit is on what the system resolves the path to
+    let path = std::path::absolute(&path).unwrap_or(path);
+
+    let too_long = |path: PathBuf, limit| Err(TappiError::PathTooLong { path, limit });
+    if path
+        .components()
+        .any(|c| path_length(c.as_os_str()) > MAX_NAME_LENGTH)
+    {
+        return too_long(output_dir.join(metadata.get_path()), MAX_NAME_LENGTH);
+    }
+    if path_length(path.as_os_str()) > MAX_PATH_LENGTH {
+        return too_long(output_dir.join(metadata.get_path()), MAX_PATH_LENGTH);
+    }
+    Ok(())
+}
+
+/// Length the way the system counts it
+fn path_length(path: &OsStr) -> usize {
+    if cfg!(windows) {
+        path.to_string_lossy().encode_utf16().count()
+    } else {
+        path.len()
+    }
+}
+
+fn create_folder_structure(metadata: &MetaData, output_dir: &Path) -> color_eyre::Result<()> {
+    let path = output_dir.join(metadata.get_path());
     if metadata.is_dir {
-        create_dir_all(metadata.get_path())?;
-    } else if let Some(parent) = metadata.get_path().parent()
+        create_dir_all(path)?;
+    } else if let Some(parent) = path.parent()
         && !parent.exists()
         && parent.to_string_lossy() != ""
     {
@@ -185,11 +614,17 @@ fn create_file(path: PathBuf, append_part: bool) -> color_eyre::Result<File> {
     } else {
         path
     };
-    Ok(fs::OpenOptions::new().create(true).append(true).open(p)?)
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&p)
+        .map_err(TappiError::file_io(&p))?;
+    Ok(file)
 }
 fn append_data_to_file(path: PathBuf, data: &[u8]) -> color_eyre::Result<()> {
-    let mut file = create_file(path, true)?;
-    file.write_all(data)?;
+    let mut file = create_file(path.clone(), true)?;
+    file.write_all(data)
+        .map_err(TappiError::file_io(append_part_ext(path)))?;
     Ok(())
 }
 
@@ -204,6 +639,232 @@ pub fn append_part_ext(path: PathBuf) -> PathBuf {
     append_ext("part", path)
 }
 pub fn remove_part_ext(path: PathBuf) -> color_eyre::Result<()> {
-    fs::rename(append_part_ext(path.clone()), path)?;
+    fs::rename(append_part_ext(path.clone()), &path).map_err(TappiError::file_io(path))?;
+    Ok(())
+}
+
+/// Moves a finished staging tree into the output directory, merging into existing folders
+///
+/// Nothing moves if any file would take the place of one that's already there
+pub fn commit_staging(staging_dir: &Path, output_dir: &Path) -> color_eyre::Result<()> {
+    if let Some(taken) = first_taken(staging_dir, output_dir)? {
+        return Err(TappiError::AlreadyExists(taken).into());
+    }
+    move_tree(staging_dir, output_dir)?;
+    fs::remove_dir_all(staging_dir).map_err(TappiError::file_io(staging_dir))?;
     Ok(())
 }
+/// First path under `to` that moving `from` over would replace, folders only merge
+fn first_taken(from: &Path, to: &Path) -> color_eyre::Result<Option<PathBuf>> {
+    for entry in fs::read_dir(from).map_err(TappiError::file_io(from))? {
+        let entry = entry?;
+        let source = entry.path();
+        let target = to.join(entry.file_name());
+        if source.is_dir() && target.is_dir() {
+            if let Some(taken) = first_taken(&source, &target)? {
+                return Ok(Some(taken));
+            }
+        } else if target.symlink_metadata().is_ok() {
+            return Ok(Some(target));
+        }
+    }
+    Ok(None)
+}
+fn move_tree(from: &Path, to: &Path) -> color_eyre::Result<()> {
+    create_dir_all(to).map_err(TappiError::file_io(to))?;
+    for entry in fs::read_dir(from).map_err(TappiError::file_io(from))? {
+        let entry = entry?;
+        let source = entry.path();
+        let target = to.join(entry.file_name());
+        // Whole folders go in one rename, only the existing ones need merging
+        if source.is_dir() && target.is_dir() {
+            move_tree(&source, &target)?;
+        } else {
+            fs::rename(&source, &target).map_err(TappiError::file_io(&target))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::app_event::AppEvent;
+    use crate::app::file_manager::FileManager;
+    use crate::test_util::TempDir;
+
+    #[tokio::test]
+    async fn gate_asks_once() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        // Without a prompt everything is accepted silently
+        let mut gate = AcceptGate::new(false);
+        assert!(gate.ask(TransferSummary::default(), &tx, || {}).await);
+        assert!(rx.try_recv().is_err());
+
+        // With a prompt the user's answer is asked for and remembered
+        let mut gate = AcceptGate::new(true);
+        let answer = tokio::spawn(async move {
+            let Some(BasicEvent::App(AppEvent::Client(AppEventClient::IncomingTransferOffer(
+                offer,
+            )))) = rx.recv().await
+            else {
+                panic!("Expected a transfer offer");
+            };
+            offer.reply_tx.send(false).ok();
+        });
+        assert!(!gate.ask(TransferSummary::default(), &tx, || {}).await);
+        answer.await.unwrap();
+        assert!(!gate.ask(TransferSummary::default(), &tx, || {}).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gate_keeps_the_peer_waiting() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut gate = AcceptGate::new(true);
+        let answer = tokio::spawn(async move {
+            let Some(BasicEvent::App(AppEvent::Client(AppEventClient::IncomingTransferOffer(
+                offer,
+            )))) = rx.recv().await
+            else {
+                panic!("Expected a transfer offer");
+            };
+            tokio::time::sleep(DECIDING_KEEPALIVE * 5 + DECIDING_KEEPALIVE / 2).await;
+            offer.reply_tx.send(true).ok();
+        });
+
+        // A slow decision keeps answering for the stuck handler
+        let sent = std::sync::atomic::AtomicUsize::new(0);
+        let keepalive = || {
+            sent.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        };
+        assert!(gate.ask(TransferSummary::default(), &tx, keepalive).await);
+        answer.await.unwrap();
+        assert_eq!(sent.into_inner(), 5);
+    }
+
+    #[test]
+    fn peer_paths_stay_in_the_output_directory() {
+        let received = |raw_path: &[u8]| {
+            let mut meta = MetaData::new(Path::new("evil"), 1, None, false);
+            meta.raw_path = Some(raw_path.to_vec());
+            let json = serde_json::to_string(&meta).unwrap();
+            serde_json::from_str::<MetaData>(&json).unwrap()
+        };
+        for raw_path in [&b"../evil"[..], b"/tmp/evil", b"docs/../../evil"] {
+            let err = check_safe_path(&received(raw_path)).unwrap_err();
+            assert!(matches!(err, TappiError::UnsafePath(_)));
+        }
+        assert!(check_safe_path(&received(b"docs/evil")).is_ok());
+    }
+
+    #[test]
+    fn metadata_is_capped() {
+        let mut map = HashMap::new();
+        let piece = vec![b'x'; MAX_META_LENGTH / 4];
+        for _ in 0..4 {
+            collect_meta(&mut map, 1, piece.clone()).unwrap();
+        }
+        let err = collect_meta(&mut map, 1, vec![b'x']).unwrap_err();
+        assert!(matches!(
+            TappiError::find(&err),
+            Some(TappiError::MetadataTooLarge { id: 1, .. })
+        ));
+        assert!(!map.contains_key(&1));
+
+        for id in 0..MAX_PENDING_META {
+            collect_meta(&mut map, id, piece.clone()).unwrap();
+        }
+        let err = collect_meta(&mut map, MAX_PENDING_META, vec![b'x']).unwrap_err();
+        assert!(matches!(
+            TappiError::find(&err),
+            Some(TappiError::TooMuchPendingMetadata(_))
+        ));
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn staging_merges_into_output() {
+        let root = TempDir::new("staging");
+        let staging = root.join("staging");
+        let output = root.join("out");
+        fs::create_dir_all(staging.join("docs/new")).unwrap();
+        fs::create_dir_all(output.join("docs")).unwrap();
+        fs::write(staging.join("docs/new/a.txt"), "a").unwrap();
+        fs::write(staging.join("b.txt"), "b").unwrap();
+        fs::write(output.join("docs/old.txt"), "old").unwrap();
+
+        commit_staging(&staging, &output).unwrap();
+
+        assert!(!staging.exists());
+        assert_eq!(
+            fs::read_to_string(output.join("docs/new/a.txt")).unwrap(),
+            "a"
+        );
+        assert_eq!(fs::read_to_string(output.join("b.txt")).unwrap(), "b");
+        assert!(output.join("docs/old.txt").exists());
+
+        // A file that's already there stops the whole move
+        fs::create_dir_all(staging.join("docs")).unwrap();
+        fs::write(staging.join("docs/c.txt"), "c").unwrap();
+        fs::write(staging.join("docs/old.txt"), "new").unwrap();
+        let err = commit_staging(&staging, &output).unwrap_err();
+        assert!(matches!(
+            TappiError::find(&err),
+            Some(TappiError::AlreadyExists(path)) if path == &output.join("docs/old.txt")
+        ));
+        assert_eq!(
+            fs::read_to_string(output.join("docs/old.txt")).unwrap(),
+            "old"
+        );
+        assert!(!output.join("docs/c.txt").exists());
+        assert!(staging.join("docs/c.txt").exists());
+    }
+
+    #[test]
+    fn skipped_file_still_commits_staging() {
+        let root = TempDir::new("skipped");
+        let staging = root.join("staging");
+        let output = root.join("out");
+        fs::create_dir_all(&staging).unwrap();
+        fs::write(staging.join("a.txt"), "a").unwrap();
+
+        let mut input_map = indexmap::IndexMap::new();
+        let mut done = InputFile::new(0, MetaData::new(Path::new("a.txt"), 1, None, false));
+        done.set_progress(1.0);
+        let mut skipped = InputFile::new(1, MetaData::new(Path::new("b.txt"), 1, None, false));
+        skipped.status = FileStatus::Skipped;
+        input_map.insert(0, done);
+        input_map.insert(1, skipped);
+        assert!(!FileManager::get_completion(&input_map));
+        assert!(FileManager::get_received(&input_map)); // What the staging waits for
+
+        commit_staging(&staging, &output).unwrap();
+        assert_eq!(fs::read_to_string(output.join("a.txt")).unwrap(), "a");
+    }
+
+    #[test]
+    fn paths_over_the_limits() {
+        let output = Path::new("out");
+        let base = PathBuf::from("/shared");
+        let meta =
+            |path: PathBuf, is_dir| MetaData::new(&base.join(path), 10, Some(base.clone()), is_dir);
+        assert!(check_path_length(&meta(PathBuf::from("docs/a.txt"), false), output).is_ok());
+
+        // The sidecar of the part file is what goes over
+        let name = PathBuf::from("a".repeat(250));
+        assert!(check_path_length(&meta(name.clone(), true), output).is_ok());
+        let err = check_path_length(&meta(name, false), output).unwrap_err();
+        assert!(matches!(err, TappiError::PathTooLong { limit: 255, .. }));
+
+        let deep: PathBuf = (0..30).map(|_| "b".repeat(200)).collect();
+        let err = check_path_length(&meta(deep, false), output).unwrap_err();
+        assert!(matches!(
+            err,
+            TappiError::PathTooLong {
+                limit: MAX_PATH_LENGTH,
+                ..
+            }
+        ));
+    }
+}
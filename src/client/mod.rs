@@ -1,6 +1,19 @@
 pub mod client_init;
+pub mod compress;
+pub mod data_cap;
+pub mod diagnose;
+pub mod error;
+pub mod heartbeat;
+pub mod idle;
+pub mod loopback;
 pub mod message;
+pub mod pacer;
 pub mod packet;
 pub mod payload;
+pub mod receipt;
+pub mod resume;
 pub mod rtc_base;
 pub mod signaling;
+pub mod transfer;
+pub mod turn;
+pub mod watch;
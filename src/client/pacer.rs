@@ -0,0 +1,185 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Chunks looked at before deciding anything
+const WINDOW: usize = 32;
+/// Share of blocked chunks that counts as a full link
+const HIGH: f64 = 0.5;
+/// Share of blocked chunks that counts as a drained one
+const LOW: f64 = 0.1;
+/// Rate multiplier when the link is full
+const DECREASE: f64 = 0.7;
+/// Bytes per second added after each calm window
+const INCREASE: f64 = 256.0 * 1024.0;
+/// Never crawls slower than this
+const MIN_RATE: f64 = 64.0 * 1024.0;
+/// A quiet channel for this long starts the window over, way longer than a chunk takes at `MIN_RATE`
+const IDLE_GAP: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+struct PacerState {
+    /// Bytes per second, unlimited until the link first fills up
+    rate: Option<f64>,
+    chunks: usize,
+    blocked: usize,
+    bytes: usize,
+    /// The first chunk of the window, the time before it isn't the link's fault
+    window_start: Option<Instant>,
+    last_record: Option<Instant>,
+    /// When the next chunk may go out
+    next_send: Instant,
+}
+impl PacerState {
+    fn new(now: Instant) -> Self {
+        Self {
+            rate: None,
+            chunks: 0,
+            blocked: 0,
+            bytes: 0,
+            window_start: None,
+            last_record: None,
+            next_send: now,
+        }
+    }
+
+    /// Books the chunk a slot and returns how long to wait for it
+    fn reserve(&mut self, bytes: usize, now: Instant) -> Duration {
+        let Some(rate) = self.rate else {
+            return Duration::ZERO;
+        };
+        let start = self.next_send.max(now);
+        self.next_send = start + Duration::from_secs_f64(bytes as f64 / rate);
+        start - now
+    }
+
+    /// AIMD, backs off hard when the buffer keeps filling up and creeps back up once it doesn't
+    fn record(&mut self, blocked: bool, bytes: usize, now: Instant) {
+        // Handshakes, prompts and the gaps between files would pass for a slow link
+        if self
+            .last_record
+            .is_some_and(|last| now.duration_since(last) > IDLE_GAP)
+        {
+            self.reset_window();
+        }
+        self.last_record = Some(now);
+        let window_start = *self.window_start.get_or_insert(now);
+
+        self.chunks += 1;
+        self.bytes += bytes;
+        if blocked {
+            self.blocked += 1;
+        }
+        if self.chunks < WINDOW {
+            return;
+        }
+
+        let elapsed = now.duration_since(window_start).as_secs_f64().max(0.001);
+        let throughput = self.bytes as f64 / elapsed;
+        let ratio = self.blocked as f64 / self.chunks as f64;
+        if ratio >= HIGH {
+            let base = self.rate.map_or(throughput, |rate| rate.min(throughput));
+            self.rate = Some((base * DECREASE).max(MIN_RATE));
+            log::debug!("Link looks full, pacing at {:.0} B/s", self.rate.unwrap());
+        } else if ratio <= LOW
+            && let Some(rate) = self.rate
+        {
+            self.rate = Some(rate + INCREASE);
+        }
+        self.reset_window();
+    }
+
+    fn reset_window(&mut self) {
+        self.chunks = 0;
+        self.blocked = 0;
+        self.bytes = 0;
+        self.window_start = None;
+    }
+}
+
+/// Congestion-aware pacing for `--fair`, shared by everything sent over a connection
+///
+/// Watches how often the channel buffer is over its threshold, which is as close
+/// as we get to knowing the link is full
+#[derive(Clone, Debug)]
+pub struct Pacer(Arc<Mutex<PacerState>>);
+impl Default for Pacer {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(PacerState::new(Instant::now()))))
+    }
+}
+impl Pacer {
+    /// Waits for the chunk's turn
+    pub async fn pace(&self, bytes: usize) {
+        let wait = self.0.lock().unwrap().reserve(bytes, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Feeds back whether the chunk had to wait for the buffer to drain
+    pub fn record(&self, blocked: bool, bytes: usize) {
+        self.0
+            .lock()
+            .unwrap()
+            .record(blocked, bytes, Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_and_ramps_up() {
+        let start = Instant::now();
+        let mut state = PacerState::new(start);
+        let chunk = 64 * 1024;
+
+        // A full window of blocked chunks in one second settles below that throughput
+        let second = start + Duration::from_secs(1);
+        state.record(true, chunk, start);
+        for _ in 1..WINDOW {
+            state.record(true, chunk, second);
+        }
+        let throughput = (WINDOW * chunk) as f64;
+        let rate = state.rate.unwrap();
+        assert_eq!(rate, throughput * DECREASE);
+
+        // The first chunk goes right away, the next one waits its turn
+        assert!(state.reserve(chunk, second).is_zero());
+        let wait = state.reserve(chunk, second);
+        assert_eq!(wait, Duration::from_secs_f64(chunk as f64 / rate));
+
+        // A calm window adds a bit back
+        for _ in 0..WINDOW {
+            state.record(false, chunk, second + Duration::from_secs(1));
+        }
+        assert_eq!(state.rate.unwrap(), rate + INCREASE);
+    }
+
+    #[test]
+    fn idle_time_isnt_a_slow_link() {
+        let start = Instant::now();
+        let mut state = PacerState::new(start);
+        let chunk = 64 * 1024;
+        let window = |state: &mut PacerState, from: Instant| {
+            state.record(true, chunk, from);
+            for _ in 1..WINDOW {
+                state.record(true, chunk, from + Duration::from_secs(1));
+            }
+        };
+
+        // A minute of handshake and prompts before the first chunk
+        let first = start + Duration::from_secs(60);
+        window(&mut state, first);
+        let rate = state.rate.unwrap();
+        assert_eq!(rate, (WINDOW * chunk) as f64 * DECREASE);
+
+        // Half a window, then a long gap between files
+        for _ in 0..WINDOW / 2 {
+            state.record(false, chunk, first + Duration::from_secs(2));
+        }
+        window(&mut state, first + Duration::from_secs(60));
+        assert_eq!(state.rate.unwrap(), rate * DECREASE); // Not anywhere near MIN_RATE
+    }
+}
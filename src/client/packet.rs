@@ -1,24 +1,41 @@
 use color_eyre::eyre::eyre;
 use rmpp::{MsgPackEntry, MsgPackValue};
 
+use crate::client::compress;
+
 #[derive(Clone, Debug)]
 pub struct Packet {
     pub id: usize,
     pub meta: bool,
     pub last: bool,
     pub binary: Vec<u8>,
+    /// CRC32 of the binary, only in checked frames
+    pub crc: Option<u32>,
+    /// Where the binary goes in the file, only in checked frames
+    pub offset: Option<usize>,
 }
 impl Packet {
     pub fn new(entry: MsgPackEntry) -> color_eyre::Result<Self> {
         let array: Vec<MsgPackEntry> = get_vec(&entry)?;
+        // Compressed frames name their algorithm last
+        let algo = array.get(6).map(get_u8).transpose()?;
+        let binary = get_bin32(&array[3])?;
 
         Ok(Self {
             id: get_u32(&array[0])? as usize,
             meta: get_bool(&array[1])?,
             last: get_bool(&array[2])?,
-            binary: get_bin32(&array[3])?,
+            binary: compress::unsqueeze(algo.unwrap_or(compress::RAW), binary)?,
+            crc: array.get(4).map(get_u32).transpose()?,
+            offset: array.get(5).map(get_u64).transpose()?.map(|n| n as usize),
         })
     }
+
+    /// Unchecked frames are taken at face value
+    pub fn is_intact(&self) -> bool {
+        self.crc
+            .is_none_or(|crc| crc == crc32fast::hash(&self.binary))
+    }
 }
 
 fn get_vec(msg: &MsgPackEntry) -> color_eyre::Result<Vec<MsgPackEntry>> {
@@ -28,6 +45,13 @@ fn get_vec(msg: &MsgPackEntry) -> color_eyre::Result<Vec<MsgPackEntry>> {
         Err(eyre!("Not a FixArray"))
     }
 }
+fn get_u8(msg: &MsgPackEntry) -> color_eyre::Result<u8> {
+    if let MsgPackValue::U8(n) = msg.data {
+        Ok(n)
+    } else {
+        Err(eyre!("Not a U8"))
+    }
+}
 fn get_u32(msg: &MsgPackEntry) -> color_eyre::Result<u32> {
     if let MsgPackValue::U32(n) = msg.data {
         Ok(n)
@@ -35,6 +59,13 @@ fn get_u32(msg: &MsgPackEntry) -> color_eyre::Result<u32> {
         Err(eyre!("Not a U64"))
     }
 }
+fn get_u64(msg: &MsgPackEntry) -> color_eyre::Result<u64> {
+    if let MsgPackValue::U64(n) = msg.data {
+        Ok(n)
+    } else {
+        Err(eyre!("Not a U64"))
+    }
+}
 fn get_bool(msg: &MsgPackEntry) -> color_eyre::Result<bool> {
     if let MsgPackValue::Bool(n) = msg.data {
         Ok(n)
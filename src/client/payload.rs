@@ -2,17 +2,25 @@ use bytes::Bytes;
 use rmpp::encode;
 use rmpp::types::{MsgPackEntry, MsgPackValue};
 use std::collections::VecDeque;
+use std::io::{self, SeekFrom};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::fs::File;
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
 use tokio::sync::mpsc::UnboundedSender;
-use tokio::sync::watch;
+use tokio::sync::{mpsc, watch};
+use tokio_util::io::StreamReader;
 use webrtc::data_channel::RTCDataChannel;
+use webrtc::{data, sctp};
 
 use crate::app::app_event::{AppEventClient, DebugDataChannel};
+use crate::app::archive::ArchiveSource;
 use crate::app::event::{BasicEvent, BasicEventSenderExt};
-use crate::app::file_manager::{FileProgressReport, OutputFile};
+use crate::app::file_manager::{FileProgressReport, OutputFile, ProgressThrottle};
+use crate::client::compress::{self, CompressAlgo};
+use crate::client::error::TappiError;
 use crate::client::message::Message;
+use crate::client::pacer::Pacer;
 
 // TODO: make overhead minimal, probably using something else than MessagePack
 /// Payload base length excluding the data
@@ -27,6 +35,22 @@ use crate::client::message::Message;
 ///
 /// Not the biggest overhead!
 pub const BASE_LENGTH: usize = 13;
+/// What `--chunk-crc` adds on top of the base length
+///
+/// crc_u32:    5
+/// offset_u64: 9
+///
+/// ----------> 14 bytes
+pub const CRC_LENGTH: usize = 14;
+/// What `--compress` adds on top of a checked frame
+///
+/// algo_u8:    2
+pub const COMPRESS_LENGTH: usize = 2;
+
+/// How many times a failed send is retried by default
+pub const DEFAULT_SEND_RETRIES: u32 = 3;
+/// Delay before the first retry, doubled after each one
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
 
 /// Creates a basic MsgPackEntry, primarily for testing
 #[allow(dead_code)]
@@ -48,17 +72,46 @@ fn get_base_length() -> usize {
     encode::pack(&get_base_entry()).len()
 }
 
+/// Compressed frames are always checked
+pub fn base_length(crc: bool, compress: bool) -> usize {
+    match (crc, compress) {
+        (_, true) => BASE_LENGTH + CRC_LENGTH + COMPRESS_LENGTH,
+        (true, false) => BASE_LENGTH + CRC_LENGTH,
+        (false, false) => BASE_LENGTH,
+    }
+}
+
 /// Packs MsgPackEntry into binary
-fn pack(id: u32, meta: bool, last: bool, chunk: Vec<u8>) -> Vec<u8> {
-    encode::pack(&MsgPackEntry::new(
-        0,
-        MsgPackValue::FixArray(vec![
-            MsgPackEntry::new(0, MsgPackValue::U32(id)),
-            MsgPackEntry::new(0, MsgPackValue::Bool(meta)),
-            MsgPackEntry::new(0, MsgPackValue::Bool(last)),
-            MsgPackEntry::new(0, MsgPackValue::Bin32(chunk)), // Both meta and data can be represented by binary
-        ]),
-    ))
+///
+/// With an offset the frame is checked, it also carries the chunk's CRC32 and where it goes.
+/// Only checked frames get compressed, the CRC is the one of the uncompressed chunk
+fn pack(
+    id: u32,
+    meta: bool,
+    last: bool,
+    chunk: Vec<u8>,
+    offset: Option<usize>,
+    compress: Option<CompressAlgo>,
+) -> Vec<u8> {
+    let check = offset.map(|offset| (crc32fast::hash(&chunk), offset));
+    let (algo, chunk) = match check {
+        Some(_) => compress::squeeze(compress, chunk),
+        None => (compress::RAW, chunk),
+    };
+    let mut entries = vec![
+        MsgPackEntry::new(0, MsgPackValue::U32(id)),
+        MsgPackEntry::new(0, MsgPackValue::Bool(meta)),
+        MsgPackEntry::new(0, MsgPackValue::Bool(last)),
+        MsgPackEntry::new(0, MsgPackValue::Bin32(chunk)), // Both meta and data can be represented by binary
+    ];
+    if let Some((crc, offset)) = check {
+        entries.push(MsgPackEntry::new(0, MsgPackValue::U32(crc)));
+        entries.push(MsgPackEntry::new(0, MsgPackValue::U64(offset as u64)));
+        if compress.is_some() {
+            entries.push(MsgPackEntry::new(0, MsgPackValue::U8(algo)));
+        }
+    }
+    encode::pack(&MsgPackEntry::new(0, MsgPackValue::FixArray(entries)))
 }
 
 pub async fn send_all_meta(
@@ -67,6 +120,7 @@ pub async fn send_all_meta(
     chunk_size: usize,
     buffer_watch_rx: &mut watch::Receiver<bool>,
     sender: Option<&UnboundedSender<BasicEvent>>,
+    retries: u32,
 ) -> color_eyre::Result<()> {
     for f in files {
         let meta_json = serde_json::to_string(&f.meta)?;
@@ -77,6 +131,7 @@ pub async fn send_all_meta(
             f.id as u32,
             buffer_size,
             buffer_watch_rx,
+            retries,
         )
         .await?;
 
@@ -100,22 +155,36 @@ pub async fn send_all_meta(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn send_file_data(
     dc: Arc<RTCDataChannel>,
     output_file: &OutputFile,
     chunk_size: usize,
+    crc: bool,
+    compress: Option<CompressAlgo>,
     buffer_watch_rx: &mut watch::Receiver<bool>,
+    pause_rx: &mut watch::Receiver<bool>,
+    pacer: Option<&Pacer>,
     sender: Option<&UnboundedSender<BasicEvent>>,
+    throttle: ProgressThrottle,
+    retries: u32,
 ) -> color_eyre::Result<()> {
-    let mut file = File::open(&output_file.meta.path).await?;
-    let buffer_size = chunk_size - BASE_LENGTH;
+    let buffer_size = chunk_size - base_length(crc, compress.is_some());
+    let mut data = open_data(output_file, output_file.offset).await?;
     send_data(
         dc.clone(),
         output_file,
-        &mut file,
+        &mut data,
         buffer_size,
+        output_file.offset,
+        crc || compress.is_some(),
+        compress,
         buffer_watch_rx,
+        pause_rx,
+        pacer,
         sender,
+        throttle,
+        retries,
     )
     .await?;
 
@@ -137,55 +206,139 @@ pub async fn send_file_data(
     Ok(())
 }
 
+/// Sends the rest of a file again after the peer found a corrupt chunk at `offset`
+///
+/// Doesn't report anything, the file already counts as sent
+#[allow(clippy::too_many_arguments)]
+pub async fn resend_file_data(
+    dc: Arc<RTCDataChannel>,
+    output_file: &OutputFile,
+    offset: usize,
+    chunk_size: usize,
+    compress: Option<CompressAlgo>,
+    buffer_watch_rx: &mut watch::Receiver<bool>,
+    pause_rx: &mut watch::Receiver<bool>,
+    pacer: Option<&Pacer>,
+    retries: u32,
+) -> color_eyre::Result<()> {
+    let buffer_size = chunk_size - base_length(true, compress.is_some());
+    let mut data = open_data(output_file, offset).await?;
+    send_data(
+        dc,
+        output_file,
+        &mut data,
+        buffer_size,
+        offset,
+        true,
+        compress,
+        buffer_watch_rx,
+        pause_rx,
+        pacer,
+        None,
+        ProgressThrottle::default(),
+        retries,
+    )
+    .await
+}
+
+/// File data from `offset` on, an archive entry's byte range is read straight from the archive
+async fn open_data(
+    output_file: &OutputFile,
+    offset: usize,
+) -> color_eyre::Result<Box<dyn AsyncRead + Unpin + Send>> {
+    if let Some(source) = &output_file.source
+        && source.deflated.is_some()
+    {
+        let size = output_file.meta.size;
+        return Ok(Box::new(inflate(source.clone(), size, offset)));
+    }
+    let (path, start) = match &output_file.source {
+        Some(source) => (&source.archive, source.offset),
+        None => (&output_file.meta.path, 0),
+    };
+    let mut file = File::open(path).await.map_err(TappiError::file_io(path))?;
+    file.seek(SeekFrom::Start(start + offset as u64)).await?;
+    Ok(Box::new(
+        file.take(output_file.meta.size.saturating_sub(offset) as u64),
+    ))
+}
+
+/// Deflated zip entries only come out of zip's blocking reader, its errors make it through
+fn inflate(source: ArchiveSource, size: usize, offset: usize) -> impl AsyncRead + Unpin + Send {
+    let (tx, mut rx) = mpsc::channel::<io::Result<Bytes>>(4);
+    tokio::task::spawn_blocking(move || {
+        let read = source.read(size, offset, |entry| {
+            let mut buf = vec![0u8; 64 * 1024];
+            loop {
+                let n = entry.read(&mut buf)?;
+                // Nobody's listening once the transfer stops
+                if n == 0
+                    || tx
+                        .blocking_send(Ok(Bytes::copy_from_slice(&buf[..n])))
+                        .is_err()
+                {
+                    return Ok(());
+                }
+            }
+        });
+        if let Err(err) = read {
+            tx.blocking_send(Err(err)).ok();
+        }
+    });
+    StreamReader::new(futures::stream::poll_fn(move |cx| rx.poll_recv(cx)))
+}
+
 async fn send_meta_string(
     dc: Arc<RTCDataChannel>,
     meta_json: &String,
     file_id: u32,
     buffer_size: usize,
     buffer_watch_rx: &mut watch::Receiver<bool>,
+    retries: u32,
 ) -> color_eyre::Result<()> {
-    let bytes: &[u8] = meta_json.as_bytes();
-    let string_size: usize = bytes.len();
-    let mut counter: usize = 0;
+    // Only the last piece is marked as such, no matter how many there are
+    let chunks = meta_json.as_bytes().chunks(buffer_size);
+    let count = chunks.len();
+    for (i, chunk) in chunks.enumerate() {
+        let packed = pack(file_id, true, i + 1 == count, chunk.to_vec(), None, None);
 
-    loop {
-        if counter < string_size {
-            let borrow_size: usize = buffer_size.min(string_size);
-            let new_counter: usize = counter + borrow_size;
-            let chunk = &bytes[counter..new_counter];
-
-            let packed = pack(file_id, true, borrow_size >= string_size, chunk.to_vec());
-
-            // Send chunk
-            send_binary(dc.clone(), buffer_watch_rx, &packed).await?;
-
-            counter = new_counter;
-        } else {
-            break;
-        }
+        // Send chunk
+        send_binary(dc.clone(), buffer_watch_rx, &packed, retries).await?;
     }
 
     Ok(())
 }
 
-async fn send_data(
+#[allow(clippy::too_many_arguments)]
+async fn send_data<R: AsyncRead + Unpin>(
     dc: Arc<RTCDataChannel>,
     output_file: &OutputFile,
-    file: &mut File,
+    file: &mut R,
     buffer_size: usize,
+    start: usize, // Where in the file the reader is
+    checked: bool,
+    compress: Option<CompressAlgo>,
     buffer_watch_rx: &mut watch::Receiver<bool>,
+    pause_rx: &mut watch::Receiver<bool>,
+    pacer: Option<&Pacer>,
     sender: Option<&UnboundedSender<BasicEvent>>,
+    mut throttle: ProgressThrottle,
+    retries: u32,
 ) -> color_eyre::Result<()> {
     let mut buf = vec![0u8; buffer_size];
-    let mut counter: usize = 0;
+    let mut counter: usize = start;
     let file_size = output_file.meta.size;
 
     loop {
+        // A dropped gate doesn't hold anything back
+        pause_rx.wait_for(|paused| !paused).await.ok();
+
         let n = file.read(&mut buf).await?;
         if n == 0 {
             break;
         } // EOF
 
+        let chunk_offset = checked.then_some(counter);
         counter += n;
 
         let chunk = &buf[..n];
@@ -194,13 +347,23 @@ async fn send_data(
             false,
             counter >= file_size,
             chunk.to_vec(),
+            chunk_offset,
+            compress,
         );
 
-        // Send chunk
-        send_binary(dc.clone(), buffer_watch_rx, &packed).await?;
+        // Send chunk, a full buffer tells the pacer the link is busy
+        if let Some(pacer) = pacer {
+            pacer.pace(packed.len()).await;
+        }
+        let blocked = send_binary(dc.clone(), buffer_watch_rx, &packed, retries).await?;
+        if let Some(pacer) = pacer {
+            pacer.record(blocked, packed.len());
+        }
 
         // Report back
-        if let Some(sender) = sender {
+        if let Some(sender) = sender
+            && throttle.ready(n)
+        {
             let progress = ((counter as f64) / (file_size as f64)).clamp(0.0, 0.99); // I don't want it to show a 100 before it reaches it
             sender
                 .send_event(AppEventClient::OutputFileProgress(FileProgressReport::new(
@@ -224,32 +387,146 @@ pub async fn send_message(
     dc.send_text(message_json).await?;
     Ok(())
 }
+/// Sends a packet, retrying with a backoff on errors that might go away
+///
+/// Returns whether it had to wait for the buffer first
 async fn send_binary(
     dc: Arc<RTCDataChannel>,
     buffer_watch_rx: &mut watch::Receiver<bool>,
     binary: &[u8],
-) -> color_eyre::Result<()> {
-    await_threshold(dc.clone(), buffer_watch_rx).await?;
-    dc.send(&Bytes::copy_from_slice(binary)).await?;
-    Ok(())
+    retries: u32,
+) -> color_eyre::Result<bool> {
+    let bytes = Bytes::copy_from_slice(binary);
+    let mut attempt: u32 = 0;
+    let mut blocked = false;
+    loop {
+        blocked |= await_threshold(dc.clone(), buffer_watch_rx).await?;
+        match dc.send(&bytes).await {
+            Ok(_) => return Ok(blocked),
+            Err(err) if attempt < retries && is_transient(&err) => {
+                let delay = RETRY_BACKOFF * 2u32.pow(attempt.min(6));
+                attempt += 1;
+                log::warn!("Send failed ({}), retry {} in {:?}", err, attempt, delay);
+                tokio::time::sleep(delay).await;
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Whether a failed send is worth another try, only the errors known to pass are
+///
+/// The association not being established (yet or again) and SCTP asking for another go
+fn is_transient(err: &webrtc::Error) -> bool {
+    let transient_sctp = |err: &sctp::Error| {
+        matches!(
+            err,
+            sctp::Error::ErrPayloadDataStateNotExist | sctp::Error::ErrTryAgain
+        )
+    };
+    match err {
+        webrtc::Error::Data(data::Error::Sctp(err)) | webrtc::Error::Sctp(err) => {
+            transient_sctp(err)
+        }
+        _ => false,
+    }
 }
 
+/// Returns whether the buffer was over the threshold
 async fn await_threshold(
     dc: Arc<RTCDataChannel>,
     buffer_watch_rx: &mut watch::Receiver<bool>,
-) -> color_eyre::Result<()> {
+) -> color_eyre::Result<bool> {
     if dc.buffered_amount().await > dc.buffered_amount_low_threshold().await {
         buffer_watch_rx.changed().await?; // Await a change of any kind
+        return Ok(true);
     }
-    Ok(())
+    Ok(false)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::client::packet::Packet;
+    use clap::ValueEnum;
 
     #[test]
     fn ensure_length() {
         assert_eq!(get_base_length(), BASE_LENGTH);
     }
+
+    #[test]
+    fn crc_length() {
+        let plain = pack(0, false, false, vec![], None, None).len();
+        let checked = pack(0, false, false, vec![], Some(0), None).len();
+        assert_eq!(checked - plain, CRC_LENGTH);
+        // The offset is always packed as a u64, the size doesn't depend on it
+        let far = pack(0, false, false, vec![], Some(usize::MAX), None).len();
+        assert_eq!(far, checked);
+        // An empty chunk doesn't shrink, it goes out raw but still names an algorithm
+        let compressed = pack(0, false, false, vec![], Some(0), Some(CompressAlgo::Gzip)).len();
+        assert_eq!(compressed - checked, COMPRESS_LENGTH);
+    }
+
+    #[test]
+    fn corrupt_chunks_are_detected() {
+        let unpack = |bytes: &[u8]| Packet::new(rmpp::unpack(bytes).unwrap()).unwrap();
+
+        let mut packed = pack(7, false, true, b"some file data".to_vec(), Some(42), None);
+        let packet = unpack(&packed);
+        assert!(packet.is_intact());
+        assert_eq!(packet.offset, Some(42));
+
+        // Flip a bit of the data, it sits right before the crc and the offset
+        let data_end = packed.len() - CRC_LENGTH - 1;
+        packed[data_end] ^= 0x01;
+        assert!(!unpack(&packed).is_intact());
+
+        // Unchecked frames have nothing to compare against
+        let plain = pack(7, false, true, b"some file data".to_vec(), None, None);
+        assert!(unpack(&plain).is_intact());
+    }
+
+    #[test]
+    fn compressed_round_trip() {
+        let unpack = |bytes: &[u8]| Packet::new(rmpp::unpack(bytes).unwrap()).unwrap();
+        let chunk = b"tappi ".repeat(100);
+
+        for algo in CompressAlgo::value_variants() {
+            let packed = pack(3, false, true, chunk.clone(), Some(0), Some(*algo));
+            assert!(packed.len() < chunk.len());
+            let packet = unpack(&packed);
+            assert!(packet.is_intact());
+            assert_eq!(packet.binary, chunk);
+
+            // Whatever doesn't shrink goes out raw
+            let noise: Vec<u8> = (0..64u32)
+                .map(|n| (n.wrapping_mul(2654435761) >> 24) as u8)
+                .collect();
+            let packed = pack(3, false, true, noise.clone(), Some(0), Some(*algo));
+            assert_eq!(unpack(&packed).binary, noise);
+        }
+    }
+
+    #[test]
+    fn only_known_errors_are_retried() {
+        assert!(!is_transient(&webrtc::Error::ErrClosedPipe));
+        assert!(!is_transient(&webrtc::Error::Sctp(
+            sctp::Error::ErrStreamClosed
+        )));
+        assert!(!is_transient(&webrtc::Error::Data(data::Error::Sctp(
+            sctp::Error::ErrOutboundPacketTooLarge
+        ))));
+        assert!(is_transient(&webrtc::Error::Sctp(
+            sctp::Error::ErrPayloadDataStateNotExist
+        )));
+        assert!(is_transient(&webrtc::Error::Data(data::Error::Sctp(
+            sctp::Error::ErrTryAgain
+        ))));
+        // Anything unknown gives up
+        assert!(!is_transient(&webrtc::Error::ErrUnknownType));
+        assert!(!is_transient(&webrtc::Error::Sctp(
+            sctp::Error::ErrChecksumMismatch
+        )));
+    }
 }
@@ -0,0 +1,167 @@
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, Read};
+
+use crate::app::app_event::AppEventClient;
+use crate::app::event::BasicEventSenderExt;
+use crate::app::file_manager::{FileId, MetaData, OutputFile};
+use crate::app::models::Maid;
+
+/// Mismatches listed in the summary, the log has all of them
+pub const SHOWN_MISMATCHES: usize = 3;
+
+/// What the receiver ended up with for a single file
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReceiptEntry {
+    pub id: FileId,
+    pub size: usize,
+    /// CRC32 of everything written, missing for resumed files and the web receiver
+    pub crc: Option<u32>,
+}
+impl ReceiptEntry {
+    pub fn new(id: FileId, meta: &MetaData) -> Self {
+        Self {
+            id,
+            size: meta.progress_bytes,
+            crc: meta.crc,
+        }
+    }
+}
+
+/// `--receipt` exchange of a session
+#[derive(Clone, Debug, Default)]
+pub enum ReceiptState {
+    #[default]
+    Idle,
+    /// Asked the receiver, or already checking its answer
+    Pending,
+    Checked(Verdict),
+}
+
+/// How the receipt compares to what was sent
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Verdict {
+    pub matched: usize,
+    pub total: usize,
+    /// One line for every file that doesn't match
+    pub mismatches: Vec<String>,
+}
+impl Verdict {
+    /// Files without a CRC on the receipt only get their size compared
+    pub fn check(files: &[OutputFile], receipt: &[ReceiptEntry]) -> Self {
+        let mut verdict = Self::default();
+        for file in files.iter().filter(|f| !f.meta.is_dir) {
+            verdict.total += 1;
+            let name = file.meta.get_path().display().to_string();
+            let mismatch = match receipt.iter().find(|entry| entry.id == file.id) {
+                None => Some(format!("{}: never arrived", name)),
+                Some(entry) if entry.size != file.meta.size => Some(format!(
+                    "{}: {} of {} bytes",
+                    name, entry.size, file.meta.size
+                )),
+                Some(ReceiptEntry { crc: Some(crc), .. }) => match checksum(file) {
+                    Ok(ours) if ours == *crc => None,
+                    Ok(_) => Some(format!("{}: contents differ", name)),
+                    Err(err) => Some(format!("{}: couldn't read it back, {}", name, err)),
+                },
+                Some(_) => None,
+            };
+            match mismatch {
+                Some(mismatch) => verdict.mismatches.push(mismatch),
+                None => verdict.matched += 1,
+            }
+        }
+        verdict
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+
+    /// "verified: 40/40 files match"
+    pub fn text(&self) -> String {
+        let label = if self.is_ok() { "verified" } else { "mismatch" };
+        format!("{}: {}/{} files match", label, self.matched, self.total)
+    }
+}
+
+/// Hashes the sent files again on a blocking thread, results in a `ReceiptChecked` event
+pub fn check_receipt(maid: Maid, files: Vec<OutputFile>, receipt: Vec<ReceiptEntry>) {
+    tokio::spawn(async move {
+        let token = maid.token.child_token();
+        let check = tokio::task::spawn_blocking(move || Verdict::check(&files, &receipt));
+        tokio::select! {
+            _ = token.cancelled() => {},
+            result = check => match result {
+                Ok(verdict) => {
+                    maid.event_tx.send_event(AppEventClient::ReceiptChecked(verdict)).await;
+                }
+                Err(err) => maid.error_tx.send_error(eyre!(err)),
+            },
+        }
+    });
+}
+
+/// CRC32 of the data the way it was sent, archive entries are read out of the archive
+fn checksum(file: &OutputFile) -> io::Result<u32> {
+    let hash = |reader: &mut dyn Read| {
+        let mut hasher = crc32fast::Hasher::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        Ok(hasher.finalize())
+    };
+    match &file.source {
+        Some(source) => source.read(file.meta.size, 0, hash),
+        None => hash(&mut File::open(&file.meta.path)?.take(file.meta.size as u64)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::file_manager::FileManager;
+    use crate::cli::SendOrder;
+    use crate::test_util::TempDir;
+    use std::fs;
+
+    #[test]
+    fn receipt_against_sent_files() {
+        let dir = TempDir::new("receipt");
+        let data = b"tappi tappi tappi";
+        for name in ["a", "b", "c", "d"] {
+            fs::write(dir.join(name), data).unwrap();
+        }
+        let mut file_manager = FileManager::new(false, 10, SendOrder::AsGiven);
+        let paths: Vec<_> = ["a", "b", "c", "d"].iter().map(|n| dir.join(n)).collect();
+        file_manager.add_output_files(&paths).unwrap();
+        let files: Vec<OutputFile> = file_manager.output_map.values().cloned().collect();
+
+        // The receiver hashes chunk by chunk
+        let mut hasher = crc32fast::Hasher::new_with_initial(crc32fast::hash(&data[..5]));
+        hasher.update(&data[5..]);
+        let crc = hasher.finalize();
+        assert_eq!(crc, crc32fast::hash(data));
+
+        let entry = |i: usize, size, crc| ReceiptEntry {
+            id: files[i].id,
+            size,
+            crc,
+        };
+        let receipt = vec![
+            entry(0, data.len(), Some(crc)),
+            entry(1, data.len(), None),
+            entry(2, data.len(), Some(crc ^ 1)),
+        ];
+        let verdict = Verdict::check(&files, &receipt);
+        assert_eq!((verdict.matched, verdict.total), (2, 4));
+        assert!(verdict.mismatches[0].ends_with("contents differ"));
+        assert!(verdict.mismatches[1].ends_with("never arrived"));
+    }
+}
@@ -0,0 +1,233 @@
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::app::file_manager::{FileId, FileStatus, InputFile, MetaData, ProgressFile};
+use crate::client::error::TappiError;
+use crate::client::message::{append_ext, append_part_ext};
+
+/// Lives in the output directory, next to the files it describes
+pub const STATE_FILE: &str = ".tappi-session.json";
+/// The state file gets rewritten at most this often while data flows
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Next to every part file while it's being written, says which file the part file was started for
+///
+/// The sender doesn't announce a hash of its files, so a changed file of the same size and path
+/// still matches, only a different one is told apart
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PartMeta {
+    /// Same as `MetaData::get_path`
+    pub path: PathBuf,
+    pub size: usize,
+}
+impl PartMeta {
+    pub fn new(meta: &MetaData) -> Self {
+        Self {
+            path: meta.get_path(),
+            size: meta.size,
+        }
+    }
+
+    /// "foo.txt.part.meta" next to "foo.txt.part"
+    pub fn path_for(part: &Path) -> PathBuf {
+        append_ext("meta", part.to_path_buf())
+    }
+
+    pub fn save(&self, part: &Path) -> color_eyre::Result<()> {
+        let path = Self::path_for(part);
+        fs::write(&path, serde_json::to_string_pretty(self)?)
+            .map_err(TappiError::file_io(&path))?;
+        Ok(())
+    }
+
+    /// Whether the part file was started for a file of this path and size, no sidecar means it can't be told
+    pub fn matches(part: &Path, meta: &MetaData) -> bool {
+        fs::read_to_string(Self::path_for(part))
+            .ok()
+            .and_then(|json| serde_json::from_str::<Self>(&json).ok())
+            .is_some_and(|saved| saved == Self::new(meta))
+    }
+
+    /// Removes the part file and its sidecar, either one might not be there
+    pub fn discard(part: &Path) -> color_eyre::Result<()> {
+        for path in [part.to_path_buf(), Self::path_for(part)] {
+            match fs::remove_file(&path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                    return Err(TappiError::file_io(&path)(err).into());
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A file the receiver didn't finish
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ResumeEntry {
+    /// Same as `MetaData::get_path`
+    pub path: PathBuf,
+    pub size: usize,
+    /// Bytes written when the state was saved, the part file might be further along
+    pub offset: usize,
+}
+
+/// What the receiver had of an unfinished transfer, `--resume` picks it up after a restart
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ResumeState {
+    pub files: Vec<ResumeEntry>,
+}
+impl ResumeState {
+    pub fn path(output_dir: &Path) -> PathBuf {
+        output_dir.join(STATE_FILE)
+    }
+
+    /// A missing or broken state just means there's nothing to resume
+    pub fn load(output_dir: &Path) -> Self {
+        let path = Self::path(output_dir);
+        let Ok(json) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        match serde_json::from_str(&json) {
+            Ok(state) => state,
+            Err(err) => {
+                log::warn!("Ignoring the broken session state {:?}: {}", path, err);
+                Self::default()
+            }
+        }
+    }
+
+    /// The files that still have data coming
+    pub fn from_input_map(input_map: &IndexMap<FileId, InputFile>) -> Self {
+        let files = input_map
+            .values()
+            .filter(|f| !f.get_finished() && f.status != FileStatus::Skipped)
+            .map(|f| ResumeEntry {
+                path: f.meta.get_path(),
+                size: f.meta.size,
+                offset: ((f.meta.size as f64) * f.progress) as usize,
+            })
+            .collect();
+        Self { files }
+    }
+
+    /// Through a temporary file so it's never partial, nothing left to resume removes it
+    pub fn save(&self, output_dir: &Path) -> color_eyre::Result<()> {
+        let path = Self::path(output_dir);
+        if self.files.is_empty() {
+            if path.exists() {
+                fs::remove_file(&path).map_err(TappiError::file_io(&path))?;
+            }
+            return Ok(());
+        }
+
+        let temporary = append_ext("tmp", path.clone());
+        fs::write(&temporary, serde_json::to_string_pretty(self)?)
+            .map_err(TappiError::file_io(&temporary))?;
+        fs::rename(&temporary, &path).map_err(TappiError::file_io(&path))?;
+        Ok(())
+    }
+
+    /// Bytes of the file that are already on disk, only for files the state knows about
+    ///
+    /// The part file is the one to trust, the saved offset lags behind it
+    pub fn offset_for(&self, output_dir: &Path, meta: &MetaData) -> Option<usize> {
+        let path = meta.get_path();
+        self.files
+            .iter()
+            .find(|entry| entry.path == path && entry.size == meta.size)?;
+
+        let part = append_part_ext(output_dir.join(path));
+        let written = fs::metadata(part).ok()?.len() as usize;
+        // A complete part file has no last chunk left to finish it with
+        (written > 0 && written < meta.size).then_some(written)
+    }
+}
+
+/// Keeps the receiver's state file up to date without writing it for every chunk
+#[derive(Debug)]
+pub struct ResumeJournal {
+    output_dir: PathBuf,
+    saved: Option<Instant>,
+}
+impl ResumeJournal {
+    pub fn new(output_dir: PathBuf) -> Self {
+        Self {
+            output_dir,
+            saved: None,
+        }
+    }
+
+    /// Saves the unfinished files, `force` skips the rate limit
+    pub fn update(
+        &mut self,
+        input_map: &IndexMap<FileId, InputFile>,
+        force: bool,
+    ) -> color_eyre::Result<()> {
+        if !force
+            && self
+                .saved
+                .is_some_and(|saved| saved.elapsed() < SAVE_INTERVAL)
+        {
+            return Ok(());
+        }
+        self.saved = Some(Instant::now());
+        ResumeState::from_input_map(input_map).save(&self.output_dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn resumes_known_part_files() {
+        let dir = TempDir::new("resume");
+        let meta = MetaData::new(Path::new("a.bin"), 10, None, false);
+
+        let mut input_map = IndexMap::new();
+        let mut file = InputFile::new(0, meta.clone());
+        file.set_progress(0.3);
+        input_map.insert(0, file);
+        ResumeState::from_input_map(&input_map).save(&dir).unwrap();
+
+        // The part file is further along than the saved offset
+        fs::write(dir.join("a.bin.part"), [0u8; 4]).unwrap();
+        let state = ResumeState::load(&dir);
+        assert_eq!(state.files[0].offset, 3);
+        assert_eq!(state.offset_for(&dir, &meta), Some(4));
+
+        // A different file under the same name starts over
+        let other = MetaData::new(Path::new("a.bin"), 11, None, false);
+        assert_eq!(state.offset_for(&dir, &other), None);
+
+        // Nothing unfinished, nothing to keep
+        input_map[0].set_progress(1.0);
+        ResumeState::from_input_map(&input_map).save(&dir).unwrap();
+        assert!(!ResumeState::path(&dir).exists());
+    }
+
+    #[test]
+    fn part_meta_names_its_file() {
+        let dir = TempDir::new("part-meta");
+        let meta = MetaData::new(Path::new("a.bin"), 10, None, false);
+        let part = dir.join("a.bin.part");
+        fs::write(&part, [0u8; 4]).unwrap();
+        assert!(!PartMeta::matches(&part, &meta)); // Nothing to go by
+
+        PartMeta::new(&meta).save(&part).unwrap();
+        assert!(dir.join("a.bin.part.meta").exists());
+        assert!(PartMeta::matches(&part, &meta));
+        let other = MetaData::new(Path::new("a.bin"), 11, None, false);
+        assert!(!PartMeta::matches(&part, &other));
+
+        PartMeta::discard(&part).unwrap();
+        assert!(!part.exists() && !PartMeta::path_for(&part).exists());
+        PartMeta::discard(&part).unwrap(); // Already gone is fine
+    }
+}
@@ -1,35 +1,93 @@
 use color_eyre::eyre::eyre;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::{Mutex, watch};
+use uuid::Uuid;
 use webrtc::api::APIBuilder;
 use webrtc::data_channel::RTCDataChannel;
 use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
+use webrtc::ice_transport::ice_candidate_type::RTCIceCandidateType;
 use webrtc::ice_transport::ice_connection_state::RTCIceConnectionState;
 use webrtc::ice_transport::ice_gatherer_state::RTCIceGathererState;
+use webrtc::ice_transport::ice_gathering_state::RTCIceGatheringState;
 use webrtc::ice_transport::ice_server::RTCIceServer;
 use webrtc::peer_connection::RTCPeerConnection;
 use webrtc::peer_connection::configuration::RTCConfiguration;
 use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::policy::ice_transport_policy::RTCIceTransportPolicy;
 
-use crate::app::app_event::{AppEventClient, DebugDataChannel};
+use crate::app::app_event::{AppEventClient, DebugDataChannel, DisconnectKind};
 use crate::app::event::BasicEvent;
 use crate::app::event::BasicEventSenderExt;
-use crate::app::file_manager::MetaData;
+use crate::app::file_manager::{MetaData, ProgressThrottle};
 use crate::app::models::{ErrorTX, Maid};
-use crate::cli::ClientArgs;
-use crate::client::message::handle_message;
+use crate::cli::{ClientArgs, IceTransportPolicy, MIN_CHUNK_SIZE};
+use crate::client::error::TappiError;
+use crate::client::heartbeat::Heartbeat;
+use crate::client::message::{Message, ReceiveOptions, ReceiveState, handle_message};
+use crate::client::pacer::Pacer;
+use crate::client::payload::send_message;
+use crate::client::resume::ResumeState;
+use crate::client::turn::TurnAuth;
 
 /// File output KiB threshold
 // I'm fighting the urge to make it 640K
 const THRESHOLD: usize = 128 * 1024; // 128KB sounds reasonable enough
 
+/// Bump it whenever the frame format changes, peers on different versions refuse to talk
+pub const PROTOCOL_VERSION: u32 = 2;
+pub const DEFAULT_CHANNEL_LABEL: &str = "data";
+pub const DEFAULT_CHANNEL_ID: u16 = 0;
+/// Longest a goodbye gets to go out before the connection closes anyway
+pub const HANG_UP_TIMEOUT: Duration = Duration::from_millis(500);
+/// What a peer takes when its SDP doesn't say
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+pub fn protocol_name() -> String {
+    format!("tappi/{}", PROTOCOL_VERSION)
+}
+
+/// Pre-negotiated data channel parameters, both peers have to agree on them
+#[derive(Clone, Debug)]
+pub struct ChannelSettings {
+    pub label: String,
+    pub id: u16,
+}
+impl Default for ChannelSettings {
+    fn default() -> Self {
+        Self {
+            label: DEFAULT_CHANNEL_LABEL.to_string(),
+            id: DEFAULT_CHANNEL_ID,
+        }
+    }
+}
+
 /// Manages WebRTC and signaling
 #[derive(Clone, Debug)]
 pub struct WebConnection {
     pub pc: Arc<RTCPeerConnection>,
     pub buffer_watch_tx: watch::Sender<bool>,
+    /// File data is held back while it's true
+    pub pause_tx: watch::Sender<bool>,
+    /// Where `--staging` receives into, lives inside the output directory so the moves are renames
+    pub staging_dir: Option<PathBuf>,
+    /// Paces the file data with `--fair`
+    pub pacer: Option<Pacer>,
+    /// Hears everything the peer sends, only pings once it's spawned
+    pub heartbeat: Heartbeat,
+    /// How often the files being sent report their progress
+    pub throttle: ProgressThrottle,
+    /// True while the connection is lost, `--ice-restart` watches it
+    pub lost_tx: watch::Sender<bool>,
+    /// True once either side hung up on purpose, losing the connection is expected then
+    pub closing_tx: watch::Sender<bool>,
+    /// Largest message the peer takes, from its SDP once connected
+    pub max_message_tx: watch::Sender<Option<usize>>,
+    /// Files being received, their part files are only written while it's locked
+    pub metadata_map: Arc<Mutex<HashMap<usize, MetaData>>>,
 }
 impl WebConnection {
     pub async fn init(maid: Maid, args: ClientArgs) -> color_eyre::Result<()> {
@@ -41,15 +99,70 @@ impl WebConnection {
     }
 
     pub async fn new(maid: Maid, args: &ClientArgs) -> color_eyre::Result<Self> {
-        let config = Self::conf(
-            args.username.clone(),
-            args.credential.clone(),
-            &mut args.additional_servers.clone(),
-        );
+        // Time-limited credentials are made right before they're needed
+        let (username, credential) = match TurnAuth::from_args(args) {
+            Some(auth) => {
+                let (username, credential) = auth.credentials(SystemTime::now())?;
+                (Some(username), Some(credential))
+            }
+            None => (args.username.clone(), args.credential.clone()),
+        };
+        let mut config = Self::conf(username, credential, &mut args.additional_servers.clone());
+        config.ice_transport_policy = match args.ice_transport_policy {
+            IceTransportPolicy::All => RTCIceTransportPolicy::All,
+            // Would just never connect otherwise
+            IceTransportPolicy::Relay if !has_turn(&config) => {
+                return Err(eyre!(
+                    "--ice-transport-policy relay needs a TURN server, add one with --additional-servers"
+                ));
+            }
+            IceTransportPolicy::Relay => RTCIceTransportPolicy::Relay,
+        };
+        let channel = ChannelSettings {
+            label: args.channel_label.clone(),
+            id: args.channel_id,
+        };
+        let staging_dir = args.staging.then(|| {
+            args.output_dir
+                .join(format!(".tappi-staging-{}", Uuid::new_v4()))
+        });
+        if let Some(dir) = &staging_dir {
+            log::info!("Staging the received files in {:?}", dir);
+        }
+
+        let receive_dir = staging_dir.clone().unwrap_or(args.output_dir.clone());
+        // Absolute so joining it onto the output directory doesn't change anything
+        let output_file = args
+            .output
+            .as_deref()
+            .map(std::path::absolute)
+            .transpose()?;
+        let options = ReceiveOptions {
+            output_file,
+            resume: args.resume.then(|| ResumeState::load(&args.output_dir)),
+            prompt_accept: args.prompt_accept,
+            throttle: args.progress_throttle(),
+            strict: args.strict,
+            staging: staging_dir.is_some(),
+        };
+        let mut wc = Self::with_config(maid, config, channel, receive_dir, options).await?;
+        wc.staging_dir = staging_dir;
+        wc.pacer = args.fair.then(Pacer::default);
+        Ok(wc)
+    }
 
+    pub async fn with_config(
+        maid: Maid,
+        config: RTCConfiguration,
+        channel: ChannelSettings,
+        output_dir: PathBuf,
+        options: ReceiveOptions,
+    ) -> color_eyre::Result<Self> {
+        // Negotiated channels don't exchange the protocol, it's checked with a hello instead
         let dc_init = RTCDataChannelInit {
-            negotiated: Some(0),
+            negotiated: Some(channel.id),
             ordered: Some(true),
+            protocol: Some(protocol_name()),
             ..Default::default()
         };
 
@@ -60,18 +173,41 @@ impl WebConnection {
 
         // Create a data and message channel, ordered by default
         // Let's use pre-negotiated channels since the clients are simplistic and completely symmetrical
-        let dc = pc.create_data_channel("data", Some(dc_init)).await?;
+        let dc = pc
+            .create_data_channel(&channel.label, Some(dc_init))
+            .await?;
         dc.set_buffered_amount_low_threshold(THRESHOLD).await;
 
         // Attach handlers
         let buffer_watch_tx = watch::channel(true).0;
         attach_buffer_handler(dc.clone(), buffer_watch_tx.clone()).await;
-        attach_connection_handler(pc.clone(), maid.event_tx.clone(), maid.error_tx.clone());
-        attach_channel_open_handler(dc.clone(), maid.event_tx.clone());
+        let lost_tx = watch::channel(false).0;
+        let closing_tx = watch::channel(false).0;
+        let max_message_tx = watch::channel(None).0;
+        attach_connection_handler(
+            pc.clone(),
+            maid.event_tx.clone(),
+            maid.error_tx.clone(),
+            lost_tx.clone(),
+            closing_tx.subscribe(),
+            max_message_tx.clone(),
+        );
+        attach_channel_open_handler(
+            dc.clone(),
+            maid.event_tx.clone(),
+            maid.error_tx.clone(),
+            buffer_watch_tx.subscribe(),
+        );
 
         // Attach on message method
+        let heartbeat = Heartbeat::default();
+        let throttle = options.throttle;
+        let receiving = ReceiveState::new(output_dir, options);
+        let metadata_map = receiving.metadata_map.clone();
         on_message(
             dc.clone(),
+            heartbeat.clone(),
+            receiving,
             maid.error_tx.clone(),
             buffer_watch_tx.subscribe(),
             maid.event_tx.clone(),
@@ -80,10 +216,49 @@ impl WebConnection {
         Ok(Self {
             pc,
             buffer_watch_tx,
+            pause_tx: watch::channel(false).0,
+            staging_dir: None,
+            pacer: None,
+            heartbeat,
+            throttle,
+            lost_tx,
+            closing_tx,
+            max_message_tx,
+            metadata_map,
         })
     }
 
-    fn conf(
+    /// `chunk_size` shrunk to what the peer takes in a single message
+    pub fn fit_chunk(&self, chunk_size: usize) -> usize {
+        fit_chunk(chunk_size, self.max_message())
+    }
+    /// Tells the peer first if there's a channel to tell it through, it'd take the hang up for a lost connection otherwise
+    pub async fn hang_up(&self, dc: Option<Arc<RTCDataChannel>>) {
+        self.closing_tx.send_replace(true);
+        if let Some(dc) = dc {
+            let goodbye = serde_json::to_string(&Message::Goodbye).unwrap_or_default();
+            match dc.send_text(goodbye).await {
+                Ok(_) => {
+                    // Closing drops whatever is still buffered
+                    let flushed = async {
+                        while dc.buffered_amount().await > 0 {
+                            tokio::time::sleep(Duration::from_millis(10)).await;
+                        }
+                    };
+                    tokio::time::timeout(HANG_UP_TIMEOUT, flushed).await.ok();
+                }
+                Err(err) => log::debug!("Couldn't say goodbye: {}", err),
+            }
+        }
+        self.pc.close().await.ok();
+    }
+
+    /// What the peer's SDP says it takes, none if there's no limit
+    pub fn max_message(&self) -> Option<usize> {
+        *self.max_message_tx.borrow()
+    }
+
+    pub fn conf(
         username: Option<String>,
         credential: Option<String>,
         additional_servers: &mut Option<Vec<String>>,
@@ -105,33 +280,86 @@ impl WebConnection {
     }
 }
 
+/// Relaying needs at least one turn: or turns: URL
+fn has_turn(config: &RTCConfiguration) -> bool {
+    (config.ice_servers.iter())
+        .flat_map(|server| &server.urls)
+        .any(|url| url.starts_with("turn:") || url.starts_with("turns:"))
+}
+
 fn attach_connection_handler(
     pc: Arc<RTCPeerConnection>,
     sender: UnboundedSender<BasicEvent>,
     error_tx: ErrorTX,
+    lost_tx: watch::Sender<bool>,
+    closing_rx: watch::Receiver<bool>,
+    max_message_tx: watch::Sender<Option<usize>>,
 ) {
+    // Candidates only go to the logs, they tell a direct transfer from a relayed one
+    pc.on_ice_candidate(Box::new(move |candidate| {
+        if let Some(candidate) = candidate {
+            log::info!("Local candidate: {}", candidate);
+        }
+        Box::pin(async {})
+    }));
+
     let etx = error_tx.clone();
+    let p = pc.clone();
     pc.on_ice_connection_state_change(Box::new(move |state| {
         let etx = etx.clone();
+        let p = p.clone();
 
         Box::pin(async move {
-            if state == RTCIceConnectionState::Failed {
-                etx.send_error(eyre!(state).wrap_err("ICE connection Failed"));
+            match state {
+                // The remote description is in place by now
+                RTCIceConnectionState::Checking => {
+                    if let Some(remote) = p.remote_description().await {
+                        for candidate in remote_candidates(&remote.sdp) {
+                            log::info!("Remote candidate: {}", candidate);
+                        }
+                    }
+                }
+                RTCIceConnectionState::Failed => {
+                    etx.send_error(TappiError::IceTimeout.into());
+                }
+                _ => {}
             }
         })
     }));
 
+    let p = pc.clone();
     pc.on_peer_connection_state_change(Box::new(move |state: RTCPeerConnectionState| {
         let sender = sender.clone();
         let error_tx = error_tx.clone();
+        let lost_tx = lost_tx.clone();
+        let closing = *closing_rx.borrow();
+        let max_message_tx = max_message_tx.clone();
+        let p = p.clone();
 
         Box::pin(async move {
             match state {
                 RTCPeerConnectionState::Connected => {
+                    lost_tx.send_replace(false);
+                    if let Some(remote) = p.remote_description().await {
+                        max_message_tx.send_replace(max_message_size(&remote.sdp));
+                    }
+                    log_selected_pair(&p).await;
                     sender.send_event(AppEventClient::Connected).await;
                 }
+                RTCPeerConnectionState::Closed => {
+                    let event = AppEventClient::Disconnected(DisconnectKind::Closed);
+                    sender.send_event(event).await;
+                }
+                // A hang up, ours or the peer's, isn't worth reconnecting or failing over
+                RTCPeerConnectionState::Disconnected | RTCPeerConnectionState::Failed
+                    if closing =>
+                {
+                    log::debug!("Connection {} after hanging up", state);
+                }
                 RTCPeerConnectionState::Disconnected => {
-                    sender.send_event(AppEventClient::Disconnected).await;
+                    lost_tx.send_replace(true);
+                    let event = AppEventClient::Disconnected(DisconnectKind::Lost);
+                    sender.send_event(event).await;
                 }
                 RTCPeerConnectionState::Failed => {
                     error_tx.send_error(eyre!(state).wrap_err("RTC connection failed"));
@@ -142,12 +370,60 @@ fn attach_connection_handler(
     }));
 }
 
-fn attach_channel_open_handler(dc: Arc<RTCDataChannel>, sender: UnboundedSender<BasicEvent>) {
+/// Candidates aren't trickled, the remote ones are only found in the SDP
+fn remote_candidates(sdp: &str) -> impl Iterator<Item = &str> {
+    sdp.lines()
+        .filter_map(|line| line.trim().strip_prefix("a=candidate:"))
+}
+
+/// Never below `MIN_CHUNK_SIZE`, a tinier chunk wouldn't even fit the frame headers
+fn fit_chunk(chunk_size: usize, max: Option<usize>) -> usize {
+    match max {
+        Some(max) => chunk_size.min(max.max(MIN_CHUNK_SIZE)),
+        None => chunk_size,
+    }
+}
+
+/// RFC 8841, a missing attribute means 64K and zero means there's no limit
+fn max_message_size(sdp: &str) -> Option<usize> {
+    let size = sdp
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("a=max-message-size:"))
+        .and_then(|size| size.trim().parse().ok())
+        .unwrap_or(DEFAULT_MAX_MESSAGE_SIZE);
+    (size > 0).then_some(size)
+}
+
+async fn log_selected_pair(pc: &RTCPeerConnection) {
+    let dtls = pc.sctp().transport();
+    let Some(pair) = dtls.ice_transport().get_selected_candidate_pair().await else {
+        log::warn!("Connected without a selected candidate pair");
+        return;
+    };
+
+    // The pair keeps its candidates private, their types only show up in the text
+    let pair = pair.to_string();
+    let relayed = pair.contains(&format!(" {} ", RTCIceCandidateType::Relay));
+    let route = if relayed { "relayed" } else { "direct" };
+    log::info!("Selected candidate pair ({}): {}", route, pair);
+}
+
+fn attach_channel_open_handler(
+    dc: Arc<RTCDataChannel>,
+    sender: UnboundedSender<BasicEvent>,
+    error_tx: ErrorTX,
+    mut buffer_watch_rx: watch::Receiver<bool>,
+) {
     dc.on_open(Box::new({
         let dc = dc.clone();
 
         move || {
             Box::pin(async move {
+                // Goes out first so the peer can check it before anything else arrives
+                let hello = Message::Hello(dc.protocol().to_string());
+                if let Err(err) = send_message(dc.clone(), &mut buffer_watch_rx, hello).await {
+                    error_tx.send_error(err);
+                }
                 sender
                     .send_event(AppEventClient::ChannelOpened(DebugDataChannel::new(
                         dc.clone(),
@@ -182,6 +458,11 @@ pub async fn wait_for_ice_completion(pc: Arc<RTCPeerConnection>) {
         })
     }));
 
+    // Gathering might've finished before the handler got attached
+    if pc.ice_gathering_state() == RTCIceGatheringState::Complete {
+        return;
+    }
+
     // Wait for ICE gathering to complete
     while !*rx.borrow() {
         rx.changed().await.ok();
@@ -190,36 +471,84 @@ pub async fn wait_for_ice_completion(pc: Arc<RTCPeerConnection>) {
 
 fn on_message(
     dc: Arc<RTCDataChannel>,
+    heartbeat: Heartbeat,
+    receiving: ReceiveState,
     error_tx: ErrorTX,
     buffer_watch_rx: watch::Receiver<bool>,
     sender: UnboundedSender<BasicEvent>,
 ) {
     let channel = dc.clone();
-    let metadata_map = Arc::new(Mutex::new(HashMap::<usize, MetaData>::new()));
-    let metadata_bytes_map = Arc::new(Mutex::new(HashMap::<usize, Vec<u8>>::new()));
+    let receiving = Arc::new(receiving);
 
     dc.on_message(Box::new(move |msg| {
         let channel = channel.clone();
         let buffer_watch_rx = buffer_watch_rx.clone();
         let sender = sender.clone();
-        let metadata_map = metadata_map.clone();
-        let metadata_bytes_map = metadata_bytes_map.clone();
+        let receiving = receiving.clone();
         let error_tx = error_tx.clone();
+        let heartbeat = heartbeat.clone();
 
         Box::pin(async move {
+            heartbeat.touch(true);
             let buffer_watch_rx = &mut buffer_watch_rx.clone();
-            if let Err(err) = handle_message(
-                msg,
-                channel,
-                buffer_watch_rx,
-                sender,
-                metadata_map,
-                metadata_bytes_map,
-            )
-            .await
+            if let Err(err) =
+                handle_message(msg, channel, buffer_watch_rx, sender, &receiving).await
             {
                 error_tx.send_error(err);
             }
+            heartbeat.touch(false);
         })
     }));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::payload;
+
+    #[test]
+    fn finds_remote_candidates() {
+        let sdp = "v=0\r\n\
+            m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\n\
+            a=candidate:1 1 udp 2130706431 192.168.1.2 50000 typ host\r\n\
+            a=candidate:2 1 udp 1694498815 203.0.113.7 50000 typ srflx raddr 0.0.0.0 rport 50000\r\n\
+            a=end-of-candidates\r\n";
+        let candidates: Vec<&str> = remote_candidates(sdp).collect();
+        assert_eq!(candidates.len(), 2);
+        assert!(candidates[1].contains("typ srflx"));
+    }
+
+    #[test]
+    fn reads_max_message_size() {
+        let sdp = |attribute: &str| {
+            format!("v=0\r\nm=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\n{attribute}")
+        };
+        assert_eq!(
+            max_message_size(&sdp("a=max-message-size:16384\r\n")),
+            Some(16_384)
+        );
+        assert_eq!(max_message_size(&sdp("")), Some(DEFAULT_MAX_MESSAGE_SIZE));
+        assert_eq!(max_message_size(&sdp("a=max-message-size:0\r\n")), None);
+    }
+
+    #[test]
+    fn tiny_max_message_size() {
+        let chunk = 64 * 1024;
+        assert_eq!(fit_chunk(chunk, Some(16_384)), 16_384);
+        assert_eq!(fit_chunk(chunk, None), chunk);
+        // Whatever the peer claims, the frame headers still fit
+        for max in [1, 10, MIN_CHUNK_SIZE - 1] {
+            let fitted = fit_chunk(chunk, Some(max));
+            assert_eq!(fitted, MIN_CHUNK_SIZE);
+            assert!(fitted > payload::base_length(true, true));
+        }
+    }
+
+    #[test]
+    fn relay_needs_turn() {
+        let conf = |url: &str| WebConnection::conf(None, None, &mut Some(vec![url.to_string()]));
+        assert!(!has_turn(&conf("stun:stun.example.com:3478")));
+        assert!(has_turn(&conf("turn:turn.example.com:3478")));
+        assert!(has_turn(&conf("turns:turn.example.com:5349")));
+    }
+}
@@ -1,4 +1,5 @@
 pub mod negotiator;
+pub mod signaling_loopback;
 pub mod signaling_manual;
 pub mod signaling_mqtt;
 pub mod signaling_solution;
@@ -1,8 +1,16 @@
-use color_eyre::eyre::eyre;
-use std::sync::Arc;
-use tokio::sync::mpsc::UnboundedSender;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    sync::{mpsc::UnboundedSender, watch},
+    time::Instant,
+};
 use uuid::Uuid;
-use webrtc::peer_connection::{RTCPeerConnection, sdp::session_description::RTCSessionDescription};
+use webrtc::peer_connection::{
+    RTCPeerConnection, offer_answer_options::RTCOfferOptions,
+    sdp::session_description::RTCSessionDescription,
+};
 
 use crate::{
     app::{
@@ -10,8 +18,9 @@ use crate::{
         event::{BasicEvent, BasicEventSenderExt},
         models::Maid,
     },
-    cli::{ClientArgs, SignalingSolutions},
+    cli::{ClientArgs, ForceRole, SignalingSolutions},
     client::{
+        error::TappiError,
         rtc_base::wait_for_ice_completion,
         signaling::{
             signaling_manual::SignalingManual,
@@ -19,9 +28,25 @@ use crate::{
             signaling_solution::{SignalingInterface, SignalingMessage},
             signaling_websocket::SignalingWebsocket,
         },
+        turn::TurnAuth,
     },
 };
 
+/// New UUIDs to try after a clash before giving up, a relay echoing ours back would clash forever
+const MAX_UUID_CLASHES: u32 = 3;
+/// Polite manual peers wait this long for an offer before suspecting both are polite
+pub const MANUAL_STALL_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// What `--ice-restart` watches the connection with
+pub struct Restarts {
+    /// True while the connection is lost
+    pub lost_rx: watch::Receiver<bool>,
+    /// True once either side hung up on purpose, there's nothing to restart then
+    pub closing_rx: watch::Receiver<bool>,
+    /// Every restart gets fresh credentials, the first ones might have expired by then
+    pub turn: Option<TurnAuth>,
+}
+
 // Connecting to server -> connected to server -> uuid sent ->
 // uuid received -> offer sent -> answer received -> connection established
 //               -> offer received -> answer sent ->
@@ -32,6 +57,8 @@ pub enum HandshakeState {
     ConnectingToServer,
     ConnectedToServer,
     UUIDSent,
+    /// Registered with the signaling, but nobody else showed up yet
+    WaitingForPeer,
     UUIDReceived,
     OfferSent,
     OfferReceived,
@@ -39,6 +66,24 @@ pub enum HandshakeState {
     AnswerReceived,
     ExchangeFinished,
 }
+impl HandshakeState {
+    /// How many steps the exchange takes
+    pub const STEPS: usize = 7;
+
+    /// Position in the exchange, both sides of a branch share a step
+    pub fn step(&self) -> Option<usize> {
+        match self {
+            HandshakeState::Initial => None,
+            HandshakeState::ConnectingToServer => Some(0),
+            HandshakeState::ConnectedToServer => Some(1),
+            HandshakeState::UUIDSent | HandshakeState::WaitingForPeer => Some(2),
+            HandshakeState::UUIDReceived => Some(3),
+            HandshakeState::OfferSent | HandshakeState::OfferReceived => Some(4),
+            HandshakeState::AnswerSent | HandshakeState::AnswerReceived => Some(5),
+            HandshakeState::ExchangeFinished => Some(6),
+        }
+    }
+}
 
 /// Negotiator struct
 ///
@@ -49,6 +94,16 @@ pub struct Negotiator<S: SignalingInterface> {
     signaling: S,
     uuid: Uuid,
     handle_same_uuid: bool,
+    /// Whether the UUID was picked to force a role
+    forced: bool,
+    uuid_clashes: u32,
+    /// How long a polite peer waits for an offer before telling about it
+    stall_timeout: Option<Duration>,
+    stall_deadline: Option<Instant>,
+    /// We made the offer, so ICE restarts are ours to start too
+    offerer: bool,
+    /// Keeps the signaling open after the exchange when set
+    restarts: Option<Restarts>,
 }
 impl<S: SignalingInterface> Negotiator<S> {
     pub fn new(
@@ -63,7 +118,39 @@ impl<S: SignalingInterface> Negotiator<S> {
             signaling,
             uuid: Uuid::exclude_edge_cases(),
             handle_same_uuid,
+            forced: false,
+            uuid_clashes: 0,
+            stall_timeout: None,
+            stall_deadline: None,
+            offerer: false,
+            restarts: None,
+        }
+    }
+
+    /// Restarts ICE whenever the connection gets lost, the signaling stays open for that
+    pub fn with_restarts(mut self, restarts: Restarts) -> Self {
+        self.restarts = Some(restarts);
+        self
+    }
+
+    /// Both manual peers being polite leaves both waiting for an offer that never comes,
+    /// there's no way to tell but to wait
+    pub fn with_stall_timeout(mut self, timeout: Duration) -> Self {
+        self.stall_timeout = Some(timeout);
+        self
+    }
+
+    /// Forces a role the same way manual signaling does, with a UUID that always wins or loses
+    /// the comparison. Both peers forcing the same role end up with the same UUID
+    pub fn with_role(mut self, role: Option<ForceRole>) -> Self {
+        if let Some(role) = role {
+            self.uuid = match role {
+                ForceRole::Offer => Uuid::full(),
+                ForceRole::Answer => Uuid::nil(),
+            };
+            self.forced = true;
         }
+        self
     }
 
     pub async fn run(&mut self) -> color_eyre::Result<()> {
@@ -90,12 +177,42 @@ impl<S: SignalingInterface> Negotiator<S> {
                 HandshakeState::UUIDSent,
             ))
             .await;
+        self.sender
+            .send_event(AppEventClient::UpdateHandshakeState(
+                HandshakeState::WaitingForPeer,
+            ))
+            .await;
 
         loop {
-            if let Some(signaling_message) = self.signaling.receive_message().await? {
+            let received = match self.stall_deadline {
+                Some(deadline) => {
+                    match tokio::time::timeout_at(deadline, self.signaling.receive_message()).await
+                    {
+                        Ok(received) => received?,
+                        Err(_) => {
+                            self.stall_deadline = None; // Telling once is enough
+                            log::warn!("No offer arrived, both peers might be polite");
+                            self.sender
+                                .send_event(AppEventClient::HandshakeStalled)
+                                .await;
+                            continue;
+                        }
+                    }
+                }
+                None => self.signaling.receive_message().await?,
+            };
+            if let Some(signaling_message) = received {
+                if self.is_own_description(&signaling_message).await {
+                    log::warn!(
+                        "Ignored our own session description, self-connection isn't possible"
+                    );
+                    continue;
+                }
+
                 match signaling_message {
                     SignalingMessage::Uuid(uuid) => self.handle_uuid(uuid).await?,
                     SignalingMessage::Offer(sdp) => {
+                        self.stall_deadline = None;
                         self.handle_offer(sdp).await?;
                         break;
                     } // TODO: fix, this is a hack
@@ -103,6 +220,9 @@ impl<S: SignalingInterface> Negotiator<S> {
                         self.handle_answer(sdp).await?;
                         break;
                     } // TODO: fix, this is a hack
+                    SignalingMessage::Unknown => {
+                        log::warn!("Ignored an unknown signaling message, is the peer newer?");
+                    }
                 }
             }
         }
@@ -113,11 +233,95 @@ impl<S: SignalingInterface> Negotiator<S> {
                 HandshakeState::ExchangeFinished,
             ))
             .await;
+        if let Some(restarts) = self.restarts.take() {
+            log::info!("Keeping the signaling open for ICE restarts");
+            return self.keep_alive(restarts).await;
+        }
         self.signaling.disconnect().await?;
 
         Ok(())
     }
 
+    /// Renegotiates until the session ends, the offering side restarts ICE once the connection is lost
+    ///
+    /// The data channel lives through a restart, whatever was on its way just carries on
+    async fn keep_alive(&mut self, restarts: Restarts) -> color_eyre::Result<()> {
+        let Restarts {
+            mut lost_rx,
+            mut closing_rx,
+            turn,
+        } = restarts;
+        let hung_up = closing_rx.clone();
+        loop {
+            if *closing_rx.borrow_and_update() {
+                log::info!("Hung up, no more ICE restarts");
+                return Ok(());
+            }
+            tokio::select! {
+                changed = lost_rx.changed(), if self.offerer => {
+                    if changed.is_err() {
+                        return Ok(()); // The connection is gone for good
+                    }
+                    if *lost_rx.borrow_and_update() && !*hung_up.borrow() {
+                        log::info!("Connection lost, restarting ICE");
+                        if let Some(auth) = &turn {
+                            self.refresh_turn(auth).await?;
+                        }
+                        self.make_offer(true).await?;
+                    }
+                }
+                changed = closing_rx.changed() => {
+                    if changed.is_err() {
+                        return Ok(());
+                    }
+                }
+                received = self.signaling.receive_message() => {
+                    let Some(signaling_message) = received? else {
+                        continue;
+                    };
+                    if self.is_own_description(&signaling_message).await {
+                        continue;
+                    }
+                    match signaling_message {
+                        SignalingMessage::Offer(sdp) => self.handle_offer(sdp).await?,
+                        SignalingMessage::Answer(sdp) => self.handle_answer(sdp).await?,
+                        _ => continue, // The UUIDs are settled
+                    }
+                    self.sender
+                        .send_event(AppEventClient::UpdateHandshakeState(
+                            HandshakeState::ExchangeFinished,
+                        ))
+                        .await;
+                }
+            }
+        }
+    }
+
+    /// Swaps in fresh TURN credentials, a restart gathers its relay candidates with them
+    async fn refresh_turn(&self, auth: &TurnAuth) -> color_eyre::Result<()> {
+        let (username, credential) = auth.credentials(SystemTime::now())?;
+        let mut config = self.pc.get_configuration().await;
+        for server in &mut config.ice_servers {
+            server.username = username.clone();
+            server.credential = credential.clone();
+        }
+        self.pc.set_configuration(config).await?;
+        Ok(())
+    }
+
+    /// Checks if an offer or answer is the one we've generated ourselves
+    async fn is_own_description(&self, message: &SignalingMessage) -> bool {
+        let sdp = match message {
+            SignalingMessage::Offer(sdp) | SignalingMessage::Answer(sdp) => sdp,
+            _ => return false,
+        };
+
+        self.pc
+            .local_description()
+            .await
+            .is_some_and(|local_desc| local_desc.sdp == *sdp)
+    }
+
     async fn handle_uuid(&mut self, uuid: Uuid) -> color_eyre::Result<()> {
         self.sender
             .send_event(AppEventClient::UpdateHandshakeState(
@@ -129,36 +333,65 @@ impl<S: SignalingInterface> Negotiator<S> {
         // I mean, it could happen on manual signaling simply by mistake, to be honest
         // Upd.: i don't actually think it could happen on manual signaling
         if self.uuid == uuid {
-            if self.handle_same_uuid {
+            if self.forced {
+                return Err(TappiError::SignalingFailed(
+                    "Both peers forced the same role".to_string(),
+                )
+                .into());
+            } else if self.handle_same_uuid {
+                self.uuid_clashes += 1;
+                if self.uuid_clashes > MAX_UUID_CLASHES {
+                    return Err(
+                        TappiError::SignalingFailed("UUID negotiation failed".to_string()).into(),
+                    );
+                }
                 self.uuid = Uuid::exclude_edge_cases(); // Assign a new UUID
                 self.signaling
                     .send_message(SignalingMessage::Uuid(self.uuid))
                     .await?; // Report it
             } else {
-                return Err(eyre!("UUID clash"));
+                return Err(TappiError::SignalingFailed("UUID clash".to_string()).into());
             }
         } else {
+            self.sender
+                .send_event(AppEventClient::PeerIdentified(uuid))
+                .await;
             let polite: bool = self.uuid < uuid; // Determine politeness
 
+            if polite {
+                self.stall_deadline = self.stall_timeout.map(|timeout| Instant::now() + timeout);
+            }
             // If impolite - make an offer
-            if !polite {
-                // Create an offer, confirm it and wait for all of the ice candidates
-                let offer = self.pc.create_offer(None).await?;
-                self.pc.set_local_description(offer.clone()).await?;
-                wait_for_ice_completion(self.pc.clone()).await;
+            else {
+                self.make_offer(false).await?;
+            }
+        }
 
-                if let Some(local_desc) = self.pc.local_description().await {
-                    self.signaling
-                        .send_message(SignalingMessage::Offer(local_desc.sdp))
-                        .await?;
+        Ok(())
+    }
 
-                    self.sender
-                        .send_event(AppEventClient::UpdateHandshakeState(
-                            HandshakeState::OfferSent,
-                        ))
-                        .await;
-                }
-            }
+    async fn make_offer(&mut self, ice_restart: bool) -> color_eyre::Result<()> {
+        self.offerer = true;
+
+        // Create an offer, confirm it and wait for all of the ice candidates
+        let options = RTCOfferOptions {
+            ice_restart,
+            ..Default::default()
+        };
+        let offer = self.pc.create_offer(Some(options)).await?;
+        self.pc.set_local_description(offer.clone()).await?;
+        wait_for_ice_completion(self.pc.clone()).await;
+
+        if let Some(local_desc) = self.pc.local_description().await {
+            self.signaling
+                .send_message(SignalingMessage::Offer(local_desc.sdp))
+                .await?;
+
+            self.sender
+                .send_event(AppEventClient::UpdateHandshakeState(
+                    HandshakeState::OfferSent,
+                ))
+                .await;
         }
 
         Ok(())
@@ -212,21 +445,37 @@ pub async fn negotiate(
     args: ClientArgs,
     maid: Maid,
     signaling_manual: Option<SignalingManual>,
+    restarts: Restarts,
 ) -> color_eyre::Result<()> {
     match &args.signaling_mode {
         SignalingSolutions::Manual(_signaling_args) => {
+            if args.ice_restart {
+                log::warn!(
+                    "Manual signaling can't restart ICE, --ice-restart needs a signaling server"
+                );
+            }
             if let Some(signaling_manual) = signaling_manual {
                 let mut negotiator =
-                    Negotiator::new(maid.event_tx.clone(), pc.clone(), signaling_manual, false);
+                    Negotiator::new(maid.event_tx.clone(), pc.clone(), signaling_manual, false)
+                        .with_stall_timeout(MANUAL_STALL_TIMEOUT);
                 negotiator.run().await?;
             }
         }
         SignalingSolutions::Socket(signaling_args) => {
-            let url = SignalingWebsocket::build_url(&signaling_args.address, &signaling_args.room)?;
+            let url = SignalingWebsocket::build_url(
+                &signaling_args.address,
+                signaling_args.port,
+                &signaling_args.room,
+                signaling_args.name.as_deref(),
+            )?;
             let sc =
                 SignalingWebsocket::from_url(&url, maid.error_tx.clone(), maid.token.child_token())
                     .await?;
-            let mut negotiator = Negotiator::new(maid.event_tx.clone(), pc.clone(), sc, true);
+            let mut negotiator = Negotiator::new(maid.event_tx.clone(), pc.clone(), sc, true)
+                .with_role(signaling_args.force_role);
+            if args.ice_restart {
+                negotiator = negotiator.with_restarts(restarts);
+            }
             negotiator.run().await?;
         }
         SignalingSolutions::Mqtt(signaling_args) => {
@@ -235,7 +484,11 @@ pub async fn negotiate(
                 maid.error_tx.clone(),
                 maid.token.child_token(),
             );
-            let mut negotiator = Negotiator::new(maid.event_tx.clone(), pc.clone(), sc, true);
+            let mut negotiator = Negotiator::new(maid.event_tx.clone(), pc.clone(), sc, true)
+                .with_role(signaling_args.force_role);
+            if args.ice_restart {
+                negotiator = negotiator.with_restarts(restarts);
+            }
             negotiator.run().await?;
         }
     }
@@ -263,3 +516,273 @@ impl UuidExt for Uuid {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::app_event::AppEvent;
+    use async_trait::async_trait;
+    use std::collections::VecDeque;
+    use webrtc::{api::APIBuilder, peer_connection::configuration::RTCConfiguration};
+
+    /// Scripted signaling that records everything sent through it
+    #[derive(Default)]
+    struct SignalingMock {
+        incoming: VecDeque<SignalingMessage>,
+        outgoing: Vec<SignalingMessage>,
+        /// Sends our UUIDs right back like a misbehaving relay
+        echo: bool,
+        /// Waits forever once everything scripted was received, like a peer that never answers
+        hang: bool,
+    }
+    #[async_trait]
+    impl SignalingInterface for SignalingMock {
+        async fn connect(&mut self) -> color_eyre::Result<()> {
+            Ok(())
+        }
+        async fn disconnect(&mut self) -> color_eyre::Result<()> {
+            Ok(())
+        }
+        async fn send_message(&mut self, message: SignalingMessage) -> color_eyre::Result<()> {
+            if self.echo
+                && let SignalingMessage::Uuid(uuid) = &message
+            {
+                self.incoming.push_back(SignalingMessage::Uuid(*uuid));
+            }
+            self.outgoing.push(message);
+            Ok(())
+        }
+        async fn receive_message(&mut self) -> color_eyre::Result<Option<SignalingMessage>> {
+            if self.hang && self.incoming.is_empty() {
+                std::future::pending::<()>().await;
+            }
+            Ok(self.incoming.pop_front())
+        }
+    }
+
+    async fn peer_connection() -> Arc<RTCPeerConnection> {
+        let api = APIBuilder::new().build();
+        let pc = api
+            .new_peer_connection(RTCConfiguration::default())
+            .await
+            .unwrap();
+        pc.create_data_channel("data", None).await.unwrap();
+        Arc::new(pc)
+    }
+
+    async fn negotiator(handle_same_uuid: bool) -> Negotiator<SignalingMock> {
+        let (sender, _) = tokio::sync::mpsc::unbounded_channel::<BasicEvent>();
+        let pc = peer_connection().await;
+        Negotiator::new(sender, pc, SignalingMock::default(), handle_same_uuid)
+    }
+
+    #[tokio::test]
+    async fn polite_waits_for_offer() {
+        let mut negotiator = negotiator(true).await;
+        negotiator.handle_uuid(Uuid::full()).await.unwrap();
+        assert!(negotiator.signaling.outgoing.is_empty());
+    }
+
+    #[tokio::test]
+    async fn impolite_sends_offer() {
+        let mut negotiator = negotiator(true).await;
+        negotiator.handle_uuid(Uuid::nil()).await.unwrap();
+        assert!(matches!(
+            negotiator.signaling.outgoing.as_slice(),
+            [SignalingMessage::Offer(_)]
+        ));
+    }
+
+    #[tokio::test]
+    async fn uuid_clash_reassigns() {
+        let mut negotiator = negotiator(true).await;
+        let uuid = negotiator.uuid;
+        negotiator.handle_uuid(uuid).await.unwrap();
+
+        assert_ne!(negotiator.uuid, uuid);
+        assert!(matches!(
+            negotiator.signaling.outgoing.as_slice(),
+            [SignalingMessage::Uuid(new)] if *new == negotiator.uuid
+        ));
+    }
+
+    #[tokio::test]
+    async fn uuid_clash_fails() {
+        let mut negotiator = negotiator(false).await;
+        let uuid = negotiator.uuid;
+        assert!(negotiator.handle_uuid(uuid).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn uuid_echo_gives_up() {
+        let mut negotiator = negotiator(true).await;
+        negotiator.signaling.echo = true;
+        let err = negotiator.run().await.unwrap_err();
+
+        assert!(matches!(
+            TappiError::find(&err),
+            Some(TappiError::SignalingFailed(reason)) if reason == "UUID negotiation failed"
+        ));
+        // The first UUID and one for every allowed clash
+        assert_eq!(
+            negotiator.signaling.outgoing.len(),
+            MAX_UUID_CLASHES as usize + 1
+        );
+    }
+
+    #[tokio::test]
+    async fn forced_role_wins_and_clashes() {
+        let mut offerer = negotiator(true).await.with_role(Some(ForceRole::Offer));
+        offerer
+            .handle_uuid(Uuid::exclude_edge_cases())
+            .await
+            .unwrap();
+        assert!(matches!(
+            offerer.signaling.outgoing.as_slice(),
+            [SignalingMessage::Offer(_)]
+        ));
+
+        let mut answerer = negotiator(true).await.with_role(Some(ForceRole::Answer));
+        assert!(answerer.handle_uuid(Uuid::nil()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn offer_gets_answered() {
+        let remote = peer_connection().await;
+        let offer = remote.create_offer(None).await.unwrap();
+
+        let mut negotiator = negotiator(true).await;
+        negotiator.handle_offer(offer.sdp).await.unwrap();
+        assert!(matches!(
+            negotiator.signaling.outgoing.as_slice(),
+            [SignalingMessage::Answer(_)]
+        ));
+    }
+
+    #[tokio::test]
+    async fn answer_gets_applied() {
+        let mut negotiator = negotiator(true).await;
+        let offer = negotiator.pc.create_offer(None).await.unwrap();
+        negotiator
+            .pc
+            .set_local_description(offer.clone())
+            .await
+            .unwrap();
+
+        let remote = peer_connection().await;
+        remote.set_remote_description(offer).await.unwrap();
+        let answer = remote.create_answer(None).await.unwrap();
+
+        negotiator.handle_answer(answer.sdp).await.unwrap();
+        assert!(negotiator.pc.remote_description().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn own_offer_is_recognized() {
+        let mut negotiator = negotiator(true).await;
+        negotiator.handle_uuid(Uuid::nil()).await.unwrap();
+
+        let own_offer = negotiator.signaling.outgoing[0].clone();
+        assert!(negotiator.is_own_description(&own_offer).await);
+    }
+
+    #[tokio::test]
+    async fn run_stops_after_offer() {
+        let remote = peer_connection().await;
+        let offer = remote.create_offer(None).await.unwrap();
+
+        let mut negotiator = negotiator(true).await;
+        negotiator.signaling.incoming = VecDeque::from([
+            SignalingMessage::Uuid(Uuid::full()),
+            SignalingMessage::Offer(offer.sdp),
+        ]);
+        negotiator.run().await.unwrap();
+
+        assert!(matches!(
+            negotiator.signaling.outgoing.as_slice(),
+            [SignalingMessage::Uuid(_), SignalingMessage::Answer(_)]
+        ));
+    }
+
+    #[tokio::test]
+    async fn offerer_restarts_ice_when_lost() {
+        let mut negotiator = negotiator(true).await;
+        negotiator.signaling.hang = true;
+        negotiator.handle_uuid(Uuid::nil()).await.unwrap();
+
+        let (lost_tx, lost_rx) = watch::channel(false);
+        let (_closing_tx, closing_rx) = watch::channel(false);
+        lost_tx.send_replace(true);
+        let restarts = Restarts {
+            lost_rx,
+            closing_rx,
+            turn: None,
+        };
+        let keep_alive = negotiator.keep_alive(restarts);
+        assert!(
+            tokio::time::timeout(Duration::from_secs(2), keep_alive)
+                .await
+                .is_err()
+        );
+
+        // A new offer with new ICE credentials
+        let ufrag = |message: &SignalingMessage| match message {
+            SignalingMessage::Offer(sdp) => sdp
+                .lines()
+                .find_map(|line| line.strip_prefix("a=ice-ufrag:"))
+                .map(str::to_string),
+            _ => None,
+        };
+        let outgoing = &negotiator.signaling.outgoing;
+        assert_eq!(outgoing.len(), 2);
+        assert!(ufrag(&outgoing[1]).is_some());
+        assert_ne!(ufrag(&outgoing[0]), ufrag(&outgoing[1]));
+    }
+
+    #[tokio::test]
+    async fn no_restart_after_hanging_up() {
+        let mut negotiator = negotiator(true).await;
+        negotiator.signaling.hang = true;
+        negotiator.handle_uuid(Uuid::nil()).await.unwrap();
+
+        let (lost_tx, lost_rx) = watch::channel(false);
+        let (closing_tx, closing_rx) = watch::channel(false);
+        closing_tx.send_replace(true);
+        lost_tx.send_replace(true);
+        let restarts = Restarts {
+            lost_rx,
+            closing_rx,
+            turn: None,
+        };
+        let keep_alive = negotiator.keep_alive(restarts);
+        tokio::time::timeout(Duration::from_secs(2), keep_alive)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(negotiator.signaling.outgoing.len(), 1); // Just the first offer
+    }
+
+    #[tokio::test]
+    async fn polite_stall_is_reported() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<BasicEvent>();
+        let signaling = SignalingMock {
+            incoming: VecDeque::from([SignalingMessage::Uuid(Uuid::full())]),
+            hang: true,
+            ..Default::default()
+        };
+        let mut negotiator = Negotiator::new(sender, peer_connection().await, signaling, false)
+            .with_stall_timeout(Duration::from_millis(50));
+        tokio::spawn(async move { negotiator.run().await });
+
+        let stalled = async {
+            while let Some(event) = receiver.recv().await {
+                if let BasicEvent::App(AppEvent::Client(AppEventClient::HandshakeStalled)) = event {
+                    return true;
+                }
+            }
+            false
+        };
+        let stalled = tokio::time::timeout(Duration::from_secs(5), stalled).await;
+        assert!(stalled.unwrap());
+    }
+}
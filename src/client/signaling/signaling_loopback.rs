@@ -0,0 +1,34 @@
+use async_trait::async_trait;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+
+use crate::client::signaling::signaling_solution::{SignalingInterface, SignalingMessage};
+
+/// In-memory signaling, one end of a connected pair
+pub struct SignalingLoopback {
+    tx: UnboundedSender<SignalingMessage>,
+    rx: UnboundedReceiver<SignalingMessage>,
+}
+impl SignalingLoopback {
+    /// Creates two ends that deliver messages to each other
+    pub fn pair() -> (Self, Self) {
+        let (a_tx, a_rx) = unbounded_channel::<SignalingMessage>();
+        let (b_tx, b_rx) = unbounded_channel::<SignalingMessage>();
+        (Self { tx: a_tx, rx: b_rx }, Self { tx: b_tx, rx: a_rx })
+    }
+}
+#[async_trait]
+impl SignalingInterface for SignalingLoopback {
+    async fn connect(&mut self) -> color_eyre::Result<()> {
+        Ok(())
+    }
+    async fn disconnect(&mut self) -> color_eyre::Result<()> {
+        Ok(())
+    }
+    async fn send_message(&mut self, message: SignalingMessage) -> color_eyre::Result<()> {
+        self.tx.send(message)?;
+        Ok(())
+    }
+    async fn receive_message(&mut self) -> color_eyre::Result<Option<SignalingMessage>> {
+        Ok(self.rx.recv().await)
+    }
+}
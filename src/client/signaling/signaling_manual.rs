@@ -70,7 +70,7 @@ impl SignalingInterface for SignalingManual {
         }
         // Skip any UUID messages
         else {
-            let json = serde_json::to_string(&message)?;
+            let json = message.to_json()?;
             let text = try_encrypt_claims(json, &self.args.secret)?;
             self.sender
                 .send_event(AppEventClient::ManualSignalingOutput(text))
@@ -1,17 +1,150 @@
 use async_trait::async_trait;
-use rumqttc::{AsyncClient, EventLoop, LastWill, MqttOptions, Packet, QoS};
+use rumqttc::{
+    AsyncClient, ConnectReturnCode, ConnectionError, Event, EventLoop, LastWill, MqttOptions,
+    Outgoing, Packet, QoS,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tokio::sync::{Mutex, watch};
 use tokio::time;
 use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
 
 use crate::app::encrypt::{Secret, try_decrypt_claims, try_encrypt_claims};
 use crate::app::models::ErrorTX;
 use crate::cli::SignalingSolutionMqttArgs;
 use crate::client::signaling::signaling_solution::{SignalingInterface, SignalingMessage};
 
+/// Wraps every published message so that stale retained ones can be told apart
+///
+/// A crashed session might leave its retained message behind, so the retained
+/// message gets republished with a fresh timestamp for as long as the session lives
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct MqttEnvelope {
+    session: Uuid,  // Publisher session nonce
+    seq: u64,       // Message number within the session
+    timestamp: u64, // Unix time in seconds
+    message: String,
+}
+impl MqttEnvelope {
+    fn new(session: Uuid, seq: u64, message: String) -> Self {
+        Self {
+            session,
+            seq,
+            timestamp: unix_now(),
+            message,
+        }
+    }
+    fn refreshed(&self) -> Self {
+        Self {
+            timestamp: unix_now(),
+            ..self.clone()
+        }
+    }
+    fn age(&self) -> u64 {
+        unix_now().saturating_sub(self.timestamp)
+    }
+}
+
+/// Keeps track of the messages that were already let through
+struct MqttFilter {
+    max_age: u64,
+    last_seq: HashMap<Uuid, u64>,
+}
+impl MqttFilter {
+    fn new(max_age: u64) -> Self {
+        Self {
+            max_age,
+            last_seq: HashMap::new(),
+        }
+    }
+    fn accept(&mut self, envelope: &MqttEnvelope, retained: bool) -> bool {
+        if retained && envelope.age() > self.max_age {
+            log::warn!(
+                "Discarded a stale retained message from session {}",
+                envelope.session
+            );
+            return false;
+        }
+
+        // Republished retained messages arrive again, so skip the ones we've already seen
+        if let Some(last_seq) = self.last_seq.get(&envelope.session)
+            && envelope.seq <= *last_seq
+        {
+            return false;
+        }
+
+        self.last_seq.insert(envelope.session, envelope.seq);
+        true
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// How long closing waits for the broker to confirm what we've published
+const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+/// Delay before the first reconnect, doubled after each one
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(500);
+/// Reconnects never wait longer than this
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Dropped connections are worth another try, a broker that refuses us isn't
+fn is_recoverable(err: &ConnectionError) -> bool {
+    match err {
+        ConnectionError::ConnectionRefused(code) => *code == ConnectReturnCode::ServiceUnavailable,
+        ConnectionError::RequestsDone => false, // The client is gone
+        _ => true,
+    }
+}
+fn reconnect_delay(attempt: u32) -> Duration {
+    (RECONNECT_BACKOFF * 2u32.pow(attempt.min(6))).min(MAX_RECONNECT_DELAY)
+}
+
+/// Our retained message, the last will wipes it when the connection drops
+type Retained = Arc<std::sync::Mutex<Option<MqttEnvelope>>>;
+
+/// Counts the publishes the broker hasn't confirmed yet
+#[derive(Clone)]
+struct InFlight(watch::Sender<usize>);
+impl Default for InFlight {
+    fn default() -> Self {
+        Self(watch::channel(0).0)
+    }
+}
+impl InFlight {
+    fn add(&self) {
+        self.0.send_modify(|n| *n += 1);
+    }
+    fn done(&self) {
+        self.0.send_modify(|n| *n = n.saturating_sub(1));
+    }
+    /// Resolves once there's nothing left to confirm
+    async fn settled(&self) {
+        self.0.subscribe().wait_for(|n| *n == 0).await.ok();
+    }
+
+    /// QoS 0 is done once it's written out, the others once the broker acknowledges it
+    fn track(&self, event: &Event, qos: QoS) {
+        let done = matches!(
+            (event, qos),
+            (Event::Outgoing(Outgoing::Publish(_)), QoS::AtMostOnce)
+                | (Event::Incoming(Packet::PubAck(_)), QoS::AtLeastOnce)
+                | (Event::Incoming(Packet::PubComp(_)), QoS::ExactlyOnce)
+        );
+        if done {
+            self.done();
+        }
+    }
+}
+
 pub struct SignalingMqtt {
     client: AsyncClient,
     event_loop: Arc<Mutex<EventLoop>>,
@@ -24,15 +157,33 @@ pub struct SignalingMqtt {
 
     // Error sender
     error_tx: ErrorTX,
+    // Publishes waiting for the broker
+    in_flight: InFlight,
+    // Put back after a reconnect
+    retained: Retained,
     // Cancellation token
     token: CancellationToken,
 
     // Tasks
     receive_task: Option<tokio::task::JoinHandle<()>>,
+    refresh_task: Option<tokio::task::JoinHandle<()>>,
 
     // First message should be retained
     retain_flag: bool,
+
+    // Session nonce and message counter
+    session: Uuid,
+    seq: u64,
+}
+/// Levels are validated by the argument parser, anything else falls back to the safest one
+fn qos(level: u8) -> QoS {
+    match level {
+        0 => QoS::AtMostOnce,
+        1 => QoS::AtLeastOnce,
+        _ => QoS::ExactlyOnce,
+    }
 }
+
 impl SignalingMqtt {
     pub fn new(
         args: SignalingSolutionMqttArgs,
@@ -45,7 +196,7 @@ impl SignalingMqtt {
             .set_last_will(LastWill::new(
                 args.local_topic(),
                 "",
-                QoS::ExactlyOnce,
+                qos(args.mqtt_qos),
                 true,
             ))
             .set_keep_alive(Duration::from_secs(args.keep_alive as u64))
@@ -62,26 +213,43 @@ impl SignalingMqtt {
             rx,
             args,
             error_tx,
+            in_flight: InFlight::default(),
+            retained: Retained::default(),
             token,
             receive_task: None,
+            refresh_task: None,
             retain_flag: true,
+            session: Uuid::new_v4(),
+            seq: 0,
         }
     }
 
     pub async fn init(&mut self) -> color_eyre::Result<()> {
         self.client
-            .subscribe(self.args.remote_topic(), QoS::ExactlyOnce)
+            .subscribe(self.args.remote_topic(), qos(self.args.mqtt_qos))
             .await?; // Subscribe
         self.receive_task = Some(self.spawn_receive_task()?);
         Ok(())
     }
 
     pub async fn close(&mut self) -> color_eyre::Result<()> {
-        time::sleep(Duration::from_secs(5)).await; // TODO: this is a hack, but otherwise the last message might get lost
+        if let Some(refresh_task) = &self.refresh_task {
+            refresh_task.abort();
+        }
+        self.retained.lock().unwrap().take();
 
+        self.in_flight.add();
         self.client
-            .publish(self.args.local_topic(), QoS::ExactlyOnce, true, "")
+            .publish(self.args.local_topic(), qos(self.args.mqtt_qos), true, "")
             .await?; // Emulate last will
+
+        // Hanging up right away might lose the last message
+        if time::timeout(ACK_TIMEOUT, self.in_flight.settled())
+            .await
+            .is_err()
+        {
+            log::warn!("The broker didn't confirm the last messages in time");
+        }
         self.client.disconnect().await?; // Disconnect gracefully
 
         if let Some(spawn_loop) = &self.receive_task {
@@ -91,60 +259,186 @@ impl SignalingMqtt {
         Ok(())
     }
 
-    pub async fn send(&self, text: String, retain: bool) -> color_eyre::Result<()> {
-        let msg = try_encrypt_claims(text, &self.args.secret)?;
-        self.client
-            .publish(self.args.local_topic(), QoS::ExactlyOnce, retain, msg)
+    pub async fn send(&mut self, text: String, retain: bool) -> color_eyre::Result<()> {
+        self.seq += 1;
+        let envelope = MqttEnvelope::new(self.session, self.seq, text);
+        Self::publish(&self.client, &self.args, &self.in_flight, &envelope, retain).await?;
+
+        if retain {
+            *self.retained.lock().unwrap() = Some(envelope.clone());
+            self.refresh_task = Some(self.spawn_refresh_task(envelope));
+        }
+
+        Ok(())
+    }
+
+    async fn publish(
+        client: &AsyncClient,
+        args: &SignalingSolutionMqttArgs,
+        in_flight: &InFlight,
+        envelope: &MqttEnvelope,
+        retain: bool,
+    ) -> color_eyre::Result<()> {
+        let msg = Self::payload(args, envelope)?;
+        in_flight.add();
+        client
+            .publish(args.local_topic(), qos(args.mqtt_qos), retain, msg)
             .await?;
         Ok(())
     }
+    fn payload(
+        args: &SignalingSolutionMqttArgs,
+        envelope: &MqttEnvelope,
+    ) -> color_eyre::Result<String> {
+        let json = serde_json::to_string(envelope)?;
+        Ok(try_encrypt_claims(json, &args.secret)?)
+    }
+
+    /// A clean session forgets the subscription, and the last will cleared our retained message
+    fn restore(
+        client: &AsyncClient,
+        args: &SignalingSolutionMqttArgs,
+        in_flight: &InFlight,
+        retained: &Retained,
+    ) -> color_eyre::Result<()> {
+        client.try_subscribe(args.remote_topic(), qos(args.mqtt_qos))?;
+
+        let envelope = retained.lock().unwrap().as_ref().map(|e| e.refreshed());
+        if let Some(envelope) = envelope {
+            let msg = Self::payload(args, &envelope)?;
+            in_flight.add();
+            client.try_publish(args.local_topic(), qos(args.mqtt_qos), true, msg)?;
+        }
+        Ok(())
+    }
+
+    /// Keeps the retained message fresh so the other side doesn't consider it stale
+    fn spawn_refresh_task(&self, envelope: MqttEnvelope) -> tokio::task::JoinHandle<()> {
+        let client = self.client.clone();
+        let args = self.args.clone();
+        let in_flight = self.in_flight.clone();
+        let error_tx = self.error_tx.clone();
+        let token = self.token.child_token();
+        let period = Duration::from_secs(self.args.retained_max_age / 4);
+
+        tokio::spawn(async move {
+            let refresh_loop = async {
+                loop {
+                    time::sleep(period).await;
+                    let envelope = envelope.refreshed();
+                    let result = Self::publish(&client, &args, &in_flight, &envelope, true).await;
+                    if let Err(err) = result {
+                        error_tx.send_error(err);
+                        break;
+                    }
+                }
+            };
+
+            tokio::select! {
+                _ = token.cancelled() => {},
+                _ = refresh_loop => {}
+            }
+        })
+    }
 
     fn spawn_receive_task(&self) -> color_eyre::Result<tokio::task::JoinHandle<()>> {
         let event_loop = self.event_loop.clone();
-        let secret = self.args.secret.clone();
+        let client = self.client.clone();
+        let args = self.args.clone();
+        let mut filter = MqttFilter::new(self.args.retained_max_age);
         let mut tx = self.tx.clone();
+        let in_flight = self.in_flight.clone();
+        let retained = self.retained.clone();
         let error_tx = self.error_tx.clone();
         let token = self.token.child_token();
 
         let task = tokio::spawn(async move {
             tokio::select! {
                 _ = token.cancelled() => {},
-                _ = Self::receive_loop(&event_loop, &mut tx, &secret, error_tx) => {}
+                _ = Self::receive_loop(&event_loop, &client, &args, &mut tx, &mut filter, &in_flight, &retained, error_tx) => {}
             }
         });
 
         Ok(task)
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn receive_loop(
         event_loop: &Arc<Mutex<EventLoop>>,
+        client: &AsyncClient,
+        args: &SignalingSolutionMqttArgs,
         tx: &mut UnboundedSender<String>,
-        secret: &Option<Secret>,
+        filter: &mut MqttFilter,
+        in_flight: &InFlight,
+        retained: &Retained,
         error_tx: ErrorTX,
     ) {
+        let mut attempts: u32 = 0;
         loop {
-            if let Err(err) = Self::receive(event_loop, tx, secret).await {
+            // Polling again after an error is what reconnects
+            let polled = event_loop.lock().await.poll().await;
+            let event = match polled {
+                Ok(event) => event,
+                Err(err) if attempts < args.reconnect_attempts && is_recoverable(&err) => {
+                    let delay = reconnect_delay(attempts);
+                    attempts += 1;
+                    log::warn!(
+                        "MQTT connection lost ({}), reconnect {}/{} in {:?}",
+                        err,
+                        attempts,
+                        args.reconnect_attempts,
+                        delay
+                    );
+                    time::sleep(delay).await;
+                    continue;
+                }
+                Err(err) => {
+                    error_tx.send_error(err.into());
+                    break;
+                }
+            };
+
+            if let Event::Incoming(Packet::ConnAck(_)) = event
+                && attempts > 0
+            {
+                log::info!("Reconnected to the MQTT broker");
+                attempts = 0;
+                if let Err(err) = Self::restore(client, args, in_flight, retained) {
+                    error_tx.send_error(err);
+                    break;
+                }
+            }
+
+            let secret = &args.secret;
+            if let Err(err) =
+                Self::receive(event, tx, secret, filter, in_flight, qos(args.mqtt_qos))
+            {
                 error_tx.send_error(err);
                 break;
             }
         }
     }
 
-    async fn receive(
-        event_loop: &Arc<Mutex<EventLoop>>,
+    fn receive(
+        event: Event,
         tx: &mut UnboundedSender<String>,
         secret: &Option<Secret>,
+        filter: &mut MqttFilter,
+        in_flight: &InFlight,
+        qos: QoS,
     ) -> color_eyre::Result<()> {
-        let mut event_loop_lock = event_loop.lock().await;
-        let event = event_loop_lock.poll().await?;
-        if let rumqttc::Event::Incoming(packet) = event
+        in_flight.track(&event, qos);
+        if let Event::Incoming(packet) = event
             && let Packet::Publish(publish) = packet
         {
             let payload_str = std::str::from_utf8(&publish.payload)?;
 
             if !payload_str.is_empty() {
                 let text = try_decrypt_claims(payload_str, secret)?;
-                tx.send(text)?;
+                let envelope: MqttEnvelope = serde_json::from_str(&text)?;
+                if filter.accept(&envelope, publish.retain) {
+                    tx.send(envelope.message)?;
+                }
             }
         }
 
@@ -162,21 +456,70 @@ impl SignalingInterface for SignalingMqtt {
         Ok(())
     }
     async fn send_message(&mut self, message: SignalingMessage) -> color_eyre::Result<()> {
-        let json = serde_json::to_string(&message)?;
+        let json = message.to_json()?;
         self.send(json, self.retain_flag).await?;
         self.retain_flag = false;
         Ok(())
     }
     async fn receive_message(&mut self) -> color_eyre::Result<Option<SignalingMessage>> {
-        let mut result: Option<SignalingMessage> = None;
-        let message = self.rx.recv().await;
-
-        if let Some(message) = message
-            && let Ok(signaling_message) = serde_json::from_str(&message)
-        {
-            result = Some(signaling_message);
+        match self.rx.recv().await {
+            Some(message) => Ok(SignalingMessage::from_json(&message)?),
+            None => Ok(None),
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discard_stale_and_repeated() {
+        let mut filter = MqttFilter::new(60);
+        let session = Uuid::new_v4();
+
+        let mut stale = MqttEnvelope::new(session, 1, "".to_string());
+        stale.timestamp -= 120;
+        assert!(!filter.accept(&stale, true));
+
+        let fresh = MqttEnvelope::new(session, 1, "".to_string());
+        assert!(filter.accept(&fresh, true));
+        assert!(!filter.accept(&fresh.refreshed(), true));
+        assert!(filter.accept(&MqttEnvelope::new(session, 2, "".to_string()), false));
+    }
+
+    #[test]
+    fn only_drops_are_recoverable() {
+        let io = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        assert!(is_recoverable(&ConnectionError::Io(io)));
+        assert!(is_recoverable(&ConnectionError::ConnectionRefused(
+            ConnectReturnCode::ServiceUnavailable
+        )));
+        assert!(!is_recoverable(&ConnectionError::ConnectionRefused(
+            ConnectReturnCode::NotAuthorized
+        )));
+        assert_eq!(reconnect_delay(20), MAX_RECONNECT_DELAY);
+    }
+
+    #[tokio::test]
+    async fn in_flight_waits_for_the_right_ack() {
+        let in_flight = InFlight::default();
+        in_flight.add();
+
+        // Writing it out isn't enough past QoS 0
+        in_flight.track(&Event::Outgoing(Outgoing::Publish(1)), QoS::ExactlyOnce);
+        in_flight.track(
+            &Event::Incoming(Packet::PubAck(rumqttc::PubAck::new(1))),
+            QoS::ExactlyOnce,
+        );
+        let pending = time::timeout(Duration::from_millis(10), in_flight.settled()).await;
+        assert!(pending.is_err());
 
-        Ok(result)
+        in_flight.track(
+            &Event::Incoming(Packet::PubComp(rumqttc::PubComp::new(1))),
+            QoS::ExactlyOnce,
+        );
+        let settled = time::timeout(Duration::from_millis(10), in_flight.settled()).await;
+        assert!(settled.is_ok());
     }
 }
@@ -2,11 +2,80 @@ use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::client::error::TappiError;
+
+/// Signaling wire format version, bump it on incompatible changes
+pub const PROTOCOL_VERSION: u32 = 1;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
 pub enum SignalingMessage {
     Uuid(Uuid),
     Offer(String),
     Answer(String),
+    /// Anything a newer peer might send, gets ignored
+    #[serde(skip)]
+    Unknown,
+}
+impl SignalingMessage {
+    pub fn to_json(&self) -> color_eyre::Result<String> {
+        let packet = SignalingPacket {
+            version: PROTOCOL_VERSION,
+            message: serde_json::to_value(self)?,
+        };
+        Ok(serde_json::to_string(&packet)?)
+    }
+
+    /// Returns `None` for anything that isn't a signaling packet at all,
+    /// a peer from before the versioning is a mismatch since it can't read ours either
+    pub fn from_json(text: &str) -> Result<Option<Self>, TappiError> {
+        let packet: SignalingPacket = match serde_json::from_str(text) {
+            Ok(packet) => packet,
+            Err(_) if serde_json::from_str::<BareMessage>(text).is_ok() => {
+                return Err(TappiError::ProtocolMismatch {
+                    ours: format!("signaling v{}", PROTOCOL_VERSION),
+                    theirs: "unversioned signaling".to_string(),
+                });
+            }
+            Err(err) => {
+                log::warn!("Dropped a malformed signaling message: {}", err);
+                return Ok(None);
+            }
+        };
+        if packet.version != PROTOCOL_VERSION {
+            log::warn!(
+                "Peer uses signaling protocol v{}, we use v{}",
+                packet.version,
+                PROTOCOL_VERSION
+            );
+        }
+
+        let tagged = packet.message.get("type").is_some_and(|t| t.is_string());
+        match serde_json::from_value(packet.message) {
+            Ok(message) => Ok(Some(message)),
+            Err(_) if tagged => Ok(Some(SignalingMessage::Unknown)),
+            Err(err) => {
+                log::warn!("Dropped an unreadable signaling message: {}", err);
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// What actually goes over the wire
+#[derive(Serialize, Deserialize)]
+struct SignalingPacket {
+    version: u32,
+    message: serde_json::Value,
+}
+
+/// How the messages went over the wire before they were versioned
+#[derive(Deserialize)]
+#[allow(dead_code)]
+enum BareMessage {
+    Uuid(Uuid),
+    Offer(String),
+    Answer(String),
 }
 
 // Simple enough!
@@ -17,3 +86,35 @@ pub trait SignalingInterface {
     async fn send_message(&mut self, message: SignalingMessage) -> color_eyre::Result<()>;
     async fn receive_message(&mut self) -> color_eyre::Result<Option<SignalingMessage>>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_messages_survive_parsing() {
+        let offer = SignalingMessage::Offer("sdp".to_string())
+            .to_json()
+            .unwrap();
+        assert!(matches!(
+            SignalingMessage::from_json(&offer),
+            Ok(Some(SignalingMessage::Offer(sdp))) if sdp == "sdp"
+        ));
+
+        let future = r#"{"version":2,"message":{"type":"Candidate","data":"c"}}"#;
+        assert!(matches!(
+            SignalingMessage::from_json(future),
+            Ok(Some(SignalingMessage::Unknown))
+        ));
+        assert!(matches!(SignalingMessage::from_json("not json"), Ok(None)));
+    }
+
+    #[test]
+    fn older_peers_are_a_mismatch() {
+        let bare = r#"{"Offer":"sdp"}"#;
+        assert!(matches!(
+            SignalingMessage::from_json(bare),
+            Err(TappiError::ProtocolMismatch { .. })
+        ));
+    }
+}
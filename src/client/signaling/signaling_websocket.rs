@@ -4,7 +4,7 @@ use futures::{
     SinkExt, StreamExt,
     stream::{SplitSink, SplitStream},
 };
-use std::sync::Arc;
+use std::{net::Ipv6Addr, sync::Arc};
 use tokio::{
     net::TcpStream,
     sync::{
@@ -18,7 +18,10 @@ use url::Url;
 
 use crate::{
     app::models::ErrorTX,
-    client::signaling::signaling_solution::{SignalingInterface, SignalingMessage},
+    client::{
+        error::TappiError,
+        signaling::signaling_solution::{SignalingInterface, SignalingMessage},
+    },
 };
 
 pub struct SignalingWebsocket {
@@ -75,6 +78,7 @@ impl SignalingWebsocket {
     ) -> color_eyre::Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
         let (socket, _) = connect_async(url.as_str())
             .await
+            .map_err(|err| TappiError::SignalingFailed(err.to_string()))
             .wrap_err("Failed to establish a WebSocket connection")?;
 
         log::info!("WebSocket connection was established");
@@ -83,13 +87,30 @@ impl SignalingWebsocket {
     }
 
     // Build a request url
-    pub fn build_url(address: &str, room_id: &str) -> color_eyre::Result<Url> {
-        let base_address = format!("ws://{}/room", address);
+    pub fn build_url(
+        address: &str,
+        port: u16,
+        room_id: &str,
+        name: Option<&str>,
+    ) -> color_eyre::Result<Url> {
+        let base_address = format!("ws://{}:{}/room", Self::format_host(address), port);
         let mut url = Url::parse(&base_address)?;
         url.query_pairs_mut().append_pair("room", room_id);
+        if let Some(name) = name {
+            url.query_pairs_mut().append_pair("name", name);
+        }
         Ok(url)
     }
 
+    // IPv6 literals have to be bracketed inside of a URL
+    fn format_host(address: &str) -> String {
+        if address.parse::<Ipv6Addr>().is_ok() {
+            format!("[{}]", address)
+        } else {
+            address.to_string()
+        }
+    }
+
     pub fn init(&mut self) {
         self.receive_task = Some(self.spawn_receive_task());
     }
@@ -151,6 +172,12 @@ impl SignalingWebsocket {
         match socket_rx_lock.next().await {
             Some(result) => {
                 let msg = result.wrap_err("WebSocket message error")?;
+                // The server explains rejections in the close frame
+                if let Message::Close(Some(frame)) = &msg
+                    && !frame.reason.is_empty()
+                {
+                    return Err(TappiError::ServerRejected(frame.reason.to_string()).into());
+                }
                 let msg_text = msg.to_text()?.to_string();
                 tx.send(msg_text)?;
                 Ok(None)
@@ -171,20 +198,34 @@ impl SignalingInterface for SignalingWebsocket {
     }
 
     async fn send_message(&mut self, message: SignalingMessage) -> color_eyre::Result<()> {
-        let json = serde_json::to_string(&message)?;
+        let json = message.to_json()?;
         self.send(json).await?;
         Ok(())
     }
     async fn receive_message(&mut self) -> color_eyre::Result<Option<SignalingMessage>> {
-        let mut result: Option<SignalingMessage> = None;
-        let message = self.rx.recv().await;
-
-        if let Some(message) = message
-            && let Ok(signaling_message) = serde_json::from_str(&message)
-        {
-            result = Some(signaling_message);
+        match self.rx.recv().await {
+            Some(message) => Ok(SignalingMessage::from_json(&message)?),
+            None => Ok(None),
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_url_hosts() {
+        let url = SignalingWebsocket::build_url("127.0.0.1", 3030, "room", None).unwrap();
+        assert_eq!(url.as_str(), "ws://127.0.0.1:3030/room?room=room");
+
+        let url = SignalingWebsocket::build_url("::1", 3030, "room", None).unwrap();
+        assert_eq!(url.as_str(), "ws://[::1]:3030/room?room=room");
+
+        let url = SignalingWebsocket::build_url("[::1]", 3030, "room", None).unwrap();
+        assert_eq!(url.as_str(), "ws://[::1]:3030/room?room=room");
 
-        Ok(result)
+        let url = SignalingWebsocket::build_url("example.com", 8080, "room", Some("me")).unwrap();
+        assert_eq!(url.as_str(), "ws://example.com:8080/room?room=room&name=me");
     }
 }
@@ -0,0 +1,156 @@
+use std::collections::VecDeque;
+
+use crate::{
+    app::{
+        app_event::DebugDataChannel,
+        file_manager::{FileId, FileManager, OutputFile},
+        models::Maid,
+    },
+    client::{
+        compress::CompressAlgo,
+        message::{Message, TransferSummary},
+        payload,
+        rtc_base::WebConnection,
+    },
+};
+
+/// Drives outgoing transfers independently of the UI
+///
+/// Both calls spawn a task that reports back via the maid's event channel
+pub struct Transfer;
+impl Transfer {
+    /// Offers the transfer and sends the metadata of every queued file,
+    /// results in a `MetaSent` event
+    pub fn send_all_meta(
+        maid: Maid,
+        wc: &WebConnection,
+        ddc: DebugDataChannel,
+        output_files: VecDeque<OutputFile>,
+        chunk_size: usize,
+        sender_name: Option<String>,
+        retries: u32,
+    ) {
+        let mut buffer_watch_rx = wc.buffer_watch_tx.subscribe();
+        let summary = TransferSummary::new(sender_name, output_files.iter());
+
+        tokio::spawn(async move {
+            let token = maid.token.child_token();
+            let send = async {
+                let offer = Message::TransferOffer(summary);
+                payload::send_message(ddc.dc.clone(), &mut buffer_watch_rx, offer).await?;
+                payload::send_all_meta(
+                    ddc.dc,
+                    &output_files,
+                    chunk_size,
+                    &mut buffer_watch_rx,
+                    Some(&maid.event_tx),
+                    retries,
+                )
+                .await
+            };
+            tokio::select! {
+                _ = token.cancelled() => {},
+                result = send => {
+                    if let Err(err) = result { maid.error_tx.send_error(err); }
+                },
+            }
+        });
+    }
+
+    /// Sends a single message on its own task
+    pub fn send_message(maid: Maid, wc: &WebConnection, ddc: DebugDataChannel, message: Message) {
+        let mut buffer_watch_rx = wc.buffer_watch_tx.subscribe();
+
+        tokio::spawn(async move {
+            let token = maid.token.child_token();
+            tokio::select! {
+                _ = token.cancelled() => {},
+                result = payload::send_message(ddc.dc, &mut buffer_watch_rx, message) => {
+                    if let Err(err) = result { maid.error_tx.send_error(err); }
+                }
+            }
+        });
+    }
+
+    /// Sends the next queued file that has any data, results in an `OutputFileFinished` event
+    ///
+    /// Returns the file that went out, none once there's nothing left to send
+    #[allow(clippy::too_many_arguments)]
+    pub fn send_next_file(
+        maid: Maid,
+        wc: &WebConnection,
+        ddc: DebugDataChannel,
+        file_manager: &mut FileManager,
+        chunk_size: usize,
+        crc: bool,
+        compress: Option<CompressAlgo>,
+        retries: u32,
+    ) -> Option<FileId> {
+        while let Some(of) = file_manager.get_next_output_file() {
+            if !of.meta.is_dir && of.meta.size > 0 {
+                let id = of.id;
+                Self::send_file_data(maid, wc, ddc, of, chunk_size, crc, compress, retries);
+                return Some(id);
+            }
+        }
+        None
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn send_file_data(
+        maid: Maid,
+        wc: &WebConnection,
+        ddc: DebugDataChannel,
+        output_file: OutputFile,
+        chunk_size: usize,
+        crc: bool,
+        compress: Option<CompressAlgo>,
+        retries: u32,
+    ) {
+        let mut buffer_watch_rx = wc.buffer_watch_tx.subscribe();
+        let mut pause_rx = wc.pause_tx.subscribe();
+        let pacer = wc.pacer.clone();
+        let throttle = wc.throttle;
+
+        tokio::spawn(async move {
+            let token = maid.token.child_token();
+            tokio::select! {
+                _ = token.cancelled() => {},
+                result = payload::send_file_data(
+                    ddc.dc, &output_file, chunk_size, crc, compress, &mut buffer_watch_rx, &mut pause_rx, pacer.as_ref(), Some(&maid.event_tx), throttle, retries
+                ) => {
+                    if let Err(err) = result { maid.error_tx.send_error(err); }
+                }
+            }
+        });
+    }
+
+    /// Sends a file again from `offset` on, runs next to whatever is being sent right now
+    #[allow(clippy::too_many_arguments)]
+    pub fn resend_file_data(
+        maid: Maid,
+        wc: &WebConnection,
+        ddc: DebugDataChannel,
+        output_file: OutputFile,
+        offset: usize,
+        chunk_size: usize,
+        compress: Option<CompressAlgo>,
+        retries: u32,
+    ) {
+        let mut buffer_watch_rx = wc.buffer_watch_tx.subscribe();
+        let mut pause_rx = wc.pause_tx.subscribe();
+        let pacer = wc.pacer.clone();
+
+        tokio::spawn(async move {
+            let token = maid.token.child_token();
+            tokio::select! {
+                _ = token.cancelled() => {},
+                result = payload::resend_file_data(
+                    ddc.dc, &output_file, offset, chunk_size, compress, &mut buffer_watch_rx, &mut pause_rx, pacer.as_ref(), retries
+                ) => {
+                    if let Err(err) = result { maid.error_tx.send_error(err); }
+                }
+            }
+        });
+    }
+}
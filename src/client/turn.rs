@@ -0,0 +1,69 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use color_eyre::eyre::eyre;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::cli::ClientArgs;
+
+/// Lifetime of generated credentials, coturn's REST API suggests a day
+pub const DEFAULT_TURN_TTL: u64 = 24 * 60 * 60;
+
+/// Time-limited TURN credentials out of a shared secret, the way coturn's `use-auth-secret` does it
+///
+/// The username is the expiry timestamp, optionally followed by `:user`,
+/// and the credential is the base64 HMAC-SHA1 of it
+#[derive(Clone, Debug)]
+pub struct TurnAuth {
+    secret: String,
+    user: Option<String>,
+    ttl: Duration,
+}
+impl TurnAuth {
+    /// Only with `--turn-secret`, static credentials are used as they are otherwise
+    pub fn from_args(args: &ClientArgs) -> Option<Self> {
+        let secret = args.turn_secret.clone()?;
+        Some(Self {
+            secret,
+            user: args.username.clone(),
+            ttl: Duration::from_secs(args.turn_ttl),
+        })
+    }
+
+    /// Username and credential good until `now` plus the lifetime
+    pub fn credentials(&self, now: SystemTime) -> color_eyre::Result<(String, String)> {
+        let expiry = (now + self.ttl)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let username = match &self.user {
+            Some(user) => format!("{}:{}", expiry, user),
+            None => expiry.to_string(),
+        };
+
+        let mut mac = Hmac::<Sha1>::new_from_slice(self.secret.as_bytes())
+            .map_err(|err| eyre!("Invalid TURN secret: {}", err))?;
+        mac.update(username.as_bytes());
+        let credential = STANDARD.encode(mac.finalize().into_bytes());
+        Ok((username, credential))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rest_api_credentials() {
+        let auth = TurnAuth {
+            secret: "north".to_string(),
+            user: Some("tappi".to_string()),
+            ttl: Duration::from_secs(3600),
+        };
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        let (username, credential) = auth.credentials(now).unwrap();
+        assert_eq!(username, "4600:tappi");
+        // `echo -n 4600:tappi | openssl dgst -sha1 -hmac north -binary | base64`
+        assert_eq!(credential, "zvR+YGH0/OZiRnEtz2MdiUi5toQ=");
+    }
+}
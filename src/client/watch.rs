@@ -0,0 +1,77 @@
+use color_eyre::eyre::Context;
+use notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{DebounceEventResult, DebouncedEventKind, Debouncer, new_debouncer};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::app::app_event::AppEventClient;
+use crate::app::event::BasicEvent;
+use crate::app::models::Maid;
+
+/// How long a file has to stay untouched before it's considered written
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Watches a directory for `--watch` and collects the files that appear in it
+pub struct DirWatch {
+    pub dir: PathBuf,
+    seen: HashSet<PathBuf>, // Files that were already there or queued
+    pending: Vec<PathBuf>,  // New files waiting for the current batch to finish
+    _debouncer: Debouncer<RecommendedWatcher>, // Stops watching when dropped
+}
+impl DirWatch {
+    /// Results in `WatchedFiles` events, files that are already there are left alone
+    pub fn new(maid: Maid, dir: &Path) -> color_eyre::Result<Self> {
+        let dir = dir
+            .canonicalize()
+            .wrap_err_with(|| format!("Couldn't watch {:?}", dir))?;
+        let seen = fs::read_dir(&dir)?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .collect();
+
+        let event_tx = maid.event_tx.clone();
+        let mut debouncer = new_debouncer(DEBOUNCE, move |result: DebounceEventResult| {
+            match result {
+                Ok(events) => {
+                    // Continuous events mean the file is still being written to
+                    let paths: Vec<PathBuf> = events
+                        .into_iter()
+                        .filter(|e| e.kind == DebouncedEventKind::Any)
+                        .map(|e| e.path)
+                        .collect();
+                    if !paths.is_empty() {
+                        let event = AppEventClient::WatchedFiles(paths);
+                        event_tx.send(BasicEvent::from(event)).ok();
+                    }
+                }
+                Err(err) => log::warn!("Watch error: {}", err),
+            }
+        })?;
+        debouncer
+            .watcher()
+            .watch(&dir, RecursiveMode::NonRecursive)?;
+        log::info!("Watching {:?}", dir);
+
+        Ok(Self {
+            dir,
+            seen,
+            pending: vec![],
+            _debouncer: debouncer,
+        })
+    }
+
+    /// Keeps the files it hasn't seen before
+    pub fn accept(&mut self, paths: Vec<PathBuf>) {
+        for path in paths {
+            if path.is_file() && self.seen.insert(path.clone()) {
+                self.pending.push(path);
+            }
+        }
+    }
+
+    pub fn take_pending(&mut self) -> Vec<PathBuf> {
+        std::mem::take(&mut self.pending)
+    }
+}
@@ -1,17 +1,151 @@
-use log::LevelFilter;
-use simplelog::{CombinedLogger, Config, WriteLogger};
-use std::fs::File;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use simplelog::{CombinedLogger, Config, SharedLogger, WriteLogger};
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::Write,
+    sync::{Arc, Mutex},
+};
 
 use crate::cli::Cli;
 
-pub fn init_logger(cli: &Cli) -> color_eyre::Result<()> {
+/// Lines the log panel keeps, older ones get dropped
+pub const LOG_CAPACITY: usize = 1000;
+
+#[derive(Clone, Debug)]
+pub struct LogLine {
+    pub level: Level,
+    pub text: String,
+}
+
+/// Recent log lines, shared between the logger and the log panel
+#[derive(Clone, Default)]
+pub struct LogBuffer(Arc<Mutex<VecDeque<LogLine>>>);
+impl LogBuffer {
+    pub fn push(&self, line: LogLine) {
+        let mut lines = self.0.lock().unwrap();
+        if lines.len() == LOG_CAPACITY {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+    pub fn lines(&self) -> Vec<LogLine> {
+        self.0.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// Logs into the buffer next to the file so the TUI can show them
+struct RingLogger {
+    level: LevelFilter,
+    config: Config,
+    buffer: LogBuffer,
+}
+impl Log for RingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.buffer.push(LogLine {
+                level: record.level(),
+                text: format!("[{}] {}", record.target(), record.args()),
+            });
+        }
+    }
+    fn flush(&self) {}
+}
+impl SharedLogger for RingLogger {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+    fn config(&self) -> Option<&Config> {
+        Some(&self.config)
+    }
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
+
+/// Truncated unless `--append-log`, the previous runs stay above the separator then
+fn open_log_file(cli: &Cli) -> color_eyre::Result<File> {
+    if !cli.append_log {
+        return Ok(File::create(&cli.log_file)?);
+    }
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&cli.log_file)?;
+    writeln!(file, "{}", separator())?;
+    Ok(file)
+}
+
+/// The log lines carry the time already
+fn separator() -> String {
+    format!(
+        "----- {} {} (pid {}) -----",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        std::process::id()
+    )
+}
+
+/// Returns the buffer the log panel reads from, it stays empty with logging off
+pub fn init_logger(cli: &Cli) -> color_eyre::Result<LogBuffer> {
+    let buffer = LogBuffer::default();
     if cli.log_level != LevelFilter::Off {
-        CombinedLogger::init(vec![WriteLogger::new(
-            cli.log_level,
-            Config::default(),
-            File::create(cli.log_file.clone())?,
-        )])?;
+        CombinedLogger::init(vec![
+            WriteLogger::new(cli.log_level, Config::default(), open_log_file(cli)?),
+            Box::new(RingLogger {
+                level: cli.log_level,
+                config: Config::default(),
+                buffer: buffer.clone(),
+            }),
+        ])?;
     }
 
-    Ok(())
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use clap::Parser;
+
+    #[test]
+    fn buffer_drops_the_oldest() {
+        let buffer = LogBuffer::default();
+        for i in 0..LOG_CAPACITY + 5 {
+            buffer.push(LogLine {
+                level: Level::Info,
+                text: i.to_string(),
+            });
+        }
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), LOG_CAPACITY);
+        assert_eq!(lines[0].text, "5");
+    }
+
+    #[test]
+    fn appended_runs_are_separated() {
+        let dir = TempDir::new("log");
+        let path = dir.join("tappi.log");
+        let cli = |append: bool| {
+            let mut args = vec!["tappi-share", "-f", path.to_str().unwrap(), "server"];
+            if append {
+                args.insert(1, "--append-log");
+            }
+            Cli::try_parse_from(args).unwrap()
+        };
+
+        writeln!(open_log_file(&cli(false)).unwrap(), "first run").unwrap();
+        writeln!(open_log_file(&cli(true)).unwrap(), "second run").unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text, format!("first run\n{}\nsecond run\n", separator()));
+
+        // Truncating is still the default
+        open_log_file(&cli(false)).unwrap();
+        assert!(std::fs::read_to_string(&path).unwrap().is_empty());
+    }
 }
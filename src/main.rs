@@ -1,11 +1,25 @@
-use crate::{app::app_main::App, cli::Cli, logger::init_logger};
-use clap::Parser;
+use color_eyre::eyre::eyre;
+use crossterm::{
+    event::{DisableBracketedPaste, EnableBracketedPaste},
+    execute,
+};
+use std::io;
+
+use crate::{
+    app::{app_main::App, encrypt::Secret},
+    cli::Commands,
+    client::{diagnose, loopback},
+    logger::init_logger,
+};
 
 pub mod app;
 pub mod cli;
 pub mod client;
 pub mod logger;
+pub mod profile;
 pub mod server;
+#[cfg(test)]
+mod test_util;
 pub mod ui;
 
 #[tokio::main]
@@ -22,14 +36,40 @@ async fn main() -> color_eyre::Result<()> {
 async fn startup() -> color_eyre::Result<()> {
     color_eyre::install()?; // Init debug
 
-    let args = Cli::parse(); // Parse arguments
+    let mut args = profile::load()?; // Parse arguments
+    args.resolve_secret()?;
+
+    // None of these need a terminal
+    if let Commands::GenSecret = &args.app_mode {
+        println!("{}", Secret::generate()?);
+        return Ok(());
+    }
+    if let Commands::Loopback(loopback_args) = &args.app_mode {
+        init_logger(&args)?;
+        let verified = loopback::run(loopback_args.clone()).await?;
+        println!("Loopback transfer succeeded, {} entries verified", verified);
+        return Ok(());
+    }
+    if let Commands::Diagnose(diagnose_args) = &args.app_mode {
+        init_logger(&args)?;
+        if !diagnose::run(diagnose_args.clone()).await? {
+            return Err(eyre!("Some of the ICE checks failed"));
+        }
+        println!("All ICE checks passed");
+        return Ok(());
+    }
+
     let mut terminal = ratatui::init(); // Create terminal
+    execute!(io::stdout(), EnableBracketedPaste)?; // Dropped files arrive as a paste
 
-    init_logger(&args)?; // Init logger
+    let log_buffer = init_logger(&args)?; // Init logger
 
     log::info!("Application started");
-    let result = App::new(args.clone())?.run(&args, &mut terminal).await; // Run main loop
+    let result = App::new(args.clone(), log_buffer)?
+        .run(&args, &mut terminal)
+        .await; // Run main loop
 
+    execute!(io::stdout(), DisableBracketedPaste).ok();
     ratatui::restore(); // Restore terminal
     result
 }
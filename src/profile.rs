@@ -0,0 +1,198 @@
+use clap::{ArgMatches, Command, CommandFactory, FromArgMatches, Parser, parser::ValueSource};
+use color_eyre::eyre::{Context, eyre};
+use std::{fs, path::PathBuf};
+
+use crate::cli::{Cli, Commands, SignalingSolutions};
+
+/// Parses the arguments, merging them into a profile if one was given
+pub fn load() -> color_eyre::Result<Cli> {
+    let matches = relaxed(Cli::command())
+        .try_get_matches()
+        .unwrap_or_else(|err| err.exit());
+
+    let cli = if let Some(name) = matches.get_one::<String>("profile") {
+        let mut cli = read(name)?;
+        overlay(&mut cli, matches)?;
+        cli
+    } else {
+        Cli::parse()
+    };
+
+    if let Some(name) = &cli.save_profile {
+        write(name, &cli)?;
+    }
+
+    Ok(cli)
+}
+
+/// Whatever is required can come from a profile, anything unknown is still an error
+fn relaxed(command: Command) -> Command {
+    command
+        .subcommand_required(false)
+        .arg_required_else_help(false)
+        .mut_args(|arg| arg.required(false))
+        .mut_subcommands(relaxed)
+}
+
+fn read(name: &str) -> color_eyre::Result<Cli> {
+    let path = profile_path(name)?;
+    let json =
+        fs::read_to_string(&path).wrap_err_with(|| format!("Couldn't read profile {:?}", path))?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+fn write(name: &str, cli: &Cli) -> color_eyre::Result<()> {
+    let path = profile_path(name)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(cli)?)
+        .wrap_err_with(|| format!("Couldn't write profile {:?}", path))?;
+    Ok(())
+}
+
+fn profile_path(name: &str) -> color_eyre::Result<PathBuf> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if name.is_empty() || !name.chars().all(valid) {
+        return Err(eyre!(
+            "Profile names may only contain letters, digits, '-' and '_'"
+        ));
+    }
+
+    let config_dir = dirs::config_dir().ok_or(eyre!("Couldn't find the config directory"))?;
+    Ok(config_dir
+        .join("tappi-share")
+        .join("profiles")
+        .join(format!("{}.json", name)))
+}
+
+/// Applies explicitly passed arguments on top of the profile, one subcommand level at a time
+fn overlay(cli: &mut Cli, matches: ArgMatches) -> color_eyre::Result<()> {
+    let (matches, subcommand) = explicit_only(matches);
+    cli.update_from_arg_matches(&matches)?;
+
+    let Some((name, matches)) = subcommand else {
+        return Ok(());
+    };
+    let (matches, subcommand) = explicit_only(matches);
+    match (&mut cli.app_mode, name.as_str()) {
+        (Commands::Client(args), "client") => {
+            args.update_from_arg_matches(&matches)?;
+
+            if let Some((name, matches)) = subcommand {
+                let (matches, _) = explicit_only(matches);
+                match (&mut args.signaling_mode, name.as_str()) {
+                    (SignalingSolutions::Manual(args), "manual") => {
+                        args.update_from_arg_matches(&matches)?
+                    }
+                    (SignalingSolutions::Socket(args), "socket") => {
+                        args.update_from_arg_matches(&matches)?
+                    }
+                    (SignalingSolutions::Mqtt(args), "mqtt") => {
+                        args.update_from_arg_matches(&matches)?
+                    }
+                    _ => {
+                        return Err(eyre!(
+                            "The profile uses a different signaling solution than {}",
+                            name
+                        ));
+                    }
+                }
+            }
+        }
+        (Commands::Server(args), "server") => args.update_from_arg_matches(&matches)?,
+        (Commands::Loopback(args), "loopback") => args.update_from_arg_matches(&matches)?,
+        (Commands::Diagnose(args), "diagnose") => args.update_from_arg_matches(&matches)?,
+        (Commands::GenSecret, "gen-secret") => {}
+        _ => {
+            return Err(eyre!(
+                "The profile was saved for a different mode than {}",
+                name
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Drops the default values so they don't override the profile, splits off the subcommand
+fn explicit_only(mut matches: ArgMatches) -> (ArgMatches, Option<(String, ArgMatches)>) {
+    let subcommand = matches.remove_subcommand();
+    let implicit: Vec<String> = matches
+        .ids()
+        .filter(|id| matches.value_source(id.as_str()) != Some(ValueSource::CommandLine))
+        .map(|id| id.to_string())
+        .collect();
+    for id in implicit {
+        matches.try_clear_id(&id).ok();
+    }
+
+    (matches, subcommand)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(args: &[&str]) -> ArgMatches {
+        relaxed(Cli::command()).try_get_matches_from(args).unwrap()
+    }
+
+    #[test]
+    fn unknown_flags_arent_dropped() {
+        let args = ["tappi-share", "-P", "work", "client", "--chunk-sise", "32"];
+        assert!(relaxed(Cli::command()).try_get_matches_from(args).is_err());
+        // Missing the required ones is fine, the profile has them
+        let args = ["tappi-share", "-P", "work", "client", "mqtt", "-r", "them"];
+        assert!(relaxed(Cli::command()).try_get_matches_from(args).is_ok());
+    }
+
+    #[test]
+    fn explicit_arguments_override_profile() {
+        let mut cli = Cli::try_parse_from([
+            "tappi-share",
+            "client",
+            "-s",
+            "32",
+            "-i",
+            "mqtt",
+            "-l",
+            "me",
+            "-r",
+            "you",
+        ])
+        .unwrap();
+
+        overlay(
+            &mut cli,
+            matches(&[
+                "tappi-share",
+                "-P",
+                "work",
+                "client",
+                "-o",
+                "out",
+                "mqtt",
+                "-r",
+                "them",
+            ]),
+        )
+        .unwrap();
+
+        let Commands::Client(args) = &cli.app_mode else {
+            panic!("Expected client mode");
+        };
+        assert_eq!(args.chunk_size, 32 * 1024); // Kept from the profile
+        assert!(args.ignore_empty);
+        assert_eq!(args.output_dir, PathBuf::from("out"));
+        let SignalingSolutions::Mqtt(mqtt) = &args.signaling_mode else {
+            panic!("Expected MQTT signaling");
+        };
+        assert_eq!(mqtt.local_name, "me");
+        assert_eq!(mqtt.remote_name, "them");
+        assert_eq!(cli.profile.as_deref(), Some("work"));
+
+        // Switching modes isn't a merge anymore
+        assert!(overlay(&mut cli, matches(&["tappi-share", "server"])).is_err());
+    }
+}
@@ -1,2 +1,3 @@
 pub mod signal;
 pub mod types;
+pub mod web;
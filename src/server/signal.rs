@@ -2,15 +2,23 @@ use futures::{SinkExt, StreamExt, stream::SplitSink};
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::mpsc::UnboundedSender;
-use warp::Filter;
 use warp::filters::ws;
 use warp::filters::ws::{Message, WebSocket};
+use warp::{Filter, Rejection, Reply};
 
 use crate::app::app_event::AppEventServer;
 use crate::app::event::{BasicEvent, BasicEventSenderExt};
 use crate::app::models::Maid;
 use crate::cli::ServerArgs;
-use crate::server::types::{History, Room, RoomId, RoomUser, Rooms, UserId, UserMessage};
+use crate::server::types::{
+    FirstRoom, History, Room, RoomId, RoomListing, RoomUser, Rooms, UserId, UserMessage,
+};
+use crate::server::web;
+
+/// Maximum length of a user-proposed name
+const MAX_NAME_LENGTH: usize = 24;
+/// WebSocket close code for rejected joins
+const POLICY_VIOLATION: u16 = 1008;
 
 // Custom rejection for forbidden access
 #[derive(Debug)]
@@ -20,8 +28,12 @@ impl warp::reject::Reject for Forbidden {}
 pub async fn main(maid: Maid, args: ServerArgs) -> color_eyre::Result<()> {
     let rooms: Rooms = Rooms::default();
 
+    let rooms_route = rooms_route(args.discovery, rooms.clone());
     let maid = warp::any().map(move || maid.clone());
     let rooms = warp::any().map(move || rooms.clone());
+    let petname_words = args.petname_words;
+    let max_rooms = args.max_rooms;
+    let once = args.once.then(FirstRoom::default);
 
     let room_route = warp::path("room".to_string())
         .and(warp::ws())
@@ -29,28 +41,92 @@ pub async fn main(maid: Maid, args: ServerArgs) -> color_eyre::Result<()> {
         .and(maid)
         .and(rooms)
         .and_then(
-            |ws: ws::Ws, query: HashMap<String, String>, maid: Maid, rooms: Rooms| async move {
-                if let Some(room_id) = query.get("room") {
-                    let room_id: String = room_id.clone();
-                    let reply = ws.on_upgrade(move |socket| {
-                        connect(socket, maid.clone(), rooms.clone(), room_id.clone())
-                    });
-
-                    Ok(reply)
-                } else {
-                    Err(warp::reject::custom(Forbidden))
+            move |ws: ws::Ws, query: HashMap<String, String>, maid: Maid, rooms: Rooms| {
+                let once = once.clone();
+                async move {
+                    if let Some(room_id) = query.get("room") {
+                        let room_id: String = room_id.clone();
+                        let name = query.get("name").and_then(|n| sanitize_name(n));
+                        let observe = query.get("observe").is_some_and(|o| o == "1");
+                        let reply = ws.on_upgrade(move |socket| {
+                            connect(
+                                socket,
+                                maid.clone(),
+                                rooms.clone(),
+                                room_id,
+                                name,
+                                observe,
+                                petname_words,
+                                max_rooms,
+                                once,
+                            )
+                        });
+
+                        Ok(reply)
+                    } else {
+                        Err(warp::reject::custom(Forbidden))
+                    }
                 }
             },
         );
 
-    warp::serve(room_route).run(args.address).await;
+    if args.serve_web {
+        log::info!("Serving the web receiver at http://{}/web", args.address);
+    }
+    if args.discovery {
+        log::info!("Listing the open rooms at http://{}/rooms", args.address);
+    }
+    warp::serve(room_route.or(rooms_route).or(web::routes(args.serve_web)))
+        .run(args.address)
+        .await;
     log::info!("Server started at ws://{}/room", args.address);
 
     Ok(())
 }
 
-#[allow(unused_assignments)]
-async fn connect(ws: WebSocket, maid: Maid, rooms: Rooms, room_id: RoomId) {
+/// `GET /rooms` for `--discovery`, rejects everything unless `enabled` like the web routes
+fn rooms_route(
+    enabled: bool,
+    rooms: Rooms,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::get().and(warp::path!("rooms")).and_then(move || {
+        let rooms = rooms.clone();
+        async move {
+            if !enabled {
+                return Err(warp::reject::not_found());
+            }
+            Ok(warp::reply::json(&list_rooms(&rooms).await))
+        }
+    })
+}
+
+/// Sorted by name so the list doesn't shuffle between requests
+async fn list_rooms(rooms: &Rooms) -> Vec<RoomListing> {
+    let rooms: Vec<Arc<Room>> = rooms.lock().await.values().cloned().collect();
+    let mut listings = Vec::with_capacity(rooms.len());
+    for room in rooms {
+        listings.push(RoomListing {
+            room: room.id.clone(),
+            users: room.users.lock().await.len(),
+            capacity: room.capacity,
+        });
+    }
+    listings.sort_by(|a, b| a.room.cmp(&b.room));
+    listings
+}
+
+#[allow(unused_assignments, clippy::too_many_arguments)]
+async fn connect(
+    ws: WebSocket,
+    maid: Maid,
+    rooms: Rooms,
+    room_id: RoomId,
+    name: Option<String>,
+    observe: bool,
+    petname_words: u8,
+    max_rooms: Option<usize>,
+    once: Option<FirstRoom>,
+) {
     // Bookkeeping
     let mut user: Option<Arc<RoomUser>> = None;
 
@@ -60,7 +136,33 @@ async fn connect(ws: WebSocket, maid: Maid, rooms: Rooms, room_id: RoomId) {
     let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Message>(); // Multi-tx, single-rx
     {
         // Try joining room
-        user = join_room(maid.event_tx.clone(), rooms.clone(), &room_id, tx).await;
+        let joined = join_room(
+            maid.event_tx.clone(),
+            rooms.clone(),
+            &room_id,
+            tx,
+            name,
+            observe,
+            petname_words,
+            max_rooms,
+        )
+        .await;
+        match joined {
+            Ok(joined) => {
+                if let Some(first) = &once {
+                    first.get_or_init(|| room_id.clone());
+                }
+                user = Some(joined);
+            }
+            Err(reason) => {
+                // Let the client know why instead of just hanging up
+                log::warn!("Rejected a user joining {}: {}", room_id, reason);
+                user_tx
+                    .send(Message::close_with(POLICY_VIOLATION, reason))
+                    .await
+                    .ok();
+            }
+        }
         if let Some(user) = user.clone() {
             // Send all of the chat history directly to the new user
             {
@@ -102,14 +204,22 @@ async fn connect(ws: WebSocket, maid: Maid, rooms: Rooms, room_id: RoomId) {
     if let Some(user) = user {
         // Reading and broadcasting the messages
         while let Some(result) = user_rx.next().await {
-            // When we receive a message from user
-            if let Ok(result) = result {
+            // When we receive a message from user, observers are read-only
+            if let Ok(result) = result
+                && !user.observer
+            {
                 broadcast_msg(maid.event_tx.clone(), rooms.clone(), user.clone(), result).await; // Redirect it to server
             }
         }
 
         // Handle disconnect
-        disconnect(maid.event_tx.clone(), rooms.clone(), user.clone()).await;
+        disconnect(
+            maid.event_tx.clone(),
+            rooms.clone(),
+            user.clone(),
+            once.as_ref(),
+        )
+        .await;
     }
 }
 
@@ -117,43 +227,89 @@ async fn get_room(rooms: Rooms, room_id: &RoomId) -> Option<Arc<Room>> {
     rooms.lock().await.get(room_id).cloned()
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn join_room(
     sender: UnboundedSender<BasicEvent>,
     rooms: Rooms,
     room_id: &RoomId,
     tx: UnboundedSender<Message>,
-) -> Option<Arc<RoomUser>> {
-    let mut result: Option<Arc<RoomUser>> = None;
+    name: Option<String>,
+    observe: bool,
+    petname_words: u8,
+    max_rooms: Option<usize>,
+) -> Result<Arc<RoomUser>, &'static str> {
+    let mut result: Result<Arc<RoomUser>, &'static str> = Err("The room is full");
     let mut create_flag = false;
 
     let mut room_lock = rooms.lock().await;
+    // Joining existing rooms is always fine, only new ones count against the limit
+    if let Some(max_rooms) = max_rooms
+        && !room_lock.contains_key(room_id)
+        && room_lock.len() >= max_rooms
+    {
+        return Err("The server has reached its room limit");
+    }
     let room = room_lock.entry(room_id.clone()).or_insert_with(|| {
         create_flag = true;
         Arc::new(Room::new(room_id))
     });
 
     let mut users_lock = room.users.lock().await;
-    if users_lock.len() < room.capacity {
-        let user = Arc::new(RoomUser::new(
-            petname::petname(2, "-")?,
-            room_id.clone(),
-            tx,
-        ));
-
-        users_lock.insert(user.id, user.clone());
-        result = Some(user);
+    let mut observers_lock = room.observers.lock().await;
+    if observe || users_lock.len() < room.capacity {
+        // Fall back to a generated name if the proposed one is absent or taken
+        let is_taken = |n: &str| {
+            (users_lock.values())
+                .chain(observers_lock.values())
+                .any(|u| u.name == n)
+        };
+        let name = match name {
+            Some(name) if !is_taken(&name) => name,
+            _ => loop {
+                let generated =
+                    petname::petname(petname_words, "-").ok_or("Couldn't generate a name")?;
+                if !is_taken(&generated) {
+                    break generated;
+                }
+            },
+        };
+        if observe {
+            let user = Arc::new(RoomUser::observer(name, room_id.clone(), tx));
+            observers_lock.insert(user.id, user.clone());
+            result = Ok(user);
+        } else {
+            let user = Arc::new(RoomUser::new(name, room_id.clone(), tx));
+            users_lock.insert(user.id, user.clone());
+            result = Ok(user);
+        }
     }
 
     // Report back room
     if create_flag {
         sender
-            .send_event(AppEventServer::AddRoom(room_id.clone()))
+            .send_event(AppEventServer::AddRoom(room_id.clone(), room.capacity))
             .await; // Should be fine
     }
 
     result
 }
 
+/// Keeps alphanumerics, dashes and underscores of a proposed name
+fn sanitize_name(name: &str) -> Option<String> {
+    let sanitized: String = name
+        .trim()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+        .take(MAX_NAME_LENGTH)
+        .collect();
+
+    if sanitized.is_empty() {
+        None
+    } else {
+        Some(sanitized)
+    }
+}
+
 async fn send_history(history: History, user_tx: &mut SplitSink<WebSocket, Message>) {
     // Bypasses the redirect and therefore getting in the history
     let history_guard = history.lock().await;
@@ -211,6 +367,9 @@ async fn broadcast_msg(
                     tx.send(msg.clone()).ok(); // TODO: review it
                 }
             }
+            for ru in room.observers.lock().await.values() {
+                ru.tx.send(msg.clone()).ok();
+            }
 
             // Append text message to the history
             append_to_history(
@@ -225,25 +384,154 @@ async fn broadcast_msg(
     }
 }
 
-// Remove user as well as room if empty
-async fn disconnect(sender: UnboundedSender<BasicEvent>, rooms: Rooms, user: Arc<RoomUser>) {
+// Remove user as well as room if empty, `once` quits along with the first room
+async fn disconnect(
+    sender: UnboundedSender<BasicEvent>,
+    rooms: Rooms,
+    user: Arc<RoomUser>,
+    once: Option<&FirstRoom>,
+) {
     // println!("Bye-bye user {my_id}");
     let room = get_room(rooms.clone(), &user.room_id).await;
     if let Some(room) = room {
-        room.users.lock().await.remove(&user.id);
+        if user.observer {
+            room.observers.lock().await.remove(&user.id);
+        } else {
+            room.users.lock().await.remove(&user.id);
+        }
 
         //Report back user change
         sender
             .send_event(AppEventServer::RemoveRoomUser((*user).clone()))
             .await; // Should be fine
 
-        if room.users.lock().await.is_empty() {
+        if room.users.lock().await.is_empty() && room.observers.lock().await.is_empty() {
             rooms.lock().await.remove(&room.id);
 
             // Report back room change
             sender
                 .send_event(AppEventServer::RemoveRoom(room.id.clone()))
                 .await; // Should be fine
+
+            // Quitting cancels everything else that's still running
+            if once.and_then(|first| first.get()) == Some(&room.id) {
+                log::info!("Room {} is empty, shutting down", room.id);
+                sender.send_event(AppEventServer::Quit).await;
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::app_event::AppEvent;
+
+    async fn join_as(
+        rooms: &Rooms,
+        room_id: &str,
+        observe: bool,
+    ) -> Result<Arc<RoomUser>, &'static str> {
+        let (sender, _events) = tokio::sync::mpsc::unbounded_channel();
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        join_room(
+            sender,
+            rooms.clone(),
+            &room_id.to_string(),
+            tx,
+            None,
+            observe,
+            2,
+            Some(1),
+        )
+        .await
+    }
+    async fn join(rooms: &Rooms, room_id: &str) -> Result<Arc<RoomUser>, &'static str> {
+        join_as(rooms, room_id, false).await
+    }
+
+    #[tokio::test]
+    async fn room_limit_spares_existing_rooms() {
+        let rooms = Rooms::default();
+
+        assert!(join(&rooms, "first").await.is_ok());
+        assert!(join(&rooms, "second").await.is_err());
+        assert!(join(&rooms, "first").await.is_ok()); // Still has a free spot
+        assert_eq!(rooms.lock().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn observers_take_no_slot() {
+        let rooms = Rooms::default();
+
+        let observer = join_as(&rooms, "room", true).await.unwrap();
+        assert!(observer.observer);
+        assert!(join(&rooms, "room").await.is_ok());
+        assert!(join(&rooms, "room").await.is_ok());
+        assert!(join(&rooms, "room").await.is_err()); // Full with two peers
+        assert!(join_as(&rooms, "room", true).await.is_ok()); // Still open to observers
+
+        let room = get_room(rooms.clone(), &"room".to_string()).await.unwrap();
+        assert_eq!(room.users.lock().await.len(), 2);
+        assert_eq!(room.observers.lock().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn lists_rooms_only_with_discovery() {
+        let rooms = Rooms::default();
+        join(&rooms, "b").await.unwrap();
+        join(&rooms, "b").await.unwrap();
+        join_as(&rooms, "b", true).await.unwrap();
+        rooms
+            .lock()
+            .await
+            .insert("a".to_string(), Arc::new(Room::new("a")));
+
+        let reply = warp::test::request()
+            .path("/rooms")
+            .reply(&rooms_route(true, rooms.clone()))
+            .await;
+        assert_eq!(reply.status(), 200);
+        let json: serde_json::Value = serde_json::from_slice(reply.body()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {"room": "a", "users": 0, "capacity": 2},
+                {"room": "b", "users": 2, "capacity": 2},
+            ])
+        );
+
+        let off = warp::test::request()
+            .path("/rooms")
+            .reply(&rooms_route(false, rooms))
+            .await;
+        assert_eq!(off.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn once_quits_with_the_room() {
+        let rooms = Rooms::default();
+        let (sender, mut events) = tokio::sync::mpsc::unbounded_channel();
+        let quit = |events: &mut tokio::sync::mpsc::UnboundedReceiver<BasicEvent>| {
+            std::iter::from_fn(|| events.try_recv().ok()).any(|event| {
+                matches!(
+                    event,
+                    BasicEvent::App(AppEvent::Server(AppEventServer::Quit))
+                )
+            })
+        };
+        let once = FirstRoom::default();
+        once.set("room".to_string()).unwrap();
+
+        let other = join(&rooms, "other").await.unwrap();
+        disconnect(sender.clone(), rooms.clone(), other, Some(&once)).await;
+        assert!(!quit(&mut events)); // Not the room it was started for
+
+        let first = join(&rooms, "room").await.unwrap();
+        let second = join(&rooms, "room").await.unwrap();
+        disconnect(sender.clone(), rooms.clone(), first, Some(&once)).await;
+        assert!(!quit(&mut events)); // Somebody's still in
+        disconnect(sender, rooms.clone(), second, Some(&once)).await;
+        assert!(quit(&mut events));
+    }
+}
@@ -1,6 +1,7 @@
+use serde::Serialize;
 use std::{
     collections::HashMap,
-    sync::{Arc, atomic},
+    sync::{Arc, OnceLock, atomic},
 };
 use tokio::sync::{Mutex, mpsc::UnboundedSender};
 use warp::filters::ws::Message;
@@ -17,6 +18,8 @@ pub struct RoomUser {
     pub name: String,
     pub room_id: RoomId,
     pub tx: UnboundedSender<Message>,
+    /// Only listens, doesn't take a slot and can't send anything
+    pub observer: bool,
 }
 impl RoomUser {
     pub fn new(name: String, room_id: RoomId, tx: UnboundedSender<Message>) -> Self {
@@ -25,6 +28,13 @@ impl RoomUser {
             name,
             room_id,
             tx,
+            observer: false,
+        }
+    }
+    pub fn observer(name: String, room_id: RoomId, tx: UnboundedSender<Message>) -> Self {
+        Self {
+            observer: true,
+            ..Self::new(name, room_id, tx)
         }
     }
 
@@ -38,7 +48,10 @@ pub type RoomId = String;
 pub type RoomUsers = Arc<Mutex<HashMap<UserId, Arc<RoomUser>>>>;
 pub struct Room {
     pub id: RoomId,
+    /// Peers, only they count towards the capacity
     pub users: RoomUsers,
+    /// Listeners that get the history and the broadcasts
+    pub observers: RoomUsers,
     pub history: History,
     pub capacity: usize,
 }
@@ -47,12 +60,23 @@ impl Room {
         Self {
             id: id.to_string(),
             users: RoomUsers::default(),
+            observers: RoomUsers::default(),
             history: History::default(),
             capacity: 2,
         }
     }
 }
 pub type Rooms = Arc<Mutex<HashMap<RoomId, Arc<Room>>>>;
+/// Room somebody joined first, `--once` quits when it empties and no sooner
+pub type FirstRoom = Arc<OnceLock<RoomId>>;
+
+/// What `GET /rooms` tells about a room, observers aren't counted
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RoomListing {
+    pub room: RoomId,
+    pub users: usize,
+    pub capacity: usize,
+}
 
 /// Message history
 pub type History = Arc<Mutex<Vec<UserMessage>>>;
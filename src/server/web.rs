@@ -0,0 +1,54 @@
+use warp::{Filter, Rejection, Reply};
+
+// Bundled into the binary so the server stays a single file
+const INDEX: &str = include_str!("web/index.html");
+const SCRIPT: &str = include_str!("web/receiver.js");
+
+/// Browser receiver for people without the client, `/web?room=...` joins the room like a peer
+///
+/// Rejects everything unless `enabled`, so it can always sit next to the room route
+pub fn routes(enabled: bool) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let enabled = warp::any()
+        .and_then(move || async move {
+            if enabled {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one();
+
+    let index = warp::path!("web").map(|| warp::reply::html(INDEX));
+    let script = warp::path!("web" / "receiver.js")
+        .map(|| warp::reply::with_header(SCRIPT, "content-type", "text/javascript; charset=utf-8"));
+
+    warp::get().and(enabled).and(index.or(script))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_only_when_enabled() {
+        let page = warp::test::request()
+            .path("/web?room=room")
+            .reply(&routes(true))
+            .await;
+        assert_eq!(page.status(), 200);
+        assert_eq!(
+            warp::test::request()
+                .path("/web/receiver.js")
+                .reply(&routes(true))
+                .await
+                .headers()["content-type"],
+            "text/javascript; charset=utf-8"
+        );
+
+        let off = warp::test::request()
+            .path("/web?room=room")
+            .reply(&routes(false))
+            .await;
+        assert_eq!(off.status(), 404);
+    }
+}
@@ -0,0 +1,334 @@
+// Receives files from a tappi-share client, speaks the same signaling JSON and frames
+"use strict";
+
+const PROTOCOL = "tappi/2";
+const SIGNALING_VERSION = 1;
+// The lowest UUID always loses the comparison, so we're the one answering
+const NIL_UUID = "00000000-0000-0000-0000-000000000000";
+const MAX_META_LENGTH = 64 * 1024; // Same as message.rs
+
+const params = new URLSearchParams(location.search);
+const statusLine = document.getElementById("status");
+const fileList = document.getElementById("files");
+// Set once the peer said goodbye, the connection going down after that is no news
+let peerLeft = false;
+
+function setStatus(text, error = false) {
+  statusLine.textContent = text;
+  statusLine.classList.toggle("error", error);
+}
+
+// Signaling
+
+function signalingJson(type, data) {
+  return JSON.stringify({ version: SIGNALING_VERSION, message: { type, data } });
+}
+
+function waitForGathering(pc) {
+  if (pc.iceGatheringState === "complete") return Promise.resolve();
+  return new Promise((resolve) => {
+    pc.addEventListener("icegatheringstatechange", () => {
+      if (pc.iceGatheringState === "complete") resolve();
+    });
+  });
+}
+
+function connect(room) {
+  const iceServers = (params.get("ice") || "")
+    .split(",")
+    .filter((url) => url)
+    .map((url) => ({ urls: url }));
+  const pc = new RTCPeerConnection({ iceServers });
+  pc.addEventListener("connectionstatechange", () => {
+    if (pc.connectionState === "connected") setStatus("Connected, waiting for files");
+    if (peerLeft) return;
+    if (pc.connectionState === "failed") setStatus("Connection failed", true);
+    if (pc.connectionState === "disconnected") setStatus("Disconnected");
+  });
+
+  // Pre-negotiated the same way the client does it
+  const channel = pc.createDataChannel(params.get("label") || "data", {
+    negotiated: true,
+    id: Number(params.get("id") || 0),
+    ordered: true,
+    protocol: PROTOCOL,
+  });
+  channel.binaryType = "arraybuffer";
+  attachChannel(channel);
+
+  const url = new URL("/room", location.href);
+  url.protocol = location.protocol === "https:" ? "wss:" : "ws:";
+  url.searchParams.set("room", room);
+  if (params.get("name")) url.searchParams.set("name", params.get("name"));
+
+  const socket = new WebSocket(url);
+  socket.addEventListener("open", () => {
+    socket.send(signalingJson("Uuid", NIL_UUID));
+    setStatus(`Waiting for a peer in ${room}`);
+  });
+  socket.addEventListener("close", (event) => {
+    if (event.reason) setStatus(`Server refused: ${event.reason}`, true);
+  });
+  socket.addEventListener("message", async (event) => {
+    let packet;
+    try {
+      packet = JSON.parse(event.data);
+    } catch {
+      return; // Not a signaling message
+    }
+    const message = packet.message || {};
+
+    if (message.type === "Uuid" && message.data === NIL_UUID) {
+      setStatus("The peer forced the answering role too, it has to offer", true);
+      socket.close();
+    } else if (message.type === "Offer" && !pc.remoteDescription) {
+      setStatus("Answering the offer");
+      await pc.setRemoteDescription({ type: "offer", sdp: message.data });
+      await pc.setLocalDescription(await pc.createAnswer());
+      await waitForGathering(pc); // Candidates aren't trickled
+      socket.send(signalingJson("Answer", pc.localDescription.sdp));
+      socket.close();
+    }
+  });
+}
+
+// Data channel messages
+
+function sendMessage(channel, message) {
+  channel.send(JSON.stringify(message));
+}
+
+function timestamp() {
+  const now = Date.now();
+  return {
+    secs_since_epoch: Math.floor(now / 1000),
+    nanos_since_epoch: (now % 1000) * 1_000_000,
+  };
+}
+
+function attachChannel(channel) {
+  const metaBytes = new Map(); // Metadata comes in pieces too
+  const files = new Map();
+  let frames = Promise.resolve(); // Decompressing is async, the frames still go in order
+
+  channel.addEventListener("open", () => sendMessage(channel, { Hello: PROTOCOL }));
+  channel.addEventListener("message", (event) => {
+    if (typeof event.data === "string") {
+      onText(channel, JSON.parse(event.data), files);
+      return;
+    }
+    frames = frames
+      .then(() => onFrame(new Uint8Array(event.data)))
+      .catch((err) => setStatus(err.message, true));
+  });
+
+  async function onFrame(bytes) {
+    const [id, meta, last, packed, crc, offset, algo] = unpack(bytes);
+    const binary = await decompress(algo, packed);
+    if (meta) {
+      if (files.has(id)) return;
+      const collected = concat(metaBytes.get(id), binary);
+      if (collected.length > MAX_META_LENGTH) {
+        metaBytes.delete(id);
+        throw new Error(`The metadata of file ${id} went over ${MAX_META_LENGTH} bytes`);
+      }
+      metaBytes.set(id, collected);
+      if (last) {
+        const file = newFile(JSON.parse(new TextDecoder().decode(metaBytes.get(id))));
+        metaBytes.delete(id);
+        files.set(id, file);
+        if (file.meta.is_dir || file.meta.size === 0) {
+          if (!file.meta.is_dir) save(file);
+          sendMessage(channel, { FileReceived: id });
+        }
+      }
+      return;
+    }
+
+    const file = files.get(id);
+    if (!file) return;
+    // Checked chunks say where they go, the ones that don't fit are from before a resend
+    if (offset !== undefined && offset !== file.received) return;
+    if (crc !== undefined && crc32(binary) !== crc) {
+      sendMessage(channel, { ResendChunk: [id, file.received] });
+      return;
+    }
+
+    file.chunks.push(binary);
+    file.received += binary.length;
+    file.progress.value = file.received / file.meta.size;
+    sendMessage(channel, {
+      FilePacketReceived: { file_id: id, timestamp: timestamp(), bytes: binary.length },
+    });
+    if (last) {
+      save(file);
+      sendMessage(channel, { FileReceived: id });
+    }
+  }
+}
+
+// Same numbers as the algorithms in compress.rs, a chunk that doesn't decompress fails its CRC
+// Browsers only come with gzip, zstd and lz4 are for Tappi receivers
+async function decompress(algo, bytes) {
+  if (!algo) return bytes;
+  if (algo !== 1) throw new Error(`Unknown compression algorithm ${algo}`);
+  const stream = new Blob([bytes]).stream().pipeThrough(new DecompressionStream("gzip"));
+  try {
+    return new Uint8Array(await new Response(stream).arrayBuffer());
+  } catch {
+    return bytes;
+  }
+}
+
+function onText(channel, message, files) {
+  // Unit messages come as plain strings
+  if (message === "Ping") {
+    sendMessage(channel, "Pong");
+  } else if (message === "Goodbye") {
+    peerLeft = true;
+    setStatus("The peer closed the connection");
+  } else if (message === "ResumeQuery") {
+    sendMessage(channel, { ResumeOffsets: [] }); // Nothing survives a reload
+  } else if (message === "ReceiptQuery") {
+    const receipt = [...files]
+      .filter(([, file]) => !file.meta.is_dir)
+      .map(([id, file]) => ({ id, size: file.received, crc: null })); // Sizes are all we keep track of
+    sendMessage(channel, { Receipt: receipt });
+  } else if (typeof message !== "object") {
+    return;
+  } else if ("Hello" in message && message.Hello !== PROTOCOL) {
+    setStatus(`Protocol mismatch, ours is ${PROTOCOL} and theirs is ${message.Hello}`, true);
+    channel.close();
+  } else if ("TransferOffer" in message) {
+    const offer = message.TransferOffer;
+    const from = offer.sender ? ` from ${offer.sender}` : "";
+    setStatus(`Receiving ${offer.files} file(s), ${formatSize(offer.size)}${from}`);
+  }
+}
+
+// Files
+
+// Same as the client's MetaData::get_path, folders can't be made so they're flattened
+// Null for a path that leads outside the output directory, the client skips those too
+function relativePath(meta) {
+  let path = meta.name;
+  const base = meta.base_path;
+  if (meta.raw_path) {
+    path = new TextDecoder().decode(new Uint8Array(meta.raw_path));
+  } else if (base && meta.path.startsWith(base)) {
+    const parent = base.split("/").filter((p) => p).pop();
+    path = `${parent}${meta.path.slice(base.length)}`;
+  }
+  // Only the last component, a name can't move the file somewhere else
+  const renamed = meta.renamed?.split("/").pop();
+  if (renamed) path = path.replace(/[^/]*$/, renamed);
+
+  const parts = path.split("/").filter((p) => p !== ".");
+  if (!path || path.startsWith("/") || parts.includes("..")) {
+    return null;
+  }
+  return parts.filter((p) => p).join("_");
+}
+
+function newFile(meta) {
+  const path = relativePath(meta);
+  const item = document.createElement("li");
+  const progress = document.createElement("progress");
+  progress.max = 1;
+  progress.value = 0;
+  const name = path ?? `${meta.name}, skipped for leading outside the output directory`;
+  item.append(progress, ` ${name} (${formatSize(meta.size)})`);
+  if (!meta.is_dir) fileList.append(item);
+  return { meta, path, chunks: [], received: 0, item, progress };
+}
+
+// Kept in memory until the last chunk, big files want the real client
+function save(file) {
+  if (file.path) {
+    const link = document.createElement("a");
+    link.href = URL.createObjectURL(new Blob(file.chunks));
+    link.download = file.path;
+    link.click();
+    setTimeout(() => URL.revokeObjectURL(link.href), 10_000);
+  }
+
+  file.chunks = [];
+  file.progress.value = 1;
+  file.item.classList.add("done");
+}
+
+function formatSize(bytes) {
+  const units = ["B", "KiB", "MiB", "GiB", "TiB"];
+  let i = 0;
+  while (bytes >= 1024 && i < units.length - 1) {
+    bytes /= 1024;
+    i++;
+  }
+  return `${bytes.toFixed(i ? 1 : 0)} ${units[i]}`;
+}
+
+function concat(a, b) {
+  if (!a) return b;
+  const result = new Uint8Array(a.length + b.length);
+  result.set(a);
+  result.set(b, a.length);
+  return result;
+}
+
+// Just enough MessagePack for the frames: arrays, unsigned ints, bools and binary
+
+function unpack(bytes) {
+  const view = new DataView(bytes.buffer, bytes.byteOffset, bytes.byteLength);
+  let pos = 0;
+
+  function read() {
+    const tag = bytes[pos++];
+    if (tag <= 0x7f) return tag;
+    if ((tag & 0xf0) === 0x90) return Array.from({ length: tag & 0x0f }, read);
+    switch (tag) {
+      case 0xc2: return false;
+      case 0xc3: return true;
+      case 0xc4: return binary(view.getUint8(pos), 1);
+      case 0xc5: return binary(view.getUint16(pos), 2);
+      case 0xc6: return binary(view.getUint32(pos), 4);
+      case 0xcc: return number(view.getUint8(pos), 1);
+      case 0xcd: return number(view.getUint16(pos), 2);
+      case 0xce: return number(view.getUint32(pos), 4);
+      case 0xcf: return number(Number(view.getBigUint64(pos)), 8);
+      default: throw new Error(`Unexpected MessagePack tag 0x${tag.toString(16)}`);
+    }
+  }
+  function number(value, size) {
+    pos += size;
+    return value;
+  }
+  function binary(length, size) {
+    pos += size;
+    const result = bytes.slice(pos, pos + length);
+    pos += length;
+    return result;
+  }
+
+  return read();
+}
+
+const CRC_TABLE = Array.from({ length: 256 }, (_, n) => {
+  let c = n;
+  for (let k = 0; k < 8; k++) c = c & 1 ? 0xedb88320 ^ (c >>> 1) : c >>> 1;
+  return c >>> 0;
+});
+
+function crc32(bytes) {
+  let crc = 0xffffffff;
+  for (const byte of bytes) crc = CRC_TABLE[(crc ^ byte) & 0xff] ^ (crc >>> 8);
+  return (crc ^ 0xffffffff) >>> 0;
+}
+
+// Start
+
+const room = params.get("room");
+if (room) {
+  connect(room);
+} else {
+  setStatus("Add ?room=<name> to the address to join a room", true);
+}
@@ -0,0 +1,35 @@
+use std::{
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+
+/// A fresh directory under the system's temporary one, gone with everything in it once dropped
+///
+/// A failing test still cleans up after itself this way
+pub struct TempDir(PathBuf);
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("tappi-{}-{}", name, Uuid::new_v4()));
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).ok();
+    }
+}
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
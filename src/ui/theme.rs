@@ -1,6 +1,7 @@
 use config::{Config, File, FileFormat};
-use ratatui::style::Color;
+use ratatui::style::{Color, Modifier, Style};
 use serde::Deserialize;
+use std::collections::HashMap;
 
 static DEFAULT_THEME: &str = include_str!("../config/themes/catpuccin_frappe.toml");
 
@@ -16,6 +17,15 @@ pub struct Theme {
     pub success: ThemeColor,
     pub error: ThemeColor,
     pub warning: ThemeColor,
+    #[serde(default)]
+    pub selection_symbol: SelectionSymbol,
+    #[serde(default)]
+    pub selection_style: SelectionStyle,
+    #[serde(default)]
+    pub extensions: HashMap<String, ThemeColor>,
+    /// Everything is in the terminal's own colors
+    #[serde(skip)]
+    pub monochrome: bool,
 }
 impl Theme {
     pub fn load_default() -> color_eyre::Result<Theme> {
@@ -23,6 +33,115 @@ impl Theme {
         let cfg = Config::builder().add_source(default_source).build()?;
         Ok(cfg.try_deserialize()?)
     }
+    /// The default theme, or the monochrome one when colors are off
+    pub fn load(no_color: bool) -> color_eyre::Result<Theme> {
+        let theme = Self::load_default()?;
+        Ok(if no_color {
+            theme.into_monochrome()
+        } else {
+            theme
+        })
+    }
+    /// Every color falls back to the terminal default, the selection is shown in reverse video
+    fn into_monochrome(self) -> Theme {
+        let reset = || ThemeColor(Color::Reset);
+        Theme {
+            surface0: reset(),
+            surface1: reset(),
+            surface2: reset(),
+            primary: reset(),
+            accent: reset(),
+            text: reset(),
+            info: reset(),
+            success: reset(),
+            error: reset(),
+            warning: reset(),
+            selection_symbol: self.selection_symbol,
+            selection_style: SelectionStyle {
+                fg: None,
+                bg: None,
+                bold: self.selection_style.bold,
+                reversed: true,
+            },
+            extensions: HashMap::new(),
+            monochrome: true,
+        }
+    }
+    /// Plain white where the widgets want it, the terminal's own color without colors
+    pub fn base_fg(&self) -> Color {
+        if self.monochrome {
+            Color::Reset
+        } else {
+            Color::White
+        }
+    }
+    pub fn extension_color(&self, extension: &str) -> Option<Color> {
+        self.extensions
+            .get(&extension.to_lowercase())
+            .map(|color| color.clone().into())
+    }
+    /// Style of the selected list item, info colored unless the theme says otherwise
+    pub fn selected_style(&self) -> Style {
+        let selection = &self.selection_style;
+        let fg = selection.fg.clone().unwrap_or(self.info.clone());
+        let mut style = Style::default().fg(fg.into());
+        if let Some(bg) = &selection.bg {
+            style = style.bg(bg.clone().into());
+        }
+        if selection.bold {
+            style = style.add_modifier(Modifier::BOLD);
+        }
+        if selection.reversed {
+            style = style.add_modifier(Modifier::REVERSED);
+        }
+        style
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct SelectionStyle {
+    pub fg: Option<ThemeColor>,
+    pub bg: Option<ThemeColor>,
+    #[serde(default)]
+    pub bold: bool,
+    #[serde(default)]
+    pub reversed: bool,
+}
+
+/// Marker in front of the selected list item
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelectionSymbol(String);
+impl SelectionSymbol {
+    /// Anything wider eats into the list
+    const MAX_LENGTH: usize = 4;
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+impl Default for SelectionSymbol {
+    fn default() -> Self {
+        Self("> ".to_string())
+    }
+}
+impl<'de> Deserialize<'de> for SelectionSymbol {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+
+        if s.chars().any(char::is_control) {
+            return Err(serde::de::Error::custom(
+                "Selection symbol can't contain control characters",
+            ));
+        }
+        if s.chars().count() > Self::MAX_LENGTH {
+            return Err(serde::de::Error::custom("Selection symbol is too long"));
+        }
+
+        Ok(SelectionSymbol(s))
+    }
 }
 
 pub struct ThemeColor(Color);
@@ -56,3 +175,38 @@ impl<'de> Deserialize<'de> for ThemeColor {
         Ok(ThemeColor(Color::Rgb(r, g, b)))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(toml: &str) -> Result<SelectionSymbol, config::ConfigError> {
+        let cfg = Config::builder()
+            .add_source(File::from_str(toml, FileFormat::Toml))
+            .build()?;
+        cfg.get("selection_symbol")
+    }
+
+    #[test]
+    fn selection_symbol_parsing() {
+        assert_eq!(symbol("selection_symbol = '» '").unwrap().as_str(), "» ");
+        assert!(symbol("selection_symbol = '-----> '").is_err());
+        assert!(symbol("selection_symbol = \"\\t\"").is_err());
+
+        let theme = Theme::load_default().unwrap();
+        assert_eq!(theme.selection_symbol.as_str(), "> ");
+    }
+
+    #[test]
+    fn monochrome_has_no_colors() {
+        let theme = Theme::load(true).unwrap();
+        assert_eq!(Color::from(theme.text.clone()), Color::Reset);
+        assert_eq!(theme.extension_color("png"), None);
+        assert!(
+            theme
+                .selected_style()
+                .add_modifier
+                .contains(Modifier::REVERSED)
+        );
+    }
+}
@@ -1,13 +1,23 @@
+use ratatui::layout::Flex;
 use ratatui::prelude::*;
+use ratatui::widgets::{Block, Paragraph, Wrap};
+use ratatui_macros::vertical;
 
 use crate::app::app_main::App;
 use crate::cli::Commands;
+use crate::ui::utils::RectExt;
+use crate::ui::views::accessible_view::render as render_accessible;
 use crate::ui::views::client_view::render as render_client;
 use crate::ui::views::error_view::render as render_error;
 use crate::ui::views::server_view::render as render_server;
+use crate::ui::widgets::log_widget::log_widget;
 
 // A MESSAGE TO THAT SILLY PERSON CALLED "ME": ALWAYS RENDER FROM OUTER TO INNER!
 
+/// Below this the nested layouts fall apart
+const MIN_WIDTH: u16 = 60;
+const MIN_HEIGHT: u16 = 16;
+
 impl Widget for &mut App {
     /// Renders the user interface widgets.
     ///
@@ -16,14 +26,24 @@ impl Widget for &mut App {
     // - https://docs.rs/ratatui/latest/ratatui/widgets/index.html
     // - https://github.com/ratatui/ratatui/tree/master/examples
     fn render(self, area: Rect, buf: &mut Buffer) {
-        if !self.exit {
+        self.animating = false; // Whatever moves sets it again
+        if !area.fits(MIN_WIDTH, MIN_HEIGHT) {
+            render_too_small(self, area, buf);
+        } else if !self.exit {
             match self.args.app_mode {
+                Commands::Client(_) if self.args.accessible => {
+                    render_accessible(self, area, buf);
+                }
                 Commands::Client(_) => {
                     render_client(self, area, buf);
                 }
                 Commands::Server(_) => {
                     render_server(self, area, buf);
                 }
+                Commands::Loopback(_) | Commands::Diagnose(_) | Commands::GenSecret => {}
+            }
+            if self.log_panel_state.open {
+                log_widget(self, area, buf);
             }
         } else {
             render_error(self, area, buf);
@@ -32,3 +52,20 @@ impl Widget for &mut App {
         self.redraw = false;
     }
 }
+
+fn render_too_small(app: &App, area: Rect, buf: &mut Buffer) {
+    let text = format!(
+        "Terminal too small\n{}x{}, need at least {}x{}",
+        area.width, area.height, MIN_WIDTH, MIN_HEIGHT
+    );
+    Block::new()
+        .bg(app.theme.surface0.clone())
+        .render(area, buf);
+
+    let [area] = vertical![==2].flex(Flex::Center).areas(area);
+    Paragraph::new(text)
+        .fg(app.theme.warning.clone())
+        .wrap(Wrap::default())
+        .centered()
+        .render(area, buf);
+}
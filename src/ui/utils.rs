@@ -1,4 +1,5 @@
-use crossterm::event::KeyEvent;
+use arboard::Clipboard;
+use crossterm::event::{KeyCode, KeyEvent};
 use rat_focus::HasFocus;
 use ratatui::prelude::*;
 use ratatui::symbols::border;
@@ -10,6 +11,7 @@ use tachyonfx::ToRgbComponents;
 use tui_widget_list::{ListState as WidgetListState, ListView};
 
 use crate::app::app_event::AppEvent;
+use crate::app::models::Toast;
 use crate::ui::theme::Theme;
 
 pub struct MainFrame<'a> {
@@ -33,6 +35,19 @@ impl<'a> MainFrame<'a> {
     pub fn render(&self, area: Rect, buf: &mut Buffer) {
         self.block.clone().render(area, buf);
     }
+    pub fn add_toast(&mut self, theme: &Theme, toast: Option<&Toast>) {
+        if let Some(toast) = toast {
+            let color = if toast.is_error {
+                theme.error.clone()
+            } else {
+                theme.success.clone()
+            };
+            self.block = self
+                .block
+                .clone()
+                .title_bottom(Line::from(toast.text.as_str().spaced().fg(color)).centered());
+        }
+    }
 }
 
 pub struct BlockDefault;
@@ -133,7 +148,7 @@ pub struct ShortcutStyle {
 impl ShortcutStyle {
     pub fn new(theme: &Theme) -> Self {
         Self {
-            base_color: Color::White,
+            base_color: theme.base_fg(),
             button_color: theme.info.clone().into(),
             bold: false,
         }
@@ -150,6 +165,42 @@ pub trait CombinedWidgetState: HasFocus {
     fn handle_key_events(&mut self, _key_event: &KeyEvent) -> color_eyre::Result<AppEvent> {
         Ok(AppEvent::None)
     }
+    /// Drops half-typed input like count prefixes, called when the focus moves
+    fn reset_pending(&mut self) {}
+}
+
+/// Vim-like count prefix, `5j` moves down five rows
+#[derive(Default)]
+pub struct CountPrefix {
+    count: Option<usize>,
+}
+impl CountPrefix {
+    /// Way past any list we'd show, keeps it from overflowing
+    const MAX: usize = 9999;
+
+    /// Returns true if the key was a digit and got consumed
+    pub fn push(&mut self, key_event: &KeyEvent) -> bool {
+        let KeyCode::Char(c) = key_event.code else {
+            return false;
+        };
+        let Some(digit) = c.to_digit(10) else {
+            return false;
+        };
+        if digit == 0 && self.count.is_none() {
+            return false; // A leading zero isn't a count
+        }
+
+        let count = self.count.unwrap_or(0) * 10 + digit as usize;
+        self.count = Some(count.min(Self::MAX));
+        true
+    }
+    /// Returns the repeat count for the current key and clears it
+    pub fn take(&mut self) -> usize {
+        self.count.take().unwrap_or(1)
+    }
+    pub fn reset(&mut self) {
+        self.count = None;
+    }
 }
 
 pub trait StringExt {
@@ -181,6 +232,9 @@ impl Ansi {
             Into::<Color>::into(theme.primary.clone()).to_rgb(),
         )
     }
+    pub fn strip_colors(string: &str) -> String {
+        Self::ansi_replace_color(string, r"\x1b\[[0-9;]*m", "")
+    }
     pub fn ansi_replace_color(string: &str, regex: &str, replace: &str) -> String {
         let re = Regex::new(regex);
         if let Ok(re) = re {
@@ -200,6 +254,23 @@ impl Ansi {
     }
 }
 
+/// Thin wrapper around the system clipboard
+pub struct SystemClipboard;
+impl SystemClipboard {
+    pub fn copy(text: &str) -> color_eyre::Result<()> {
+        let mut clipboard = Clipboard::new()?;
+        if !text.is_empty() {
+            clipboard.set_text(text)?;
+        }
+        Ok(())
+    }
+    pub fn paste() -> color_eyre::Result<String> {
+        let mut clipboard = Clipboard::new()?;
+        let text = clipboard.get_text()?;
+        Ok(text)
+    }
+}
+
 pub trait BlockExt {
     fn inner_with_margin(&self, area: Rect, vertical: u16, horizontal: u16) -> Rect;
 }
@@ -307,6 +378,7 @@ pub trait RectExt {
     fn with_height(self, height: u16) -> Self;
     fn clamp_width(self, min: u16, max: u16) -> Self;
     fn clamp_height(self, min: u16, max: u16) -> Self;
+    fn fits(self, width: u16, height: u16) -> bool;
 }
 impl RectExt for Rect {
     fn min_width(self, min: u16) -> Self {
@@ -351,6 +423,9 @@ impl RectExt for Rect {
     fn with_height(self, height: u16) -> Self {
         Self { height, ..self }
     }
+    fn fits(self, width: u16, height: u16) -> bool {
+        self.width >= width && self.height >= height
+    }
 }
 
 // Usually it's CollapsedBorder::bottom() + CollapsedSet::top_collapsed()
@@ -440,3 +515,30 @@ impl CollapsedSet {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_prefix() {
+        let key = |c| KeyEvent::from(KeyCode::Char(c));
+        let mut count = CountPrefix::default();
+
+        assert!(!count.push(&key('0'))); // Not a count on its own
+        assert!(count.push(&key('1')));
+        assert!(count.push(&key('2')));
+        assert!(!count.push(&key('j')));
+        assert_eq!(count.take(), 12);
+        assert_eq!(count.take(), 1); // Cleared after use
+
+        for _ in 0..8 {
+            count.push(&key('9'));
+        }
+        assert_eq!(count.take(), CountPrefix::MAX);
+
+        count.push(&key('5'));
+        count.reset();
+        assert_eq!(count.take(), 1);
+    }
+}
@@ -0,0 +1,239 @@
+use rat_focus::{FocusBuilder, HasFocus};
+use ratatui::prelude::*;
+use ratatui::widgets::Paragraph;
+use std::collections::{HashMap, VecDeque};
+
+use crate::app::app_main::App;
+use crate::app::file_manager::{FileId, FileStatus, ProgressFile};
+use crate::app::models::Toast;
+use crate::app::session::ClientSession;
+use crate::cli::{Commands, SignalingSolutions};
+use crate::ui::widgets::files_widget::format_size;
+use crate::ui::widgets::stepper::step_label;
+
+/// Lines kept around, the oldest ones scroll away
+const MAX_LINES: usize = 500;
+/// A moving file gets a new line every this many percent
+const PERCENT_STEP: u8 = 10;
+
+/// Plain text account of the session for `--accessible`, only meaningful changes make it in
+#[derive(Debug, Default)]
+pub struct Transcript {
+    lines: VecDeque<String>,
+    step: Option<usize>,
+    connected: bool,
+    toast: Option<String>,
+    prompt: Option<String>,
+    /// Last status and percent step told for every file, keyed by direction too
+    files: HashMap<(bool, FileId), (FileStatus, u8)>,
+    /// Lines pushed so far, the oldest ones scrolling away doesn't stop the count
+    pushed: usize,
+    /// Our manual handshake as last told
+    handshake: String,
+}
+impl Transcript {
+    /// Returns true if anything new got in
+    pub fn update(&mut self, session: &ClientSession, toast: Option<&Toast>) -> bool {
+        let pushed = self.pushed;
+        let step = session.handshake_state.step();
+        if step != self.step {
+            self.step = step;
+            if let Some(label) = step_label(&session.handshake_state) {
+                self.push(format!("Handshake: {}", label.to_lowercase()));
+            }
+        }
+        if self.connected != session.client_state.connected {
+            self.connected = session.client_state.connected;
+            self.push(
+                if self.connected {
+                    "Connected"
+                } else {
+                    "Disconnected"
+                }
+                .to_string(),
+            );
+        }
+
+        // Manual signaling has nothing to go on but the text itself
+        let output = &session.handshake_widget_state.output_text;
+        if *output != self.handshake {
+            self.handshake = output.clone();
+            if !output.is_empty() {
+                self.push(format!(
+                    "Your handshake, c copies it for the peer: {}",
+                    output
+                ));
+            }
+        }
+
+        let prompt = prompt(session);
+        if prompt != self.prompt {
+            if let Some(prompt) = &prompt {
+                self.push(prompt.clone());
+            }
+            self.prompt = prompt;
+        }
+
+        let file_manager = &session.file_manager;
+        for file in file_manager.output_map.values() {
+            self.track(true, file.id, file);
+        }
+        for file in file_manager.input_map.values() {
+            self.track(false, file.id, file);
+        }
+
+        // Toasts tick down on their own, only a new text is news
+        let text = toast.map(|toast| toast.text.clone());
+        if text.is_some() && text != self.toast {
+            self.push(text.clone().unwrap_or_default());
+        }
+        self.toast = text;
+        self.pushed != pushed
+    }
+
+    fn track<P: ProgressFile>(&mut self, sending: bool, id: FileId, file: &P) {
+        if file.get_meta().is_dir {
+            return;
+        }
+        let status = file.get_status();
+        let step = ((file.get_progress().clamp(0.0, 1.0) * 100.0) as u8) / PERCENT_STEP;
+        let told = self.files.insert((sending, id), (status, step));
+        let news = match told {
+            None => status != FileStatus::Pending,
+            Some((told_status, told_step)) => told_status != status || told_step != step,
+        };
+        if news {
+            self.push(file_line(sending, file));
+        }
+    }
+
+    fn push(&mut self, line: String) {
+        if self.lines.len() == MAX_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+        self.pushed += 1;
+    }
+}
+
+/// "sending foo.txt: 43%, 2.1 Mbps" and the like
+fn file_line<P: ProgressFile>(sending: bool, file: &P) -> String {
+    let verb = if sending { "sending" } else { "receiving" };
+    let name = file.get_name().unwrap_or_default();
+    let state = match file.get_status() {
+        FileStatus::Pending => "waiting".to_string(),
+        FileStatus::Active => format!(
+            "{:.0}%, {:.1} Mbps",
+            file.get_progress() * 100.0,
+            file.get_speed()
+        ),
+        FileStatus::Done => "done".to_string(),
+        FileStatus::Failed => "failed".to_string(),
+        FileStatus::Skipped => "skipped".to_string(),
+        FileStatus::Corrupted => "corrupt chunk, sending it again".to_string(),
+    };
+    format!("{} {}: {}", verb, name, state)
+}
+
+/// Whatever waits for a key press
+fn prompt(session: &ClientSession) -> Option<String> {
+    let state = &session.client_state;
+    if let Some(offer) = &state.incoming_offer {
+        let summary = &offer.summary;
+        let sender = summary.sender.as_deref().unwrap_or("The peer");
+        Some(format!(
+            "{} wants to send you {} file(s), {}. Press y to accept or n to decline",
+            sender,
+            summary.files,
+            format_size(summary.size)
+        ))
+    } else if let Some(paths) = &state.pasted_paths {
+        Some(format!("Add {} dropped path(s)? Press y or n", paths.len()))
+    } else if state.pending_confirm.is_some() {
+        let files = session.file_manager.output_queue.iter();
+        let (count, size) = files
+            .filter(|f| !f.meta.is_dir)
+            .fold((0, 0), |(c, s), f| (c + 1, s + f.meta.size));
+        Some(format!(
+            "Send {} file(s), {}? Press Enter to start",
+            count,
+            format_size(size)
+        ))
+    } else if session.handshake_widget_state.awaits_input() {
+        Some("Paste the peer's handshake with v, or press i to type it".to_string())
+    } else {
+        None
+    }
+}
+
+/// The last `height` rows once the lines are wrapped to `width`, long ones take several
+fn tail(lines: &VecDeque<String>, width: usize, height: usize) -> Vec<String> {
+    let mut rows: VecDeque<String> = VecDeque::new();
+    for line in lines.iter().rev() {
+        if rows.len() >= height {
+            break;
+        }
+        for row in textwrap::wrap(line, width.max(1)).into_iter().rev() {
+            rows.push_front(row.into_owned());
+        }
+    }
+    let skip = rows.len().saturating_sub(height);
+    rows.into_iter().skip(skip).collect()
+}
+
+pub fn render(app: &mut App, area: Rect, buf: &mut Buffer) {
+    // Manual signaling still needs its widget for the copy and paste keys
+    let mut builder = FocusBuilder::default();
+    if let Commands::Client(client_args) = &app.args.app_mode
+        && let SignalingSolutions::Manual(_) = &client_args.signaling_mode
+    {
+        app.session.handshake_widget_state.build(&mut builder);
+    }
+    app.focus = builder.build();
+
+    let rows = tail(
+        &app.transcript.lines,
+        area.width as usize,
+        area.height as usize,
+    );
+    let lines: Vec<Line> = rows.into_iter().map(Line::raw).collect();
+    Paragraph::new(lines).render(area, buf);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::file_manager::{InputFile, MetaData};
+    use std::path::Path;
+
+    #[test]
+    fn only_meaningful_changes() {
+        let mut transcript = Transcript::default();
+        let mut file = InputFile::new(0, MetaData::new(Path::new("foo.txt"), 100, None, false));
+
+        transcript.track(false, 0, &file);
+        assert!(transcript.lines.is_empty()); // Nothing moved yet
+
+        for progress in [0.01, 0.02, 0.05, 0.12, 0.15] {
+            file.set_progress(progress);
+            transcript.track(false, 0, &file);
+        }
+        assert_eq!(transcript.lines.len(), 2);
+        assert!(transcript.lines[1].starts_with("receiving foo.txt: 12%"));
+
+        file.set_progress(1.0);
+        transcript.track(false, 0, &file);
+        assert_eq!(transcript.lines.back().unwrap(), "receiving foo.txt: done");
+    }
+
+    #[test]
+    fn newest_rows_stay_in_view() {
+        let lines = VecDeque::from(["old".to_string(), "a long line wrapping".to_string()]);
+        assert_eq!(tail(&lines, 10, 2), vec!["line", "wrapping"]);
+        assert_eq!(tail(&lines, 10, 3), vec!["a long", "line", "wrapping"]);
+        assert_eq!(
+            tail(&lines, 10, 4),
+            vec!["old", "a long", "line", "wrapping"]
+        );
+    }
+}
@@ -1,13 +1,18 @@
 use rat_focus::FocusBuilder;
+use ratatui::layout::Flex;
 use ratatui::prelude::*;
-use ratatui_macros::{line, vertical};
+use ratatui::widgets::{Clear, Paragraph};
+use ratatui_macros::{horizontal, line, vertical};
 
 use crate::app::app_main::App;
+use crate::app::session::ClientSession;
 use crate::cli::{Commands, SignalingSolutions};
-use crate::ui::utils::{MainFrame, Shortcut, ShortcutStyle};
-use crate::ui::widgets::files_widget::files_widget;
+use crate::client::data_cap::CapState;
+use crate::client::receipt::{ReceiptState, SHOWN_MISMATCHES};
+use crate::ui::utils::{BlockDefault, MainFrame, Shortcut, ShortcutStyle};
+use crate::ui::widgets::files_widget::{files_widget, format_size, seconds_to_hms};
 use crate::ui::widgets::manual_handshake_widget::manual_handshake_widget;
-use crate::ui::widgets::server_handshake_widget::server_handshake_widget;
+use crate::ui::widgets::server_handshake_widget::{connection_banner, server_handshake_widget};
 
 const TITLE: &str = "tappi-share client";
 
@@ -19,14 +24,44 @@ pub fn render(app: &mut App, area: Rect, buf: &mut Buffer) {
         manual_flag = true
     }
 
+    let mut shortcuts = vec![Shortcut::new("Quit".to_string(), "q".to_string())];
+    if app.session.client_state.pending_confirm.is_some()
+        && app.session.completion_summary.is_none()
+    {
+        shortcuts.push(Shortcut::new(
+            "Start transfer".to_string(),
+            "Enter".to_string(),
+        ));
+    }
+    if !app.session.file_manager.input_map.is_empty() {
+        shortcuts.push(Shortcut::new("Open folder".to_string(), "o".to_string()));
+    }
+    shortcuts.push(Shortcut::new("New tab".to_string(), "t".to_string()));
+    shortcuts.push(Shortcut::new("Peers".to_string(), "p".to_string()));
+    shortcuts.push(Shortcut::new("Logs".to_string(), "L".to_string()));
+    if !app.sessions.is_empty() {
+        shortcuts.push(Shortcut::new("Tabs".to_string(), "[/]".to_string()));
+        shortcuts.push(Shortcut::new("Close tab".to_string(), "x".to_string()));
+    }
     let instructions = ShortcutStyle::new(&app.theme)
-        .shortcut_line(vec![Shortcut::new("Quit".to_string(), "q".to_string())])
+        .shortcut_line(shortcuts)
         .left_aligned();
 
     // Main frame
     let mut main_frame = MainFrame::create(&app.theme, area, TITLE);
     main_frame.block = main_frame.block.title_bottom(instructions);
+    if let Some(signaling) = &app.session.signaling {
+        let signaling = signaling_line(app, signaling);
+        main_frame.block = main_frame.block.title(signaling.left_aligned());
+    }
+    if !app.sessions.is_empty() {
+        main_frame.block = main_frame.block.title(tab_line(app).left_aligned());
+    }
     main_frame.block = main_frame.block.title(status_line(app).right_aligned());
+    if app.session.client_state.pending_confirm.is_some() {
+        main_frame.block = main_frame.block.title(confirm_line(app).centered());
+    }
+    main_frame.add_toast(&app.theme, app.toast.as_ref());
     main_frame.block = Shortcut::add_shortcut_bottom_title(
         &app.theme,
         app.widget_shortcuts.clone(),
@@ -36,30 +71,324 @@ pub fn render(app: &mut App, area: Rect, buf: &mut Buffer) {
     // Render
     let mut builder = FocusBuilder::default(); // Init focus builder
     main_frame.render(area, buf);
+    let inner = main_frame.inner;
 
     let vertical_layout = vertical![==4, *=1].spacing(1);
-    let inner_areas: [Rect; 2] = vertical_layout.areas(main_frame.inner);
+    let inner_areas: [Rect; 2] = vertical_layout.areas(inner);
 
-    if manual_flag {
+    // Manual signaling needs its widget to paste into, so it keeps the usual layout
+    let modal_area = if manual_flag {
         manual_handshake_widget(app, inner_areas[0], buf, &mut builder);
         files_widget(app, inner_areas[1], buf, &mut builder);
+        inner_areas[1]
+    } else if !app.session.client_state.was_connected {
+        connection_banner(app, inner, buf);
+        inner
     } else {
         server_handshake_widget(app, inner_areas[0], buf);
         files_widget(app, inner_areas[1], buf, &mut builder);
-    }
+        inner_areas[1]
+    };
 
     app.focus = builder.build(); // Build
+
+    // Modal goes on top of everything else
+    if app.new_tab.open {
+        new_tab_modal(app, modal_area, buf);
+    } else if app.session.client_state.incoming_offer.is_some() {
+        offer_modal(app, modal_area, buf);
+    } else if app.session.client_state.pasted_paths.is_some() {
+        pasted_paths_modal(app, modal_area, buf);
+    } else if app.session.client_state.data_cap.state == CapState::Reached {
+        data_cap_modal(app, modal_area, buf);
+    } else if app.session.completion_summary.is_some() {
+        summary_modal(app, modal_area, buf);
+    } else if app.peers_panel {
+        peers_modal(app, modal_area, buf);
+    }
+}
+
+/// Every tab needs a room or remote of its own, the files are optional
+fn new_tab_modal(app: &App, area: Rect, buf: &mut Buffer) {
+    let state = &app.new_tab;
+    let field = |label: &str, value: &str, editing: bool| {
+        let cursor = if editing { "▏" } else { "" };
+        line!(
+            format!("{}: ", label),
+            format!("{}{}", value, cursor).fg(app.theme.accent.clone())
+        )
+    };
+
+    let mut lines = vec![];
+    if let Some(target) = &state.target {
+        let label = match &app.session.signaling {
+            Some(SignalingSolutions::Mqtt(_)) => "Remote name",
+            _ => "Room",
+        };
+        lines.push(field(label, target, !state.editing_files));
+    }
+    lines.push(field("Files", &state.files, state.editing_files));
+    lines.push(line!("Type or drop paths, none is fine too"));
+
+    let mut shortcuts = vec![Shortcut::new("Next".to_string(), "Enter".to_string())];
+    if state.target.is_some() {
+        shortcuts.push(Shortcut::new("Switch".to_string(), "Tab".to_string()));
+    }
+    shortcuts.push(Shortcut::new("Cancel".to_string(), "Esc".to_string()));
+    modal(app, area, buf, "New tab", Text::from(lines), shortcuts);
+}
+
+/// A line for every tab, each one is a peer of its own
+fn peers_modal(app: &App, area: Rect, buf: &mut Buffer) {
+    let mut sessions: Vec<&ClientSession> = app.sessions.iter().collect();
+    sessions.push(&app.session);
+    sessions.sort_by_key(|session| session.id);
+
+    let lines: Vec<Line> = sessions
+        .into_iter()
+        .map(|session| {
+            let state = &session.client_state;
+            let (status, color) = if state.connected {
+                ("connected", app.theme.success.clone())
+            } else if state.was_connected {
+                ("disconnected", app.theme.error.clone())
+            } else {
+                ("connecting", app.theme.info.clone())
+            };
+            let progress = (session.progress())
+                .map(|progress| format!(" {:.0}%", progress * 100.0))
+                .unwrap_or_default();
+            let label = session.peer_label().unwrap_or("unknown".to_string());
+            let tab = format!("{} ", session.id + 1);
+            let tab = if session.id == app.session.id {
+                Span::styled(tab, app.theme.selected_style())
+            } else {
+                tab.fg(app.theme.text.clone())
+            };
+            line!(
+                tab,
+                label.fg(app.theme.accent.clone()),
+                " ",
+                status.fg(color),
+                progress.fg(app.theme.text.clone()),
+            )
+        })
+        .collect();
+
+    let shortcuts = vec![Shortcut::new("Close".to_string(), "p".to_string())];
+    modal(app, area, buf, "Peers", Text::from(lines), shortcuts);
+}
+
+fn offer_modal(app: &App, area: Rect, buf: &mut Buffer) {
+    let Some(offer) = &app.session.client_state.incoming_offer else {
+        return;
+    };
+    let summary = &offer.summary;
+    let sender = summary.sender.as_deref().unwrap_or("The peer");
+
+    let shortcuts = vec![
+        Shortcut::new("Accept".to_string(), "y".to_string()),
+        Shortcut::new("Decline".to_string(), "n".to_string()),
+    ];
+    let text = Text::from(vec![
+        line!(
+            sender.to_string().fg(app.theme.accent.clone()),
+            " wants to send you"
+        ),
+        line!(format!(
+            "{} file(s), {}",
+            summary.files,
+            format_size(summary.size)
+        )),
+    ]);
+
+    modal(app, area, buf, "Incoming transfer", text, shortcuts);
+}
+
+fn pasted_paths_modal(app: &App, area: Rect, buf: &mut Buffer) {
+    let Some(paths) = &app.session.client_state.pasted_paths else {
+        return;
+    };
+    let first = paths[0].file_name().unwrap_or(paths[0].as_os_str());
+    let first = first.to_string_lossy().to_string();
+
+    let shortcuts = vec![
+        Shortcut::new("Add".to_string(), "y".to_string()),
+        Shortcut::new("Ignore".to_string(), "n".to_string()),
+    ];
+    let text = Text::from(vec![
+        line!("Add to the transfer?"),
+        if paths.len() > 1 {
+            line!(
+                first.fg(app.theme.accent.clone()),
+                format!(" and {} more", paths.len() - 1)
+            )
+        } else {
+            line!(first.fg(app.theme.accent.clone()))
+        },
+    ]);
+
+    modal(app, area, buf, "Dropped files", text, shortcuts);
+}
+
+fn data_cap_modal(app: &App, area: Rect, buf: &mut Buffer) {
+    let Some(limit) = app.session.client_state.data_cap.limit() else {
+        return;
+    };
+
+    let shortcuts = vec![
+        Shortcut::new("Continue".to_string(), "y".to_string()),
+        Shortcut::new("Stop".to_string(), "n".to_string()),
+    ];
+    let text = Text::from(vec![
+        line!(
+            "Data cap of ",
+            format_size(limit).fg(app.theme.accent.clone()),
+            " reached"
+        ),
+        line!("Files stop going either way until you decide"),
+    ]);
+    modal(app, area, buf, "Data cap", text, shortcuts);
+}
+
+fn summary_modal(app: &App, area: Rect, buf: &mut Buffer) {
+    let Some(summary) = &app.session.completion_summary else {
+        return;
+    };
+
+    let shortcuts = vec![Shortcut::new("Close".to_string(), "Enter".to_string())];
+    let mut text = Text::from(vec![
+        line!("All files delivered!".fg(app.theme.success.clone()).bold()),
+        line!(format!(
+            "{} file(s), {}",
+            summary.files,
+            format_size(summary.bytes)
+        )),
+        line!(format!(
+            "in {} at {:.1} Mbps",
+            seconds_to_hms(summary.elapsed.as_secs()),
+            summary.speed()
+        )),
+    ]);
+    match &app.session.client_state.receipt {
+        ReceiptState::Idle => {}
+        ReceiptState::Pending => {
+            text.push_line(line!("Checking the receipt...".fg(app.theme.info.clone())))
+        }
+        ReceiptState::Checked(verdict) => {
+            let color = if verdict.is_ok() {
+                app.theme.success.clone()
+            } else {
+                app.theme.error.clone()
+            };
+            text.push_line(line!(verdict.text().fg(color)));
+            for mismatch in verdict.mismatches.iter().take(SHOWN_MISMATCHES) {
+                text.push_line(line!(mismatch.clone()));
+            }
+            if verdict.mismatches.len() > SHOWN_MISMATCHES {
+                let more = verdict.mismatches.len() - SHOWN_MISMATCHES;
+                text.push_line(line!(format!("and {} more in the log", more)));
+            }
+        }
+    }
+
+    modal(app, area, buf, "Transfer complete", text, shortcuts);
+}
+
+/// Centered popup, sized to fit the text
+fn modal(
+    app: &App,
+    area: Rect,
+    buf: &mut Buffer,
+    title: &str,
+    text: Text,
+    shortcuts: Vec<Shortcut>,
+) {
+    let instructions = ShortcutStyle::new(&app.theme)
+        .shortcut_line(shortcuts)
+        .centered();
+    let block = BlockDefault::window(&app.theme, Some(title), true).title_bottom(instructions);
+
+    let height = text.lines.len() as u16 + 2;
+    let [area] = vertical![==height].flex(Flex::Center).areas(area);
+    let [area] = horizontal![==48].flex(Flex::Center).areas(area);
+    Clear.render(area, buf);
+    Paragraph::new(text.centered())
+        .block(block)
+        .render(area, buf);
 }
 
 fn status_line<'a>(app: &mut App) -> Line<'a> {
-    line!(
+    let (sent, received) = app.session.file_manager.total_transferred();
+    let mut line = Line::default();
+    if let Some(watch) = &app.session.client_state.watch {
+        let dir = watch.dir.file_name().unwrap_or(watch.dir.as_os_str());
+        line.push_span(" watching: ".fg(app.theme.text.clone()));
+        line.push_span(
+            dir.to_string_lossy()
+                .to_string()
+                .fg(app.theme.accent.clone()),
+        );
+    }
+    if app.session.client_state.data_cap.state != CapState::Under {
+        line.push_span(" data cap reached".fg(app.theme.error.clone()));
+    }
+    line.extend(line!(
+        " ",
+        "sent: ".fg(app.theme.text.clone()),
+        format_size(sent).fg(app.theme.info.clone()),
+        " received: ".fg(app.theme.text.clone()),
+        format_size(received).fg(app.theme.info.clone()),
+        " ",
+        "peer: ".fg(app.theme.text.clone()),
+        (app.session.peer_label()).unwrap_or("none".to_string()).fg(
+            if app.session.client_state.connected {
+                app.theme.success.clone()
+            } else {
+                app.theme.error.clone()
+            }
+        ),
         " ",
-        "connected: ".fg(app.theme.text.clone()),
-        format!("{:5}", app.client_state.connected).fg(if app.client_state.connected {
-            app.theme.success.clone()
+    ));
+    line
+}
+
+/// Backend and whatever it connects through, args from a profile are easy to lose track of
+fn signaling_line<'a>(app: &App, signaling: &SignalingSolutions) -> Line<'a> {
+    let mut line = line!(" ", signaling.kind().fg(app.theme.accent.clone()));
+    if let Some(target) = signaling.target() {
+        line.push_span(": ".fg(app.theme.text.clone()));
+        line.push_span(target.fg(app.theme.info.clone()));
+    }
+    line.push_span(" ");
+    line
+}
+
+/// Tabs are numbered from one, the shown one is highlighted
+fn tab_line<'a>(app: &App) -> Line<'a> {
+    let mut line = Line::default();
+    for id in app.session_ids() {
+        let label = format!(" {} ", id + 1);
+        if id == app.session.id {
+            line.push_span(Span::styled(label, app.theme.selected_style()));
         } else {
-            app.theme.error.clone()
-        }),
+            line.push_span(label.fg(app.theme.text.clone()));
+        }
+    }
+    line
+}
+
+fn confirm_line<'a>(app: &App) -> Line<'a> {
+    let files = app
+        .session
+        .file_manager
+        .output_queue
+        .iter()
+        .filter(|f| !f.meta.is_dir);
+    let (count, size) = files.fold((0, 0), |(c, s), f| (c + 1, s + f.meta.size));
+    line!(
+        " ",
+        format!("Send {} file(s), {}?", count, format_size(size)).fg(app.theme.accent.clone()),
         " ",
     )
 }
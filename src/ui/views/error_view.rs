@@ -9,12 +9,16 @@ const TITLE: &str = "tappi ERROR";
 
 pub fn render(app: &mut App, area: Rect, buf: &mut Buffer) {
     let instructions = ShortcutStyle::new(&app.theme)
-        .shortcut_line(vec![Shortcut::new("Quit".to_string(), "q".to_string())])
+        .shortcut_line(vec![
+            Shortcut::new("Quit".to_string(), "q".to_string()),
+            Shortcut::new("Copy report".to_string(), "c".to_string()),
+        ])
         .left_aligned();
 
     // Main frame
     let mut main_frame = MainFrame::create(&app.theme, area, TITLE);
     main_frame.block = main_frame.block.title_bottom(instructions);
+    main_frame.add_toast(&app.theme, app.toast.as_ref());
     main_frame.block = Shortcut::add_shortcut_bottom_title(
         &app.theme,
         app.widget_shortcuts.clone(),
@@ -30,7 +34,11 @@ pub fn render(app: &mut App, area: Rect, buf: &mut Buffer) {
     }
     let mut paragraph = Paragraph::new("");
     if !full_report.is_empty() {
-        let full_report = Ansi::replace_colors(&app.theme, &full_report);
+        let full_report = if app.theme.monochrome {
+            Ansi::strip_colors(&full_report)
+        } else {
+            Ansi::replace_colors(&app.theme, &full_report)
+        };
         let colored = full_report.as_bytes().into_text();
 
         if let Ok(colored) = colored {
@@ -1,3 +1,4 @@
+pub mod accessible_view;
 pub mod client_view;
 pub mod error_view;
 pub mod server_view;
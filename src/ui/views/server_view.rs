@@ -5,6 +5,7 @@ use ratatui_macros::{horizontal, vertical};
 use crate::app::app_main::App;
 use crate::ui::utils::{BlockDefault, MainFrame, Shortcut, ShortcutStyle};
 use crate::ui::widgets::history_widget::history_widget;
+use crate::ui::widgets::room_detail_widget::room_detail_widget;
 use crate::ui::widgets::rooms_widget::rooms_widget;
 use crate::ui::widgets::users_widget::users_widget;
 
@@ -12,7 +13,10 @@ const TITLE: &str = "tappi-share server";
 
 pub fn render(app: &mut App, area: Rect, buf: &mut Buffer) {
     let instructions = ShortcutStyle::new(&app.theme)
-        .shortcut_line(vec![Shortcut::new("Quit".to_string(), "q".to_string())])
+        .shortcut_line(vec![
+            Shortcut::new("Quit".to_string(), "q".to_string()),
+            Shortcut::new("Logs".to_string(), "L".to_string()),
+        ])
         .left_aligned();
 
     // Main frame
@@ -41,7 +45,10 @@ pub fn render(app: &mut App, area: Rect, buf: &mut Buffer) {
 pub fn render_room_info(app: &mut App, area: Rect, buf: &mut Buffer, builder: &mut FocusBuilder) {
     let vertical_layout = vertical![*=1, *=5];
     let areas: [Rect; 2] = vertical_layout.areas(area);
+    let horizontal_layout = horizontal![*=3, *=2];
+    let top_areas: [Rect; 2] = horizontal_layout.areas(areas[0]);
 
-    users_widget(app, areas[0], buf, builder);
+    users_widget(app, top_areas[0], buf, builder);
+    room_detail_widget(app, top_areas[1], buf, builder);
     history_widget(app, areas[1], buf, builder);
 }
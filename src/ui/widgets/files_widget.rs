@@ -3,27 +3,92 @@ use indexmap::IndexMap;
 use rat_focus::{FocusBuilder, FocusFlag, HasFocus};
 use ratatui::{prelude::*, widgets::*};
 use ratatui::{style::Style, symbols::border};
-use ratatui_macros::horizontal;
 use ratatui_macros::line;
+use ratatui_macros::{horizontal, vertical};
+use std::time::{Duration, Instant};
+use tachyonfx::Interpolatable;
 use tui_widget_list::{ListBuilder, ListState as WidgetListState, ListView};
 
-use crate::app::app_event::AppEvent;
+use crate::app::app_event::{AppEvent, AppEventClient};
 use crate::app::app_main::App;
-use crate::app::file_manager::{FileId, FileManager, ProgressFile};
+use crate::app::file_manager::{
+    FileId, FileManager, FileStatus, MetaData, OutputRow, ProgressFile,
+};
+use crate::cli::{ActiveHighlight, ClientArgs, Commands};
 use crate::ui::theme::Theme;
 use crate::ui::utils::{
-    BlockDefault, CollapsedBorder, CombinedWidgetState, ScrollbarStateExt, Shortcut, StringExt,
-    WidgetListStateExt,
+    BlockDefault, CollapsedBorder, CombinedWidgetState, CountPrefix, ScrollbarStateExt, Shortcut,
+    StringExt, WidgetListStateExt,
 };
 
 const CHECK_MARK: &str = "[✓]";
+/// In front of the file being sent, unless `--active-highlight off`
+const ACTIVE_MARKER: &str = "▶ ";
+
+/// Rows a file takes up, compact drops the bordered block
+const ITEM_HEIGHT: u16 = 3;
+const COMPACT_ITEM_HEIGHT: u16 = 1;
+/// Five lines of details, the speed history and the borders
+const DETAILS_HEIGHT: u16 = 8;
 
 #[derive(Default)]
 pub struct FileListWidgetState {
     pub area: Rect, // Should get updated when it renders
     pub focus: FocusFlag,
+    pub count: CountPrefix, // Repeat count typed before a motion
     pub list_state: WidgetListState,
     pub scrollbar_state: ScrollbarState,
+    pub eta: EtaSmoother,
+    /// Shows the details of the selected file under the list
+    pub details: bool,
+    /// Only set on the outgoing list, `r` renames there
+    pub renamable: bool,
+    /// Outgoing file being renamed and the name typed so far
+    pub renaming: Option<(FileId, String)>,
+}
+impl FileListWidgetState {
+    /// Takes every key while renaming, Enter applies the name and Esc leaves it as it was
+    pub fn handle_typing(&mut self, key_event: &KeyEvent) -> Option<AppEvent> {
+        let (id, name) = self.renaming.as_mut()?;
+        if !key_event.is_release() {
+            return Some(AppEvent::None);
+        }
+
+        match key_event.code {
+            KeyCode::Enter => {
+                let event = AppEventClient::RenameFile(*id, std::mem::take(name));
+                self.renaming = None;
+                return Some(event.into());
+            }
+            KeyCode::Esc => self.renaming = None,
+            KeyCode::Backspace => {
+                name.pop();
+            }
+            KeyCode::Char(c) => name.push(c),
+            _ => {}
+        }
+        Some(AppEvent::None)
+    }
+
+    /// Pasted text goes into the name while renaming
+    pub fn handle_paste(&mut self, text: &str) -> bool {
+        match &mut self.renaming {
+            Some((_, name)) => {
+                name.push_str(text.trim());
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get_selected_index(&self) -> Option<usize> {
+        self.list_state.selected
+    }
+    /// The selected file of the list it was rendered with
+    pub fn get_selected<'a, K, V: ?Sized>(&self, files: &IndexMap<K, &'a V>) -> Option<&'a V> {
+        let i = self.get_selected_index()?;
+        files.get_index(i).map(|(_, file)| *file)
+    }
 }
 impl HasFocus for FileListWidgetState {
     fn area(&self) -> Rect {
@@ -38,7 +103,19 @@ impl HasFocus for FileListWidgetState {
 }
 impl CombinedWidgetState for FileListWidgetState {
     fn get_shortcuts(&self) -> Vec<Shortcut> {
-        vec![
+        if self.renaming.is_some() {
+            return vec![
+                Shortcut {
+                    description: "Rename".to_string(),
+                    button: "Enter".to_string(),
+                },
+                Shortcut {
+                    description: "Cancel".to_string(),
+                    button: "Esc".to_string(),
+                },
+            ];
+        }
+        let mut shortcuts = vec![
             Shortcut {
                 description: "First".to_string(),
                 button: "g".to_string(),
@@ -59,12 +136,27 @@ impl CombinedWidgetState for FileListWidgetState {
                 description: "Up".to_string(),
                 button: "k".to_string(),
             },
-        ]
+            Shortcut {
+                description: "Details".to_string(),
+                button: "i".to_string(),
+            },
+        ];
+        if self.renamable {
+            shortcuts.push(Shortcut {
+                description: "Rename".to_string(),
+                button: "r".to_string(),
+            });
+        }
+        shortcuts
     }
     fn handle_key_events(&mut self, key_event: &KeyEvent) -> color_eyre::Result<AppEvent> {
-        let result: AppEvent = AppEvent::None;
+        let mut result: AppEvent = AppEvent::None;
 
         if key_event.is_release() {
+            if self.count.push(key_event) {
+                return Ok(result); // Wait for the motion
+            }
+            let count = self.count.take();
             match key_event.code {
                 KeyCode::Char('g') | KeyCode::Home => {
                     self.list_state.first();
@@ -82,45 +174,130 @@ impl CombinedWidgetState for FileListWidgetState {
                         .match_widget_list_state(&self.list_state);
                 }
                 KeyCode::Char('j') | KeyCode::Down => {
-                    self.list_state.next();
+                    for _ in 0..count {
+                        self.list_state.next();
+                    }
                     self.scrollbar_state
                         .match_widget_list_state(&self.list_state);
                 }
                 KeyCode::Char('k') | KeyCode::Up => {
-                    self.list_state.previous();
+                    for _ in 0..count {
+                        self.list_state.previous();
+                    }
                     self.scrollbar_state
                         .match_widget_list_state(&self.list_state);
                 }
+                KeyCode::Char('i') => self.details = !self.details,
+                KeyCode::Char('r') if self.renamable => {
+                    result = AppEventClient::StartRename.into();
+                }
                 _ => {}
             }
         }
 
         Ok(result)
     }
+    fn reset_pending(&mut self) {
+        self.count.reset();
+    }
+}
+
+/// Display toggles shared by every gauge in a list
+#[derive(Clone, Copy, Default)]
+struct GaugeOptions {
+    icons: bool,
+    speed_colors: bool,
+    compact: bool,
+    byte_counts: bool,
+    highlight: ActiveHighlight,
+}
+impl GaugeOptions {
+    fn from_args(args: &ClientArgs) -> Self {
+        Self {
+            icons: args.icons,
+            speed_colors: args.speed_colors,
+            compact: args.compact,
+            byte_counts: args.byte_counts,
+            highlight: args.active_highlight,
+        }
+    }
+}
+
+/// Low-pass filters the ETA so it doesn't flicker every frame
+#[derive(Default)]
+pub struct EtaSmoother {
+    value: Option<f64>,
+    started: Option<Instant>,
+    updated: Option<Instant>,
+}
+impl EtaSmoother {
+    const ALPHA: f64 = 0.3;
+    const INTERVAL: Duration = Duration::from_secs(1);
+    const WARMUP: Duration = Duration::from_secs(3);
+
+    pub fn update(&mut self, estimate: f64) {
+        if estimate <= 0.0 {
+            *self = Self::default(); // Nothing left to transfer
+            return;
+        }
+        if !estimate.is_finite() {
+            return; // No speed data at the moment
+        }
+
+        let now = Instant::now();
+        self.started.get_or_insert(now);
+        if self
+            .updated
+            .is_some_and(|updated| now.duration_since(updated) < Self::INTERVAL)
+        {
+            return;
+        }
+
+        self.updated = Some(now);
+        self.value = Some(match self.value {
+            Some(value) => value + Self::ALPHA * (estimate - value),
+            None => estimate,
+        });
+    }
+    /// Stays empty until enough data was gathered
+    pub fn get(&self) -> Option<f64> {
+        let warmed_up = self
+            .started
+            .is_some_and(|started| started.elapsed() >= Self::WARMUP);
+        if warmed_up { self.value } else { None }
+    }
 }
 
 // Rebuild it on the fly for simplicity
-struct FileListWidget<'a, V: ProgressFile> {
+struct FileListWidget<'a, K, V: ProgressFile + ?Sized> {
     theme: &'a Theme,
     title: Option<String>,
     borders: Borders,
     border_set: symbols::border::Set,
-    files: &'a IndexMap<&'a FileId, &'a V>,
+    files: &'a IndexMap<K, &'a V>,
     speed: f64,
-    estimate: f64,
+    estimate: Option<f64>,
+    elapsed: Option<Duration>,
     completed: bool,
+    counts: (usize, usize),
+    options: GaugeOptions,
+    /// File being sent right now
+    active: Option<K>,
 }
-impl<'a, V: ProgressFile> FileListWidget<'a, V> {
+impl<'a, K, V: ProgressFile + ?Sized> FileListWidget<'a, K, V> {
     #[allow(clippy::too_many_arguments)] // TODO: investigate
     fn new(
         theme: &'a Theme,
         title: Option<String>,
         borders: Borders,
         border_set: symbols::border::Set,
-        files: &'a IndexMap<&'a FileId, &V>,
+        files: &'a IndexMap<K, &V>,
         speed: f64,
-        estimate: f64,
+        estimate: Option<f64>,
+        elapsed: Option<Duration>,
         completed: bool,
+        counts: (usize, usize),
+        options: GaugeOptions,
     ) -> Self {
         Self {
             theme,
@@ -130,16 +307,40 @@ impl<'a, V: ProgressFile> FileListWidget<'a, V> {
             files,
             speed,
             estimate,
+            elapsed,
             completed,
+            counts,
+            options,
+            active: None,
         }
     }
+
+    fn active(mut self, active: Option<K>) -> Self {
+        self.active = active;
+        self
+    }
 }
-impl<'a, V: ProgressFile> StatefulWidget for FileListWidget<'a, V> {
+impl<'a, K, V> StatefulWidget for FileListWidget<'a, K, V>
+where
+    K: std::hash::Hash + Eq,
+    V: ProgressFile + ?Sized,
+{
     type State = FileListWidgetState;
 
     fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
         state.area = area; // Set the area
 
+        // Details take the bottom of the list
+        let details = state.get_selected(self.files).filter(|_| state.details);
+        let area = match details {
+            Some(file) if area.height > DETAILS_HEIGHT * 2 => {
+                let [area, details_area] = vertical![*=1, ==DETAILS_HEIGHT].areas(area);
+                details_pane(self.theme, file, details_area, buf);
+                area
+            }
+            _ => area,
+        };
+
         // Create a block
         let mut block = BlockDefault::plain(self.theme)
             .borders(self.borders)
@@ -147,7 +348,13 @@ impl<'a, V: ProgressFile> StatefulWidget for FileListWidget<'a, V> {
 
         // Add title
         if let Some(widget_title) = &self.title {
-            block = block.title(widget_title.spaced());
+            let (completed, total) = self.counts;
+            let title = if total > 0 {
+                format!("{} ({}/{})", widget_title, completed, total)
+            } else {
+                widget_title.clone()
+            };
+            block = block.title(title.as_str().spaced());
         }
 
         // Set focus style
@@ -157,8 +364,14 @@ impl<'a, V: ProgressFile> StatefulWidget for FileListWidget<'a, V> {
 
         // Add speed estimate
         if self.speed > 0.0 {
-            block = block
-                .title_bottom(line!(format_speed_estimate(self.speed, self.estimate)).centered());
+            block = block.title_bottom(
+                line!(format_speed_estimate(
+                    self.speed,
+                    self.elapsed,
+                    self.estimate
+                ))
+                .centered(),
+            );
         }
 
         // Add check mark
@@ -166,16 +379,31 @@ impl<'a, V: ProgressFile> StatefulWidget for FileListWidget<'a, V> {
             block = block.title_bottom(line!(CHECK_MARK).right_aligned());
         }
 
+        // The name being typed replaces the bottom line
+        if let Some((_, name)) = &state.renaming {
+            block = BlockDefault::focus_style_block(&block)
+                .title_bottom(line!(" Rename: ".bold(), name.clone(), "▏ ").left_aligned());
+        }
+
         // Render
         let selected = if state.is_focused() {
             state.list_state.selected
         } else {
             None
         };
-        let file_list_view = file_list_widget(self.theme, self.files, selected, None);
+        let average_speed = self.options.speed_colors.then_some(self.speed);
+        let file_list_view = file_list_widget(
+            self.theme,
+            self.files,
+            selected,
+            None,
+            self.options,
+            average_speed,
+            self.active.as_ref(),
+        );
 
         let size = self.files.len();
-        let length = (size as u16) * 3;
+        let length = (size as u16) * item_height(self.options.compact);
         let inner = block.inner(area);
 
         block.render(area, buf);
@@ -191,21 +419,48 @@ impl<'a, V: ProgressFile> StatefulWidget for FileListWidget<'a, V> {
 }
 
 pub fn files_widget(app: &mut App, area: Rect, buf: &mut Buffer, builder: &mut FocusBuilder) {
+    let options = match &app.args.app_mode {
+        Commands::Client(client_args) => GaugeOptions::from_args(client_args),
+        _ => GaugeOptions::default(),
+    };
+
     // Compose layout
     let containing_block = BlockDefault::window(&app.theme, None, false);
     let layout = horizontal![==50%, ==50%];
     let a: [Rect; 2] = layout.areas(containing_block.inner(area));
 
     // File lists init
-    let input_speed = FileManager::get_average_speed(&app.file_manager.input_map);
-    let input_estimate = FileManager::get_estimate(&app.file_manager.input_map);
-    let input_completed = FileManager::get_completion(&app.file_manager.input_map);
-
-    let output_speed = FileManager::get_average_speed(&app.file_manager.output_map);
-    let output_estimate = FileManager::get_estimate(&app.file_manager.output_map);
-    let output_completed = FileManager::get_completion(&app.file_manager.output_map);
+    let input_speed = FileManager::get_average_speed(&app.session.file_manager.input_map);
+    app.session
+        .input_list_widget_state
+        .eta
+        .update(FileManager::get_estimate(
+            &app.session.file_manager.input_map,
+        ));
+    let input_estimate = app.session.input_list_widget_state.eta.get();
+    let input_elapsed = FileManager::get_elapsed(app.session.file_manager.input_started);
+    let input_completed = FileManager::get_received(&app.session.file_manager.input_map);
+    let input_counts = FileManager::get_counts(&app.session.file_manager.input_map);
+
+    let output_speed = FileManager::get_average_speed(&app.session.file_manager.output_map);
+    app.session
+        .output_list_widget_state
+        .eta
+        .update(FileManager::get_estimate(
+            &app.session.file_manager.output_map,
+        ));
+    let output_estimate = app.session.output_list_widget_state.eta.get();
+    let output_elapsed = FileManager::get_elapsed(app.session.file_manager.output_started);
+    let output_completed = FileManager::get_completion(&app.session.file_manager.output_map);
+    let output_counts = FileManager::get_counts(&app.session.file_manager.output_map);
+    // The clocks keep going while a transfer stalls
+    if (input_elapsed.is_some() && !input_completed)
+        || (output_elapsed.is_some() && !output_completed)
+    {
+        app.animating = true;
+    }
 
-    let input_files = app.file_manager.get_input_map();
+    let input_files = app.session.file_manager.get_input_map();
     let input_list = FileListWidget::new(
         &app.theme,
         Some("Incoming files".to_string()),
@@ -214,9 +469,22 @@ pub fn files_widget(app: &mut App, area: Rect, buf: &mut Buffer, builder: &mut F
         &input_files,
         input_speed,
         input_estimate,
+        input_elapsed,
         input_completed,
+        input_counts,
+        options,
     );
-    let output_files = app.file_manager.get_output_map_no_dir();
+    let show_dirs = matches!(&app.args.app_mode, Commands::Client(args) if args.show_dirs);
+    let output_dirs = if show_dirs {
+        app.session.file_manager.get_output_dirs()
+    } else {
+        vec![]
+    };
+    let output_files = if show_dirs {
+        app.session.file_manager.get_output_tree(&output_dirs)
+    } else {
+        app.session.file_manager.get_output_map_no_dir()
+    };
     let output_list = FileListWidget::new(
         &app.theme,
         Some("Outgoing files".to_string()),
@@ -225,31 +493,43 @@ pub fn files_widget(app: &mut App, area: Rect, buf: &mut Buffer, builder: &mut F
         &output_files,
         output_speed,
         output_estimate,
+        output_elapsed,
         output_completed,
+        output_counts,
+        options,
+    )
+    .active(
+        app.session
+            .client_state
+            .active_file
+            .as_ref()
+            .map(OutputRow::File),
     );
 
     // Render
     containing_block.render(area, buf); // Render first because otherwise colors get discarded
-    input_list.render(a[0], buf, &mut app.input_list_widget_state);
-    output_list.render(a[1], buf, &mut app.output_list_widget_state);
+    input_list.render(a[0], buf, &mut app.session.input_list_widget_state);
+    output_list.render(a[1], buf, &mut app.session.output_list_widget_state);
 
     // Build focus
-    app.input_list_widget_state.build(builder);
-    app.output_list_widget_state.build(builder);
+    app.session.input_list_widget_state.build(builder);
+    app.session.output_list_widget_state.build(builder);
 }
 
 fn file_list_widget<'a, K, V>(
     theme: &'a Theme,
-    files: &'a IndexMap<&K, &V>,
+    files: &'a IndexMap<K, &V>,
     selected: Option<usize>,
     bg_color: Option<Color>,
+    options: GaugeOptions,
+    average_speed: Option<f64>,
+    active: Option<&'a K>,
 ) -> ListView<'a, Gauge<'a>>
 where
     K: std::hash::Hash + Eq,
-    V: ProgressFile,
+    V: ProgressFile + ?Sized,
 {
     // Dang, this crate is clean
-    let keys = files.keys();
     let builder = ListBuilder::new(move |lbc| {
         let selected = if let Some(s) = selected {
             lbc.index == s
@@ -257,84 +537,337 @@ where
             false
         };
 
-        let fg_color = if selected {
-            theme.info.clone().into()
-        } else {
-            Color::White
-        };
-
-        let key = keys[lbc.index];
-        let file = files[key]; // Should be fine
-        let gauge = progress_gauge(theme, file, fg_color, bg_color);
+        let (key, &file) = files.get_index(lbc.index).unwrap(); // Should be fine
+        let active = active == Some(key);
+        let gauge = progress_gauge(
+            theme,
+            file,
+            selected,
+            bg_color,
+            options,
+            average_speed,
+            active,
+        );
 
-        (gauge, 3)
+        (gauge, item_height(options.compact))
     });
 
     ListView::new(builder, files.len())
 }
 
-fn progress_gauge<'a, F: ProgressFile>(
+fn progress_gauge<'a, F: ProgressFile + ?Sized>(
     theme: &Theme,
     file: &'a F,
-    fg_color: Color,
+    selected: bool,
     bg_color: Option<Color>,
+    options: GaugeOptions,
+    average_speed: Option<f64>,
+    active: bool,
 ) -> Gauge<'a> {
-    let mut block = Block::bordered()
-        .border_set(border::PLAIN)
+    let highlight = if active {
+        options.highlight
+    } else {
+        ActiveHighlight::Off
+    };
+    let mut style = Style::default()
         .bg(bg_color.unwrap_or(theme.surface1.clone().into())) // Hack to bypass the black background bug
-        .fg(fg_color);
+        .fg(theme.base_fg());
+    if selected {
+        style = style.patch(theme.selected_style());
+    }
+    let mut block = Block::bordered().border_set(border::PLAIN).style(style);
+    if highlight == ActiveHighlight::Border {
+        block = block
+            .border_set(border::THICK)
+            .border_style(Style::default().fg(theme.accent.clone().into()));
+    }
 
     // Add name
     if let Some(name) = file.get_name() {
-        block = block.title(format!("[{name}]"));
-    }
-
-    // Add check mark
-    block = if !file.get_finished() {
-        block
-    } else {
-        block.title(line!(CHECK_MARK).right_aligned())
-    };
+        let meta = file.get_meta();
+        let mut label = name.to_string();
+        if options.icons {
+            label = format!("{} {}", file_icon(meta), label);
+        } else if meta.is_dir {
+            label.push('/');
+        }
 
-    // Add speed
-    if file.get_progress() > 0.0 {
-        block = if file.get_finished() {
-            block
+        let label = match theme.extension_color(&meta.extension) {
+            Some(color) => label.fg(color),
+            None => label.into(),
+        };
+        let symbol = if selected {
+            theme.selection_symbol.as_str()
         } else {
-            block.title_bottom(line!(format_speed(file.get_speed())).right_aligned())
+            ""
         };
+        let marker = if highlight == ActiveHighlight::Marker {
+            ACTIVE_MARKER.fg(theme.accent.clone())
+        } else {
+            "".into()
+        };
+        block = block.title(line!(symbol.to_string(), marker, "[", label, "]"));
+    }
+
+    // Add status mark
+    let status = file.get_status();
+    if let Some(mark) = status_mark(status) {
+        let mark = mark.fg(status_color(theme, status).unwrap_or(theme.base_fg()));
+        block = block.title(line!(mark).right_aligned());
+    }
+
+    // Add speed, folders don't have one of their own
+    if file.get_progress() > 0.0 && !file.get_meta().is_dir && status.is_open() {
+        block = block.title_bottom(line!(format_speed(file.get_speed())).right_aligned());
     }
 
     // Set gauge style
-    let gauge_style = if file.get_progress() >= 1.0 {
+    let gauge_style = if status == FileStatus::Done {
         Style::default()
             .fg(theme.success.clone().into())
             .add_modifier(Modifier::BOLD) // BG doesn't matter
+    } else if let Some(color) = status_color(theme, status).filter(|_| !status.is_open()) {
+        Style::default().bg(theme.surface2.clone().into()).fg(color) // Frozen where it stopped
     } else {
-        Style::default()
-            .bg(theme.surface2.clone().into())
-            .fg(theme.warning.clone().into()) // BG matters
+        let color = match average_speed {
+            Some(average) if average > 0.0 && file.get_speed() > 0.0 => {
+                speed_color(theme, file.get_speed() / average)
+            }
+            _ => theme.warning.clone().into(),
+        };
+        Style::default().bg(theme.surface2.clone().into()).fg(color) // BG matters
     };
 
+    if options.compact {
+        let marked = highlight != ActiveHighlight::Off; // No border to thicken
+        let label = compact_label(theme, file, selected, marked, options);
+        let label = if selected {
+            Span::styled(label, theme.selected_style())
+        } else {
+            label.into()
+        };
+        return Gauge::default()
+            .gauge_style(gauge_style)
+            .ratio(file.get_progress())
+            .label(label);
+    }
+
     // Assemble
-    Gauge::default()
+    let mut gauge = Gauge::default()
         .gauge_style(gauge_style)
         .ratio(file.get_progress())
         .block(block)
-        .fg(theme.text.clone())
+        .fg(theme.text.clone());
+    if options.byte_counts && !file.get_meta().is_dir {
+        gauge = gauge.label(format!(
+            "{} ({:.0}%)",
+            format_byte_counts(file),
+            file.get_progress() * 100.0
+        ));
+    }
+    gauge
 }
 
-fn format_speed(speed: f64) -> String {
-    format!("[{:.1} Mbps]", speed)
+/// Name, progress and speed squeezed into the gauge label
+fn compact_label<F: ProgressFile + ?Sized>(
+    theme: &Theme,
+    file: &F,
+    selected: bool,
+    marked: bool,
+    options: GaugeOptions,
+) -> String {
+    let mut label = String::new();
+    if selected {
+        label.push_str(theme.selection_symbol.as_str());
+    }
+    if marked {
+        label.push_str(ACTIVE_MARKER);
+    }
+    if let Some(name) = file.get_name() {
+        if options.icons {
+            label = format!("{}{} ", label, file_icon(file.get_meta()));
+        }
+        let slash = if file.get_meta().is_dir && !options.icons {
+            "/"
+        } else {
+            ""
+        };
+        label = format!("{}{}{} ", label, name, slash);
+    }
+    if options.byte_counts && !file.get_meta().is_dir {
+        label = format!("{}{} ", label, format_byte_counts(file));
+    }
+    label = format!("{}{:.0}%", label, file.get_progress() * 100.0);
+
+    let status = file.get_status();
+    if let Some(mark) = status_mark(status).filter(|_| !status.is_open()) {
+        format!("{} {}", label, mark)
+    } else if file.get_progress() > 0.0 && !file.get_meta().is_dir {
+        let speed = format_speed(file.get_speed());
+        match status_mark(status) {
+            Some(mark) => format!("{} {} {}", label, mark, speed),
+            None => format!("{} {}", label, speed),
+        }
+    } else {
+        label
+    }
+}
+
+/// Everything about a single file that doesn't fit on its gauge
+fn details_pane<F: ProgressFile + ?Sized>(theme: &Theme, file: &F, area: Rect, buf: &mut Buffer) {
+    let meta = file.get_meta();
+    let kind = if meta.is_dir {
+        "folder".to_string()
+    } else if meta.extension.is_empty() {
+        "file".to_string()
+    } else {
+        format!("{} file", meta.extension)
+    };
+    let status = file.get_status();
+    let status_style = status_color(theme, status).unwrap_or(theme.text.clone().into());
+    let key = |text: &'static str| text.fg(theme.info.clone());
+    let text = Text::from(vec![
+        line!(key("Path: "), file.get_location().display().to_string()),
+        line!(key("Size: "), format_size(meta.size), ", ", kind),
+        line!(
+            key("Progress: "),
+            format_byte_counts(file),
+            format!(" ({:.0}%)", file.get_progress() * 100.0)
+        ),
+        line!(key("Speed: "), format_speed(file.get_speed())),
+        line!(key("Status: "), status_label(status).fg(status_style)),
+    ]);
+
+    let block = BlockDefault::bordered(theme).title(line!(" Details ".to_string()));
+    let inner = block.inner(area);
+    block.render(area, buf);
+    let [text_area, history_area] = vertical![*=1, ==1].areas(inner);
+    Paragraph::new(text)
+        .wrap(Wrap { trim: false })
+        .render(text_area, buf);
+
+    // Sparklines only take integers, kbps keeps it smooth enough
+    let history: Vec<u64> = (file.get_speed_counter().history().iter())
+        .map(|speed| (speed * 1000.0) as u64)
+        .collect();
+    Sparkline::default()
+        .data(&history)
+        .style(Style::default().fg(theme.warning.clone().into()))
+        .render(history_area, buf);
+}
+
+/// What a file that isn't simply pending or moving gets next to its name
+fn status_mark(status: FileStatus) -> Option<&'static str> {
+    match status {
+        FileStatus::Pending | FileStatus::Active => None,
+        FileStatus::Done => Some(CHECK_MARK),
+        FileStatus::Failed => Some("[✗]"),
+        FileStatus::Skipped => Some("[skipped]"),
+        FileStatus::Corrupted => Some("[!]"),
+    }
+}
+fn status_color(theme: &Theme, status: FileStatus) -> Option<Color> {
+    match status {
+        FileStatus::Pending | FileStatus::Active => None,
+        FileStatus::Done => Some(theme.success.clone().into()),
+        FileStatus::Failed => Some(theme.error.clone().into()),
+        FileStatus::Skipped => Some(theme.info.clone().into()),
+        FileStatus::Corrupted => Some(theme.warning.clone().into()),
+    }
+}
+
+fn status_label(status: FileStatus) -> &'static str {
+    match status {
+        FileStatus::Pending => "waiting",
+        FileStatus::Active => "moving",
+        FileStatus::Done => "done",
+        FileStatus::Failed => "failed",
+        FileStatus::Skipped => "skipped",
+        FileStatus::Corrupted => "corrupt chunk, sent again",
+    }
 }
-fn format_speed_estimate(speed: f64, estimate: f64) -> String {
+
+fn item_height(compact: bool) -> u16 {
+    if compact {
+        COMPACT_ITEM_HEIGHT
+    } else {
+        ITEM_HEIGHT
+    }
+}
+
+/// Shades from error through warning to success, half the average speed is warning
+fn speed_color(theme: &Theme, ratio: f64) -> Color {
+    if theme.monochrome {
+        return Color::Reset; // Nothing to blend
+    }
+    let error: Color = theme.error.clone().into();
+    let warning: Color = theme.warning.clone().into();
+    let success: Color = theme.success.clone().into();
+
+    let ratio = ratio.clamp(0.0, 1.0) as f32;
+    if ratio < 0.5 {
+        error.lerp(&warning, ratio * 2.0)
+    } else {
+        warning.lerp(&success, (ratio - 0.5) * 2.0)
+    }
+}
+
+/// Nerd Font icon for the file type
+fn file_icon(meta: &MetaData) -> &'static str {
+    if meta.is_dir {
+        return "\u{f07b}";
+    }
+
+    match meta.extension.to_lowercase().as_str() {
+        "png" | "jpg" | "jpeg" | "gif" | "svg" | "webp" | "bmp" => "\u{f1c5}",
+        "mp4" | "mkv" | "webm" | "mov" | "avi" => "\u{f1c8}",
+        "mp3" | "flac" | "wav" | "ogg" | "m4a" => "\u{f1c7}",
+        "zip" | "tar" | "gz" | "xz" | "7z" | "rar" => "\u{f1c6}",
+        "pdf" => "\u{f1c1}",
+        "doc" | "docx" | "odt" => "\u{f1c2}",
+        "txt" | "md" => "\u{f15c}",
+        "rs" => "\u{e7a8}",
+        "py" | "js" | "ts" | "c" | "cpp" | "go" | "java" | "toml" | "json" | "sh" => "\u{f1c9}",
+        _ => "\u{f15b}",
+    }
+}
+
+/// "42.3 MiB / 108.0 MiB"
+fn format_byte_counts<F: ProgressFile + ?Sized>(file: &F) -> String {
     format!(
-        "[{:.1} Mbps, ETA: {}]",
-        speed,
-        seconds_to_hms(estimate as u64)
+        "{} / {}",
+        format_size(file.get_progress_bytes()),
+        format_size(file.get_meta().size)
     )
 }
-fn seconds_to_hms(seconds: u64) -> String {
+pub fn format_size(bytes: usize) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+fn format_speed(speed: f64) -> String {
+    format!("[{:.1} Mbps]", speed)
+}
+fn format_speed_estimate(speed: f64, elapsed: Option<Duration>, estimate: Option<f64>) -> String {
+    let mut parts = vec![format!("{:.1} Mbps", speed)];
+    if let Some(elapsed) = elapsed {
+        parts.push(format!("Elapsed: {}", seconds_to_hms(elapsed.as_secs())));
+    }
+    if let Some(estimate) = estimate {
+        parts.push(format!("ETA: {}", seconds_to_hms(estimate as u64)));
+    }
+    format!("[{}]", parts.join(", "))
+}
+pub fn seconds_to_hms(seconds: u64) -> String {
     let hours = seconds / 3600;
     let minutes = (seconds % 3600) / 60;
     let seconds = seconds % 60;
@@ -1,4 +1,4 @@
-use crossterm::event::{KeyCode, KeyEvent};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use rat_focus::{FocusBuilder, FocusFlag, HasFocus};
 use ratatui::symbols::border;
 use ratatui::{prelude::*, widgets::*};
@@ -14,12 +14,27 @@ use crate::ui::utils::{
     BlockDefault, BlockExt, CollapsedBorder, CombinedWidgetState, Shortcut, StringExt,
 };
 
+/// Narrower than this and wrapping the messages makes no sense
+const MIN_TEXT_WIDTH: u16 = 4;
+
 #[derive(Default)]
 pub struct HistoryWidgetState {
     pub area: Rect, // Should get updated when it renders
     pub focus: FocusFlag,
     pub scroll_view_state: ScrollViewState,
 }
+impl HistoryWidgetState {
+    /// Rows visible inside the borders on the last render
+    fn page_height(&self) -> u16 {
+        self.area.height.saturating_sub(2)
+    }
+    /// Positive goes down, the render clamps it to the content
+    fn scroll_by(&mut self, rows: i32) {
+        let mut offset = self.scroll_view_state.offset();
+        offset.y = (offset.y as i32 + rows).clamp(0, u16::MAX as i32) as u16;
+        self.scroll_view_state.set_offset(offset);
+    }
+}
 impl HasFocus for HistoryWidgetState {
     fn area(&self) -> Rect {
         self.area
@@ -50,13 +65,30 @@ impl CombinedWidgetState for HistoryWidgetState {
                 description: "Up".to_string(),
                 button: "k".to_string(),
             },
+            Shortcut {
+                description: "Half page".to_string(),
+                button: "^d/^u".to_string(),
+            },
+            Shortcut {
+                description: "Page".to_string(),
+                button: "PgDn/PgUp".to_string(),
+            },
         ]
     }
     fn handle_key_events(&mut self, key_event: &KeyEvent) -> color_eyre::Result<AppEvent> {
         let result: AppEvent = AppEvent::None;
 
         if key_event.is_release() {
+            let page = self.page_height().max(1) as i32;
+            let half_page = (page / 2).max(1);
+            let ctrl = key_event.modifiers.contains(KeyModifiers::CONTROL);
             match key_event.code {
+                KeyCode::Char('d') if ctrl => self.scroll_by(half_page),
+                KeyCode::Char('u') if ctrl => self.scroll_by(-half_page),
+                KeyCode::Char('f') if ctrl => self.scroll_by(page),
+                KeyCode::Char('b') if ctrl => self.scroll_by(-page),
+                KeyCode::PageDown => self.scroll_by(page),
+                KeyCode::PageUp => self.scroll_by(-page),
                 KeyCode::Char('g') | KeyCode::Home => {
                     self.scroll_view_state.scroll_to_top();
                 }
@@ -129,7 +161,10 @@ impl<'a> StatefulWidget for HistoryWidget<'a> {
         block.render(area, buf); // Render first because otherwise colors get discarded
 
         if let Some(users) = self.history {
-            let width: u16 = inner.width - 2; // 1 for scrollbar + 1 for margin
+            let width: u16 = inner.width.saturating_sub(2); // 1 for scrollbar + 1 for margin
+            if width < MIN_TEXT_WIDTH || inner.height == 0 {
+                return; // No room to draw anything
+            }
             let mut layout_constraints: Vec<Constraint> = vec![];
             let items: Vec<Paragraph> = users
                 .iter()
@@ -191,3 +226,63 @@ pub fn history_widget(app: &mut App, area: Rect, buf: &mut Buffer, builder: &mut
     // Build focus
     app.history_widget_state.build(builder);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn narrow_area_doesnt_panic() {
+        let theme = Theme::load_default().unwrap();
+        let history = vec![UserMessage::new(
+            "room".to_string(),
+            1,
+            "hello there".to_string(),
+        )];
+        let mut state = HistoryWidgetState::default();
+
+        for width in 0..8 {
+            let area = Rect::new(0, 0, width, 6);
+            let mut buf = Buffer::empty(area);
+            HistoryWidget::new(
+                &theme,
+                Some("History".to_string()),
+                CollapsedBorder::all(),
+                border::PLAIN,
+                Some(&history),
+            )
+            .render(area, &mut buf, &mut state);
+        }
+    }
+    #[test]
+    fn pages_follow_the_area_height() {
+        let mut state = HistoryWidgetState {
+            area: Rect::new(0, 0, 20, 12), // 10 rows inside the borders
+            ..Default::default()
+        };
+        let ctrl = |c| {
+            KeyEvent::new_with_kind(
+                KeyCode::Char(c),
+                KeyModifiers::CONTROL,
+                crossterm::event::KeyEventKind::Release,
+            )
+        };
+        let release = |code| {
+            KeyEvent::new_with_kind(
+                code,
+                KeyModifiers::NONE,
+                crossterm::event::KeyEventKind::Release,
+            )
+        };
+
+        state.handle_key_events(&ctrl('d')).unwrap();
+        assert_eq!(state.scroll_view_state.offset().y, 5);
+        state
+            .handle_key_events(&release(KeyCode::PageDown))
+            .unwrap();
+        assert_eq!(state.scroll_view_state.offset().y, 15);
+        state.handle_key_events(&ctrl('b')).unwrap();
+        state.handle_key_events(&ctrl('b')).unwrap();
+        assert_eq!(state.scroll_view_state.offset().y, 0); // Doesn't go past the top
+    }
+}
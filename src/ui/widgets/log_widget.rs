@@ -0,0 +1,97 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::layout::Flex;
+use ratatui::{prelude::*, widgets::*};
+use ratatui_macros::{horizontal, vertical};
+
+use crate::app::app_main::App;
+use crate::ui::utils::{BlockDefault, Shortcut, ShortcutStyle};
+
+/// Overlay with the recent log lines, toggled with `L`
+#[derive(Default)]
+pub struct LogPanelState {
+    pub open: bool,
+    /// Lines scrolled up from the bottom, 0 follows the new ones
+    pub scroll: usize,
+    /// Rows visible on the last render
+    pub height: usize,
+}
+impl LogPanelState {
+    /// Returns true if the key was meant for the panel
+    pub fn handle_key_events(&mut self, key_event: &KeyEvent) -> bool {
+        if !key_event.is_release() {
+            return self.open;
+        }
+
+        let page = self.height.max(1);
+        match key_event.code {
+            KeyCode::Char('L') => {
+                self.open = !self.open;
+                self.scroll = 0;
+            }
+            _ if !self.open => return false,
+            KeyCode::Esc => self.open = false,
+            KeyCode::Char('k') | KeyCode::Up => self.scroll = self.scroll.saturating_add(1),
+            KeyCode::Char('j') | KeyCode::Down => self.scroll = self.scroll.saturating_sub(1),
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_add(page),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(page),
+            KeyCode::Char('g') | KeyCode::Home => self.scroll = usize::MAX, // Clamped on render
+            KeyCode::Char('G') | KeyCode::End => self.scroll = 0,
+            _ => {}
+        }
+        true
+    }
+}
+
+pub fn log_widget(app: &mut App, area: Rect, buf: &mut Buffer) {
+    app.animating = true; // New lines come in without an event
+    let lines = app.log_buffer.lines();
+    let theme = &app.theme;
+    let state = &mut app.log_panel_state;
+
+    let [area] = vertical![==80%].flex(Flex::Center).areas(area);
+    let [area] = horizontal![==90%].flex(Flex::Center).areas(area);
+
+    let shortcuts = vec![
+        Shortcut::new("Close".to_string(), "L/Esc".to_string()),
+        Shortcut::new("Scroll".to_string(), "j/k".to_string()),
+        Shortcut::new("Top/Bottom".to_string(), "g/G".to_string()),
+    ];
+    let instructions = ShortcutStyle::new(theme)
+        .shortcut_line(shortcuts)
+        .centered();
+    let block = BlockDefault::window(theme, Some("Logs"), true).title_bottom(instructions);
+
+    // Keep the scroll within the lines so it reacts right away when going back down
+    state.height = block.inner(area).height as usize;
+    state.scroll = state.scroll.min(lines.len().saturating_sub(state.height));
+    let end = lines.len() - state.scroll;
+    let start = end.saturating_sub(state.height);
+
+    let text: Vec<Line> = if lines.is_empty() {
+        let hint = if app.args.log_level == log::LevelFilter::Off {
+            "Logging is off, start with --log-level to see something here"
+        } else {
+            "Nothing logged yet"
+        };
+        vec![Line::from(hint.fg(theme.surface2.clone())).centered()]
+    } else {
+        lines[start..end]
+            .iter()
+            .map(|line| {
+                let color = match line.level {
+                    log::Level::Error => theme.error.clone(),
+                    log::Level::Warn => theme.warning.clone(),
+                    log::Level::Info => theme.info.clone(),
+                    log::Level::Debug | log::Level::Trace => theme.surface2.clone(),
+                };
+                Line::from(vec![
+                    format!("{:<5} ", line.level).fg(color),
+                    line.text.clone().fg(theme.text.clone()),
+                ])
+            })
+            .collect()
+    };
+
+    Clear.render(area, buf);
+    Paragraph::new(text).block(block).render(area, buf);
+}
@@ -1,4 +1,3 @@
-use arboard::Clipboard;
 use crossterm::event::{KeyCode, KeyEvent};
 use rat_focus::{FocusBuilder, FocusFlag, HasFocus};
 use ratatui::symbols::border;
@@ -7,9 +6,11 @@ use ratatui_macros::line;
 
 use crate::app::app_event::{AppEvent, AppEventClient};
 use crate::app::app_main::App;
+use crate::client::signaling::negotiator::HandshakeState;
 use crate::ui::theme::Theme;
 use crate::ui::utils::{
     BlockDefault, BlockExt, CollapsedBorder, CombinedWidgetState, Shortcut, StringExt,
+    SystemClipboard,
 };
 
 #[derive(Default)]
@@ -19,20 +20,56 @@ pub struct ManualHandshakeWidgetState {
     pub input_text: String,
     pub output_text: String,
     pub polite: Option<bool>,
+    /// Typed or pasted in pieces so far, Enter submits it
+    pub pending: String,
+    pub typing: bool,
 }
 impl ManualHandshakeWidgetState {
-    fn copy(&self) -> color_eyre::Result<()> {
-        let output = &self.output_text;
-        let mut clipboard = Clipboard::new()?;
-        if !output.is_empty() {
-            clipboard.set_text(output)?;
+    /// Whether the peer's handshake is still expected
+    pub fn awaits_input(&self) -> bool {
+        self.polite.is_some() && self.input_text.is_empty()
+    }
+
+    /// Takes every key while typing, so shortcuts don't eat parts of the handshake
+    pub fn handle_typing(&mut self, key_event: &KeyEvent) -> Option<AppEvent> {
+        if !self.typing {
+            return None;
+        }
+        if !key_event.is_release() {
+            return Some(AppEvent::None);
+        }
+
+        match key_event.code {
+            KeyCode::Enter => {
+                self.typing = false;
+                let text = std::mem::take(&mut self.pending);
+                return Some(AppEventClient::ManualSignalingInput(text).into());
+            }
+            KeyCode::Esc => self.typing = false,
+            KeyCode::Backspace => {
+                self.pending.pop();
+            }
+            KeyCode::Char(c) => self.pending.push(c),
+            _ => {}
         }
-        Ok(())
+        Some(AppEvent::None)
+    }
+
+    /// Pieces of a split paste add up instead of getting submitted one by one
+    pub fn handle_paste(&mut self, text: &str) -> bool {
+        if !self.awaits_input() || !(self.typing || self.is_focused()) {
+            return false;
+        }
+        self.typing = true;
+        self.pending.push_str(text.trim());
+        true
+    }
+
+    fn copy(&self) -> color_eyre::Result<()> {
+        SystemClipboard::copy(&self.output_text)
     }
     fn get_clipboard_text(&self) -> color_eyre::Result<String> {
-        let mut clipboard = Clipboard::new()?;
-        let text = clipboard.get_text()?;
-        Ok(text)
+        SystemClipboard::paste()
     }
 }
 impl HasFocus for ManualHandshakeWidgetState {
@@ -49,6 +86,18 @@ impl HasFocus for ManualHandshakeWidgetState {
 impl CombinedWidgetState for ManualHandshakeWidgetState {
     fn get_shortcuts(&self) -> Vec<Shortcut> {
         let mut result = vec![];
+        if self.typing {
+            return vec![
+                Shortcut {
+                    description: "Submit".to_string(),
+                    button: "Enter".to_string(),
+                },
+                Shortcut {
+                    description: "Stop typing".to_string(),
+                    button: "Esc".to_string(),
+                },
+            ];
+        }
 
         if let Some(polite) = self.polite {
             if polite {
@@ -62,6 +111,10 @@ impl CombinedWidgetState for ManualHandshakeWidgetState {
                         description: "Paste".to_string(),
                         button: "v".to_string(),
                     });
+                    result.push(Shortcut {
+                        description: "Type".to_string(),
+                        button: "i".to_string(),
+                    });
                 }
             } else {
                 result.push(Shortcut {
@@ -72,7 +125,11 @@ impl CombinedWidgetState for ManualHandshakeWidgetState {
                     result.push(Shortcut {
                         description: "Paste".to_string(),
                         button: "v".to_string(),
-                    })
+                    });
+                    result.push(Shortcut {
+                        description: "Type".to_string(),
+                        button: "i".to_string(),
+                    });
                 };
             }
         }
@@ -91,6 +148,10 @@ impl CombinedWidgetState for ManualHandshakeWidgetState {
                 KeyCode::Char('v') => {
                     AppEventClient::ManualSignalingInput(self.get_clipboard_text()?).into()
                 }
+                KeyCode::Char('i') if self.awaits_input() => {
+                    self.typing = true;
+                    AppEvent::None
+                }
                 _ => AppEvent::None,
             }
         }
@@ -143,7 +204,18 @@ impl<'a> StatefulWidget for ManualHandshakeWidget<'a> {
         }
 
         // Render
-        let input_text = character_of_size('*', state.input_text.len());
+        // Only the length of a partial one, the stars would run off the screen
+        let input_text =
+            if state.input_text.is_empty() && (state.typing || !state.pending.is_empty()) {
+                let cursor = if state.typing { "▏" } else { "" };
+                format!(
+                    "{} characters{}, Enter submits",
+                    state.pending.len(),
+                    cursor
+                )
+            } else {
+                character_of_size('*', state.input_text.len())
+            };
         let output_text = character_of_size('*', state.output_text.len());
 
         let inner = block.inner_with_margin(area, 0, 1);
@@ -170,9 +242,14 @@ pub fn manual_handshake_widget(
 ) {
     let block = BlockDefault::window(&app.theme, None, false);
 
+    // Nothing happens until the peer gets our output
+    let title = match app.session.handshake_state {
+        HandshakeState::WaitingForPeer => "Handshake, share the output with your peer",
+        _ => "Handshake",
+    };
     let handshake_widget = ManualHandshakeWidget::new(
         &app.theme,
-        Some("Handshake".to_string()),
+        Some(title.to_string()),
         CollapsedBorder::all(),
         border::PLAIN,
     );
@@ -180,8 +257,42 @@ pub fn manual_handshake_widget(
     // Render
     let inner = block.inner(area);
     block.render(area, buf);
-    handshake_widget.render(inner, buf, &mut app.handshake_widget_state);
+    handshake_widget.render(inner, buf, &mut app.session.handshake_widget_state);
 
     // Build focus
-    app.handshake_widget_state.build(builder);
+    app.session.handshake_widget_state.build(builder);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyEventKind, KeyModifiers};
+
+    fn release(code: KeyCode) -> KeyEvent {
+        KeyEvent::new_with_kind(code, KeyModifiers::NONE, KeyEventKind::Release)
+    }
+
+    #[test]
+    fn pieces_add_up_until_enter() {
+        let mut state = ManualHandshakeWidgetState {
+            polite: Some(true),
+            ..Default::default()
+        };
+        assert!(state.handle_typing(&release(KeyCode::Char('v'))).is_none());
+
+        state.typing = true;
+        assert!(state.handle_paste("v4.local.\n"));
+        for c in "abcq".chars() {
+            state.handle_typing(&release(KeyCode::Char(c)));
+        }
+        state.handle_typing(&release(KeyCode::Backspace));
+        assert_eq!(state.pending, "v4.local.abc");
+
+        let submitted = state.handle_typing(&release(KeyCode::Enter));
+        assert!(matches!(
+            submitted,
+            Some(AppEvent::Client(AppEventClient::ManualSignalingInput(text))) if text == "v4.local.abc"
+        ));
+        assert!(!state.typing && state.pending.is_empty());
+    }
 }
@@ -1,7 +1,11 @@
 pub mod files_widget;
 pub mod history_widget;
+pub mod log_widget;
 pub mod manual_handshake_widget;
+pub mod new_tab_widget;
+pub mod room_detail_widget;
 pub mod rooms_widget;
 pub mod server_handshake_widget;
+pub mod stepper;
 pub mod throbber;
 pub mod users_widget;
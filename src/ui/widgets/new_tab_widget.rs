@@ -0,0 +1,115 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use std::path::PathBuf;
+
+use crate::app::app_event::{AppEvent, AppEventClient};
+use crate::app::file_manager::FileManager;
+
+/// Asked for before a tab opens, a tab with the same room as another one would meet it instead of a peer
+#[derive(Debug, Default)]
+pub struct NewTabState {
+    pub open: bool,
+    /// Room for socket signaling, remote name for MQTT, none for manual signaling
+    pub target: Option<String>,
+    /// Typed or dropped, same as a paste of paths
+    pub files: String,
+    /// The files come after the target
+    pub editing_files: bool,
+}
+impl NewTabState {
+    pub fn start(&mut self, needs_target: bool) {
+        *self = Self {
+            open: true,
+            target: needs_target.then(String::new),
+            editing_files: !needs_target,
+            ..Default::default()
+        };
+    }
+
+    /// Takes every key while open, Enter moves on to the files and then opens the tab
+    pub fn handle_typing(&mut self, key_event: &KeyEvent) -> Option<AppEvent> {
+        if !self.open {
+            return None;
+        }
+        if !key_event.is_release() {
+            return Some(AppEvent::None);
+        }
+
+        match key_event.code {
+            KeyCode::Enter if !self.editing_files => {
+                self.editing_files = self.target.as_ref().is_some_and(|t| !t.trim().is_empty());
+            }
+            KeyCode::Enter => {
+                let target = self.target.take().map(|t| t.trim().to_string());
+                let files = self.paths();
+                self.open = false;
+                return Some(AppEventClient::OpenSession(target, files).into());
+            }
+            KeyCode::Tab if self.target.is_some() => self.editing_files = !self.editing_files,
+            KeyCode::Esc => self.open = false,
+            KeyCode::Backspace => {
+                self.field().pop();
+            }
+            KeyCode::Char(c) => self.field().push(c),
+            _ => {}
+        }
+        Some(AppEvent::None)
+    }
+
+    /// Dropped paths end up in the files
+    pub fn handle_paste(&mut self, text: &str) -> bool {
+        if !self.open {
+            return false;
+        }
+        let field = self.field();
+        if !field.is_empty() {
+            field.push(' ');
+        }
+        field.push_str(text.trim());
+        true
+    }
+
+    pub fn paths(&self) -> Vec<PathBuf> {
+        FileManager::parse_pasted_paths(&self.files)
+    }
+
+    fn field(&mut self) -> &mut String {
+        match &mut self.target {
+            Some(target) if !self.editing_files => target,
+            _ => &mut self.files,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyEventKind, KeyModifiers};
+
+    fn release(code: KeyCode) -> KeyEvent {
+        KeyEvent::new_with_kind(code, KeyModifiers::NONE, KeyEventKind::Release)
+    }
+
+    #[test]
+    fn asks_for_the_room_then_the_files() {
+        let mut state = NewTabState::default();
+        state.start(true);
+        state.handle_typing(&release(KeyCode::Enter));
+        assert!(!state.editing_files); // No room, no moving on
+
+        for c in "lab".chars() {
+            state.handle_typing(&release(KeyCode::Char(c)));
+        }
+        state.handle_typing(&release(KeyCode::Enter));
+        assert!(state.editing_files);
+        assert!(state.handle_paste("/tmp/a.txt"));
+
+        let event = state.handle_typing(&release(KeyCode::Enter));
+        assert!(matches!(
+            event,
+            Some(AppEvent::Client(AppEventClient::OpenSession(Some(target), files)))
+                if target == "lab" && files == vec![PathBuf::from("/tmp/a.txt")]
+        ));
+        assert!(!state.open);
+        assert!(state.handle_typing(&release(KeyCode::Char('q'))).is_none());
+    }
+}
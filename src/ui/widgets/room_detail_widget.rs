@@ -0,0 +1,154 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use rat_focus::{FocusBuilder, FocusFlag, HasFocus};
+use ratatui::symbols::border;
+use ratatui::{prelude::*, widgets::*};
+use ratatui_macros::line;
+
+use crate::app::app_event::AppEvent;
+use crate::app::app_main::App;
+use crate::app::models::SyncRoom;
+use crate::server::types::RoomId;
+use crate::ui::theme::Theme;
+use crate::ui::utils::{
+    BlockDefault, BlockExt, CollapsedBorder, CombinedWidgetState, Shortcut, StringExt,
+    SystemClipboard,
+};
+use crate::ui::widgets::files_widget::seconds_to_hms;
+
+#[derive(Default)]
+pub struct RoomDetailWidgetState {
+    pub area: Rect, // Should get updated when it renders
+    pub focus: FocusFlag,
+    pub room_id: Option<RoomId>, // Room shown on the last render
+}
+impl HasFocus for RoomDetailWidgetState {
+    fn area(&self) -> Rect {
+        self.area
+    }
+    fn build(&self, builder: &mut FocusBuilder) {
+        builder.leaf_widget(self);
+    }
+    fn focus(&self) -> FocusFlag {
+        self.focus.clone()
+    }
+}
+impl CombinedWidgetState for RoomDetailWidgetState {
+    fn get_shortcuts(&self) -> Vec<Shortcut> {
+        let mut result = vec![];
+        if self.room_id.is_some() {
+            result.push(Shortcut {
+                description: "Copy room name".to_string(),
+                button: "c".to_string(),
+            });
+        }
+        result
+    }
+    fn handle_key_events(&mut self, key_event: &KeyEvent) -> color_eyre::Result<AppEvent> {
+        let result: AppEvent = AppEvent::None;
+
+        if key_event.is_release()
+            && key_event.code == KeyCode::Char('c')
+            && let Some(room_id) = &self.room_id
+        {
+            SystemClipboard::copy(room_id)?;
+        }
+
+        Ok(result)
+    }
+}
+
+// Rebuild it on the fly for simplicity
+struct RoomDetailWidget<'a> {
+    theme: &'a Theme,
+    title: Option<String>,
+    borders: Borders,
+    border_set: symbols::border::Set,
+    room: Option<(&'a RoomId, &'a SyncRoom)>,
+}
+impl<'a> RoomDetailWidget<'a> {
+    fn new(
+        theme: &'a Theme,
+        title: Option<String>,
+        borders: Borders,
+        border_set: symbols::border::Set,
+        room: Option<(&'a RoomId, &'a SyncRoom)>,
+    ) -> Self {
+        Self {
+            theme,
+            title,
+            borders,
+            border_set,
+            room,
+        }
+    }
+
+    fn field(&self, name: &str, value: String) -> Line<'a> {
+        line!(
+            format!("{}: ", name).fg(self.theme.text.clone()),
+            value.fg(self.theme.info.clone())
+        )
+    }
+}
+impl<'a> StatefulWidget for RoomDetailWidget<'a> {
+    type State = RoomDetailWidgetState;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
+        state.area = area; // Set the area
+        state.room_id = self.room.map(|(room_id, _)| room_id.clone());
+
+        // Create a block
+        let mut block = BlockDefault::plain(self.theme)
+            .borders(self.borders)
+            .border_set(self.border_set);
+
+        // Add title
+        if let Some(widget_title) = &self.title {
+            block = block.title(widget_title.spaced());
+        }
+
+        // Set focus style
+        if state.is_focused() {
+            block = BlockDefault::focus_style_block(&block);
+        }
+
+        // Render
+        let inner = block.inner_with_margin(area, 0, 1);
+        block.render(area, buf); // Render first because otherwise colors get discarded
+        if let Some((room_id, room)) = self.room {
+            let lines = vec![
+                self.field("Name", room_id.clone()),
+                self.field(
+                    "Open for",
+                    seconds_to_hms(room.created_at.elapsed().as_secs()),
+                ),
+                self.field("Users", format!("{}/{}", room.peers(), room.capacity)),
+                self.field("Observers", (room.users.len() - room.peers()).to_string()),
+                self.field("Messages", room.messages.to_string()),
+            ];
+            Paragraph::new(lines).render(inner, buf);
+        }
+    }
+}
+
+pub fn room_detail_widget(app: &mut App, area: Rect, buf: &mut Buffer, builder: &mut FocusBuilder) {
+    let state = &app.room_list_widget_state;
+    let room = state
+        .get_selected_id()
+        .and_then(|room_id| state.rooms.get_key_value(room_id));
+    let animating = room.is_some(); // How long it's been open keeps counting
+
+    let room_detail = RoomDetailWidget::new(
+        &app.theme,
+        Some("Room details".to_string()),
+        CollapsedBorder::all(),
+        border::PLAIN,
+        room,
+    );
+
+    // Render
+    room_detail.render(area, buf, &mut app.room_detail_widget_state);
+    app.animating |= animating;
+
+    // Build focus
+    app.room_detail_widget_state.build(builder);
+}
@@ -11,8 +11,8 @@ use crate::app::models::SyncRoom;
 use crate::server::types::RoomId;
 use crate::ui::theme::Theme;
 use crate::ui::utils::{
-    BlockDefault, BlockExt, CollapsedBorder, CombinedWidgetState, ScrollbarStateExt, Shortcut,
-    StringExt,
+    BlockDefault, BlockExt, CollapsedBorder, CombinedWidgetState, CountPrefix, ScrollbarStateExt,
+    Shortcut, StringExt,
 };
 
 type SyncRooms = IndexMap<RoomId, SyncRoom>;
@@ -20,6 +20,7 @@ type SyncRooms = IndexMap<RoomId, SyncRoom>;
 pub struct RoomListWidgetState {
     pub area: Rect, // Should get updated when it renders
     pub focus: FocusFlag,
+    pub count: CountPrefix, // Repeat count typed before a motion
     pub list_state: ListState,
     pub scrollbar_state: ScrollbarState,
     pub rooms: SyncRooms,
@@ -85,6 +86,10 @@ impl CombinedWidgetState for RoomListWidgetState {
         let result: AppEvent = AppEvent::None;
 
         if key_event.is_release() {
+            if self.count.push(key_event) {
+                return Ok(result); // Wait for the motion
+            }
+            let count = self.count.take();
             match key_event.code {
                 KeyCode::Char('g') | KeyCode::Home => {
                     self.list_state.select_first();
@@ -98,11 +103,15 @@ impl CombinedWidgetState for RoomListWidgetState {
                     self.list_state.select(None);
                 }
                 KeyCode::Char('j') | KeyCode::Down => {
-                    self.list_state.select_next();
+                    for _ in 0..count {
+                        self.list_state.select_next();
+                    }
                     self.scrollbar_state.match_list_state(&self.list_state);
                 }
                 KeyCode::Char('k') | KeyCode::Up => {
-                    self.list_state.select_previous();
+                    for _ in 0..count {
+                        self.list_state.select_previous();
+                    }
                     self.scrollbar_state.match_list_state(&self.list_state);
                 }
                 _ => {}
@@ -111,6 +120,9 @@ impl CombinedWidgetState for RoomListWidgetState {
 
         Ok(result)
     }
+    fn reset_pending(&mut self) {
+        self.count.reset();
+    }
 }
 
 // Rebuild it on the fly for simplicity
@@ -163,7 +175,8 @@ impl<'a> StatefulWidget for RoomListWidget<'a> {
             .collect();
 
         let list = List::new(items)
-            .highlight_symbol("> ")
+            .highlight_symbol(self.theme.selection_symbol.as_str())
+            .highlight_style(self.theme.selected_style())
             .highlight_spacing(HighlightSpacing::Always);
 
         // Render
@@ -1,39 +1,67 @@
 use ratatui::{
     buffer::Buffer,
-    layout::Rect,
-    widgets::{Paragraph, Widget},
+    layout::{Flex, Rect},
+    style::Stylize,
+    text::Line,
+    widgets::{Clear, Paragraph, Widget},
 };
-use ratatui_macros::line;
+use ratatui_macros::{horizontal, line, vertical};
 
 use crate::{
     app::app_main::App,
-    cli::{Commands, SignalingSolutions},
-    ui::utils::{BlockDefault, BlockExt, StringExt},
+    cli::SignalingSolutions,
+    client::signaling::negotiator::HandshakeState,
+    ui::{
+        utils::{BlockDefault, BlockExt, StringExt},
+        widgets::{
+            files_widget::seconds_to_hms,
+            stepper::{handshake_stepper, step_label},
+            throbber::{custom_throbber, label_throbber},
+        },
+    },
 };
 
+/// The narrowest the banner gets, wider if the stepper needs it
+const BANNER_MIN_WIDTH: u16 = 48;
+
+/// Where the signaling is at, tells what to share while nobody showed up
+fn status_line<'a>(app: &App, signaling: &SignalingSolutions) -> Line<'a> {
+    let waiting = matches!(app.session.handshake_state, HandshakeState::WaitingForPeer);
+    match signaling {
+        // Tell what to share so a quiet wait doesn't look like a hang
+        SignalingSolutions::Socket(args) if waiting => line!(
+            "Waiting for a peer, share the room name: ",
+            args.room.clone().fg(app.theme.accent.clone())
+        ),
+        SignalingSolutions::Mqtt(args) if waiting => line!(
+            format!("Waiting for {}, share your name: ", args.remote_name),
+            args.local_name.clone().fg(app.theme.accent.clone())
+        ),
+        SignalingSolutions::Socket(args) => {
+            line!(format!("{} ({}:{})", args.room, args.address, args.port))
+        }
+        SignalingSolutions::Mqtt(args) => {
+            line!(format!(
+                "Local: {} Remote: {}",
+                args.local_name, args.remote_name
+            ))
+        }
+        _ => {
+            line!("")
+        }
+    }
+}
+
 pub fn server_handshake_widget(app: &mut App, area: Rect, buf: &mut Buffer) {
-    if let Commands::Client(client_args) = &app.args.app_mode {
-        let line = match &client_args.signaling_mode {
-            SignalingSolutions::Socket(args) => {
-                line!(format!("{} ({}:{})", args.room, args.address, args.port))
-            }
-            SignalingSolutions::Mqtt(args) => {
-                line!(format!(
-                    "Local: {} Remote: {}",
-                    args.local_name, args.remote_name
-                ))
-            }
-            _ => {
-                line!("")
-            }
-        };
+    if let Some(signaling) = &app.session.signaling {
+        let line = status_line(app, signaling);
 
         let window_block = BlockDefault::window(&app.theme, None, false);
         let block = BlockDefault::bordered(&app.theme).title("Signaling status".spaced());
 
         let paragraph = Paragraph::new(vec![
             line,
-            line!(format!("Status: {:?}", app.handshake_state)),
+            handshake_stepper(&app.theme, &app.session.handshake_state),
         ]);
 
         let block_area = window_block.inner(area);
@@ -43,3 +71,56 @@ pub fn server_handshake_widget(app: &mut App, area: Rect, buf: &mut Buffer) {
         paragraph.render(paragraph_area, buf);
     }
 }
+
+/// Takes the whole view until the peers are connected, the file lists would be empty anyway
+pub fn connection_banner(app: &mut App, area: Rect, buf: &mut Buffer) {
+    let Some(signaling) = app.session.signaling.clone() else {
+        return;
+    };
+    let signaling = &signaling;
+    app.animating = true;
+
+    let mut backend = line!(
+        "Connecting through ",
+        signaling.kind().fg(app.theme.accent.clone())
+    );
+    if let Some(target) = signaling.target() {
+        backend.push_span(" ");
+        backend.push_span(target.fg(app.theme.info.clone()));
+    }
+    let stepper = handshake_stepper(&app.theme, &app.session.handshake_state);
+    let label = step_label(&app.session.handshake_state).unwrap_or("Starting");
+    let mut progress = label_throbber(
+        custom_throbber(),
+        &mut app.throbber_sc.state,
+        label.to_string(),
+        Some(true),
+    );
+    progress.push_span(format!(
+        "  {}",
+        seconds_to_hms(app.session.started.elapsed().as_secs())
+    ));
+
+    let lines = vec![
+        backend,
+        status_line(app, signaling),
+        Line::default(),
+        stepper.clone(),
+        Line::default(),
+        progress.fg(app.theme.text.clone()),
+    ];
+
+    // Sized to fit, the stepper is the widest part
+    let width = (stepper.width() as u16 + 4)
+        .max(BANNER_MIN_WIDTH)
+        .min(area.width);
+    let height = lines.len() as u16 + 4;
+    let [area] = vertical![==height].flex(Flex::Center).areas(area);
+    let [area] = horizontal![==width].flex(Flex::Center).areas(area);
+
+    let block = BlockDefault::window(&app.theme, Some("Connecting"), true);
+    let inner = block.inner_with_margin(area, 1, 1);
+    Clear.render(area, buf);
+    block.render(area, buf);
+    Paragraph::new(lines).centered().render(inner, buf);
+}
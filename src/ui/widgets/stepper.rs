@@ -0,0 +1,41 @@
+use ratatui::prelude::*;
+
+use crate::{client::signaling::negotiator::HandshakeState, ui::theme::Theme};
+
+/// Step labels, ordered the same way as `HandshakeState::step`
+const LABELS: [&str; HandshakeState::STEPS] = [
+    "Connecting",
+    "Connected",
+    "Waiting for peer",
+    "Peer joined",
+    "Offer",
+    "Answer",
+    "Done",
+];
+
+/// The label of the step the exchange is at, none before it starts
+pub fn step_label(state: &HandshakeState) -> Option<&'static str> {
+    state.step().map(|step| LABELS[step])
+}
+
+/// Renders the handshake progress as a single line of steps
+pub fn handshake_stepper<'a>(theme: &Theme, state: &HandshakeState) -> Line<'a> {
+    let finished = matches!(state, HandshakeState::ExchangeFinished);
+    let current = state.step();
+    let mut spans: Vec<Span> = vec![];
+
+    for (i, label) in LABELS.iter().enumerate() {
+        if i > 0 {
+            spans.push(" › ".fg(theme.surface2.clone()));
+        }
+
+        let span = match current {
+            Some(c) if i < c || finished => format!("✓ {}", label).fg(theme.success.clone()),
+            Some(c) if i == c => format!("● {}", label).fg(theme.accent.clone()).bold(),
+            _ => format!("○ {}", label).fg(theme.surface2.clone()),
+        };
+        spans.push(span);
+    }
+
+    Line::from(spans)
+}
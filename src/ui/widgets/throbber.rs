@@ -39,10 +39,13 @@ impl ThrobberStateCounter {
         }
     }
 
-    pub fn update(&mut self) {
-        if self.counter.update() {
+    /// Returns true if the throbber moved
+    pub fn update(&mut self) -> bool {
+        let spun = self.counter.update();
+        if spun {
             self.state.calc_next();
         }
+        spun
     }
 }
 
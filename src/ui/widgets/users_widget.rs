@@ -11,13 +11,14 @@ use crate::app::models::SyncRoom;
 use crate::server::types::{RoomUser, UserId};
 use crate::ui::theme::Theme;
 use crate::ui::utils::{
-    BlockDefault, BlockExt, CollapsedBorder, CombinedWidgetState, Shortcut, StringExt,
+    BlockDefault, BlockExt, CollapsedBorder, CombinedWidgetState, CountPrefix, Shortcut, StringExt,
 };
 
 #[derive(Default)]
 pub struct UserListWidgetState {
     pub area: Rect, // Should get updated when it renders
     pub focus: FocusFlag,
+    pub count: CountPrefix, // Repeat count typed before a motion
     pub list_state: ListState,
 }
 impl UserListWidgetState {
@@ -65,6 +66,10 @@ impl CombinedWidgetState for UserListWidgetState {
         let result: AppEvent = AppEvent::None;
 
         if key_event.is_release() {
+            if self.count.push(key_event) {
+                return Ok(result); // Wait for the motion
+            }
+            let count = self.count.take();
             match key_event.code {
                 KeyCode::Char('g') | KeyCode::Home => {
                     self.list_state.select_first();
@@ -76,10 +81,14 @@ impl CombinedWidgetState for UserListWidgetState {
                     self.list_state.select(None);
                 }
                 KeyCode::Char('j') | KeyCode::Down => {
-                    self.list_state.select_next();
+                    for _ in 0..count {
+                        self.list_state.select_next();
+                    }
                 }
                 KeyCode::Char('k') | KeyCode::Up => {
-                    self.list_state.select_previous();
+                    for _ in 0..count {
+                        self.list_state.select_previous();
+                    }
                 }
                 _ => {}
             }
@@ -87,6 +96,9 @@ impl CombinedWidgetState for UserListWidgetState {
 
         Ok(result)
     }
+    fn reset_pending(&mut self) {
+        self.count.reset();
+    }
 }
 
 // Rebuild it on the fly for simplicity
@@ -143,21 +155,23 @@ impl<'a> StatefulWidget for UserListWidget<'a> {
                 .iter()
                 .enumerate()
                 .map(|(i, (_user_id, user))| {
-                    let mut item =
-                        ListItem::from(line!(format!("{}: {}", i + 1, user.name_with_id())));
-
-                    if let Some(selected) = state.list_state.selected()
-                        && state.is_focused()
-                        && i == selected
-                    {
-                        item = item.fg(self.theme.info.clone());
+                    let mut line = line!(format!("{}: {}", i + 1, user.name_with_id()));
+                    // Observers only listen, keep them apart from the peers
+                    if user.observer {
+                        line = line.fg(self.theme.surface2.clone()).italic();
+                        line.push_span(" (observer)");
                     }
-
-                    item
+                    ListItem::from(line)
                 })
                 .collect();
 
-            let list = List::new(items);
+            // Selection only shows while focused
+            let mut list = List::new(items);
+            if state.is_focused() {
+                list = list
+                    .highlight_symbol(self.theme.selection_symbol.as_str())
+                    .highlight_style(self.theme.selected_style());
+            }
 
             StatefulWidget::render(list, inner, buf, &mut state.list_state);
         }